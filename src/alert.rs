@@ -0,0 +1,139 @@
+//! Alerts when the water temperature of a station crosses a threshold
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::{AlertRule, Config},
+    notify::{Notifier, Topic},
+    observation::StationObservation,
+    timezone,
+};
+
+/// Alert posted to the webhook when a rule is triggered
+#[derive(Debug, Serialize)]
+struct TemperatureAlert<'a> {
+    station_id: u32,
+    station_name: &'a str,
+    temperature: f32,
+    previous_temperature: Option<f32>,
+    rule: AlertRule,
+    time: DateTime<Utc>,
+}
+
+impl AlertRule {
+    /// Whether the temperature crossed the threshold of the rule
+    ///
+    /// A rule is only triggered when the threshold is crossed (or without a
+    /// previous temperature), not while the temperature stays beyond it.
+    pub fn is_triggered(&self, previous: Option<f32>, temperature: f32) -> bool {
+        match *self {
+            AlertRule::Above(threshold) => {
+                temperature > threshold && previous.is_none_or(|previous| previous <= threshold)
+            }
+            AlertRule::Below(threshold) => {
+                temperature < threshold && previous.is_none_or(|previous| previous >= threshold)
+            }
+        }
+    }
+
+    /// Description of the rule for messages, e.g. "above 24.0°C"
+    fn describe(&self) -> String {
+        match self {
+            AlertRule::Above(threshold) => format!("above {threshold:.1}°C"),
+            AlertRule::Below(threshold) => format!("below {threshold:.1}°C"),
+        }
+    }
+}
+
+/// Checks a new observation against the alert rules of its station and sends
+/// an alert for every triggered rule
+///
+/// `previous` is the temperature of the previous observation of the station.
+pub async fn check(
+    config: &Config,
+    notifier: &Notifier,
+    previous: Option<f32>,
+    observation: &StationObservation,
+    dry_run: bool,
+) -> Result<()> {
+    let rules = config.station_alerts(observation.station_id);
+    let temperature = observation.temperature();
+    for rule in rules
+        .iter()
+        .filter(|r| r.is_triggered(previous, temperature))
+    {
+        warn!(
+            station_id = observation.station_id,
+            temperature,
+            "Station {} ({}) temperature is {} at {:.3}°C",
+            observation.station_id,
+            observation.station_name,
+            rule.describe(),
+            temperature,
+        );
+        if dry_run {
+            info!(
+                "Station {} ({}) temperature alert would be sent [DRY RUN]",
+                observation.station_id, observation.station_name
+            );
+            continue;
+        }
+
+        if let Some(url) = config.alerts_webhook_url() {
+            let alert = TemperatureAlert {
+                station_id: observation.station_id,
+                station_name: &observation.station_name,
+                temperature,
+                previous_temperature: previous,
+                rule: *rule,
+                time: observation.time(),
+            };
+            notifier
+                .post_webhook(url, "temperature alert", &alert)
+                .await?;
+        }
+        let subject = format!(
+            "Station {} ({}) is {}",
+            observation.station_id,
+            observation.station_name,
+            rule.describe()
+        );
+        let body = format!(
+            "The water temperature of station {} ({}) is {} at {:.3}°C (measured at {}).\n",
+            observation.station_id,
+            observation.station_name,
+            rule.describe(),
+            temperature,
+            timezone::log_time(observation.time()),
+        );
+        notifier
+            .email(Topic::TemperatureAlert, &subject, body)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_triggered() {
+        let above = AlertRule::Above(24.0);
+        // Crossing the threshold
+        assert!(above.is_triggered(Some(23.9), 24.1));
+        assert!(above.is_triggered(Some(24.0), 24.1));
+        assert!(above.is_triggered(None, 24.1));
+        // Staying above or below the threshold
+        assert!(!above.is_triggered(Some(24.2), 24.5));
+        assert!(!above.is_triggered(Some(23.0), 24.0));
+
+        let below = AlertRule::Below(5.0);
+        assert!(below.is_triggered(Some(5.2), 4.9));
+        assert!(!below.is_triggered(Some(4.8), 4.5));
+        assert!(!below.is_triggered(Some(6.0), 5.5));
+    }
+}
@@ -0,0 +1,291 @@
+//! Detection of sudden temperature jumps between consecutive measurements
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use tracing::{info, warn};
+
+use crate::{
+    config::AnomalyDetectionConfig,
+    database::{HeldMeasurement, MeasurementStore, QuarantinedMeasurement, StationState},
+    observation::StationObservation,
+    timezone,
+};
+
+/// Outcome of the anomaly check for a fetched observation
+#[derive(Debug, Default)]
+pub struct Evaluation {
+    /// Whether the observation is held back until it is confirmed
+    pub hold: bool,
+    /// A previously held measurement that was confirmed by this observation
+    /// and should now be sent as well
+    pub confirmed: Option<HeldMeasurement>,
+}
+
+/// Returns the temperature delta if it exceeds the configured threshold
+///
+/// Measurements further apart than the configured window are not compared.
+pub fn detect_jump(
+    config: &AnomalyDetectionConfig,
+    previous_time: DateTime<Utc>,
+    previous_temperature: f32,
+    observation: &StationObservation,
+) -> Option<f32> {
+    let elapsed = observation.time() - previous_time;
+    if elapsed <= Duration::zero() || elapsed > Duration::minutes(config.window_minutes().into()) {
+        return None;
+    }
+    let delta = observation.temperature() - previous_temperature;
+    (delta.abs() > config.max_delta).then_some(delta)
+}
+
+/// Checks a fetched observation against the previous one of the station
+///
+/// Jumps are always logged. If confirmation is required, the observation is
+/// held back in the database until the next reading confirms it (i.e. does
+/// not deviate more than the threshold from the held value). Unconfirmed
+/// measurements are moved to the quarantine for manual review.
+pub async fn evaluate(
+    config: &AnomalyDetectionConfig,
+    store: &dyn MeasurementStore,
+    previous_state: Option<&StationState>,
+    observation: &StationObservation,
+    dry_run: bool,
+) -> Result<Evaluation> {
+    let station_id = observation.station_id;
+    let mut evaluation = Evaluation::default();
+
+    // Resolve a previously held measurement first
+    let mut reference = previous_state.map(|s| (s.last_measurement_time, s.last_temperature));
+    if let Some(held) = store.held_measurement(station_id).await? {
+        if held.time == observation.time() {
+            info!(
+                "Station {} ({}) measurement at {} is still awaiting confirmation",
+                station_id,
+                observation.station_name,
+                timezone::log_time(observation.time()),
+            );
+            evaluation.hold = true;
+            return Ok(evaluation);
+        }
+
+        if !dry_run {
+            store.release_held_measurement(station_id).await?;
+        }
+        if (observation.temperature() - held.temperature).abs() <= config.max_delta {
+            info!(
+                "Station {} ({}) held measurement of {:.3}°C at {} was confirmed",
+                station_id,
+                observation.station_name,
+                held.temperature,
+                timezone::log_time(held.time),
+            );
+            reference = Some((held.time, held.temperature));
+            evaluation.confirmed = Some(held);
+        } else {
+            warn!(
+                "Station {} ({}) held measurement of {:.3}°C at {} was not confirmed, quarantining it",
+                station_id,
+                observation.station_name,
+                held.temperature,
+                timezone::log_time(held.time),
+            );
+            if !dry_run {
+                store
+                    .quarantine_measurement(&QuarantinedMeasurement {
+                        id: 0,
+                        station_id,
+                        time: held.time,
+                        temperature: held.temperature,
+                        reason: format!(
+                            "Not confirmed by the next reading of {:.3}°C at {}",
+                            observation.temperature(),
+                            timezone::log_time(observation.time()),
+                        ),
+                        quarantined_at: Utc::now(),
+                        released_at: None,
+                    })
+                    .await?;
+            }
+        }
+    }
+
+    let Some((previous_time, previous_temperature)) = reference else {
+        return Ok(evaluation);
+    };
+    let Some(delta) = detect_jump(config, previous_time, previous_temperature, observation) else {
+        return Ok(evaluation);
+    };
+
+    warn!(
+        "Station {} ({}) temperature jumped by {:+.3}°C within {} minutes",
+        station_id,
+        observation.station_name,
+        delta,
+        (observation.time() - previous_time).num_minutes(),
+    );
+    if config.require_confirmation() {
+        warn!(
+            "Station {} ({}) measurement at {} is held until confirmed by the next reading",
+            station_id,
+            observation.station_name,
+            timezone::log_time(observation.time()),
+        );
+        if !dry_run {
+            store
+                .hold_measurement(&HeldMeasurement {
+                    station_id,
+                    time: observation.time(),
+                    temperature: observation.temperature(),
+                    held_at: Utc::now(),
+                })
+                .await?;
+        }
+        evaluation.hold = true;
+    }
+
+    Ok(evaluation)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::database::SqliteStore;
+
+    fn config() -> AnomalyDetectionConfig {
+        AnomalyDetectionConfig {
+            max_delta: 5.0,
+            window_minutes: Some(10),
+            require_confirmation: None,
+        }
+    }
+
+    fn observation(temperature: f32, minute: u32) -> StationObservation {
+        StationObservation::new(
+            2104,
+            "Weesen",
+            Utc.with_ymd_and_hms(2025, 1, 15, 12, minute, 0).unwrap(),
+            temperature,
+        )
+    }
+
+    #[test]
+    fn test_detect_jump() {
+        let previous = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            detect_jump(&config(), previous, 15.0, &observation(16.0, 10)),
+            None
+        );
+        assert_eq!(
+            detect_jump(&config(), previous, 15.0, &observation(21.0, 10)),
+            Some(6.0)
+        );
+        assert_eq!(
+            detect_jump(&config(), previous, 15.0, &observation(9.0, 10)),
+            Some(-6.0)
+        );
+    }
+
+    #[test]
+    fn test_detect_jump_outside_window() {
+        let previous = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+
+        // Too far apart
+        assert_eq!(
+            detect_jump(&config(), previous, 15.0, &observation(21.0, 20)),
+            None
+        );
+        // Same observation
+        assert_eq!(
+            detect_jump(&config(), previous, 15.0, &observation(21.0, 0)),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_with_confirmation() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let config = AnomalyDetectionConfig {
+            require_confirmation: Some(true),
+            ..config()
+        };
+        let previous = StationState {
+            station_id: 2104,
+            last_fetch_at: Utc::now(),
+            last_measurement_time: Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+            last_temperature: 15.0,
+        };
+
+        // Jump is held back
+        let jump = observation(21.0, 10);
+        let evaluation = evaluate(&config, &store, Some(&previous), &jump, false)
+            .await
+            .unwrap();
+        assert!(evaluation.hold);
+        assert!(store.held_measurement(2104).await.unwrap().is_some());
+
+        // Same observation fetched again is still held
+        let evaluation = evaluate(&config, &store, Some(&previous), &jump, false)
+            .await
+            .unwrap();
+        assert!(evaluation.hold);
+
+        // Next reading confirms it
+        let evaluation = evaluate(
+            &config,
+            &store,
+            Some(&previous),
+            &observation(21.5, 20),
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(!evaluation.hold);
+        assert_eq!(evaluation.confirmed.unwrap().temperature, 21.0);
+        assert!(store.held_measurement(2104).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_not_confirmed() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let config = AnomalyDetectionConfig {
+            require_confirmation: Some(true),
+            ..config()
+        };
+        let previous = StationState {
+            station_id: 2104,
+            last_fetch_at: Utc::now(),
+            last_measurement_time: Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+            last_temperature: 15.0,
+        };
+
+        evaluate(
+            &config,
+            &store,
+            Some(&previous),
+            &observation(21.0, 10),
+            false,
+        )
+        .await
+        .unwrap();
+
+        // Next reading is back at the previous level, the glitch is quarantined
+        let evaluation = evaluate(
+            &config,
+            &store,
+            Some(&previous),
+            &observation(15.2, 20),
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(!evaluation.hold);
+        assert!(evaluation.confirmed.is_none());
+        assert!(store.held_measurement(2104).await.unwrap().is_none());
+        let quarantined = store.quarantined_measurements(false).await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].temperature, 21.0);
+    }
+}
@@ -0,0 +1,304 @@
+//! Backfill of past measurements from LINDAS (`backfill` subcommand)
+//!
+//! The time range is split into windows, which are queried and delivered
+//! concurrently. Every completed window is stored in the database, so that an
+//! interrupted backfill skips the completed windows when the same command is
+//! run again.
+
+use std::{
+    sync::{Mutex, PoisonError},
+    time::Duration as StdDuration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Duration, Utc};
+use futures::{StreamExt, stream};
+use indicatif::{ProgressBar, ProgressStyle};
+use tokio::time::{Instant, sleep_until};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    database::MeasurementStore,
+    gfroerli::GfroerliTarget,
+    http::HttpClients,
+    observation::Parameter,
+    pipeline::deliver_to_targets,
+    sparql::{ObservationQuery, SparqlSource, fetch_station_observations},
+    timezone,
+};
+
+/// Settings of a backfill
+#[derive(Debug, Clone)]
+pub struct BackfillOptions {
+    /// Start of the time range
+    pub from: DateTime<Utc>,
+    /// End of the time range (exclusive)
+    pub to: DateTime<Utc>,
+    /// Stations to backfill
+    pub station_ids: Vec<u32>,
+    /// Length of the time window of a query
+    pub window: Duration,
+    /// Number of windows processed at the same time
+    pub concurrency: usize,
+    /// Maximum number of SPARQL queries per second (unlimited if not set)
+    pub rate_limit: Option<f64>,
+}
+
+/// Time window of a query, from `start` (inclusive) to `end` (exclusive)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Split a time range into consecutive windows, the last one may be shorter
+pub fn windows(from: DateTime<Utc>, to: DateTime<Utc>, length: Duration) -> Vec<Window> {
+    let mut windows = Vec::new();
+    let mut start = from;
+    while start < to {
+        let end = (start + length).min(to);
+        windows.push(Window { start, end });
+        start = end;
+    }
+    windows
+}
+
+/// Spaces out queries evenly to stay below a number of queries per second
+#[derive(Debug)]
+struct RateLimiter {
+    interval: StdDuration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> Self {
+        Self {
+            interval: StdDuration::from_secs_f64(1.0 / per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for the next free slot
+    async fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+impl Window {
+    /// Whether the window lies within one of the completed windows, which
+    /// may have a different length if they were stored by another backfill
+    fn is_completed(&self, completed: &[(DateTime<Utc>, DateTime<Utc>)]) -> bool {
+        completed
+            .iter()
+            .any(|&(start, end)| start <= self.start && self.end <= end)
+    }
+}
+
+/// Query for the observations of a station in a window
+fn window_query(config: &Config, station_id: u32, window: Window) -> ObservationQuery {
+    let mut query = ObservationQuery::new(station_id)
+        .station_type(config.station_type(station_id))
+        .since(window.start)
+        .until(window.end);
+    if config.station_air_temperature(station_id) {
+        query = query.parameter(Parameter::AirTemperature);
+    }
+    query
+}
+
+/// Fetches the observations of a station in a window and delivers them to
+/// the targets of the station, skipping measurements that were already sent
+async fn backfill_window(
+    clients: &HttpClients,
+    config: &Config,
+    source: &SparqlSource,
+    store: &dyn MeasurementStore,
+    station_id: u32,
+    window: Window,
+    dry_run: bool,
+) -> Result<usize> {
+    let sensor_id = config
+        .find_gfroerli_sensor_id(station_id)
+        .ok_or_else(|| anyhow!("No sensor mapping found for station {}", station_id))?;
+    let targets = config
+        .station_targets(station_id)
+        .into_iter()
+        .map(|name| GfroerliTarget::new(config, clients, name))
+        .collect::<Result<Vec<_>>>()?;
+
+    let query = window_query(config, station_id, window);
+    let observations = fetch_station_observations(&clients.sparql, source, None, &query).await?;
+    for mut observation in observations.iter().cloned() {
+        if let Some(alias) = config.station_alias(station_id) {
+            observation.station_name = alias.to_string();
+        }
+        deliver_to_targets(&targets, None, store, &observation, sensor_id, dry_run).await?;
+    }
+    Ok(observations.len())
+}
+
+/// Backfills the measurements of the stations in a time range
+///
+/// Windows that were completed by a previous run are skipped. Failed windows are logged and retried by the next run, the
+/// backfill fails if any window failed.
+pub async fn run(
+    clients: &HttpClients,
+    config: &Config,
+    source: &SparqlSource,
+    store: &dyn MeasurementStore,
+    options: &BackfillOptions,
+    dry_run: bool,
+) -> Result<()> {
+    if options.from >= options.to {
+        bail!("The start of the backfill must be before its end");
+    }
+    if options.window <= Duration::zero() || options.concurrency == 0 {
+        bail!("The window length and concurrency of the backfill must be positive");
+    }
+    let all_windows = windows(options.from, options.to, options.window);
+
+    let mut tasks = Vec::new();
+    for &station_id in &options.station_ids {
+        let completed = store
+            .completed_backfill_windows(station_id, options.from, options.to)
+            .await?;
+        let pending: Vec<_> = all_windows
+            .iter()
+            .filter(|window| !window.is_completed(&completed))
+            .collect();
+        if pending.len() < all_windows.len() {
+            info!(
+                "Skipping {} completed windows of station {}",
+                all_windows.len() - pending.len(),
+                config.station_label(station_id)
+            );
+        }
+        tasks.extend(pending.into_iter().map(|&window| (station_id, window)));
+    }
+
+    info!(
+        "Backfilling {} stations between {} and {} in {} windows",
+        options.station_ids.len(),
+        timezone::log_time(options.from),
+        timezone::log_time(options.to),
+        tasks.len()
+    );
+    let bar = ProgressBar::new(tasks.len() as u64);
+    if let Ok(style) =
+        ProgressStyle::with_template("{bar:40} {pos}/{len} windows, {msg} measurements ({eta})")
+    {
+        bar.set_style(style);
+    }
+    let limiter = options.rate_limit.map(RateLimiter::new);
+    let mut measurements = 0;
+    let mut completed = 0;
+    let mut failed = 0;
+
+    let mut results = stream::iter(tasks)
+        .map(|(station_id, window)| {
+            let limiter = limiter.as_ref();
+            async move {
+                if let Some(limiter) = limiter {
+                    limiter.wait().await;
+                }
+                let result =
+                    backfill_window(clients, config, source, store, station_id, window, dry_run)
+                        .await;
+                (station_id, window, result)
+            }
+        })
+        .buffer_unordered(options.concurrency);
+
+    while let Some((station_id, window, result)) = results.next().await {
+        bar.inc(1);
+        match result {
+            Ok(count) => {
+                completed += 1;
+                measurements += count;
+                bar.set_message(measurements.to_string());
+                if !dry_run
+                    && let Err(e) = store
+                        .record_backfill_window(station_id, window.start, window.end, Utc::now())
+                        .await
+                {
+                    warn!("{:#}", e);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                warn!(
+                    "Failed to backfill station {} between {} and {}: {:#}",
+                    config.station_label(station_id),
+                    timezone::log_time(window.start),
+                    timezone::log_time(window.end),
+                    e
+                );
+            }
+        }
+    }
+    bar.finish_and_clear();
+
+    info!(
+        "Backfilled {} measurements in {} windows",
+        measurements, completed
+    );
+    if failed > 0 {
+        bail!("Failed to backfill {failed} windows, run the same command again to retry them");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        let from = "2025-01-01T00:00:00Z".parse().unwrap();
+        let to = "2025-01-03T12:00:00Z".parse().unwrap();
+        let at = |hours| from + Duration::hours(hours);
+
+        let windows = windows(from, to, Duration::days(1));
+        assert_eq!(
+            windows,
+            vec![
+                Window {
+                    start: at(0),
+                    end: at(24)
+                },
+                Window {
+                    start: at(24),
+                    end: at(48)
+                },
+                Window {
+                    start: at(48),
+                    end: at(60)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_completed() {
+        let from: DateTime<Utc> = "2025-01-01T00:00:00Z".parse().unwrap();
+        let at = |hours| from + Duration::hours(hours);
+        let window = Window {
+            start: at(24),
+            end: at(48),
+        };
+
+        assert!(!window.is_completed(&[]));
+        assert!(window.is_completed(&[(at(0), at(24)), (at(24), at(48))]));
+        // Covered by a longer window of another backfill
+        assert!(window.is_completed(&[(at(0), at(72))]));
+        // Only partially covered
+        assert!(!window.is_completed(&[(at(0), at(36)), (at(36), at(48))]));
+    }
+}
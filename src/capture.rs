@@ -0,0 +1,141 @@
+//! Capture of raw API traffic for debugging
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use reqwest::StatusCode;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// A captured Gfrörli API request and its response
+#[derive(Debug, Serialize)]
+struct GfroerliExchange<'a, T: Serialize> {
+    url: &'a str,
+    request: &'a T,
+    status: u16,
+    response: &'a str,
+}
+
+/// Writes raw SPARQL responses (and optionally Gfrörli requests and responses)
+/// to timestamped files in a directory
+#[derive(Debug, Clone)]
+pub struct Capture {
+    dir: PathBuf,
+    gfroerli: bool,
+}
+
+impl Capture {
+    /// Create the capture directory (if needed)
+    pub fn new(dir: impl Into<PathBuf>, gfroerli: bool) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create capture directory '{}'", dir.display()))?;
+        Ok(Self { dir, gfroerli })
+    }
+
+    /// Directory the files are written to
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Save a raw SPARQL response body
+    pub async fn sparql_response(&self, station_id: u32, body: &str) {
+        self.write(&format!("sparql-{station_id}"), body).await;
+    }
+
+    /// Save a Gfrörli API request together with its response (if enabled)
+    pub async fn gfroerli_exchange<T: Serialize>(
+        &self,
+        station_id: u32,
+        url: &str,
+        request: &T,
+        status: StatusCode,
+        response: &str,
+    ) {
+        if !self.gfroerli {
+            return;
+        }
+        let exchange = GfroerliExchange {
+            url,
+            request,
+            status: status.as_u16(),
+            response,
+        };
+        match serde_json::to_string_pretty(&exchange) {
+            Ok(contents) => {
+                self.write(&format!("gfroerli-{station_id}"), &contents)
+                    .await
+            }
+            Err(e) => warn!("Failed to serialize Gfrörli exchange for capture: {}", e),
+        }
+    }
+
+    /// Write a file named `<timestamp>-<name>.json`
+    ///
+    /// Failures are only logged, capturing must never interrupt processing.
+    async fn write(&self, name: &str, contents: &str) {
+        let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+        let path = self.dir.join(format!("{timestamp}-{name}.json"));
+        match tokio::fs::write(&path, contents).await {
+            Ok(()) => debug!("Captured {} to '{}'", name, path.display()),
+            Err(e) => warn!("Failed to write capture file '{}': {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_capture_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let capture = Capture::new(dir.path().join("capture"), true).unwrap();
+
+        capture.sparql_response(2104, "{\"results\":{}}").await;
+        capture
+            .gfroerli_exchange(
+                2104,
+                "http://localhost/measurements",
+                &serde_json::json!({ "sensor_id": 1 }),
+                StatusCode::CREATED,
+                "",
+            )
+            .await;
+
+        let names = captured_files(capture.dir());
+        assert_eq!(names.len(), 2);
+        assert!(names.iter().any(|name| name.ends_with("-sparql-2104.json")));
+        let gfroerli = names
+            .iter()
+            .find(|name| name.ends_with("-gfroerli-2104.json"))
+            .unwrap();
+        let contents: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(capture.dir().join(gfroerli)).unwrap())
+                .unwrap();
+        assert_eq!(contents["status"], 201);
+        assert_eq!(contents["request"]["sensor_id"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_capture_gfroerli_disabled() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let capture = Capture::new(dir.path(), false).unwrap();
+
+        capture
+            .gfroerli_exchange(2104, "http://localhost", &1, StatusCode::OK, "")
+            .await;
+
+        assert!(captured_files(dir.path()).is_empty());
+    }
+}
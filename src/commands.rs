@@ -0,0 +1,857 @@
+//! Implementation of the CLI subcommands
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Days, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::Config,
+    database::{MeasurementStore, SentMeasurement, StationMapping},
+    display::{Color, Painter, format_temperature},
+    export::export_parquet,
+    gfroerli::{GfroerliTarget, RemoteSensor, SendOutcome, idempotency_key},
+    http::HttpClients,
+    observation::StationObservation,
+    pipeline::deliver_to_targets,
+    report::{self, daily_report},
+    sparql::{SparqlSource, fetch_station_observation, station_query},
+    timezone::{self, format_time},
+};
+
+/// Temperatures closer than this are considered equal (values are rounded
+/// differently by the different systems)
+const COMPARE_TOLERANCE: f32 = 0.001;
+
+/// Latest measurement of a sensor as seen by one system
+type Latest = Option<(DateTime<Utc>, f32)>;
+
+/// Whether a system agrees with LINDAS on the latest measurement of a sensor
+fn agrees(lindas: Latest, latest: Latest) -> bool {
+    let (Some(lindas), Some((time, temperature))) = (lindas, latest) else {
+        return false;
+    };
+    time == lindas.0 && (temperature - lindas.1).abs() < COMPARE_TOLERANCE
+}
+
+/// Whether LINDAS, the local database and the Gfrörli API agree on the
+/// latest measurement of a sensor
+fn latest_agree(lindas: Latest, local: Latest, gfroerli: Latest) -> bool {
+    agrees(lindas, local) && agrees(lindas, gfroerli)
+}
+
+/// Color of a timestamp older than the stale threshold
+fn stale_color(config: &Config, time: DateTime<Utc>, now: DateTime<Utc>) -> Option<Color> {
+    let stale_after = Duration::minutes(config.stale_after_minutes().into());
+    (now - time > stale_after).then_some(Color::Yellow)
+}
+
+/// Color of a latest measurement in the comparison table: red if it is
+/// missing or disagrees with LINDAS, yellow if it is stale
+fn latest_color(
+    config: &Config,
+    latest: Latest,
+    agrees: bool,
+    now: DateTime<Utc>,
+) -> Option<Color> {
+    match latest {
+        Some((time, _)) if agrees => stale_color(config, time, now),
+        _ => Some(Color::Red),
+    }
+}
+
+/// Format a latest measurement for the comparison table
+fn format_latest(latest: Latest, timezone: Tz) -> String {
+    latest
+        .map(|(time, temperature)| {
+            format!(
+                "{} {}",
+                time.with_timezone(&timezone).format("%Y-%m-%d %H:%M"),
+                format_temperature(temperature)
+            )
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Prints the most recent fetch and send errors
+pub async fn db_errors(config: &Config, store: &dyn MeasurementStore, limit: u32) -> Result<()> {
+    let errors = store.recent_errors(limit).await?;
+    if errors.is_empty() {
+        println!("No errors recorded");
+        return Ok(());
+    }
+
+    println!(
+        "{:<25} {:>7} {:>6} {:<5} {:>6}  MESSAGE",
+        "TIME", "STATION", "SENSOR", "PHASE", "STATUS"
+    );
+    for error in errors {
+        println!(
+            "{:<25} {:>7} {:>6} {:<5} {:>6}  {}",
+            format_time(error.occurred_at, config.display_timezone()),
+            error.station_id,
+            error
+                .sensor_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            error.phase,
+            error
+                .http_status
+                .map(|status| status.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            error.message,
+        );
+    }
+
+    Ok(())
+}
+
+/// Exports all sent measurements to a Parquet file
+pub async fn db_export(store: &dyn MeasurementStore, path: &Path) -> Result<()> {
+    let count = export_parquet(store, path).await?;
+    info!("Exported {} measurements to '{}'", count, path.display());
+    Ok(())
+}
+
+/// Prints the quarantined measurements (released ones only if `all` is set)
+pub async fn quarantine_list(
+    config: &Config,
+    store: &dyn MeasurementStore,
+    all: bool,
+) -> Result<()> {
+    let measurements = store.quarantined_measurements(all).await?;
+    if measurements.is_empty() {
+        println!("No quarantined measurements");
+        return Ok(());
+    }
+
+    let timezone = config.display_timezone();
+    println!(
+        "{:>5} {:>7} {:<25} {:>8} {:<25}  REASON",
+        "ID", "STATION", "TIME", "TEMP", "RELEASED"
+    );
+    for measurement in measurements {
+        println!(
+            "{:>5} {:>7} {:<25} {:>8} {:<25}  {}",
+            measurement.id,
+            measurement.station_id,
+            format_time(measurement.time, timezone),
+            format!("{:.3}", measurement.temperature),
+            measurement
+                .released_at
+                .map(|time| format_time(time, timezone))
+                .unwrap_or_else(|| "-".to_string()),
+            measurement.reason,
+        );
+    }
+
+    Ok(())
+}
+
+/// Rebuilds the deduplication state from the Gfrörli API, e.g. after the
+/// local database was lost or corrupted
+///
+/// The measurements recorded as sent to each target in the time range are
+/// replaced by the measurements that exist in the API, for the sensors of all
+/// configured stations. The local state of a sensor is only replaced once its
+/// measurements were fetched, so a failed request leaves it untouched.
+pub async fn db_rebuild_from_api(
+    clients: &HttpClients,
+    config: &Config,
+    store: &dyn MeasurementStore,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<()> {
+    if from >= to {
+        bail!("The start of the time range must be before its end");
+    }
+    let sensors: BTreeSet<_> = config
+        .stations
+        .iter()
+        .flat_map(|station| {
+            config
+                .station_targets(station.foen_station_id)
+                .into_iter()
+                .map(|target| (target, station.gfroerli_sensor_id))
+        })
+        .collect();
+    info!(
+        "Rebuilding sent measurements of {} sensors between {} and {} from the Gfrörli API",
+        sensors.len(),
+        timezone::log_time(from),
+        timezone::log_time(to)
+    );
+
+    let mut failed = 0;
+    for &(name, sensor_id) in &sensors {
+        let target = GfroerliTarget::new(config, clients, name)?;
+        if let Err(e) = rebuild_sensor(&target, store, sensor_id, from, to, dry_run).await {
+            error!(
+                "Failed to rebuild sensor {} (target '{}'): {:#}",
+                sensor_id, name, e
+            );
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("Failed to rebuild {failed} of {} sensors", sensors.len());
+    }
+    Ok(())
+}
+
+/// Replaces the sent measurements of a sensor in a time range by those in the
+/// Gfrörli API
+async fn rebuild_sensor(
+    target: &GfroerliTarget<'_>,
+    store: &dyn MeasurementStore,
+    sensor_id: u32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<()> {
+    // Keyed by time, as the API may return several measurements at the same time
+    let remote: BTreeMap<_, _> = target
+        .client
+        .measurements(sensor_id, from, to)
+        .await
+        .map_err(|e| e.for_sensor(sensor_id))?
+        .into_iter()
+        .filter(|measurement| (from..=to).contains(&measurement.created_at))
+        .map(|measurement| {
+            let time = measurement.created_at;
+            let sent = SentMeasurement {
+                target: target.name.to_string(),
+                sensor_id,
+                time,
+                temperature: Some(measurement.temperature),
+                idempotency_key: idempotency_key(sensor_id, time),
+                gfroerli_id: measurement.id,
+            };
+            (time, sent)
+        })
+        .collect();
+    let remote: Vec<_> = remote.into_values().collect();
+
+    if dry_run {
+        let local = store
+            .sent_measurements(target.name, sensor_id, from, to)
+            .await?;
+        info!(
+            "Sensor {} (target '{}'): {} local measurements would be replaced by {} from the API [DRY RUN]",
+            sensor_id,
+            target.name,
+            local.len(),
+            remote.len()
+        );
+        return Ok(());
+    }
+    let removed = store
+        .replace_sent_measurements(target.name, sensor_id, from, to, &remote)
+        .await?;
+    info!(
+        "Sensor {} (target '{}'): replaced {} local measurements by {} from the API",
+        sensor_id,
+        target.name,
+        removed,
+        remote.len()
+    );
+    Ok(())
+}
+
+/// Sends a quarantined measurement to the targets of its station, e.g. after
+/// it turned out to be a false positive of anomaly detection
+///
+/// Targets that already received a measurement at the same time are skipped.
+/// The measurement is marked as released once all targets received it.
+pub async fn quarantine_release(
+    clients: &HttpClients,
+    config: &Config,
+    store: &dyn MeasurementStore,
+    id: i64,
+    dry_run: bool,
+) -> Result<()> {
+    let measurement = store
+        .quarantined_measurement(id)
+        .await?
+        .ok_or_else(|| anyhow!("No quarantined measurement with ID {id}"))?;
+    if let Some(released_at) = measurement.released_at {
+        bail!(
+            "Quarantined measurement {id} was already released at {}",
+            timezone::log_time(released_at)
+        );
+    }
+
+    let station_id = measurement.station_id;
+    let sensor_id = config
+        .find_gfroerli_sensor_id(station_id)
+        .ok_or_else(|| anyhow!("No sensor mapping found for station {}", station_id))?;
+    let targets = config
+        .station_targets(station_id)
+        .into_iter()
+        .map(|name| GfroerliTarget::new(config, clients, name))
+        .collect::<Result<Vec<_>>>()?;
+    let observation = StationObservation::new(
+        station_id,
+        config.station_label(station_id),
+        measurement.time,
+        measurement.temperature,
+    );
+    info!(
+        "Releasing quarantined measurement of station {} at {} ({:.3}°C)",
+        config.station_label(station_id),
+        timezone::log_time(measurement.time),
+        measurement.temperature
+    );
+    deliver_to_targets(&targets, None, store, &observation, sensor_id, dry_run).await?;
+
+    if !dry_run {
+        store
+            .release_quarantined_measurement(id, Utc::now())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Sends a manually entered measurement of a sensor, e.g. a correction value
+///
+/// The measurement goes to the targets of the sensor's station like a fetched
+/// one: measurements that were already sent are skipped, and the send is
+/// recorded in the database.
+pub async fn send(
+    clients: &HttpClients,
+    config: &Config,
+    store: &dyn MeasurementStore,
+    sensor_id: u32,
+    temperature: f32,
+    time: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<()> {
+    if time > Utc::now() {
+        bail!(
+            "Measurement time {} is in the future",
+            timezone::log_time(time)
+        );
+    }
+    let station_id = config
+        .find_foen_station_id(sensor_id)
+        .ok_or_else(|| anyhow!("No station mapping found for sensor {}", sensor_id))?;
+    let targets = config
+        .station_targets(station_id)
+        .into_iter()
+        .map(|name| GfroerliTarget::new(config, clients, name))
+        .collect::<Result<Vec<_>>>()?;
+    let observation = StationObservation::new(
+        station_id,
+        config.station_label(station_id),
+        time,
+        temperature,
+    );
+    info!(
+        "Sending manual measurement of sensor {} at {} ({:.3}°C)",
+        sensor_id,
+        timezone::log_time(time),
+        temperature
+    );
+    deliver_to_targets(&targets, None, store, &observation, sensor_id, dry_run).await
+}
+
+/// Whether a sensor exists in the Gfrörli API and is mapped to a station
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SensorStatus {
+    /// Configured and existing
+    Mapped,
+    /// Configured, but doesn't exist or isn't visible to the API key
+    Missing,
+    /// Exists, but no station is mapped to it
+    Unmapped,
+}
+
+/// Cross-references the configured sensors (with their station) with the
+/// sensors of the Gfrörli API, ordered by sensor ID
+fn cross_reference<'a>(
+    configured: &BTreeMap<u32, u32>,
+    remote: &'a [RemoteSensor],
+) -> Vec<(u32, Option<&'a RemoteSensor>, SensorStatus)> {
+    let remote: BTreeMap<_, _> = remote.iter().map(|sensor| (sensor.id, sensor)).collect();
+    configured
+        .keys()
+        .chain(remote.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|&sensor_id| {
+            let sensor = remote.get(&sensor_id).copied();
+            let status = match (configured.contains_key(&sensor_id), sensor) {
+                (true, Some(_)) => SensorStatus::Mapped,
+                (true, None) => SensorStatus::Missing,
+                (false, _) => SensorStatus::Unmapped,
+            };
+            (sensor_id, sensor, status)
+        })
+        .collect()
+}
+
+/// Prints the sensors of the Gfrörli API of every target in use,
+/// cross-referenced with the configured station mapping
+///
+/// Fails if a configured sensor doesn't exist, as its measurements can't be
+/// sent.
+pub async fn sensors_list(clients: &HttpClients, config: &Config) -> Result<()> {
+    let painter = Painter::stdout();
+    println!(
+        "{:<10} {:>6} {:>7} {:<8}  NAME",
+        "TARGET", "SENSOR", "STATION", "STATUS"
+    );
+
+    let mut missing = 0;
+    for (name, _) in config.gfroerli_target_configs() {
+        let configured: BTreeMap<u32, u32> = config
+            .stations
+            .iter()
+            .filter(|station| {
+                config
+                    .station_targets(station.foen_station_id)
+                    .contains(&name)
+            })
+            .map(|station| (station.gfroerli_sensor_id, station.foen_station_id))
+            .collect();
+        if configured.is_empty() {
+            continue;
+        }
+        let target = GfroerliTarget::new(config, clients, name)?;
+        let remote = target.client.sensors().await?;
+
+        for (sensor_id, sensor, status) in cross_reference(&configured, &remote) {
+            let (status, color) = match status {
+                SensorStatus::Mapped => ("ok", Color::Green),
+                SensorStatus::Missing => {
+                    missing += 1;
+                    ("MISSING", Color::Red)
+                }
+                SensorStatus::Unmapped => ("unmapped", Color::Yellow),
+            };
+            let sensor_name = sensor
+                .and_then(|sensor| sensor.device_name.as_deref().or(sensor.caption.as_deref()))
+                .unwrap_or("-");
+            println!(
+                "{:<10} {:>6} {:>7} {}  {}",
+                target.name,
+                sensor_id,
+                configured
+                    .get(&sensor_id)
+                    .map(|station_id| station_id.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                painter.paint(&format!("{status:<8}"), Some(color)),
+                sensor_name,
+            );
+        }
+    }
+
+    if missing > 0 {
+        bail!("{missing} configured sensors don't exist in the Gfrörli API");
+    }
+    Ok(())
+}
+
+/// Warns if the station mappings stored in the database are not used
+fn warn_mappings_disabled(config: &Config) {
+    if !config.database_mappings() {
+        warn!("The stored mappings are only used with `mappings = true` in the [database] section");
+    }
+}
+
+/// Prints the station mappings stored in the database
+pub async fn mapping_list(config: &Config, store: &dyn MeasurementStore) -> Result<()> {
+    warn_mappings_disabled(config);
+    println!("{:>7} {:>6}  UPDATED", "STATION", "SENSOR");
+    for mapping in store.station_mappings().await? {
+        println!(
+            "{:>7} {:>6}  {}",
+            mapping.station_id,
+            mapping.sensor_id,
+            format_time(mapping.updated_at, config.display_timezone())
+        );
+    }
+    Ok(())
+}
+
+/// Stores the mapping of a station to a sensor in the database
+///
+/// A sensor can only be mapped to one station, both in the configuration
+/// and in the database. A running fetcher picks up the mapping when its
+/// configuration is reloaded.
+pub async fn mapping_add(
+    config: &Config,
+    store: &dyn MeasurementStore,
+    station_id: u32,
+    sensor_id: u32,
+) -> Result<()> {
+    warn_mappings_disabled(config);
+    if let Some(other) = config.find_foen_station_id(sensor_id)
+        && other != station_id
+    {
+        bail!("Sensor {sensor_id} is already mapped to station {other}");
+    }
+    if let Some(other) = store
+        .station_mappings()
+        .await?
+        .into_iter()
+        .find(|mapping| mapping.sensor_id == sensor_id && mapping.station_id != station_id)
+    {
+        bail!(
+            "Sensor {sensor_id} is already mapped to station {} in the database",
+            other.station_id
+        );
+    }
+    store
+        .set_station_mapping(&StationMapping {
+            station_id,
+            sensor_id,
+            updated_at: Utc::now(),
+        })
+        .await?;
+    info!("Mapped station {} to sensor {}", station_id, sensor_id);
+    Ok(())
+}
+
+/// Removes the mapping of a station from the database
+///
+/// A mapping of the station in the configuration file applies again.
+pub async fn mapping_remove(
+    config: &Config,
+    store: &dyn MeasurementStore,
+    station_id: u32,
+) -> Result<()> {
+    warn_mappings_disabled(config);
+    if !store.remove_station_mapping(station_id).await? {
+        bail!("No mapping of station {station_id} is stored in the database");
+    }
+    info!("Removed the mapping of station {}", station_id);
+    Ok(())
+}
+
+/// Prints the configured stations with the time of the last measurement sent
+/// to each of their targets
+pub async fn stations_list(config: &Config, store: &dyn MeasurementStore) -> Result<()> {
+    let painter = Painter::stdout();
+    let now = Utc::now();
+    println!(
+        "{:>7} {:>6} {:<10} {:<7} {:<25}  ALIAS",
+        "STATION", "SENSOR", "TARGET", "ENABLED", "LAST SENT"
+    );
+    for station in &config.stations {
+        for target in config.station_targets(station.foen_station_id) {
+            let last_sent = store
+                .latest_sent_measurement(target, station.gfroerli_sensor_id)
+                .await?;
+            println!(
+                "{:>7} {:>6} {:<10} {:<7} {}  {}",
+                station.foen_station_id,
+                station.gfroerli_sensor_id,
+                target,
+                if station.is_enabled() { "yes" } else { "no" },
+                match last_sent {
+                    Some(sent) => painter.paint(
+                        &format!("{:<25}", format_time(sent.time, config.display_timezone())),
+                        stale_color(config, sent.time, now),
+                    ),
+                    None => format!("{:<25}", "-"),
+                },
+                station.alias.as_deref().unwrap_or("-"),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Re-sends measurements that were already sent to the Gfrörli API
+///
+/// The deduplication check is bypassed on purpose, e.g. to restore data after
+/// the Gfrörli database was restored from an old backup. Measurements sent
+/// before values were stored in the database are skipped.
+pub async fn replay(
+    config: &Config,
+    target: &GfroerliTarget<'_>,
+    store: &dyn MeasurementStore,
+    sensor_id: u32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<()> {
+    let station_id = config
+        .find_foen_station_id(sensor_id)
+        .ok_or_else(|| anyhow!("No station mapping found for sensor {}", sensor_id))?;
+    let measurements = store
+        .sent_measurements(target.name, sensor_id, from, to)
+        .await?;
+    info!(
+        "Replaying {} measurements of sensor {} between {} and {} to target '{}'",
+        measurements.len(),
+        sensor_id,
+        timezone::log_time(from),
+        timezone::log_time(to),
+        target.name
+    );
+
+    let mut replayed = 0;
+    let mut failed = 0;
+    for measurement in measurements {
+        let Some(temperature) = measurement.temperature else {
+            warn!(
+                "Skipping measurement of sensor {} at {}: value not stored",
+                sensor_id,
+                timezone::log_time(measurement.time)
+            );
+            continue;
+        };
+        let observation = StationObservation::new(
+            station_id,
+            station_id.to_string(),
+            measurement.time,
+            temperature,
+        );
+
+        if dry_run {
+            info!(
+                "Measurement of sensor {} at {} ({:.3}°C) would be re-sent [DRY RUN]",
+                sensor_id, measurement.time, temperature
+            );
+            continue;
+        }
+
+        match target
+            .client
+            .create_measurement(&observation, sensor_id, None)
+            .await
+        {
+            Ok(SendOutcome::AlreadyExists) => {
+                info!(
+                    "Measurement of sensor {} at {} already exists in target '{}'",
+                    sensor_id, measurement.time, target.name
+                );
+                replayed += 1;
+            }
+            Ok(SendOutcome::Created(_)) => {
+                info!(
+                    "Re-sent measurement of sensor {} at {} ({:.3}°C)",
+                    sensor_id, measurement.time, temperature
+                );
+                replayed += 1;
+            }
+            Err(e) => {
+                error!(
+                    "Failed to re-send measurement of sensor {} at {}: {:#}",
+                    sensor_id, measurement.time, e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "Failed to re-send {failed} of {} measurements",
+            replayed + failed
+        );
+    }
+    Ok(())
+}
+
+/// Compares the latest measurement per sensor between LINDAS, the local
+/// database and the Gfrörli API
+///
+/// Fails if any sensor disagrees, so that silent data loss downstream is
+/// noticed.
+pub async fn compare(
+    clients: &HttpClients,
+    config: &Config,
+    source: &SparqlSource,
+    store: &dyn MeasurementStore,
+) -> Result<()> {
+    let painter = Painter::stdout();
+    let timezone = config.display_timezone();
+    let now = Utc::now();
+    println!(
+        "{:>7} {:>6} {:<10}  {:<24} {:<24} {:<24} STATUS",
+        "STATION", "SENSOR", "TARGET", "LINDAS", "LOCAL", "GFRÖRLI"
+    );
+
+    let mut mismatches = 0;
+    let mut compared = 0;
+    for station in config.enabled_stations() {
+        let lindas = fetch_station_observation(
+            &clients.sparql,
+            source,
+            None,
+            &station_query(config, station.foen_station_id),
+        )
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "Failed to fetch station {} from LINDAS: {:#}",
+                station.foen_station_id, e
+            );
+            None
+        })
+        .map(|observation| (observation.time(), observation.temperature()));
+
+        for name in config.station_targets(station.foen_station_id) {
+            let target = GfroerliTarget::new(config, clients, name)?;
+            let local = store
+                .latest_sent_measurement(target.name, station.gfroerli_sensor_id)
+                .await?
+                .and_then(|sent| Some((sent.time, sent.temperature?)));
+            let gfroerli = target
+                .client
+                .latest_measurement(station.gfroerli_sensor_id)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Failed to fetch sensor {} from Gfrörli target '{}': {:#}",
+                        station.gfroerli_sensor_id, target.name, e
+                    );
+                    None
+                })
+                .map(|remote| (remote.created_at, remote.temperature));
+
+            let agree = latest_agree(lindas, local, gfroerli);
+            compared += 1;
+            if !agree {
+                mismatches += 1;
+            }
+            let cell = |latest: Latest, color: Option<Color>| {
+                painter.paint(&format!("{:<24}", format_latest(latest, timezone)), color)
+            };
+            println!(
+                "{:>7} {:>6} {:<10}  {} {} {} {}",
+                station.foen_station_id,
+                station.gfroerli_sensor_id,
+                target.name,
+                cell(lindas, latest_color(config, lindas, true, now)),
+                cell(
+                    local,
+                    latest_color(config, local, agrees(lindas, local), now)
+                ),
+                cell(
+                    gfroerli,
+                    latest_color(config, gfroerli, agrees(lindas, gfroerli), now)
+                ),
+                if agree {
+                    painter.paint("ok", Some(Color::Green))
+                } else {
+                    painter.paint("MISMATCH", Some(Color::Red))
+                },
+            );
+        }
+    }
+
+    if mismatches > 0 {
+        bail!("{mismatches} of {compared} sensors disagree");
+    }
+    Ok(())
+}
+
+/// Prints the daily report of a day (yesterday by default), and writes and
+/// posts it as configured
+pub async fn report_daily(
+    clients: &HttpClients,
+    config: &Config,
+    store: &dyn MeasurementStore,
+    date: Option<NaiveDate>,
+) -> Result<()> {
+    let date = match date {
+        Some(date) => date,
+        None => Utc::now()
+            .with_timezone(&config.display_timezone())
+            .date_naive()
+            .checked_sub_days(Days::new(1))
+            .ok_or_else(|| anyhow!("No day before today"))?,
+    };
+    let report = daily_report(config, store, date).await?;
+    print!("{report}");
+    report::deliver(config, clients, &report).await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::config::GfroerliConfig;
+
+    #[test]
+    fn test_latest_agree() {
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let earlier = Utc.with_ymd_and_hms(2025, 1, 15, 11, 50, 0).unwrap();
+
+        assert!(latest_agree(
+            Some((time, 6.5)),
+            Some((time, 6.5)),
+            Some((time, 6.5004))
+        ));
+        assert!(!latest_agree(
+            Some((time, 6.5)),
+            Some((time, 6.5)),
+            Some((earlier, 6.5))
+        ));
+        assert!(!latest_agree(
+            Some((time, 6.5)),
+            Some((time, 6.5)),
+            Some((time, 6.6))
+        ));
+        assert!(!latest_agree(Some((time, 6.5)), Some((time, 6.5)), None));
+    }
+
+    #[test]
+    fn test_cross_reference() {
+        let sensor = |id| RemoteSensor {
+            id,
+            device_name: None,
+            caption: None,
+        };
+        let remote = [sensor(3), sensor(1), sensor(4)];
+        let configured = BTreeMap::from([(1, 2104), (2, 2176)]);
+
+        let statuses: Vec<_> = cross_reference(&configured, &remote)
+            .into_iter()
+            .map(|(sensor_id, sensor, status)| (sensor_id, sensor.is_some(), status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (1, true, SensorStatus::Mapped),
+                (2, false, SensorStatus::Missing),
+                (3, true, SensorStatus::Unmapped),
+                (4, true, SensorStatus::Unmapped),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_latest_color() {
+        let config = Config::new(
+            Vec::new(),
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let recent = now - Duration::minutes(10);
+        let stale = now - Duration::hours(3);
+
+        assert_eq!(latest_color(&config, Some((recent, 6.5)), true, now), None);
+        assert_eq!(
+            latest_color(&config, Some((stale, 6.5)), true, now),
+            Some(Color::Yellow)
+        );
+        assert_eq!(
+            latest_color(&config, Some((recent, 6.5)), false, now),
+            Some(Color::Red)
+        );
+        assert_eq!(latest_color(&config, None, true, now), Some(Color::Red));
+    }
+}
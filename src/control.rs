@@ -0,0 +1,390 @@
+//! Control socket for runtime commands in loop mode, and graceful shutdown
+
+use std::{
+    fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::timezone;
+
+/// Command accepted on the control socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Run a cycle now
+    Trigger,
+    /// Stop running cycles until resumed
+    Pause,
+    /// Continue running cycles
+    Resume,
+    /// Report the state of the fetcher
+    Status,
+    /// Reload the configuration file
+    Reload,
+    /// Cancel the running cycle and shut down
+    Stop,
+}
+
+impl FromStr for ControlCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "trigger" => Ok(ControlCommand::Trigger),
+            "pause" => Ok(ControlCommand::Pause),
+            "resume" => Ok(ControlCommand::Resume),
+            "status" => Ok(ControlCommand::Status),
+            "reload" => Ok(ControlCommand::Reload),
+            "stop" => Ok(ControlCommand::Stop),
+            _ => bail!("unknown command '{s}'"),
+        }
+    }
+}
+
+/// Reason why [`Control::sleep_until`] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wakeup {
+    /// The deadline elapsed
+    Elapsed,
+    /// A cycle was triggered
+    Trigger,
+    /// The configuration should be reloaded (and a cycle run)
+    Reload,
+    /// The fetcher should shut down
+    Shutdown,
+}
+
+/// Outcome of the most recent cycle, as reported by the `status` command
+#[derive(Debug, Clone, Default)]
+pub struct CycleStatus {
+    /// When the cycle finished
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Number of stations processed successfully
+    pub success: usize,
+    /// Number of stations that failed
+    pub errors: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    paused: bool,
+    pending: Option<Wakeup>,
+    status: CycleStatus,
+}
+
+/// Runtime control of the processing loop
+///
+/// The loop sleeps through [`Control::sleep_until`], which returns early if a
+/// cycle is triggered and doesn't return while paused. Commands are received
+/// on a Unix domain socket, one command per line, and answered with one line.
+///
+/// The shutdown token is cancelled by the `stop` command, and cancels the
+/// sleep and the work that runs with it.
+#[derive(Debug, Clone, Default)]
+pub struct Control {
+    state: Arc<Mutex<State>>,
+    notify: Arc<Notify>,
+    shutdown: CancellationToken,
+}
+
+impl Control {
+    /// Create a control that shuts down when the token is cancelled
+    pub fn new(shutdown: CancellationToken) -> Self {
+        Self {
+            shutdown,
+            ..Default::default()
+        }
+    }
+
+    /// Token that is cancelled when the fetcher should shut down
+    pub fn shutdown(&self) -> &CancellationToken {
+        &self.shutdown
+    }
+
+    /// Accept commands on a Unix domain socket at `path`
+    ///
+    /// A stale socket file from a previous run is replaced.
+    pub fn listen(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket '{}'", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind control socket '{}'", path.display()))?;
+        info!("Listening for control commands on '{}'", path.display());
+
+        let control = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let control = control.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = control.serve(stream).await {
+                                warn!("Control connection failed: {:#}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Answer the commands of one connection
+    async fn serve(&self, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let response = match line.trim().parse() {
+                Ok(command) => {
+                    info!("Received control command '{}'", line.trim());
+                    self.handle(command)
+                }
+                Err(e) => format!("error: {e}"),
+            };
+            writer.write_all(format!("{response}\n").as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Execute a command and return the response
+    pub fn handle(&self, command: ControlCommand) -> String {
+        let mut state = self.lock();
+        let response = match command {
+            ControlCommand::Trigger => {
+                // A pending reload runs a cycle anyway
+                state.pending.get_or_insert(Wakeup::Trigger);
+                "ok".to_string()
+            }
+            ControlCommand::Pause => {
+                state.paused = true;
+                "ok".to_string()
+            }
+            ControlCommand::Resume => {
+                state.paused = false;
+                "ok".to_string()
+            }
+            ControlCommand::Reload => {
+                state.pending = Some(Wakeup::Reload);
+                "ok".to_string()
+            }
+            ControlCommand::Stop => {
+                self.shutdown.cancel();
+                "ok".to_string()
+            }
+            ControlCommand::Status => format_status(&state),
+        };
+        drop(state);
+        self.notify.notify_waiters();
+        response
+    }
+
+    /// Record the outcome of a cycle for the `status` command
+    pub fn set_status(&self, status: CycleStatus) {
+        self.lock().status = status;
+    }
+
+    /// Sleep until the deadline, a triggered cycle, a reload or the shutdown
+    ///
+    /// While paused, the deadline is ignored.
+    pub async fn sleep_until(&self, deadline: Instant) -> Wakeup {
+        loop {
+            if self.shutdown.is_cancelled() {
+                return Wakeup::Shutdown;
+            }
+            // Register before checking the state, so that no command is missed
+            let notified = self.notify.notified();
+            let paused = {
+                let mut state = self.lock();
+                if let Some(wakeup) = state.pending.take() {
+                    return wakeup;
+                }
+                if !state.paused && Instant::now() >= deadline {
+                    return Wakeup::Elapsed;
+                }
+                state.paused
+            };
+
+            if paused {
+                tokio::select! {
+                    _ = notified => {}
+                    _ = self.shutdown.cancelled() => {}
+                }
+            } else {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => {}
+                    _ = notified => {}
+                    _ = self.shutdown.cancelled() => {}
+                }
+            }
+        }
+    }
+
+    /// Wait until the fetcher isn't paused, or until the shutdown
+    pub async fn wait_resumed(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if !self.lock().paused || self.shutdown.is_cancelled() {
+                return;
+            }
+            tokio::select! {
+                _ = notified => {}
+                _ = self.shutdown.cancelled() => {}
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Cancel the token on SIGINT (Ctrl-C) or SIGTERM
+///
+/// The running work is cancelled cooperatively, so that the current requests
+/// are aborted cleanly and the leader lock is released. A second signal
+/// exits immediately.
+pub fn cancel_on_signal(shutdown: CancellationToken) -> Result<()> {
+    let mut interrupt =
+        signal(SignalKind::interrupt()).with_context(|| "Failed to listen for SIGINT")?;
+    let mut terminate =
+        signal(SignalKind::terminate()).with_context(|| "Failed to listen for SIGTERM")?;
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = interrupt.recv() => {}
+            _ = terminate.recv() => {}
+        }
+        info!("Shutting down, send the signal again to exit immediately");
+        shutdown.cancel();
+        tokio::select! {
+            _ = interrupt.recv() => {}
+            _ = terminate.recv() => {}
+        }
+        warn!("Exiting without finishing the running work");
+        std::process::exit(130);
+    });
+    Ok(())
+}
+
+/// Format the answer to the `status` command
+fn format_status(state: &State) -> String {
+    let mode = if state.paused { "paused" } else { "running" };
+    match state.status.finished_at {
+        Some(finished_at) => format!(
+            "{mode}, last cycle finished at {}: {} succeeded, {} failed",
+            timezone::log_time(finished_at),
+            state.status.success,
+            state.status.errors
+        ),
+        None => format!("{mode}, no cycle finished yet"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            "trigger".parse::<ControlCommand>().unwrap(),
+            ControlCommand::Trigger
+        );
+        assert_eq!(
+            "reload".parse::<ControlCommand>().unwrap(),
+            ControlCommand::Reload
+        );
+        assert!("restart".parse::<ControlCommand>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sleep_until() {
+        let control = Control::default();
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(control.sleep_until(deadline).await, Wakeup::Elapsed);
+
+        let far = Instant::now() + Duration::from_secs(3600);
+        control.handle(ControlCommand::Trigger);
+        assert_eq!(control.sleep_until(far).await, Wakeup::Trigger);
+
+        // Reload takes precedence over a trigger
+        control.handle(ControlCommand::Reload);
+        control.handle(ControlCommand::Trigger);
+        assert_eq!(control.sleep_until(far).await, Wakeup::Reload);
+
+        // Paused: the deadline is ignored until resumed
+        control.handle(ControlCommand::Pause);
+        let sleeper = tokio::spawn({
+            let control = control.clone();
+            async move { control.sleep_until(Instant::now()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!sleeper.is_finished());
+        assert!(control.handle(ControlCommand::Status).starts_with("paused"));
+        control.handle(ControlCommand::Resume);
+        assert_eq!(sleeper.await.unwrap(), Wakeup::Elapsed);
+
+        // Stopping wakes up a paused sleep and cancels the shutdown token
+        control.handle(ControlCommand::Pause);
+        let sleeper = tokio::spawn({
+            let control = control.clone();
+            async move { control.sleep_until(far).await }
+        });
+        control.handle(ControlCommand::Stop);
+        assert_eq!(sleeper.await.unwrap(), Wakeup::Shutdown);
+        assert!(control.shutdown().is_cancelled());
+        assert_eq!(control.sleep_until(far).await, Wakeup::Shutdown);
+    }
+
+    #[tokio::test]
+    async fn test_socket() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("control.sock");
+        let control = Control::default();
+        control.listen(&path).unwrap();
+        control.set_status(CycleStatus {
+            finished_at: None,
+            success: 0,
+            errors: 0,
+        });
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"status\nfoo\ntrigger\n").await.unwrap();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "running, no cycle finished yet"
+        );
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "error: unknown command 'foo'"
+        );
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok");
+        assert_eq!(
+            control
+                .sleep_until(Instant::now() + Duration::from_secs(3600))
+                .await,
+            Wakeup::Trigger
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Notifications about rising FOEN danger levels
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::DangerLevelConfig,
+    database::MeasurementStore,
+    notify::{Notifier, Topic},
+    observation::StationObservation,
+    timezone,
+};
+
+/// Notification posted to the webhook when the danger level of a station rises
+#[derive(Debug, Serialize)]
+struct DangerLevelNotification<'a> {
+    station_id: u32,
+    station_name: &'a str,
+    danger_level: u8,
+    previous_danger_level: Option<u8>,
+    threshold: u8,
+    time: DateTime<Utc>,
+}
+
+/// Whether a danger level is notified
+///
+/// Levels at or above the threshold are notified if they are higher than the
+/// previous level of the station, or if no previous level is known.
+pub fn should_notify(threshold: u8, previous: Option<u8>, level: u8) -> bool {
+    level >= threshold && previous.is_none_or(|previous| level > previous)
+}
+
+/// Checks the danger level of a fetched observation and notifies if it rose
+/// to or above the threshold
+///
+/// The level is only stored once the notification was posted, so that a
+/// failed notification is retried with the next observation.
+pub async fn check(
+    config: &DangerLevelConfig,
+    notifier: &Notifier,
+    store: &dyn MeasurementStore,
+    observation: &StationObservation,
+    dry_run: bool,
+) -> Result<()> {
+    let Some(level) = observation.danger_level else {
+        return Ok(());
+    };
+    let station_id = observation.station_id;
+    let previous = store.danger_level(station_id).await?;
+
+    if should_notify(config.threshold(), previous, level) {
+        warn!(
+            station_id,
+            danger_level = level,
+            "Station {} ({}) danger level rose to {}{}",
+            station_id,
+            observation.station_name,
+            level,
+            previous
+                .map(|previous| format!(" (from {previous})"))
+                .unwrap_or_default(),
+        );
+        if dry_run {
+            info!(
+                "Station {} ({}) danger level would be notified [DRY RUN]",
+                station_id, observation.station_name
+            );
+            return Ok(());
+        }
+        if let Some(url) = &config.webhook_url {
+            let notification = DangerLevelNotification {
+                station_id,
+                station_name: &observation.station_name,
+                danger_level: level,
+                previous_danger_level: previous,
+                threshold: config.threshold(),
+                time: observation.time(),
+            };
+            notifier
+                .post_webhook(url, "danger level notification", &notification)
+                .await?;
+        }
+        let subject = format!(
+            "Danger level {} at station {} ({})",
+            level, station_id, observation.station_name
+        );
+        let body = format!(
+            "The FOEN danger level of station {} ({}) rose to {}{} at {}.\n",
+            station_id,
+            observation.station_name,
+            level,
+            previous
+                .map(|previous| format!(" from {previous}"))
+                .unwrap_or_default(),
+            timezone::log_time(observation.time()),
+        );
+        notifier.email(Topic::DangerLevel, &subject, body).await?;
+    }
+
+    if !dry_run && previous != Some(level) {
+        store
+            .update_danger_level(station_id, level, Utc::now())
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_notify() {
+        // Below the threshold
+        assert!(!should_notify(3, None, 2));
+        // Reaching the threshold, also without a known previous level
+        assert!(should_notify(3, Some(2), 3));
+        assert!(should_notify(3, None, 3));
+        // Rising further above the threshold
+        assert!(should_notify(3, Some(3), 4));
+        // Unchanged or falling
+        assert!(!should_notify(3, Some(4), 4));
+        assert!(!should_notify(3, Some(5), 4));
+    }
+}
@@ -1,138 +1,499 @@
 //! Database module for tracking sent measurements
+//!
+//! The storage layer is abstracted behind the [`MeasurementStore`] trait, with
+//! implementations for SQLite (default) and PostgreSQL.
 
-use anyhow::{Context, Result};
+mod postgres;
+mod read_only;
+mod sqlite;
+
+use std::{env, fmt, fs, path::Path, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use rusqlite::{Connection, params};
-use tracing::debug;
-
-/// Create the sent_measurements table
-fn create_table(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS sent_measurements (
-            sensor_id INTEGER NOT NULL,
-            measurement_timestamp INTEGER NOT NULL,
-            sent_at INTEGER NOT NULL,
-            PRIMARY KEY (sensor_id, measurement_timestamp)
-        )",
-        [],
-    )
-    .with_context(|| "Failed to create sent_measurements table")?;
-    Ok(())
+use tracing::{debug, info};
+
+pub use self::{
+    postgres::PostgresStore,
+    read_only::ReadOnlyStore,
+    sqlite::{SqliteOptions, SqliteStore},
+};
+use crate::{config::Config, error::FetcherError, secret::SecretString};
+
+/// Processing phase in which an error occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPhase {
+    /// Fetching the measurement from LINDAS
+    Fetch,
+    /// Sending the measurement to the Gfrörli API
+    Send,
 }
 
-/// Initialize the SQLite database and create the table if it doesn't exist
-pub fn init_database(db_path: &str) -> Result<Connection> {
-    debug!("Initializing database at {}", db_path);
+impl ErrorPhase {
+    /// Name of the phase as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorPhase::Fetch => "fetch",
+            ErrorPhase::Send => "send",
+        }
+    }
+}
 
-    let conn = Connection::open(db_path)
-        .with_context(|| format!("Failed to open database at {db_path}"))?;
+impl fmt::Display for ErrorPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
-    create_table(&conn)?;
+impl FromStr for ErrorPhase {
+    type Err = anyhow::Error;
 
-    debug!("Database initialized successfully");
-    Ok(conn)
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fetch" => Ok(ErrorPhase::Fetch),
+            "send" => Ok(ErrorPhase::Send),
+            other => Err(anyhow!("Unknown error phase '{other}'")),
+        }
+    }
 }
 
-/// Check if a measurement has already been sent for the given sensor and timestamp
-pub fn is_measurement_sent(
-    conn: &Connection,
-    sensor_id: u32,
-    measurement_time: &DateTime<Utc>,
-) -> Result<bool> {
-    let measurement_timestamp = measurement_time.timestamp();
+/// A fetch or send failure recorded in the database
+#[derive(Debug, Clone)]
+pub struct ErrorRecord {
+    /// When the error occurred
+    pub occurred_at: DateTime<Utc>,
+    /// FOEN station ID
+    pub station_id: u32,
+    /// Gfrörli sensor ID (if already known when the error occurred)
+    pub sensor_id: Option<u32>,
+    /// Phase in which the error occurred
+    pub phase: ErrorPhase,
+    /// HTTP status code (if the error was caused by an HTTP response)
+    pub http_status: Option<u16>,
+    /// Error message including all causes
+    pub message: String,
+}
 
-    let mut stmt = conn
-        .prepare(
-            "SELECT 1 FROM sent_measurements WHERE sensor_id = ? AND measurement_timestamp = ?",
-        )
-        .with_context(|| "Failed to prepare select statement")?;
+/// A measurement that was successfully sent to the Gfrörli API
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentMeasurement {
+    /// Name of the Gfrörli target or sink the measurement was sent to
+    pub target: String,
+    /// Gfrörli sensor ID
+    pub sensor_id: u32,
+    /// Time of the measurement
+    pub time: DateTime<Utc>,
+    /// Temperature that was sent (missing for measurements recorded before
+    /// values were stored)
+    pub temperature: Option<f32>,
+    /// Idempotency key sent along with the request
+    pub idempotency_key: String,
+    /// ID assigned to the measurement by the Gfrörli API (if returned)
+    pub gfroerli_id: Option<i64>,
+}
 
-    let exists = stmt
-        .query_row(params![sensor_id, measurement_timestamp], |_| Ok(()))
-        .is_ok();
+/// A sent measurement with the time it was recorded, for exports
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMeasurement {
+    /// Name of the Gfrörli target or sink the measurement was sent to
+    pub target: String,
+    /// Gfrörli sensor ID
+    pub sensor_id: u32,
+    /// Time of the measurement
+    pub time: DateTime<Utc>,
+    /// Temperature that was sent (missing for measurements recorded before
+    /// values were stored)
+    pub temperature: Option<f32>,
+    /// When the measurement was sent
+    pub sent_at: DateTime<Utc>,
+}
 
-    Ok(exists)
+/// Last known state of a FOEN station
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationState {
+    /// FOEN station ID
+    pub station_id: u32,
+    /// When the station was last fetched successfully
+    pub last_fetch_at: DateTime<Utc>,
+    /// Time of the most recent measurement
+    pub last_measurement_time: DateTime<Utc>,
+    /// Temperature of the most recent measurement
+    pub last_temperature: f32,
 }
 
-/// Record that a measurement has been successfully sent
-pub fn record_measurement_sent(
-    conn: &Connection,
-    sensor_id: u32,
-    measurement_time: &DateTime<Utc>,
-) -> Result<()> {
-    let measurement_timestamp = measurement_time.timestamp();
-    let sent_at = Utc::now().timestamp();
-
-    conn.execute(
-        "INSERT INTO sent_measurements (sensor_id, measurement_timestamp, sent_at) VALUES (?, ?, ?)",
-        params![sensor_id, measurement_timestamp, sent_at],
-    )
-    .with_context(|| {
-        format!(
-            "Failed to record sent measurement for sensor {sensor_id} at timestamp {measurement_timestamp}"
-        )
-    })?;
+/// A measurement held back by anomaly detection until it is confirmed
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldMeasurement {
+    /// FOEN station ID
+    pub station_id: u32,
+    /// Time of the measurement
+    pub time: DateTime<Utc>,
+    /// Temperature of the measurement
+    pub temperature: f32,
+    /// When the measurement was held back
+    pub held_at: DateTime<Utc>,
+}
 
-    debug!(
-        "Recorded sent measurement for sensor {} at timestamp {}",
-        sensor_id, measurement_timestamp
-    );
+/// A measurement rejected by anomaly detection, kept for manual review
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedMeasurement {
+    /// ID assigned by the database (ignored when quarantining)
+    pub id: i64,
+    /// FOEN station ID
+    pub station_id: u32,
+    /// Time of the measurement
+    pub time: DateTime<Utc>,
+    /// Temperature of the measurement
+    pub temperature: f32,
+    /// Why the measurement was rejected
+    pub reason: String,
+    /// When the measurement was quarantined
+    pub quarantined_at: DateTime<Utc>,
+    /// When the measurement was released for sending (if it was)
+    pub released_at: Option<DateTime<Utc>>,
+}
 
-    Ok(())
+/// Mapping of a FOEN station to a Gfrörli sensor stored in the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationMapping {
+    /// FOEN station ID
+    pub station_id: u32,
+    /// Gfrörli sensor ID
+    pub sensor_id: u32,
+    /// When the mapping was added or last changed
+    pub updated_at: DateTime<Utc>,
 }
 
-#[cfg(test)]
-mod tests {
-    use chrono::TimeZone;
+/// Storage for the deduplication state of sent measurements
+///
+/// All methods are async so that implementations can perform blocking I/O
+/// without stalling the async runtime.
+#[async_trait]
+pub trait MeasurementStore: Send + Sync {
+    /// Check if a measurement has already been sent to a target for the given sensor and timestamp
+    async fn is_measurement_sent(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        measurement_time: DateTime<Utc>,
+    ) -> Result<bool>;
 
-    use super::*;
+    /// Record that a measurement has been successfully sent
+    async fn record_measurement_sent(&self, sent: &SentMeasurement) -> Result<()>;
 
-    #[test]
-    fn test_duplicate_detection() {
-        let conn = Connection::open_in_memory().unwrap();
+    /// Get the most recent measurement sent to a target for a sensor
+    async fn latest_sent_measurement(
+        &self,
+        target: &str,
+        sensor_id: u32,
+    ) -> Result<Option<SentMeasurement>>;
+
+    /// Get the measurements sent to a target for a sensor in a time range
+    /// (inclusive), oldest first
+    async fn sent_measurements(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<SentMeasurement>>;
+
+    /// Replace the measurements sent to a target for a sensor in a time range
+    /// (inclusive) in a single transaction, returns the number of removed
+    /// measurements
+    async fn replace_sent_measurements(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        measurements: &[SentMeasurement],
+    ) -> Result<u64>;
+
+    /// Get all measurements sent to any target, ordered by target, sensor
+    /// and time
+    async fn all_sent_measurements(&self) -> Result<Vec<RecordedMeasurement>>;
+
+    /// Record a fetch or send failure
+    async fn record_error(&self, record: &ErrorRecord) -> Result<()>;
+
+    /// Get the most recent errors, newest first
+    async fn recent_errors(&self, limit: u32) -> Result<Vec<ErrorRecord>>;
+
+    /// Get the last known state of a station
+    async fn station_state(&self, station_id: u32) -> Result<Option<StationState>>;
+
+    /// Insert or update the state of a station after a successful fetch
+    async fn update_station_state(&self, state: &StationState) -> Result<()>;
+
+    /// Get the measurement of a station that is awaiting confirmation
+    async fn held_measurement(&self, station_id: u32) -> Result<Option<HeldMeasurement>>;
+
+    /// Hold back a measurement until it is confirmed (replaces a previously held one)
+    async fn hold_measurement(&self, held: &HeldMeasurement) -> Result<()>;
+
+    /// Remove the held measurement of a station
+    async fn release_held_measurement(&self, station_id: u32) -> Result<()>;
+
+    /// Record a fetched reading of a station before it is smoothed (keeps
+    /// an existing reading at the same time)
+    async fn record_raw_measurement(
+        &self,
+        station_id: u32,
+        time: DateTime<Utc>,
+        temperature: f32,
+    ) -> Result<()>;
+
+    /// Get the temperatures of the most recent readings of a station before
+    /// `before`, newest first
+    async fn raw_temperatures(
+        &self,
+        station_id: u32,
+        before: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<f32>>;
 
-        // Initialize schema
-        create_table(&conn).unwrap();
+    /// Quarantine a rejected measurement, returns its ID
+    async fn quarantine_measurement(&self, measurement: &QuarantinedMeasurement) -> Result<i64>;
 
-        let test_time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 30, 0).unwrap();
-        let sensor_id = 1;
+    /// Get the quarantined measurements, newest first (released ones only if
+    /// `include_released` is set)
+    async fn quarantined_measurements(
+        &self,
+        include_released: bool,
+    ) -> Result<Vec<QuarantinedMeasurement>>;
 
-        // Initially, measurement should not be sent
-        assert!(!is_measurement_sent(&conn, sensor_id, &test_time).unwrap());
+    /// Get a quarantined measurement by ID
+    async fn quarantined_measurement(&self, id: i64) -> Result<Option<QuarantinedMeasurement>>;
 
-        // Record the measurement as sent
-        record_measurement_sent(&conn, sensor_id, &test_time).unwrap();
+    /// Mark a quarantined measurement as released
+    async fn release_quarantined_measurement(
+        &self,
+        id: i64,
+        released_at: DateTime<Utc>,
+    ) -> Result<()>;
 
-        // Now it should be detected as already sent
-        assert!(is_measurement_sent(&conn, sensor_id, &test_time).unwrap());
+    /// Get the last known danger level of a station
+    async fn danger_level(&self, station_id: u32) -> Result<Option<u8>>;
 
-        // Different sensor should not be affected
-        assert!(!is_measurement_sent(&conn, 2, &test_time).unwrap());
+    /// Insert or update the danger level of a station
+    async fn update_danger_level(
+        &self,
+        station_id: u32,
+        level: u8,
+        updated_at: DateTime<Utc>,
+    ) -> Result<()>;
 
-        // Different timestamp should not be affected
-        let different_time = Utc.with_ymd_and_hms(2025, 1, 15, 13, 30, 0).unwrap();
-        assert!(!is_measurement_sent(&conn, sensor_id, &different_time).unwrap());
+    /// Get the station mappings, ordered by station ID
+    async fn station_mappings(&self) -> Result<Vec<StationMapping>>;
+
+    /// Insert or update the mapping of a station
+    async fn set_station_mapping(&self, mapping: &StationMapping) -> Result<()>;
+
+    /// Remove the mapping of a station, returns whether it existed
+    async fn remove_station_mapping(&self, station_id: u32) -> Result<bool>;
+
+    /// Get the backfilled time windows of a station that overlap a time
+    /// range, as (start, end) pairs ordered by start
+    async fn completed_backfill_windows(
+        &self,
+        station_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, DateTime<Utc>)>>;
+
+    /// Record that a time window of a station was backfilled
+    async fn record_backfill_window(
+        &self,
+        station_id: u32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        completed_at: DateTime<Utc>,
+    ) -> Result<()>;
+
+    /// Acquire or renew a named lock for a holder until `expires_at`
+    ///
+    /// Returns `false` if the lock is held by another holder and has not
+    /// expired at `now`.
+    async fn acquire_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool>;
+
+    /// Release a named lock, if it is held by the holder
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<()>;
+
+    /// Write a consistent snapshot of the database to the given path
+    async fn backup(&self, path: &Path) -> Result<()>;
+
+    /// Run a full integrity check of the database, fails if it is corrupted
+    async fn check_integrity(&self) -> Result<()>;
+
+    /// Create or replace the SQL views for ad-hoc analysis, listing intervals
+    /// between measurements longer than `gap_minutes` as gaps
+    async fn create_views(&self, gap_minutes: u32) -> Result<()>;
+}
+
+/// Read the SQLCipher key from the environment variable or file configured in
+/// the `[database]` section
+///
+/// Trailing whitespace (e.g. the newline at the end of a key file) is removed.
+fn read_key(config: &Config) -> Result<Option<SecretString>> {
+    let Some(database) = &config.database else {
+        return Ok(None);
+    };
+    if let Some(name) = &database.key_env {
+        let key = env::var(name)
+            .with_context(|| format!("Database key environment variable '{name}' is not set"))?;
+        return Ok(Some(key.trim_end().into()));
     }
+    if let Some(path) = &database.key_file {
+        let key = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read database key file '{path}'"))?;
+        return Ok(Some(key.trim_end().into()));
+    }
+    Ok(None)
+}
 
-    #[test]
-    fn test_multiple_sensors_and_timestamps() {
-        let conn = Connection::open_in_memory().unwrap();
+/// How the measurement store is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreAccess {
+    /// Read and write the database
+    ReadWrite,
+    /// Never modify the database, but keep the changes of the run in memory
+    /// where possible (`--dry-run`)
+    DryRun,
+    /// Open the database read-only, without applying migrations, and discard
+    /// all writes (`--read-only`)
+    ReadOnly,
+}
+
+/// Add the station mappings stored in the database to the configuration, if
+/// enabled with `mappings` in the `[database]` section
+pub async fn load_station_mappings(
+    config: &mut Config,
+    store: &dyn MeasurementStore,
+) -> Result<()> {
+    if !config.database_mappings() {
+        return Ok(());
+    }
+    let mappings = store
+        .station_mappings()
+        .await
+        .with_context(|| "Failed to load station mappings from the database")?;
+    debug!(
+        "Loaded {} station mappings from the database",
+        mappings.len()
+    );
+    config.apply_station_mappings(&mappings);
+    Ok(())
+}
 
-        // Initialize schema
-        create_table(&conn).unwrap();
+/// Open the measurement store configured in the `[database]` section
+///
+/// If a PostgreSQL URL is configured, it takes precedence over the SQLite path.
+/// Dry runs never write to the database: they use an in-memory copy of the
+/// SQLite database, or discard all writes to PostgreSQL.
+pub async fn open_store(
+    config: &Config,
+    access: StoreAccess,
+) -> Result<Arc<dyn MeasurementStore>, FetcherError> {
+    open(config, access).await.map_err(FetcherError::Storage)
+}
+
+/// Open the configured store, see [`open_store`]
+async fn open(config: &Config, access: StoreAccess) -> Result<Arc<dyn MeasurementStore>> {
+    if config.database_tenant() != "default" {
+        info!("Using database tenant '{}'", config.database_tenant());
+    }
+    if let Some(url) = config.database_postgres_url() {
+        info!("Using PostgreSQL database");
+        if access == StoreAccess::ReadOnly {
+            let store = PostgresStore::connect_read_only(url, config.database_tenant())
+                .await
+                .with_context(|| "Failed to open PostgreSQL database read-only")?;
+            return Ok(Arc::new(ReadOnlyStore::new(Arc::new(store))));
+        }
+        let store = PostgresStore::connect(url, config.database_tenant())
+            .await
+            .with_context(|| "Failed to initialize PostgreSQL database")?;
+        if access == StoreAccess::DryRun {
+            return Ok(Arc::new(ReadOnlyStore::new(Arc::new(store))));
+        }
+        return Ok(Arc::new(store));
+    }
 
-        let time1 = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
-        let time2 = Utc.with_ymd_and_hms(2025, 1, 15, 13, 0, 0).unwrap();
+    let options = SqliteOptions {
+        wal: config.database_wal(),
+        busy_timeout: Duration::from_millis(config.database_busy_timeout_ms()),
+        key: read_key(config)?,
+        tenant: config.database_tenant().to_string(),
+        create_dirs: config.database_create_dirs(),
+    };
+    match access {
+        StoreAccess::ReadWrite => {}
+        StoreAccess::DryRun => {
+            info!(
+                "Using an in-memory copy of the SQLite database at {}",
+                config.database_path()
+            );
+            let store = SqliteStore::open_snapshot(config.database_path(), &options)
+                .with_context(|| "Failed to copy SQLite database")?;
+            return Ok(Arc::new(store));
+        }
+        StoreAccess::ReadOnly => {
+            info!(
+                "Using SQLite database at {} read-only",
+                config.database_path()
+            );
+            let store = SqliteStore::open_read_only(config.database_path(), &options)
+                .with_context(|| "Failed to open SQLite database read-only")?;
+            return Ok(Arc::new(ReadOnlyStore::new(Arc::new(store))));
+        }
+    }
+    info!("Using SQLite database at {}", config.database_path());
+    let store = SqliteStore::open(config.database_path(), &options)
+        .with_context(|| "Failed to initialize SQLite database")?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DatabaseConfig, GfroerliConfig};
+
+    #[test]
+    fn test_read_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("database.key");
+        fs::write(&path, "correct horse battery staple\n").unwrap();
 
-        // Record measurements for different sensors and times
-        record_measurement_sent(&conn, 1, &time1).unwrap();
-        record_measurement_sent(&conn, 1, &time2).unwrap();
-        record_measurement_sent(&conn, 2, &time1).unwrap();
+        let mut config = Config::new(
+            Vec::new(),
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        assert!(read_key(&config).unwrap().is_none());
 
-        // Verify all combinations
-        assert!(is_measurement_sent(&conn, 1, &time1).unwrap());
-        assert!(is_measurement_sent(&conn, 1, &time2).unwrap());
-        assert!(is_measurement_sent(&conn, 2, &time1).unwrap());
-        assert!(!is_measurement_sent(&conn, 2, &time2).unwrap());
+        config.database = Some(DatabaseConfig {
+            path: None,
+            create_dirs: None,
+            postgres_url: None,
+            wal: None,
+            busy_timeout_ms: None,
+            key_env: None,
+            key_file: Some(path.to_str().unwrap().to_string()),
+            tenant: None,
+            mappings: None,
+        });
+        assert_eq!(
+            read_key(&config).unwrap().unwrap().expose(),
+            "correct horse battery staple"
+        );
+        // Only available with the sqlcipher feature
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "sqlcipher"));
     }
 }
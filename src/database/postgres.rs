@@ -0,0 +1,982 @@
+//! PostgreSQL implementation of the measurement store
+//!
+//! Allows several fetcher instances to share a central deduplication state.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use postgres_native_tls::MakeTlsConnector;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, GenericClient};
+use tracing::{debug, error, info, warn};
+
+use super::{
+    ErrorRecord, HeldMeasurement, MeasurementStore, QuarantinedMeasurement, RecordedMeasurement,
+    SentMeasurement, StationMapping, StationState,
+};
+use crate::{gfroerli::idempotency_key, secret::SecretString};
+
+/// Schema migrations, applied in order
+///
+/// Applied versions are tracked in the `schema_migrations` table. Never modify
+/// an existing migration, always append a new one.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS sent_measurements (
+        sensor_id BIGINT NOT NULL,
+        measurement_timestamp BIGINT NOT NULL,
+        sent_at BIGINT NOT NULL,
+        PRIMARY KEY (sensor_id, measurement_timestamp)
+    )",
+    "CREATE TABLE errors (
+        id BIGSERIAL PRIMARY KEY,
+        occurred_at BIGINT NOT NULL,
+        station_id BIGINT NOT NULL,
+        sensor_id BIGINT,
+        phase TEXT NOT NULL,
+        http_status INTEGER,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX errors_occurred_at ON errors (occurred_at)",
+    "CREATE TABLE station_state (
+        station_id BIGINT PRIMARY KEY,
+        last_fetch_at BIGINT NOT NULL,
+        last_measurement_timestamp BIGINT NOT NULL,
+        last_temperature REAL NOT NULL
+    )",
+    "CREATE TABLE held_measurements (
+        station_id BIGINT PRIMARY KEY,
+        measurement_timestamp BIGINT NOT NULL,
+        temperature REAL NOT NULL,
+        held_at BIGINT NOT NULL
+    )",
+    "ALTER TABLE sent_measurements ADD COLUMN idempotency_key TEXT",
+    "ALTER TABLE sent_measurements ADD COLUMN gfroerli_measurement_id BIGINT",
+    "ALTER TABLE sent_measurements ADD COLUMN temperature REAL",
+    "ALTER TABLE sent_measurements ADD COLUMN target TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE sent_measurements ALTER COLUMN target DROP DEFAULT;
+    ALTER TABLE sent_measurements DROP CONSTRAINT sent_measurements_pkey;
+    ALTER TABLE sent_measurements ADD PRIMARY KEY (target, sensor_id, measurement_timestamp)",
+    "CREATE TABLE locks (
+        name TEXT PRIMARY KEY,
+        holder TEXT NOT NULL,
+        expires_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE danger_levels (
+        station_id BIGINT PRIMARY KEY,
+        level SMALLINT NOT NULL,
+        updated_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE backfill_state (
+        station_id BIGINT NOT NULL,
+        window_start BIGINT NOT NULL,
+        window_end BIGINT NOT NULL,
+        completed_at BIGINT NOT NULL,
+        PRIMARY KEY (station_id, window_start, window_end)
+    )",
+    "CREATE TABLE quarantine (
+        id BIGSERIAL PRIMARY KEY,
+        station_id BIGINT NOT NULL,
+        measurement_timestamp BIGINT NOT NULL,
+        temperature REAL NOT NULL,
+        reason TEXT NOT NULL,
+        quarantined_at BIGINT NOT NULL,
+        released_at BIGINT
+    )",
+    "CREATE TABLE raw_measurements (
+        station_id BIGINT NOT NULL,
+        measurement_timestamp BIGINT NOT NULL,
+        temperature REAL NOT NULL,
+        PRIMARY KEY (station_id, measurement_timestamp)
+    )",
+    "ALTER TABLE errors ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE errors ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE quarantine ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE quarantine ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE sent_measurements ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE sent_measurements ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE sent_measurements DROP CONSTRAINT sent_measurements_pkey;
+    ALTER TABLE sent_measurements ADD PRIMARY KEY (tenant, target, sensor_id, measurement_timestamp)
+    ALTER TABLE station_state ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE station_state ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE station_state DROP CONSTRAINT station_state_pkey;
+    ALTER TABLE station_state ADD PRIMARY KEY (tenant, station_id)
+    ALTER TABLE held_measurements ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE held_measurements ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE held_measurements DROP CONSTRAINT held_measurements_pkey;
+    ALTER TABLE held_measurements ADD PRIMARY KEY (tenant, station_id)
+    ALTER TABLE locks ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE locks ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE locks DROP CONSTRAINT locks_pkey;
+    ALTER TABLE locks ADD PRIMARY KEY (tenant, name)
+    ALTER TABLE danger_levels ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE danger_levels ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE danger_levels DROP CONSTRAINT danger_levels_pkey;
+    ALTER TABLE danger_levels ADD PRIMARY KEY (tenant, station_id)
+    ALTER TABLE backfill_state ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE backfill_state ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE backfill_state DROP CONSTRAINT backfill_state_pkey;
+    ALTER TABLE backfill_state ADD PRIMARY KEY (tenant, station_id, window_start, window_end)
+    ALTER TABLE raw_measurements ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE raw_measurements ALTER COLUMN tenant DROP DEFAULT;
+    ALTER TABLE raw_measurements DROP CONSTRAINT raw_measurements_pkey;
+    ALTER TABLE raw_measurements ADD PRIMARY KEY (tenant, station_id, measurement_timestamp)",
+    "CREATE TABLE station_mappings (
+        tenant TEXT NOT NULL,
+        station_id BIGINT NOT NULL,
+        sensor_id BIGINT NOT NULL,
+        updated_at BIGINT NOT NULL,
+        PRIMARY KEY (tenant, station_id)
+    )",
+];
+
+/// PostgreSQL backed measurement store
+///
+/// The connection is re-established transparently if it was closed.
+pub struct PostgresStore {
+    url: SecretString,
+    client: Mutex<Client>,
+    /// Whether the session only allows read-only transactions
+    read_only: bool,
+    /// Tenant all rows are read and written for
+    tenant: String,
+}
+
+impl PostgresStore {
+    /// Connect to the database and apply pending migrations
+    ///
+    /// Only the rows of the given tenant are read and written.
+    pub async fn connect(url: &SecretString, tenant: &str) -> Result<Self> {
+        let mut client = connect(url.expose(), false).await?;
+        migrate(&mut client).await?;
+        Ok(Self {
+            url: url.clone(),
+            client: Mutex::new(client),
+            read_only: false,
+            tenant: tenant.to_string(),
+        })
+    }
+
+    /// Connect to the database with a session that only allows read-only
+    /// transactions
+    ///
+    /// Migrations are not applied, so the schema must be up to date.
+    pub async fn connect_read_only(url: &SecretString, tenant: &str) -> Result<Self> {
+        let client = connect(url.expose(), true).await?;
+        let version = schema_version(&client).await?;
+        if version < MIGRATIONS.len() {
+            bail!(
+                "Database schema is at version {version}, but version {} is required and migrations can't be applied read-only",
+                MIGRATIONS.len()
+            );
+        }
+        Ok(Self {
+            url: url.clone(),
+            client: Mutex::new(client),
+            read_only: true,
+            tenant: tenant.to_string(),
+        })
+    }
+
+    /// Get the client, reconnecting first if the connection was lost
+    async fn client(&self) -> Result<tokio::sync::MutexGuard<'_, Client>> {
+        let mut client = self.client.lock().await;
+        if client.is_closed() {
+            warn!("PostgreSQL connection lost, reconnecting");
+            *client = connect(self.url.expose(), self.read_only).await?;
+        }
+        Ok(client)
+    }
+}
+
+#[async_trait]
+impl MeasurementStore for PostgresStore {
+    async fn is_measurement_sent(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        measurement_time: DateTime<Utc>,
+    ) -> Result<bool> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT 1 FROM sent_measurements
+                 WHERE tenant = $1 AND target = $2 AND sensor_id = $3 AND measurement_timestamp = $4",
+                &[
+                    &self.tenant,
+                    &target,
+                    &i64::from(sensor_id),
+                    &measurement_time.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| "Failed to query sent measurements")?;
+        Ok(row.is_some())
+    }
+
+    async fn record_measurement_sent(&self, sent: &SentMeasurement) -> Result<()> {
+        let client = self.client().await?;
+        record_measurement_sent(&*client, &self.tenant, sent).await
+    }
+
+    async fn latest_sent_measurement(
+        &self,
+        target: &str,
+        sensor_id: u32,
+    ) -> Result<Option<SentMeasurement>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT measurement_timestamp, temperature, idempotency_key, gfroerli_measurement_id
+                 FROM sent_measurements
+                 WHERE tenant = $1 AND target = $2 AND sensor_id = $3
+                 ORDER BY measurement_timestamp DESC LIMIT 1",
+                &[&self.tenant, &target, &i64::from(sensor_id)],
+            )
+            .await
+            .with_context(|| format!("Failed to query latest measurement of sensor {sensor_id}"))?;
+
+        row.map(|row| sent_measurement_from_row(target, sensor_id, &row))
+            .transpose()
+    }
+
+    async fn sent_measurements(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<SentMeasurement>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT measurement_timestamp, temperature, idempotency_key, gfroerli_measurement_id
+                 FROM sent_measurements
+                 WHERE tenant = $1 AND target = $2 AND sensor_id = $3
+                    AND measurement_timestamp BETWEEN $4 AND $5
+                 ORDER BY measurement_timestamp",
+                &[
+                    &self.tenant,
+                    &target,
+                    &i64::from(sensor_id),
+                    &from.timestamp(),
+                    &to.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to query sent measurements of sensor {sensor_id}"))?;
+
+        rows.iter()
+            .map(|row| sent_measurement_from_row(target, sensor_id, row))
+            .collect()
+    }
+
+    async fn replace_sent_measurements(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        measurements: &[SentMeasurement],
+    ) -> Result<u64> {
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        let removed = transaction
+            .execute(
+                "DELETE FROM sent_measurements
+                 WHERE tenant = $1 AND target = $2 AND sensor_id = $3
+                    AND measurement_timestamp BETWEEN $4 AND $5",
+                &[
+                    &self.tenant,
+                    &target,
+                    &i64::from(sensor_id),
+                    &from.timestamp(),
+                    &to.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to remove sent measurements of sensor {sensor_id}"))?;
+        for sent in measurements {
+            record_measurement_sent(&transaction, &self.tenant, sent).await?;
+        }
+        transaction.commit().await?;
+        Ok(removed)
+    }
+
+    async fn all_sent_measurements(&self) -> Result<Vec<RecordedMeasurement>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT target, sensor_id, measurement_timestamp, temperature, sent_at
+                 FROM sent_measurements WHERE tenant = $1
+                 ORDER BY target, sensor_id, measurement_timestamp",
+                &[&self.tenant],
+            )
+            .await
+            .with_context(|| "Failed to query sent measurements")?;
+
+        rows.iter()
+            .map(|row| {
+                let sensor_id: i64 = row.get(1);
+                Ok(RecordedMeasurement {
+                    target: row.get(0),
+                    sensor_id: u32::try_from(sensor_id)?,
+                    time: from_timestamp(row.get(2))?,
+                    temperature: row.get(3),
+                    sent_at: from_timestamp(row.get(4))?,
+                })
+            })
+            .collect()
+    }
+
+    async fn record_error(&self, record: &ErrorRecord) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO errors (tenant, occurred_at, station_id, sensor_id, phase, http_status, message)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[
+                    &self.tenant,
+                    &record.occurred_at.timestamp(),
+                    &i64::from(record.station_id),
+                    &record.sensor_id.map(i64::from),
+                    &record.phase.as_str(),
+                    &record.http_status.map(i32::from),
+                    &record.message,
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to record error for station {}", record.station_id))?;
+        Ok(())
+    }
+
+    async fn recent_errors(&self, limit: u32) -> Result<Vec<ErrorRecord>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT occurred_at, station_id, sensor_id, phase, http_status, message
+                 FROM errors WHERE tenant = $1 ORDER BY occurred_at DESC, id DESC LIMIT $2",
+                &[&self.tenant, &i64::from(limit)],
+            )
+            .await
+            .with_context(|| "Failed to query errors")?;
+
+        rows.iter()
+            .map(|row| {
+                let occurred_at: i64 = row.get(0);
+                Ok(ErrorRecord {
+                    occurred_at: from_timestamp(occurred_at)?,
+                    station_id: u32::try_from(row.get::<_, i64>(1))?,
+                    sensor_id: row
+                        .get::<_, Option<i64>>(2)
+                        .map(u32::try_from)
+                        .transpose()?,
+                    phase: row.get::<_, String>(3).parse()?,
+                    http_status: row
+                        .get::<_, Option<i32>>(4)
+                        .map(u16::try_from)
+                        .transpose()?,
+                    message: row.get(5),
+                })
+            })
+            .collect()
+    }
+
+    async fn station_state(&self, station_id: u32) -> Result<Option<StationState>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT last_fetch_at, last_measurement_timestamp, last_temperature
+                 FROM station_state WHERE tenant = $1 AND station_id = $2",
+                &[&self.tenant, &i64::from(station_id)],
+            )
+            .await
+            .with_context(|| format!("Failed to query state of station {station_id}"))?;
+
+        row.map(|row| {
+            Ok(StationState {
+                station_id,
+                last_fetch_at: from_timestamp(row.get(0))?,
+                last_measurement_time: from_timestamp(row.get(1))?,
+                last_temperature: row.get(2),
+            })
+        })
+        .transpose()
+    }
+
+    async fn update_station_state(&self, state: &StationState) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO station_state (tenant, station_id, last_fetch_at, last_measurement_timestamp, last_temperature)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (tenant, station_id) DO UPDATE SET
+                    last_fetch_at = excluded.last_fetch_at,
+                    last_measurement_timestamp = excluded.last_measurement_timestamp,
+                    last_temperature = excluded.last_temperature",
+                &[
+                    &self.tenant,
+                    &i64::from(state.station_id),
+                    &state.last_fetch_at.timestamp(),
+                    &state.last_measurement_time.timestamp(),
+                    &state.last_temperature,
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to update state of station {}", state.station_id))?;
+        Ok(())
+    }
+
+    async fn held_measurement(&self, station_id: u32) -> Result<Option<HeldMeasurement>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT measurement_timestamp, temperature, held_at
+                 FROM held_measurements WHERE tenant = $1 AND station_id = $2",
+                &[&self.tenant, &i64::from(station_id)],
+            )
+            .await
+            .with_context(|| format!("Failed to query held measurement of station {station_id}"))?;
+
+        row.map(|row| {
+            Ok(HeldMeasurement {
+                station_id,
+                time: from_timestamp(row.get(0))?,
+                temperature: row.get(1),
+                held_at: from_timestamp(row.get(2))?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn hold_measurement(&self, held: &HeldMeasurement) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO held_measurements (tenant, station_id, measurement_timestamp, temperature, held_at)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (tenant, station_id) DO UPDATE SET
+                    measurement_timestamp = excluded.measurement_timestamp,
+                    temperature = excluded.temperature,
+                    held_at = excluded.held_at",
+                &[
+                    &self.tenant,
+                    &i64::from(held.station_id),
+                    &held.time.timestamp(),
+                    &held.temperature,
+                    &held.held_at.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to hold measurement of station {}", held.station_id))?;
+        Ok(())
+    }
+
+    async fn release_held_measurement(&self, station_id: u32) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "DELETE FROM held_measurements WHERE tenant = $1 AND station_id = $2",
+                &[&self.tenant, &i64::from(station_id)],
+            )
+            .await
+            .with_context(|| {
+                format!("Failed to release held measurement of station {station_id}")
+            })?;
+        Ok(())
+    }
+
+    async fn record_raw_measurement(
+        &self,
+        station_id: u32,
+        time: DateTime<Utc>,
+        temperature: f32,
+    ) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO raw_measurements (tenant, station_id, measurement_timestamp, temperature)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (tenant, station_id, measurement_timestamp) DO NOTHING",
+                &[
+                    &self.tenant,
+                    &i64::from(station_id),
+                    &time.timestamp(),
+                    &temperature,
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to record raw measurement of station {station_id}"))?;
+        Ok(())
+    }
+
+    async fn raw_temperatures(
+        &self,
+        station_id: u32,
+        before: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<f32>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT temperature FROM raw_measurements
+                 WHERE tenant = $1 AND station_id = $2 AND measurement_timestamp < $3
+                 ORDER BY measurement_timestamp DESC LIMIT $4",
+                &[
+                    &self.tenant,
+                    &i64::from(station_id),
+                    &before.timestamp(),
+                    &i64::from(limit),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to query raw measurements of station {station_id}"))?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn quarantine_measurement(&self, measurement: &QuarantinedMeasurement) -> Result<i64> {
+        let client = self.client().await?;
+        let row = client
+            .query_one(
+                "INSERT INTO quarantine
+                    (tenant, station_id, measurement_timestamp, temperature, reason, quarantined_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 RETURNING id",
+                &[
+                    &self.tenant,
+                    &i64::from(measurement.station_id),
+                    &measurement.time.timestamp(),
+                    &measurement.temperature,
+                    &measurement.reason,
+                    &measurement.quarantined_at.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to quarantine measurement of station {}",
+                    measurement.station_id
+                )
+            })?;
+        Ok(row.get(0))
+    }
+
+    async fn quarantined_measurements(
+        &self,
+        include_released: bool,
+    ) -> Result<Vec<QuarantinedMeasurement>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {QUARANTINE_COLUMNS} FROM quarantine
+                     WHERE tenant = $1 AND ($2 OR released_at IS NULL) ORDER BY id DESC"
+                ),
+                &[&self.tenant, &include_released],
+            )
+            .await
+            .with_context(|| "Failed to query quarantined measurements")?;
+
+        rows.iter().map(quarantined_from_row).collect()
+    }
+
+    async fn quarantined_measurement(&self, id: i64) -> Result<Option<QuarantinedMeasurement>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                &format!(
+                    "SELECT {QUARANTINE_COLUMNS} FROM quarantine WHERE tenant = $1 AND id = $2"
+                ),
+                &[&self.tenant, &id],
+            )
+            .await
+            .with_context(|| format!("Failed to query quarantined measurement {id}"))?;
+
+        row.as_ref().map(quarantined_from_row).transpose()
+    }
+
+    async fn release_quarantined_measurement(
+        &self,
+        id: i64,
+        released_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE quarantine SET released_at = $1 WHERE tenant = $2 AND id = $3",
+                &[&released_at.timestamp(), &self.tenant, &id],
+            )
+            .await
+            .with_context(|| format!("Failed to release quarantined measurement {id}"))?;
+        Ok(())
+    }
+
+    async fn danger_level(&self, station_id: u32) -> Result<Option<u8>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT level FROM danger_levels WHERE tenant = $1 AND station_id = $2",
+                &[&self.tenant, &i64::from(station_id)],
+            )
+            .await
+            .with_context(|| format!("Failed to query danger level of station {station_id}"))?;
+
+        row.map(|row| {
+            let level: i16 = row.get(0);
+            u8::try_from(level).with_context(|| format!("Invalid danger level {level} in database"))
+        })
+        .transpose()
+    }
+
+    async fn update_danger_level(
+        &self,
+        station_id: u32,
+        level: u8,
+        updated_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO danger_levels (tenant, station_id, level, updated_at)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (tenant, station_id) DO UPDATE SET
+                    level = excluded.level,
+                    updated_at = excluded.updated_at",
+                &[
+                    &self.tenant,
+                    &i64::from(station_id),
+                    &i16::from(level),
+                    &updated_at.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to update danger level of station {station_id}"))?;
+        Ok(())
+    }
+
+    async fn station_mappings(&self) -> Result<Vec<StationMapping>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT station_id, sensor_id, updated_at FROM station_mappings
+                 WHERE tenant = $1 ORDER BY station_id",
+                &[&self.tenant],
+            )
+            .await
+            .with_context(|| "Failed to query station mappings")?;
+
+        rows.iter()
+            .map(|row| {
+                let station_id: i64 = row.get(0);
+                let sensor_id: i64 = row.get(1);
+                Ok(StationMapping {
+                    station_id: u32::try_from(station_id)?,
+                    sensor_id: u32::try_from(sensor_id)?,
+                    updated_at: from_timestamp(row.get(2))?,
+                })
+            })
+            .collect()
+    }
+
+    async fn set_station_mapping(&self, mapping: &StationMapping) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO station_mappings (tenant, station_id, sensor_id, updated_at)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (tenant, station_id) DO UPDATE SET
+                    sensor_id = excluded.sensor_id,
+                    updated_at = excluded.updated_at",
+                &[
+                    &self.tenant,
+                    &i64::from(mapping.station_id),
+                    &i64::from(mapping.sensor_id),
+                    &mapping.updated_at.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| {
+                format!("Failed to store mapping of station {}", mapping.station_id)
+            })?;
+        Ok(())
+    }
+
+    async fn remove_station_mapping(&self, station_id: u32) -> Result<bool> {
+        let client = self.client().await?;
+        let removed = client
+            .execute(
+                "DELETE FROM station_mappings WHERE tenant = $1 AND station_id = $2",
+                &[&self.tenant, &i64::from(station_id)],
+            )
+            .await
+            .with_context(|| format!("Failed to remove mapping of station {station_id}"))?;
+        Ok(removed > 0)
+    }
+
+    async fn completed_backfill_windows(
+        &self,
+        station_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT window_start, window_end FROM backfill_state
+                 WHERE tenant = $1 AND station_id = $2 AND window_end > $3 AND window_start < $4
+                 ORDER BY window_start",
+                &[
+                    &self.tenant,
+                    &i64::from(station_id),
+                    &from.timestamp(),
+                    &to.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to query backfill state of station {station_id}"))?;
+
+        rows.iter()
+            .map(|row| Ok((from_timestamp(row.get(0))?, from_timestamp(row.get(1))?)))
+            .collect()
+    }
+
+    async fn record_backfill_window(
+        &self,
+        station_id: u32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        completed_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO backfill_state (tenant, station_id, window_start, window_end, completed_at)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (tenant, station_id, window_start, window_end) DO UPDATE SET
+                    completed_at = excluded.completed_at",
+                &[
+                    &self.tenant,
+                    &i64::from(station_id),
+                    &start.timestamp(),
+                    &end.timestamp(),
+                    &completed_at.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to record backfill state of station {station_id}"))?;
+        Ok(())
+    }
+
+    async fn acquire_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let client = self.client().await?;
+        let changed = client
+            .execute(
+                "INSERT INTO locks (tenant, name, holder, expires_at) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (tenant, name) DO UPDATE
+                    SET holder = excluded.holder, expires_at = excluded.expires_at
+                    WHERE locks.holder = excluded.holder OR locks.expires_at <= $5",
+                &[
+                    &self.tenant,
+                    &name,
+                    &holder,
+                    &expires_at.timestamp(),
+                    &now.timestamp(),
+                ],
+            )
+            .await
+            .with_context(|| format!("Failed to acquire lock '{name}'"))?;
+        Ok(changed > 0)
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "DELETE FROM locks WHERE tenant = $1 AND name = $2 AND holder = $3",
+                &[&self.tenant, &name, &holder],
+            )
+            .await
+            .with_context(|| format!("Failed to release lock '{name}'"))?;
+        Ok(())
+    }
+
+    async fn backup(&self, _path: &Path) -> Result<()> {
+        bail!("Backups of PostgreSQL databases are not supported, use pg_dump instead")
+    }
+
+    async fn check_integrity(&self) -> Result<()> {
+        bail!("Integrity checks of PostgreSQL databases are not supported, use amcheck instead")
+    }
+
+    async fn create_views(&self, _gap_minutes: u32) -> Result<()> {
+        bail!("Analytical views are only supported for SQLite databases")
+    }
+}
+
+/// Record that a measurement has been successfully sent, on a client or in a
+/// transaction
+async fn record_measurement_sent(
+    client: &impl GenericClient,
+    tenant: &str,
+    sent: &SentMeasurement,
+) -> Result<()> {
+    let sensor_id = sent.sensor_id;
+    let measurement_timestamp = sent.time.timestamp();
+    let sent_at = Utc::now().timestamp();
+
+    client
+        .execute(
+            "INSERT INTO sent_measurements
+                (tenant, target, sensor_id, measurement_timestamp, sent_at, temperature, idempotency_key, gfroerli_measurement_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[
+                &tenant,
+                &sent.target,
+                &i64::from(sensor_id),
+                &measurement_timestamp,
+                &sent_at,
+                &sent.temperature,
+                &sent.idempotency_key,
+                &sent.gfroerli_id,
+            ],
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to record sent measurement for sensor {sensor_id} at timestamp {measurement_timestamp}"
+            )
+        })?;
+
+    debug!(
+        "Recorded sent measurement for sensor {} at timestamp {} (target {})",
+        sensor_id, measurement_timestamp, sent.target
+    );
+
+    Ok(())
+}
+
+/// Convert a stored unix timestamp back into a `DateTime`
+fn from_timestamp(timestamp: i64) -> Result<DateTime<Utc>> {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .ok_or_else(|| anyhow!("Invalid timestamp {timestamp} in database"))
+}
+
+/// Columns selected for a [`QuarantinedMeasurement`]
+const QUARANTINE_COLUMNS: &str =
+    "id, station_id, measurement_timestamp, temperature, reason, quarantined_at, released_at";
+
+/// Convert a `quarantine` row into a [`QuarantinedMeasurement`]
+fn quarantined_from_row(row: &tokio_postgres::Row) -> Result<QuarantinedMeasurement> {
+    Ok(QuarantinedMeasurement {
+        id: row.get(0),
+        station_id: u32::try_from(row.get::<_, i64>(1))?,
+        time: from_timestamp(row.get(2))?,
+        temperature: row.get(3),
+        reason: row.get(4),
+        quarantined_at: from_timestamp(row.get(5))?,
+        released_at: row
+            .get::<_, Option<i64>>(6)
+            .map(from_timestamp)
+            .transpose()?,
+    })
+}
+
+/// Convert a `sent_measurements` row into a [`SentMeasurement`]
+fn sent_measurement_from_row(
+    target: &str,
+    sensor_id: u32,
+    row: &tokio_postgres::Row,
+) -> Result<SentMeasurement> {
+    let time = from_timestamp(row.get(0))?;
+    Ok(SentMeasurement {
+        target: target.to_string(),
+        sensor_id,
+        time,
+        temperature: row.get(1),
+        // Measurements sent before keys were stored used the same derivation
+        idempotency_key: row
+            .get::<_, Option<String>>(2)
+            .unwrap_or_else(|| idempotency_key(sensor_id, time)),
+        gfroerli_id: row.get(3),
+    })
+}
+
+/// Open a new connection and drive it on a background task
+async fn connect(url: &str, read_only: bool) -> Result<Client> {
+    let connector = native_tls::TlsConnector::new().with_context(|| "Failed to set up TLS")?;
+    let (client, connection) = tokio_postgres::connect(url, MakeTlsConnector::new(connector))
+        .await
+        .with_context(|| "Failed to connect to PostgreSQL")?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("PostgreSQL connection error: {}", e);
+        }
+    });
+
+    if read_only {
+        client
+            .batch_execute("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY")
+            .await
+            .with_context(|| "Failed to make the session read-only")?;
+    }
+    Ok(client)
+}
+
+/// Get the number of applied migrations
+async fn schema_version(client: &Client) -> Result<usize> {
+    let version: i32 = client
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+            &[],
+        )
+        .await
+        .with_context(|| "Failed to read schema version")?
+        .get(0);
+    Ok(usize::try_from(version)?)
+}
+
+/// Apply all pending schema migrations
+async fn migrate(client: &mut Client) -> Result<()> {
+    client
+        .execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                applied_at BIGINT NOT NULL
+            )",
+            &[],
+        )
+        .await
+        .with_context(|| "Failed to create schema_migrations table")?;
+
+    let version = schema_version(client).await?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let target_version = index as i32 + 1;
+        info!("Migrating database schema to version {}", target_version);
+
+        let transaction = client.transaction().await?;
+        transaction
+            .batch_execute(migration)
+            .await
+            .with_context(|| {
+                format!("Failed to migrate database schema to version {target_version}")
+            })?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)",
+                &[&target_version, &Utc::now().timestamp()],
+            )
+            .await?;
+        transaction.commit().await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,300 @@
+//! Store wrapper that never writes to the database
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tracing::debug;
+
+use super::{
+    ErrorRecord, HeldMeasurement, MeasurementStore, QuarantinedMeasurement, RecordedMeasurement,
+    SentMeasurement, StationMapping, StationState,
+};
+
+/// Forwards reads to the wrapped store and discards the writes of fetching
+///
+/// Used with `--read-only` and for dry runs against PostgreSQL, so that they
+/// are guaranteed not to modify the database. Named locks are always granted,
+/// as these runs never hold them in the database. Changes requested
+/// explicitly, like station mappings and views, fail instead of pretending to succeed.
+pub struct ReadOnlyStore {
+    inner: Arc<dyn MeasurementStore>,
+}
+
+impl ReadOnlyStore {
+    /// Wrap a store
+    pub fn new(inner: Arc<dyn MeasurementStore>) -> Self {
+        Self { inner }
+    }
+}
+
+/// Error for a change that can't be discarded silently
+const READ_ONLY: &str = "The database is opened read-only";
+
+/// Log a discarded write
+fn discard(what: &str) {
+    debug!("Not writing {} to the read-only database", what);
+}
+
+#[async_trait]
+impl MeasurementStore for ReadOnlyStore {
+    async fn is_measurement_sent(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        measurement_time: DateTime<Utc>,
+    ) -> Result<bool> {
+        self.inner
+            .is_measurement_sent(target, sensor_id, measurement_time)
+            .await
+    }
+
+    async fn record_measurement_sent(&self, _sent: &SentMeasurement) -> Result<()> {
+        discard("sent measurement");
+        Ok(())
+    }
+
+    async fn latest_sent_measurement(
+        &self,
+        target: &str,
+        sensor_id: u32,
+    ) -> Result<Option<SentMeasurement>> {
+        self.inner.latest_sent_measurement(target, sensor_id).await
+    }
+
+    async fn sent_measurements(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<SentMeasurement>> {
+        self.inner
+            .sent_measurements(target, sensor_id, from, to)
+            .await
+    }
+
+    async fn replace_sent_measurements(
+        &self,
+        _target: &str,
+        _sensor_id: u32,
+        _from: DateTime<Utc>,
+        _to: DateTime<Utc>,
+        _measurements: &[SentMeasurement],
+    ) -> Result<u64> {
+        discard("sent measurements");
+        Ok(0)
+    }
+
+    async fn all_sent_measurements(&self) -> Result<Vec<RecordedMeasurement>> {
+        self.inner.all_sent_measurements().await
+    }
+
+    async fn record_error(&self, _record: &ErrorRecord) -> Result<()> {
+        discard("error");
+        Ok(())
+    }
+
+    async fn recent_errors(&self, limit: u32) -> Result<Vec<ErrorRecord>> {
+        self.inner.recent_errors(limit).await
+    }
+
+    async fn station_state(&self, station_id: u32) -> Result<Option<StationState>> {
+        self.inner.station_state(station_id).await
+    }
+
+    async fn update_station_state(&self, _state: &StationState) -> Result<()> {
+        discard("station state");
+        Ok(())
+    }
+
+    async fn held_measurement(&self, station_id: u32) -> Result<Option<HeldMeasurement>> {
+        self.inner.held_measurement(station_id).await
+    }
+
+    async fn hold_measurement(&self, _held: &HeldMeasurement) -> Result<()> {
+        discard("held measurement");
+        Ok(())
+    }
+
+    async fn release_held_measurement(&self, _station_id: u32) -> Result<()> {
+        discard("released held measurement");
+        Ok(())
+    }
+
+    async fn record_raw_measurement(
+        &self,
+        _station_id: u32,
+        _time: DateTime<Utc>,
+        _temperature: f32,
+    ) -> Result<()> {
+        discard("raw measurement");
+        Ok(())
+    }
+
+    async fn raw_temperatures(
+        &self,
+        station_id: u32,
+        before: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<f32>> {
+        self.inner.raw_temperatures(station_id, before, limit).await
+    }
+
+    async fn quarantine_measurement(&self, _measurement: &QuarantinedMeasurement) -> Result<i64> {
+        discard("quarantined measurement");
+        Ok(0)
+    }
+
+    async fn quarantined_measurements(
+        &self,
+        include_released: bool,
+    ) -> Result<Vec<QuarantinedMeasurement>> {
+        self.inner.quarantined_measurements(include_released).await
+    }
+
+    async fn quarantined_measurement(&self, id: i64) -> Result<Option<QuarantinedMeasurement>> {
+        self.inner.quarantined_measurement(id).await
+    }
+
+    async fn release_quarantined_measurement(
+        &self,
+        _id: i64,
+        _released_at: DateTime<Utc>,
+    ) -> Result<()> {
+        discard("released quarantined measurement");
+        Ok(())
+    }
+
+    async fn danger_level(&self, station_id: u32) -> Result<Option<u8>> {
+        self.inner.danger_level(station_id).await
+    }
+
+    async fn update_danger_level(
+        &self,
+        _station_id: u32,
+        _level: u8,
+        _updated_at: DateTime<Utc>,
+    ) -> Result<()> {
+        discard("danger level");
+        Ok(())
+    }
+
+    async fn station_mappings(&self) -> Result<Vec<StationMapping>> {
+        self.inner.station_mappings().await
+    }
+
+    async fn set_station_mapping(&self, _mapping: &StationMapping) -> Result<()> {
+        bail!(READ_ONLY)
+    }
+
+    async fn remove_station_mapping(&self, _station_id: u32) -> Result<bool> {
+        bail!(READ_ONLY)
+    }
+
+    async fn completed_backfill_windows(
+        &self,
+        station_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+        self.inner
+            .completed_backfill_windows(station_id, from, to)
+            .await
+    }
+
+    async fn record_backfill_window(
+        &self,
+        _station_id: u32,
+        _start: DateTime<Utc>,
+        _end: DateTime<Utc>,
+        _completed_at: DateTime<Utc>,
+    ) -> Result<()> {
+        discard("backfill state");
+        Ok(())
+    }
+
+    async fn acquire_lock(
+        &self,
+        _name: &str,
+        _holder: &str,
+        _now: DateTime<Utc>,
+        _expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        discard("lock");
+        Ok(true)
+    }
+
+    async fn release_lock(&self, _name: &str, _holder: &str) -> Result<()> {
+        discard("released lock");
+        Ok(())
+    }
+
+    async fn backup(&self, path: &Path) -> Result<()> {
+        self.inner.backup(path).await
+    }
+
+    async fn check_integrity(&self) -> Result<()> {
+        self.inner.check_integrity().await
+    }
+
+    async fn create_views(&self, _gap_minutes: u32) -> Result<()> {
+        bail!(READ_ONLY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::database::SqliteStore;
+
+    #[tokio::test]
+    async fn test_writes_are_discarded() {
+        let inner = Arc::new(SqliteStore::open_in_memory().unwrap());
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        inner.update_danger_level(2104, 2, time).await.unwrap();
+
+        let store = ReadOnlyStore::new(inner.clone());
+        store.update_danger_level(2104, 4, time).await.unwrap();
+        store
+            .record_measurement_sent(&SentMeasurement {
+                target: "default".to_string(),
+                sensor_id: 1,
+                time,
+                temperature: Some(6.5),
+                idempotency_key: "lindas-1-1736942400".to_string(),
+                gfroerli_id: None,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(store.danger_level(2104).await.unwrap(), Some(2));
+        assert!(!inner.is_measurement_sent("default", 1, time).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_mapping_changes_fail() {
+        let inner = Arc::new(SqliteStore::open_in_memory().unwrap());
+        let mapping = StationMapping {
+            station_id: 2104,
+            sensor_id: 1,
+            updated_at: Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+        };
+        inner.set_station_mapping(&mapping).await.unwrap();
+
+        let store = ReadOnlyStore::new(inner.clone());
+        let error = store.set_station_mapping(&mapping).await.unwrap_err();
+        assert_eq!(error.to_string(), "The database is opened read-only");
+        assert!(store.remove_station_mapping(2104).await.is_err());
+        assert_eq!(inner.station_mappings().await.unwrap(), [mapping]);
+    }
+
+    #[tokio::test]
+    async fn test_create_views_fails() {
+        let store = ReadOnlyStore::new(Arc::new(SqliteStore::open_in_memory().unwrap()));
+        assert!(store.create_views(60).await.is_err());
+    }
+}
@@ -0,0 +1,2129 @@
+//! SQLite implementation of the measurement store
+
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{
+    Connection, OpenFlags, OptionalExtension,
+    backup::{Backup, Progress},
+    params,
+};
+use tracing::{debug, info, warn};
+
+use super::{
+    ErrorRecord, HeldMeasurement, MeasurementStore, QuarantinedMeasurement, RecordedMeasurement,
+    SentMeasurement, StationMapping, StationState,
+};
+use crate::{gfroerli::idempotency_key, secret::SecretString};
+
+/// Schema migrations, applied in order
+///
+/// The number of applied migrations is tracked in `PRAGMA user_version`.
+/// Never modify an existing migration, always append a new one.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS sent_measurements (
+        sensor_id INTEGER NOT NULL,
+        measurement_timestamp INTEGER NOT NULL,
+        sent_at INTEGER NOT NULL,
+        PRIMARY KEY (sensor_id, measurement_timestamp)
+    )",
+    "CREATE TABLE errors (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        occurred_at INTEGER NOT NULL,
+        station_id INTEGER NOT NULL,
+        sensor_id INTEGER,
+        phase TEXT NOT NULL,
+        http_status INTEGER,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX errors_occurred_at ON errors (occurred_at)",
+    "CREATE TABLE station_state (
+        station_id INTEGER PRIMARY KEY,
+        last_fetch_at INTEGER NOT NULL,
+        last_measurement_timestamp INTEGER NOT NULL,
+        last_temperature REAL NOT NULL
+    )",
+    "CREATE TABLE held_measurements (
+        station_id INTEGER PRIMARY KEY,
+        measurement_timestamp INTEGER NOT NULL,
+        temperature REAL NOT NULL,
+        held_at INTEGER NOT NULL
+    )",
+    "ALTER TABLE sent_measurements ADD COLUMN idempotency_key TEXT",
+    "ALTER TABLE sent_measurements ADD COLUMN gfroerli_measurement_id INTEGER",
+    "ALTER TABLE sent_measurements ADD COLUMN temperature REAL",
+    "CREATE TABLE sent_measurements_new (
+        target TEXT NOT NULL,
+        sensor_id INTEGER NOT NULL,
+        measurement_timestamp INTEGER NOT NULL,
+        sent_at INTEGER NOT NULL,
+        idempotency_key TEXT,
+        gfroerli_measurement_id INTEGER,
+        temperature REAL,
+        PRIMARY KEY (target, sensor_id, measurement_timestamp)
+    );
+    INSERT INTO sent_measurements_new
+        SELECT 'default', sensor_id, measurement_timestamp, sent_at, idempotency_key,
+            gfroerli_measurement_id, temperature
+        FROM sent_measurements;
+    DROP TABLE sent_measurements;
+    ALTER TABLE sent_measurements_new RENAME TO sent_measurements",
+    "CREATE TABLE locks (
+        name TEXT PRIMARY KEY,
+        holder TEXT NOT NULL,
+        expires_at INTEGER NOT NULL
+    )",
+    "CREATE TABLE danger_levels (
+        station_id INTEGER PRIMARY KEY,
+        level INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    )",
+    "CREATE TABLE backfill_state (
+        station_id INTEGER NOT NULL,
+        window_start INTEGER NOT NULL,
+        window_end INTEGER NOT NULL,
+        completed_at INTEGER NOT NULL,
+        PRIMARY KEY (station_id, window_start, window_end)
+    )",
+    "CREATE TABLE quarantine (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        station_id INTEGER NOT NULL,
+        measurement_timestamp INTEGER NOT NULL,
+        temperature REAL NOT NULL,
+        reason TEXT NOT NULL,
+        quarantined_at INTEGER NOT NULL,
+        released_at INTEGER
+    )",
+    "CREATE TABLE raw_measurements (
+        station_id INTEGER NOT NULL,
+        measurement_timestamp INTEGER NOT NULL,
+        temperature REAL NOT NULL,
+        PRIMARY KEY (station_id, measurement_timestamp)
+    )",
+    "ALTER TABLE errors ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE quarantine ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+    CREATE TABLE sent_measurements_new (
+        tenant TEXT NOT NULL,
+        target TEXT NOT NULL,
+        sensor_id INTEGER NOT NULL,
+        measurement_timestamp INTEGER NOT NULL,
+        sent_at INTEGER NOT NULL,
+        idempotency_key TEXT,
+        gfroerli_measurement_id INTEGER,
+        temperature REAL,
+        PRIMARY KEY (tenant, target, sensor_id, measurement_timestamp)
+    );
+    INSERT INTO sent_measurements_new
+        SELECT 'default', target, sensor_id, measurement_timestamp, sent_at, idempotency_key,
+            gfroerli_measurement_id, temperature
+        FROM sent_measurements;
+    DROP TABLE sent_measurements;
+    ALTER TABLE sent_measurements_new RENAME TO sent_measurements;
+    CREATE TABLE station_state_new (
+        tenant TEXT NOT NULL,
+        station_id INTEGER NOT NULL,
+        last_fetch_at INTEGER NOT NULL,
+        last_measurement_timestamp INTEGER NOT NULL,
+        last_temperature REAL NOT NULL,
+        PRIMARY KEY (tenant, station_id)
+    );
+    INSERT INTO station_state_new
+        SELECT 'default', station_id, last_fetch_at, last_measurement_timestamp, last_temperature
+        FROM station_state;
+    DROP TABLE station_state;
+    ALTER TABLE station_state_new RENAME TO station_state;
+    CREATE TABLE held_measurements_new (
+        tenant TEXT NOT NULL,
+        station_id INTEGER NOT NULL,
+        measurement_timestamp INTEGER NOT NULL,
+        temperature REAL NOT NULL,
+        held_at INTEGER NOT NULL,
+        PRIMARY KEY (tenant, station_id)
+    );
+    INSERT INTO held_measurements_new
+        SELECT 'default', station_id, measurement_timestamp, temperature, held_at
+        FROM held_measurements;
+    DROP TABLE held_measurements;
+    ALTER TABLE held_measurements_new RENAME TO held_measurements;
+    CREATE TABLE locks_new (
+        tenant TEXT NOT NULL,
+        name TEXT NOT NULL,
+        holder TEXT NOT NULL,
+        expires_at INTEGER NOT NULL,
+        PRIMARY KEY (tenant, name)
+    );
+    INSERT INTO locks_new SELECT 'default', name, holder, expires_at FROM locks;
+    DROP TABLE locks;
+    ALTER TABLE locks_new RENAME TO locks;
+    CREATE TABLE danger_levels_new (
+        tenant TEXT NOT NULL,
+        station_id INTEGER NOT NULL,
+        level INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (tenant, station_id)
+    );
+    INSERT INTO danger_levels_new SELECT 'default', station_id, level, updated_at FROM danger_levels;
+    DROP TABLE danger_levels;
+    ALTER TABLE danger_levels_new RENAME TO danger_levels;
+    CREATE TABLE backfill_state_new (
+        tenant TEXT NOT NULL,
+        station_id INTEGER NOT NULL,
+        window_start INTEGER NOT NULL,
+        window_end INTEGER NOT NULL,
+        completed_at INTEGER NOT NULL,
+        PRIMARY KEY (tenant, station_id, window_start, window_end)
+    );
+    INSERT INTO backfill_state_new
+        SELECT 'default', station_id, window_start, window_end, completed_at FROM backfill_state;
+    DROP TABLE backfill_state;
+    ALTER TABLE backfill_state_new RENAME TO backfill_state;
+    CREATE TABLE raw_measurements_new (
+        tenant TEXT NOT NULL,
+        station_id INTEGER NOT NULL,
+        measurement_timestamp INTEGER NOT NULL,
+        temperature REAL NOT NULL,
+        PRIMARY KEY (tenant, station_id, measurement_timestamp)
+    );
+    INSERT INTO raw_measurements_new
+        SELECT 'default', station_id, measurement_timestamp, temperature FROM raw_measurements;
+    DROP TABLE raw_measurements;
+    ALTER TABLE raw_measurements_new RENAME TO raw_measurements",
+    "CREATE TABLE station_mappings (
+        tenant TEXT NOT NULL,
+        station_id INTEGER NOT NULL,
+        sensor_id INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (tenant, station_id)
+    )",
+];
+
+/// Views for ad-hoc analysis with sqlite3 or DuckDB, created by `db views`
+///
+/// Days are in UTC. `{gap_seconds}` is replaced by the minimum length of a gap.
+const VIEWS: &str = "
+    DROP VIEW IF EXISTS daily_temperatures;
+    CREATE VIEW daily_temperatures AS
+    SELECT tenant, target, sensor_id,
+        date(measurement_timestamp, 'unixepoch') AS day,
+        count(*) AS measurements,
+        min(temperature) AS min_temperature,
+        max(temperature) AS max_temperature,
+        round(avg(temperature), 2) AS avg_temperature
+    FROM sent_measurements
+    GROUP BY tenant, target, sensor_id, day;
+
+    DROP VIEW IF EXISTS measurement_gaps;
+    CREATE VIEW measurement_gaps AS
+    SELECT tenant, target, sensor_id,
+        datetime(previous_timestamp, 'unixepoch') AS gap_start,
+        datetime(measurement_timestamp, 'unixepoch') AS gap_end,
+        (measurement_timestamp - previous_timestamp) / 60 AS gap_minutes
+    FROM (
+        SELECT tenant, target, sensor_id, measurement_timestamp,
+            lag(measurement_timestamp) OVER (
+                PARTITION BY tenant, target, sensor_id ORDER BY measurement_timestamp
+            ) AS previous_timestamp
+        FROM sent_measurements
+    )
+    WHERE measurement_timestamp - previous_timestamp > {gap_seconds}";
+
+/// Connection options for the SQLite database
+#[derive(Debug, Clone)]
+pub struct SqliteOptions {
+    /// Enable write-ahead logging so that readers don't block the fetcher
+    pub wal: bool,
+    /// How long to wait for a lock held by another connection
+    pub busy_timeout: Duration,
+    /// SQLCipher key the database is encrypted with
+    pub key: Option<SecretString>,
+    /// Tenant all rows are read and written for
+    pub tenant: String,
+    /// Create missing parent directories of the database file
+    pub create_dirs: bool,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        Self {
+            wal: true,
+            busy_timeout: Duration::from_millis(5000),
+            key: None,
+            tenant: "default".to_string(),
+            create_dirs: true,
+        }
+    }
+}
+
+/// SQLite backed measurement store
+///
+/// The connection is shared behind a mutex and every query runs on tokio's
+/// blocking thread pool.
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    tenant: Arc<str>,
+    /// SQLCipher key of the database, backups are encrypted with it as well
+    key: Option<SecretString>,
+}
+
+impl SqliteStore {
+    /// Open (and initialize) the SQLite database at the given path
+    pub fn open(db_path: &str, options: &SqliteOptions) -> Result<Self> {
+        Ok(Self::with_options(
+            init_database(db_path, options)?,
+            options,
+        ))
+    }
+
+    /// Open the SQLite database at the given path read-only
+    ///
+    /// Migrations are not applied, so the schema must be up to date.
+    pub fn open_read_only(db_path: &str, options: &SqliteOptions) -> Result<Self> {
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open database at {db_path} read-only"))?;
+        apply_key(&conn, options.key.as_ref())?;
+        conn.busy_timeout(options.busy_timeout)
+            .with_context(|| "Failed to set busy timeout")?;
+        check_integrity(&conn, "quick_check")?;
+        let version = schema_version(&conn)?;
+        if version < MIGRATIONS.len() {
+            bail!(
+                "Database schema is at version {version}, but version {} is required and migrations can't be applied read-only",
+                MIGRATIONS.len()
+            );
+        }
+        Ok(Self::with_options(conn, options))
+    }
+
+    /// Open an in-memory copy of the SQLite database at the given path
+    ///
+    /// The file is opened read-only and only copied, so writes to the store
+    /// never reach it. If the file doesn't exist, the copy starts empty.
+    pub fn open_snapshot(db_path: &str, options: &SqliteOptions) -> Result<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        if Path::new(db_path).exists() {
+            debug!("Copying database at {} into memory", db_path);
+            let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .with_context(|| format!("Failed to open database at {db_path} read-only"))?;
+            // SQLCipher only copies between databases with the same key
+            apply_key(&source, options.key.as_ref())?;
+            apply_key(&conn, options.key.as_ref())?;
+            source
+                .busy_timeout(options.busy_timeout)
+                .with_context(|| "Failed to set busy timeout")?;
+            Backup::new(&source, &mut conn)
+                .and_then(|backup| {
+                    backup.run_to_completion(100, Duration::ZERO, None::<fn(Progress)>)
+                })
+                .with_context(|| format!("Failed to copy database at {db_path} into memory"))?;
+            check_integrity(&conn, "quick_check")?;
+        }
+        migrate(&conn)?;
+        Ok(Self::with_options(conn, options))
+    }
+
+    /// Open a fresh in-memory database
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+        Ok(Self::new(conn, "default"))
+    }
+
+    /// Wrap a connection opened with `options`
+    fn with_options(conn: Connection, options: &SqliteOptions) -> Self {
+        Self {
+            key: options.key.clone(),
+            ..Self::new(conn, &options.tenant)
+        }
+    }
+
+    /// Wrap an already initialized connection, reading and writing the rows
+    /// of the given tenant
+    pub fn new(conn: Connection, tenant: &str) -> Self {
+        Self {
+            conn: Arc::new(Mutex::new(conn)),
+            tenant: tenant.into(),
+            key: None,
+        }
+    }
+
+    /// Run a closure with the connection and tenant on the blocking thread
+    /// pool
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection, &str) -> Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        let tenant = Arc::clone(&self.tenant);
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| anyhow!("Database connection mutex poisoned"))?;
+            f(&conn, &tenant)
+        })
+        .await
+        .with_context(|| "Database task failed")?
+    }
+}
+
+#[async_trait]
+impl MeasurementStore for SqliteStore {
+    async fn is_measurement_sent(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        measurement_time: DateTime<Utc>,
+    ) -> Result<bool> {
+        let target = target.to_string();
+        self.with_conn(move |conn, tenant| {
+            is_measurement_sent(conn, tenant, &target, sensor_id, &measurement_time)
+        })
+        .await
+    }
+
+    async fn record_measurement_sent(&self, sent: &SentMeasurement) -> Result<()> {
+        let sent = sent.clone();
+        self.with_conn(move |conn, tenant| record_measurement_sent(conn, tenant, &sent))
+            .await
+    }
+
+    async fn latest_sent_measurement(
+        &self,
+        target: &str,
+        sensor_id: u32,
+    ) -> Result<Option<SentMeasurement>> {
+        let target = target.to_string();
+        self.with_conn(move |conn, tenant| {
+            latest_sent_measurement(conn, tenant, &target, sensor_id)
+        })
+        .await
+    }
+
+    async fn sent_measurements(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<SentMeasurement>> {
+        let target = target.to_string();
+        self.with_conn(move |conn, tenant| {
+            sent_measurements(conn, tenant, &target, sensor_id, &from, &to)
+        })
+        .await
+    }
+
+    async fn replace_sent_measurements(
+        &self,
+        target: &str,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        measurements: &[SentMeasurement],
+    ) -> Result<u64> {
+        let target = target.to_string();
+        let measurements = measurements.to_vec();
+        self.with_conn(move |conn, tenant| {
+            let transaction = conn.unchecked_transaction()?;
+            let removed = transaction
+                .execute(
+                    "DELETE FROM sent_measurements
+                     WHERE tenant = ? AND target = ? AND sensor_id = ?
+                        AND measurement_timestamp BETWEEN ? AND ?",
+                    params![tenant, target, sensor_id, from.timestamp(), to.timestamp()],
+                )
+                .with_context(|| {
+                    format!("Failed to remove sent measurements of sensor {sensor_id}")
+                })?;
+            for sent in &measurements {
+                record_measurement_sent(&transaction, tenant, sent)?;
+            }
+            transaction.commit()?;
+            Ok(removed as u64)
+        })
+        .await
+    }
+
+    async fn all_sent_measurements(&self) -> Result<Vec<RecordedMeasurement>> {
+        self.with_conn(|conn, tenant| {
+            let mut stmt = conn.prepare(
+                "SELECT target, sensor_id, measurement_timestamp, temperature, sent_at
+                 FROM sent_measurements WHERE tenant = ?
+                 ORDER BY target, sensor_id, measurement_timestamp",
+            )?;
+            let rows = stmt
+                .query_map(params![tenant], |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<(String, u32, i64, Option<f32>, i64)>>>()
+                .with_context(|| "Failed to query sent measurements")?;
+            rows.into_iter()
+                .map(|(target, sensor_id, time, temperature, sent_at)| {
+                    Ok(RecordedMeasurement {
+                        target,
+                        sensor_id,
+                        time: from_timestamp(time)?,
+                        temperature,
+                        sent_at: from_timestamp(sent_at)?,
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
+
+    async fn record_error(&self, record: &ErrorRecord) -> Result<()> {
+        let record = record.clone();
+        self.with_conn(move |conn, tenant| record_error(conn, tenant, &record))
+            .await
+    }
+
+    async fn recent_errors(&self, limit: u32) -> Result<Vec<ErrorRecord>> {
+        self.with_conn(move |conn, tenant| recent_errors(conn, tenant, limit))
+            .await
+    }
+
+    async fn station_state(&self, station_id: u32) -> Result<Option<StationState>> {
+        self.with_conn(move |conn, tenant| station_state(conn, tenant, station_id))
+            .await
+    }
+
+    async fn update_station_state(&self, state: &StationState) -> Result<()> {
+        let state = state.clone();
+        self.with_conn(move |conn, tenant| update_station_state(conn, tenant, &state))
+            .await
+    }
+
+    async fn held_measurement(&self, station_id: u32) -> Result<Option<HeldMeasurement>> {
+        self.with_conn(move |conn, tenant| held_measurement(conn, tenant, station_id))
+            .await
+    }
+
+    async fn hold_measurement(&self, held: &HeldMeasurement) -> Result<()> {
+        let held = held.clone();
+        self.with_conn(move |conn, tenant| hold_measurement(conn, tenant, &held))
+            .await
+    }
+
+    async fn release_held_measurement(&self, station_id: u32) -> Result<()> {
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "DELETE FROM held_measurements WHERE tenant = ? AND station_id = ?",
+                params![tenant, station_id],
+            )
+            .with_context(|| {
+                format!("Failed to release held measurement of station {station_id}")
+            })?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_raw_measurement(
+        &self,
+        station_id: u32,
+        time: DateTime<Utc>,
+        temperature: f32,
+    ) -> Result<()> {
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "INSERT OR IGNORE INTO raw_measurements
+                    (tenant, station_id, measurement_timestamp, temperature)
+                 VALUES (?, ?, ?, ?)",
+                params![tenant, station_id, time.timestamp(), temperature],
+            )
+            .with_context(|| format!("Failed to record raw measurement of station {station_id}"))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn raw_temperatures(
+        &self,
+        station_id: u32,
+        before: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<f32>> {
+        self.with_conn(move |conn, tenant| {
+            let mut stmt = conn.prepare(
+                "SELECT temperature FROM raw_measurements
+                 WHERE tenant = ? AND station_id = ? AND measurement_timestamp < ?
+                 ORDER BY measurement_timestamp DESC LIMIT ?",
+            )?;
+            stmt.query_map(
+                params![tenant, station_id, before.timestamp(), limit],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .with_context(|| format!("Failed to query raw measurements of station {station_id}"))
+        })
+        .await
+    }
+
+    async fn quarantine_measurement(&self, measurement: &QuarantinedMeasurement) -> Result<i64> {
+        let measurement = measurement.clone();
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "INSERT INTO quarantine
+                    (tenant, station_id, measurement_timestamp, temperature, reason, quarantined_at)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    tenant,
+                    measurement.station_id,
+                    measurement.time.timestamp(),
+                    measurement.temperature,
+                    measurement.reason,
+                    measurement.quarantined_at.timestamp(),
+                ],
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to quarantine measurement of station {}",
+                    measurement.station_id
+                )
+            })?;
+            Ok(conn.last_insert_rowid())
+        })
+        .await
+    }
+
+    async fn quarantined_measurements(
+        &self,
+        include_released: bool,
+    ) -> Result<Vec<QuarantinedMeasurement>> {
+        self.with_conn(move |conn, tenant| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {QUARANTINE_COLUMNS} FROM quarantine
+                     WHERE tenant = ? AND (? OR released_at IS NULL) ORDER BY id DESC"
+            ))?;
+            let rows = stmt
+                .query_map(params![tenant, include_released], quarantine_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .with_context(|| "Failed to query quarantined measurements")?;
+            rows.into_iter().map(quarantined_from_row).collect()
+        })
+        .await
+    }
+
+    async fn quarantined_measurement(&self, id: i64) -> Result<Option<QuarantinedMeasurement>> {
+        self.with_conn(move |conn, tenant| {
+            conn.query_row(
+                &format!("SELECT {QUARANTINE_COLUMNS} FROM quarantine WHERE tenant = ? AND id = ?"),
+                params![tenant, id],
+                quarantine_row,
+            )
+            .optional()
+            .with_context(|| format!("Failed to query quarantined measurement {id}"))?
+            .map(quarantined_from_row)
+            .transpose()
+        })
+        .await
+    }
+
+    async fn release_quarantined_measurement(
+        &self,
+        id: i64,
+        released_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "UPDATE quarantine SET released_at = ? WHERE tenant = ? AND id = ?",
+                params![released_at.timestamp(), tenant, id],
+            )
+            .with_context(|| format!("Failed to release quarantined measurement {id}"))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn danger_level(&self, station_id: u32) -> Result<Option<u8>> {
+        self.with_conn(move |conn, tenant| {
+            conn.query_row(
+                "SELECT level FROM danger_levels WHERE tenant = ? AND station_id = ?",
+                params![tenant, station_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .with_context(|| format!("Failed to query danger level of station {station_id}"))
+        })
+        .await
+    }
+
+    async fn update_danger_level(
+        &self,
+        station_id: u32,
+        level: u8,
+        updated_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "INSERT OR REPLACE INTO danger_levels (tenant, station_id, level, updated_at)
+                 VALUES (?, ?, ?, ?)",
+                params![tenant, station_id, level, updated_at.timestamp()],
+            )
+            .with_context(|| format!("Failed to update danger level of station {station_id}"))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn station_mappings(&self) -> Result<Vec<StationMapping>> {
+        self.with_conn(|conn, tenant| {
+            let mut stmt = conn.prepare(
+                "SELECT station_id, sensor_id, updated_at FROM station_mappings
+                 WHERE tenant = ? ORDER BY station_id",
+            )?;
+            let rows = stmt
+                .query_map(params![tenant], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<rusqlite::Result<Vec<(u32, u32, i64)>>>()
+                .with_context(|| "Failed to query station mappings")?;
+            rows.into_iter()
+                .map(|(station_id, sensor_id, updated_at)| {
+                    Ok(StationMapping {
+                        station_id,
+                        sensor_id,
+                        updated_at: from_timestamp(updated_at)?,
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
+
+    async fn set_station_mapping(&self, mapping: &StationMapping) -> Result<()> {
+        let mapping = mapping.clone();
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "INSERT OR REPLACE INTO station_mappings (tenant, station_id, sensor_id, updated_at)
+                 VALUES (?, ?, ?, ?)",
+                params![
+                    tenant,
+                    mapping.station_id,
+                    mapping.sensor_id,
+                    mapping.updated_at.timestamp()
+                ],
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to store mapping of station {}",
+                    mapping.station_id
+                )
+            })?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_station_mapping(&self, station_id: u32) -> Result<bool> {
+        self.with_conn(move |conn, tenant| {
+            let removed = conn
+                .execute(
+                    "DELETE FROM station_mappings WHERE tenant = ? AND station_id = ?",
+                    params![tenant, station_id],
+                )
+                .with_context(|| format!("Failed to remove mapping of station {station_id}"))?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    async fn completed_backfill_windows(
+        &self,
+        station_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+        self.with_conn(move |conn, tenant| {
+            let mut stmt = conn.prepare(
+                "SELECT window_start, window_end FROM backfill_state
+                 WHERE tenant = ? AND station_id = ? AND window_end > ? AND window_start < ?
+                 ORDER BY window_start",
+            )?;
+            let rows = stmt
+                .query_map(
+                    params![tenant, station_id, from.timestamp(), to.timestamp()],
+                    |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .with_context(|| {
+                    format!("Failed to query backfill state of station {station_id}")
+                })?;
+            rows.into_iter()
+                .map(|(start, end)| Ok((from_timestamp(start)?, from_timestamp(end)?)))
+                .collect()
+        })
+        .await
+    }
+
+    async fn record_backfill_window(
+        &self,
+        station_id: u32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        completed_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "INSERT OR REPLACE INTO backfill_state
+                    (tenant, station_id, window_start, window_end, completed_at)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![
+                    tenant,
+                    station_id,
+                    start.timestamp(),
+                    end.timestamp(),
+                    completed_at.timestamp()
+                ],
+            )
+            .with_context(|| format!("Failed to record backfill state of station {station_id}"))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn acquire_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let name = name.to_string();
+        let holder = holder.to_string();
+        self.with_conn(move |conn, tenant| {
+            acquire_lock(conn, tenant, &name, &holder, now, expires_at)
+        })
+        .await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<()> {
+        let name = name.to_string();
+        let holder = holder.to_string();
+        self.with_conn(move |conn, tenant| {
+            conn.execute(
+                "DELETE FROM locks WHERE tenant = ? AND name = ? AND holder = ?",
+                params![tenant, name, holder],
+            )
+            .with_context(|| format!("Failed to release lock '{name}'"))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn backup(&self, path: &Path) -> Result<()> {
+        let path = path.to_path_buf();
+        let key = self.key.clone();
+        self.with_conn(move |conn, _| backup_database(conn, &path, key.as_ref()))
+            .await
+    }
+
+    async fn check_integrity(&self) -> Result<()> {
+        self.with_conn(|conn, _| {
+            check_integrity(conn, "integrity_check")?;
+            info!("Database integrity check passed");
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_views(&self, gap_minutes: u32) -> Result<()> {
+        self.with_conn(move |conn, _| {
+            let views = VIEWS.replace("{gap_seconds}", &(u64::from(gap_minutes) * 60).to_string());
+            conn.execute_batch(&format!("BEGIN; {views}; COMMIT;"))
+                .with_context(|| "Failed to create views")?;
+            info!("Created the views daily_temperatures and measurement_gaps");
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Convert a stored unix timestamp back into a `DateTime`
+fn from_timestamp(timestamp: i64) -> Result<DateTime<Utc>> {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .ok_or_else(|| anyhow!("Invalid timestamp {timestamp} in database"))
+}
+
+/// Maximum number of problems listed when the integrity check fails
+const MAX_INTEGRITY_PROBLEMS: usize = 10;
+
+/// Run an integrity check pragma (`quick_check` or `integrity_check`), fails
+/// with the reported problems if the database is corrupted
+fn check_integrity(conn: &Connection, pragma: &str) -> Result<()> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA {pragma}({MAX_INTEGRITY_PROBLEMS})"))
+        .with_context(|| "Failed to prepare integrity check")?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .with_context(|| "Database integrity check failed, the database may be corrupted")?;
+    if problems != ["ok"] {
+        bail!(
+            "Database is corrupted, restore it from a backup: {}",
+            problems.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// Get the number of applied migrations
+fn schema_version(conn: &Connection) -> Result<usize> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .with_context(|| "Failed to read schema version")
+}
+
+/// Apply all pending schema migrations
+fn migrate(conn: &Connection) -> Result<()> {
+    let version = schema_version(conn)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let target_version = index + 1;
+        info!("Migrating database schema to version {}", target_version);
+        conn.execute_batch(&format!(
+            "BEGIN; {migration}; PRAGMA user_version = {target_version}; COMMIT;"
+        ))
+        .with_context(|| {
+            format!("Failed to migrate database schema to version {target_version}")
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Set the SQLCipher key of a connection, which must happen before any other
+/// statement
+fn apply_key(conn: &Connection, key: Option<&SecretString>) -> Result<()> {
+    let Some(key) = key else {
+        return Ok(());
+    };
+    conn.pragma_update(None, "key", key.expose())
+        .with_context(|| "Failed to set the database key")?;
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .with_context(|| "Failed to decrypt the database, check the database key")
+}
+
+/// Apply connection-level settings (busy timeout and journal mode)
+fn configure_connection(conn: &Connection, options: &SqliteOptions) -> Result<()> {
+    conn.busy_timeout(options.busy_timeout)
+        .with_context(|| "Failed to set busy timeout")?;
+
+    if options.wal {
+        let mode: String = conn
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))
+            .with_context(|| "Failed to enable WAL journal mode")?;
+        debug!("Database journal mode: {}", mode);
+    }
+
+    Ok(())
+}
+
+/// Permissions of the database file, only the owner may read the sent
+/// measurements
+const DATABASE_FILE_MODE: u32 = 0o600;
+
+/// Prepare the database file before SQLite opens it
+///
+/// SQLite only reports "unable to open database file" if the directory is
+/// missing or not writable, so missing directories are created (if enabled)
+/// and the file is created here, failing with a clear error. Group and other
+/// permissions of an existing file are removed.
+fn prepare_database_file(db_path: &str, create_dirs: bool) -> Result<()> {
+    if db_path == ":memory:" {
+        return Ok(());
+    }
+    let path = Path::new(db_path);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty())
+        && !dir.exists()
+    {
+        if !create_dirs {
+            bail!(
+                "Database directory '{}' doesn't exist (set create_dirs = true in [database] to create it)",
+                dir.display()
+            );
+        }
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create database directory '{}'", dir.display()))?;
+        info!("Created database directory '{}'", dir.display());
+    }
+
+    if !path.exists() {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(DATABASE_FILE_MODE)
+            .open(path)
+            .with_context(|| {
+                format!("Failed to create database file '{db_path}', check the permissions of its directory")
+            })?;
+        return Ok(());
+    }
+
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Database file '{db_path}' is not writable"))?;
+    let mut permissions = fs::metadata(path)
+        .with_context(|| format!("Failed to read permissions of database file '{db_path}'"))?
+        .permissions();
+    if permissions.mode() & 0o077 != 0 {
+        permissions.set_mode(DATABASE_FILE_MODE);
+        match fs::set_permissions(path, permissions) {
+            Ok(()) => info!("Restricted permissions of database file '{db_path}' to its owner"),
+            Err(e) => warn!("Failed to restrict permissions of database file '{db_path}': {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Initialize the SQLite database and apply pending migrations
+pub fn init_database(db_path: &str, options: &SqliteOptions) -> Result<Connection> {
+    debug!("Initializing database at {}", db_path);
+
+    prepare_database_file(db_path, options.create_dirs)?;
+    let conn = Connection::open(db_path)
+        .with_context(|| format!("Failed to open database at {db_path}"))?;
+
+    apply_key(&conn, options.key.as_ref())?;
+    configure_connection(&conn, options)?;
+    check_integrity(&conn, "quick_check")?;
+    migrate(&conn)?;
+
+    debug!("Database initialized successfully");
+    Ok(conn)
+}
+
+/// Check if a measurement has already been sent to a target for the given sensor and timestamp
+fn is_measurement_sent(
+    conn: &Connection,
+    tenant: &str,
+    target: &str,
+    sensor_id: u32,
+    measurement_time: &DateTime<Utc>,
+) -> Result<bool> {
+    let measurement_timestamp = measurement_time.timestamp();
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT 1 FROM sent_measurements
+             WHERE tenant = ? AND target = ? AND sensor_id = ? AND measurement_timestamp = ?",
+        )
+        .with_context(|| "Failed to prepare select statement")?;
+
+    let exists = stmt
+        .query_row(
+            params![tenant, target, sensor_id, measurement_timestamp],
+            |_| Ok(()),
+        )
+        .is_ok();
+
+    Ok(exists)
+}
+
+/// Record that a measurement has been successfully sent
+fn record_measurement_sent(conn: &Connection, tenant: &str, sent: &SentMeasurement) -> Result<()> {
+    let sensor_id = sent.sensor_id;
+    let measurement_timestamp = sent.time.timestamp();
+    let sent_at = Utc::now().timestamp();
+
+    conn.execute(
+        "INSERT INTO sent_measurements
+            (tenant, target, sensor_id, measurement_timestamp, sent_at, temperature, idempotency_key, gfroerli_measurement_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            tenant,
+            sent.target,
+            sensor_id,
+            measurement_timestamp,
+            sent_at,
+            sent.temperature,
+            sent.idempotency_key,
+            sent.gfroerli_id,
+        ],
+    )
+    .with_context(|| {
+        format!(
+            "Failed to record sent measurement for sensor {sensor_id} at timestamp {measurement_timestamp}"
+        )
+    })?;
+
+    debug!(
+        "Recorded sent measurement for sensor {} at timestamp {} (target {})",
+        sensor_id, measurement_timestamp, sent.target
+    );
+
+    Ok(())
+}
+
+/// Raw columns of a `sent_measurements` row
+type SentMeasurementRow = (i64, Option<f32>, Option<String>, Option<i64>);
+
+/// Columns selected for a [`SentMeasurement`]
+const SENT_MEASUREMENT_COLUMNS: &str =
+    "measurement_timestamp, temperature, idempotency_key, gfroerli_measurement_id";
+
+fn sent_measurement_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<SentMeasurementRow> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+}
+
+fn sent_measurement_from_row(
+    target: &str,
+    sensor_id: u32,
+    row: SentMeasurementRow,
+) -> Result<SentMeasurement> {
+    let (measurement_timestamp, temperature, key, gfroerli_id) = row;
+    let time = from_timestamp(measurement_timestamp)?;
+    Ok(SentMeasurement {
+        target: target.to_string(),
+        sensor_id,
+        time,
+        temperature,
+        // Measurements sent before keys were stored used the same derivation
+        idempotency_key: key.unwrap_or_else(|| idempotency_key(sensor_id, time)),
+        gfroerli_id,
+    })
+}
+
+/// Get the most recent measurement sent to a target for a sensor
+fn latest_sent_measurement(
+    conn: &Connection,
+    tenant: &str,
+    target: &str,
+    sensor_id: u32,
+) -> Result<Option<SentMeasurement>> {
+    let row = conn
+        .query_row(
+            &format!(
+                "SELECT {SENT_MEASUREMENT_COLUMNS} FROM sent_measurements
+                 WHERE tenant = ? AND target = ? AND sensor_id = ?
+                 ORDER BY measurement_timestamp DESC LIMIT 1"
+            ),
+            params![tenant, target, sensor_id],
+            sent_measurement_row,
+        )
+        .optional()
+        .with_context(|| format!("Failed to query latest measurement of sensor {sensor_id}"))?;
+
+    row.map(|row| sent_measurement_from_row(target, sensor_id, row))
+        .transpose()
+}
+
+/// Get the measurements sent to a target for a sensor in a time range, oldest first
+fn sent_measurements(
+    conn: &Connection,
+    tenant: &str,
+    target: &str,
+    sensor_id: u32,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+) -> Result<Vec<SentMeasurement>> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {SENT_MEASUREMENT_COLUMNS} FROM sent_measurements
+             WHERE tenant = ? AND target = ? AND sensor_id = ? AND measurement_timestamp BETWEEN ? AND ?
+             ORDER BY measurement_timestamp"
+        ))
+        .with_context(|| "Failed to prepare select statement")?;
+
+    let rows = stmt
+        .query_map(
+            params![tenant, target, sensor_id, from.timestamp(), to.timestamp()],
+            sent_measurement_row,
+        )
+        .with_context(|| format!("Failed to query sent measurements of sensor {sensor_id}"))?;
+
+    rows.map(|row| sent_measurement_from_row(target, sensor_id, row?))
+        .collect()
+}
+
+/// Record a fetch or send failure
+fn record_error(conn: &Connection, tenant: &str, record: &ErrorRecord) -> Result<()> {
+    conn.execute(
+        "INSERT INTO errors (tenant, occurred_at, station_id, sensor_id, phase, http_status, message)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![
+            tenant,
+            record.occurred_at.timestamp(),
+            record.station_id,
+            record.sensor_id,
+            record.phase.as_str(),
+            record.http_status,
+            record.message,
+        ],
+    )
+    .with_context(|| format!("Failed to record error for station {}", record.station_id))?;
+    Ok(())
+}
+
+/// Get the most recent errors, newest first
+fn recent_errors(conn: &Connection, tenant: &str, limit: u32) -> Result<Vec<ErrorRecord>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT occurred_at, station_id, sensor_id, phase, http_status, message
+             FROM errors WHERE tenant = ? ORDER BY occurred_at DESC, id DESC LIMIT ?",
+        )
+        .with_context(|| "Failed to prepare select statement")?;
+
+    let rows = stmt
+        .query_map(params![tenant, limit], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, Option<u32>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<u16>>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })
+        .with_context(|| "Failed to query errors")?;
+
+    rows.map(|row| {
+        let (occurred_at, station_id, sensor_id, phase, http_status, message) = row?;
+        Ok(ErrorRecord {
+            occurred_at: from_timestamp(occurred_at)?,
+            station_id,
+            sensor_id,
+            phase: phase.parse()?,
+            http_status,
+            message,
+        })
+    })
+    .collect()
+}
+
+/// Get the last known state of a station
+fn station_state(conn: &Connection, tenant: &str, station_id: u32) -> Result<Option<StationState>> {
+    let row = conn
+        .query_row(
+            "SELECT last_fetch_at, last_measurement_timestamp, last_temperature
+             FROM station_state WHERE tenant = ? AND station_id = ?",
+            params![tenant, station_id],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get(2)?)),
+        )
+        .optional()
+        .with_context(|| format!("Failed to query state of station {station_id}"))?;
+
+    row.map(
+        |(last_fetch_at, last_measurement_timestamp, last_temperature)| {
+            Ok(StationState {
+                station_id,
+                last_fetch_at: from_timestamp(last_fetch_at)?,
+                last_measurement_time: from_timestamp(last_measurement_timestamp)?,
+                last_temperature,
+            })
+        },
+    )
+    .transpose()
+}
+
+/// Insert or update the state of a station
+fn update_station_state(conn: &Connection, tenant: &str, state: &StationState) -> Result<()> {
+    conn.execute(
+        "INSERT INTO station_state (tenant, station_id, last_fetch_at, last_measurement_timestamp, last_temperature)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (tenant, station_id) DO UPDATE SET
+            last_fetch_at = excluded.last_fetch_at,
+            last_measurement_timestamp = excluded.last_measurement_timestamp,
+            last_temperature = excluded.last_temperature",
+        params![
+            tenant,
+            state.station_id,
+            state.last_fetch_at.timestamp(),
+            state.last_measurement_time.timestamp(),
+            state.last_temperature,
+        ],
+    )
+    .with_context(|| format!("Failed to update state of station {}", state.station_id))?;
+    Ok(())
+}
+
+/// Get the measurement of a station that is awaiting confirmation
+fn held_measurement(
+    conn: &Connection,
+    tenant: &str,
+    station_id: u32,
+) -> Result<Option<HeldMeasurement>> {
+    let row = conn
+        .query_row(
+            "SELECT measurement_timestamp, temperature, held_at
+             FROM held_measurements WHERE tenant = ? AND station_id = ?",
+            params![tenant, station_id],
+            |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get::<_, i64>(2)?)),
+        )
+        .optional()
+        .with_context(|| format!("Failed to query held measurement of station {station_id}"))?;
+
+    row.map(|(measurement_timestamp, temperature, held_at)| {
+        Ok(HeldMeasurement {
+            station_id,
+            time: from_timestamp(measurement_timestamp)?,
+            temperature,
+            held_at: from_timestamp(held_at)?,
+        })
+    })
+    .transpose()
+}
+
+/// Hold back a measurement, replacing a previously held one of the same station
+fn hold_measurement(conn: &Connection, tenant: &str, held: &HeldMeasurement) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO held_measurements (tenant, station_id, measurement_timestamp, temperature, held_at)
+         VALUES (?, ?, ?, ?, ?)",
+        params![
+            tenant,
+            held.station_id,
+            held.time.timestamp(),
+            held.temperature,
+            held.held_at.timestamp(),
+        ],
+    )
+    .with_context(|| format!("Failed to hold measurement of station {}", held.station_id))?;
+    Ok(())
+}
+
+/// Raw columns of a `quarantine` row
+type QuarantineRow = (i64, u32, i64, f32, String, i64, Option<i64>);
+
+/// Columns selected for a [`QuarantinedMeasurement`]
+const QUARANTINE_COLUMNS: &str =
+    "id, station_id, measurement_timestamp, temperature, reason, quarantined_at, released_at";
+
+fn quarantine_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<QuarantineRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+    ))
+}
+
+fn quarantined_from_row(row: QuarantineRow) -> Result<QuarantinedMeasurement> {
+    let (id, station_id, measurement_timestamp, temperature, reason, quarantined_at, released_at) =
+        row;
+    Ok(QuarantinedMeasurement {
+        id,
+        station_id,
+        time: from_timestamp(measurement_timestamp)?,
+        temperature,
+        reason,
+        quarantined_at: from_timestamp(quarantined_at)?,
+        released_at: released_at.map(from_timestamp).transpose()?,
+    })
+}
+
+/// Take or renew a lock for `holder`, unless another holder has it and it
+/// hasn't expired yet
+fn acquire_lock(
+    conn: &Connection,
+    tenant: &str,
+    name: &str,
+    holder: &str,
+    now: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> Result<bool> {
+    let changed = conn
+        .execute(
+            "INSERT INTO locks (tenant, name, holder, expires_at) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (tenant, name) DO UPDATE
+                SET holder = excluded.holder, expires_at = excluded.expires_at
+                WHERE locks.holder = excluded.holder OR locks.expires_at <= ?5",
+            params![
+                tenant,
+                name,
+                holder,
+                expires_at.timestamp(),
+                now.timestamp()
+            ],
+        )
+        .with_context(|| format!("Failed to acquire lock '{name}'"))?;
+    Ok(changed > 0)
+}
+
+/// Copy the database to the given path using SQLite's online backup API
+///
+/// The backup is performed in small steps, so that other connections (e.g. a
+/// running fetcher in loop mode) are only blocked briefly.
+fn backup_database(conn: &Connection, path: &Path, key: Option<&SecretString>) -> Result<()> {
+    // Created here, so that the copy gets the permissions of the database
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(DATABASE_FILE_MODE)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => {
+                anyhow!("Backup target '{}' already exists", path.display())
+            }
+            _ => anyhow::Error::new(e)
+                .context(format!("Failed to create backup file '{}'", path.display())),
+        })?;
+    let mut target = Connection::open(path)
+        .with_context(|| format!("Failed to open backup file '{}'", path.display()))?;
+    // SQLCipher only copies between databases with the same key
+    apply_key(&target, key)?;
+    let backup =
+        Backup::new(conn, &mut target).with_context(|| "Failed to initialize database backup")?;
+    backup
+        .run_to_completion(
+            100,
+            Duration::from_millis(50),
+            Some(|progress: Progress| {
+                debug!(
+                    "Backup progress: {} of {} pages remaining",
+                    progress.remaining, progress.pagecount
+                );
+            }),
+        )
+        .with_context(|| format!("Failed to back up database to '{}'", path.display()))?;
+
+    info!("Database backed up to '{}'", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ErrorPhase;
+
+    const TENANT: &str = "default";
+
+    fn sent(sensor_id: u32, time: DateTime<Utc>) -> SentMeasurement {
+        SentMeasurement {
+            target: "default".to_string(),
+            sensor_id,
+            time,
+            temperature: Some(17.5),
+            idempotency_key: format!("lindas-{sensor_id}-{}", time.timestamp()),
+            gfroerli_id: None,
+        }
+    }
+
+    #[test]
+    fn test_duplicate_detection() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        // Initialize schema
+        migrate(&conn).unwrap();
+
+        let test_time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 30, 0).unwrap();
+        let sensor_id = 1;
+
+        // Initially, measurement should not be sent
+        assert!(!is_measurement_sent(&conn, TENANT, "default", sensor_id, &test_time).unwrap());
+
+        // Record the measurement as sent
+        record_measurement_sent(&conn, TENANT, &sent(sensor_id, test_time)).unwrap();
+
+        // Now it should be detected as already sent
+        assert!(is_measurement_sent(&conn, TENANT, "default", sensor_id, &test_time).unwrap());
+
+        // Different sensor should not be affected
+        assert!(!is_measurement_sent(&conn, TENANT, "default", 2, &test_time).unwrap());
+
+        // Different target should not be affected
+        assert!(!is_measurement_sent(&conn, TENANT, "staging", sensor_id, &test_time).unwrap());
+
+        // Different timestamp should not be affected
+        let different_time = Utc.with_ymd_and_hms(2025, 1, 15, 13, 30, 0).unwrap();
+        assert!(
+            !is_measurement_sent(&conn, TENANT, "default", sensor_id, &different_time).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_multiple_sensors_and_timestamps() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        // Initialize schema
+        migrate(&conn).unwrap();
+
+        let time1 = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let time2 = Utc.with_ymd_and_hms(2025, 1, 15, 13, 0, 0).unwrap();
+
+        // Record measurements for different sensors and times
+        record_measurement_sent(&conn, TENANT, &sent(1, time1)).unwrap();
+        record_measurement_sent(&conn, TENANT, &sent(1, time2)).unwrap();
+        record_measurement_sent(&conn, TENANT, &sent(2, time1)).unwrap();
+
+        // Verify all combinations
+        assert!(is_measurement_sent(&conn, TENANT, "default", 1, &time1).unwrap());
+        assert!(is_measurement_sent(&conn, TENANT, "default", 1, &time2).unwrap());
+        assert!(is_measurement_sent(&conn, TENANT, "default", 2, &time1).unwrap());
+        assert!(!is_measurement_sent(&conn, TENANT, "default", 2, &time2).unwrap());
+    }
+
+    #[test]
+    fn test_sent_measurements_range() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+
+        for hour in [10, 12, 11, 14] {
+            let time = Utc.with_ymd_and_hms(2025, 1, 15, hour, 0, 0).unwrap();
+            record_measurement_sent(&conn, TENANT, &sent(1, time)).unwrap();
+        }
+        let other_sensor = Utc.with_ymd_and_hms(2025, 1, 15, 11, 0, 0).unwrap();
+        record_measurement_sent(&conn, TENANT, &sent(2, other_sensor)).unwrap();
+
+        let measurements = sent_measurements(
+            &conn,
+            TENANT,
+            "default",
+            1,
+            &Utc.with_ymd_and_hms(2025, 1, 15, 11, 0, 0).unwrap(),
+            &Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            measurements,
+            vec![
+                sent(1, Utc.with_ymd_and_hms(2025, 1, 15, 11, 0, 0).unwrap()),
+                sent(1, Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap()),
+            ]
+        );
+
+        assert_eq!(
+            latest_sent_measurement(&conn, TENANT, "default", 1).unwrap(),
+            Some(sent(
+                1,
+                Utc.with_ymd_and_hms(2025, 1, 15, 14, 0, 0).unwrap()
+            ))
+        );
+        assert_eq!(
+            latest_sent_measurement(&conn, TENANT, "default", 3).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_record_gfroerli_id() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        let test_time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+
+        record_measurement_sent(
+            &conn,
+            TENANT,
+            &SentMeasurement {
+                gfroerli_id: Some(4711),
+                ..sent(1, test_time)
+            },
+        )
+        .unwrap();
+
+        let (key, id): (String, Option<i64>) = conn
+            .query_row(
+                "SELECT idempotency_key, gfroerli_measurement_id FROM sent_measurements",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(key, "lindas-1-1736942400");
+        assert_eq!(id, Some(4711));
+    }
+
+    #[test]
+    fn test_record_and_list_errors() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+
+        for (minute, phase) in [(0, ErrorPhase::Fetch), (10, ErrorPhase::Send)] {
+            record_error(
+                &conn,
+                TENANT,
+                &ErrorRecord {
+                    occurred_at: Utc.with_ymd_and_hms(2025, 1, 15, 12, minute, 0).unwrap(),
+                    station_id: 2104,
+                    sensor_id: Some(1),
+                    phase,
+                    http_status: Some(502),
+                    message: "HTTP 502 Bad Gateway".to_string(),
+                },
+            )
+            .unwrap();
+        }
+
+        let errors = recent_errors(&conn, TENANT, 10).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].phase, ErrorPhase::Send);
+        assert_eq!(errors[1].phase, ErrorPhase::Fetch);
+        assert_eq!(errors[0].http_status, Some(502));
+
+        assert_eq!(recent_errors(&conn, TENANT, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_station_state() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+
+        assert_eq!(station_state(&conn, TENANT, 2104).unwrap(), None);
+
+        let mut state = StationState {
+            station_id: 2104,
+            last_fetch_at: Utc.with_ymd_and_hms(2025, 1, 15, 12, 5, 0).unwrap(),
+            last_measurement_time: Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+            last_temperature: 17.5,
+        };
+        update_station_state(&conn, TENANT, &state).unwrap();
+        assert_eq!(
+            station_state(&conn, TENANT, 2104).unwrap(),
+            Some(state.clone())
+        );
+
+        state.last_temperature = 17.75;
+        update_station_state(&conn, TENANT, &state).unwrap();
+        assert_eq!(station_state(&conn, TENANT, 2104).unwrap(), Some(state));
+    }
+
+    #[tokio::test]
+    async fn test_danger_level() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+
+        assert_eq!(store.danger_level(2104).await.unwrap(), None);
+        store.update_danger_level(2104, 3, now).await.unwrap();
+        store.update_danger_level(2104, 2, now).await.unwrap();
+        assert_eq!(store.danger_level(2104).await.unwrap(), Some(2));
+        assert_eq!(store.danger_level(2176).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_station_mappings() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let mapping = |station_id, sensor_id| StationMapping {
+            station_id,
+            sensor_id,
+            updated_at: now,
+        };
+
+        store.set_station_mapping(&mapping(2176, 3)).await.unwrap();
+        store.set_station_mapping(&mapping(2104, 1)).await.unwrap();
+        store.set_station_mapping(&mapping(2104, 2)).await.unwrap();
+        assert_eq!(
+            store.station_mappings().await.unwrap(),
+            vec![mapping(2104, 2), mapping(2176, 3)]
+        );
+
+        assert!(store.remove_station_mapping(2104).await.unwrap());
+        assert!(!store.remove_station_mapping(2104).await.unwrap());
+        assert_eq!(
+            store.station_mappings().await.unwrap(),
+            vec![mapping(2176, 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_all_sent_measurements() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let mut webhook = sent(1, time);
+        webhook.target = "webhook:home_assistant".to_string();
+        webhook.temperature = None;
+        store.record_measurement_sent(&webhook).await.unwrap();
+        store.record_measurement_sent(&sent(2, time)).await.unwrap();
+        store
+            .record_measurement_sent(&sent(1, time + chrono::Duration::hours(1)))
+            .await
+            .unwrap();
+        store.record_measurement_sent(&sent(1, time)).await.unwrap();
+
+        let measurements = store.all_sent_measurements().await.unwrap();
+        let rows: Vec<_> = measurements
+            .iter()
+            .map(|m| (m.target.as_str(), m.sensor_id, m.time, m.temperature))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("default", 1, time, Some(17.5)),
+                ("default", 1, time + chrono::Duration::hours(1), Some(17.5)),
+                ("default", 2, time, Some(17.5)),
+                ("webhook:home_assistant", 1, time, None),
+            ]
+        );
+        assert!(measurements.iter().all(|m| m.sent_at > time));
+    }
+
+    #[tokio::test]
+    async fn test_create_views() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        for (hour, minute, temperature) in [(10, 0, 6.0), (10, 10, 7.0), (12, 0, 6.5)] {
+            let mut measurement = sent(
+                1,
+                Utc.with_ymd_and_hms(2025, 1, 15, hour, minute, 0).unwrap(),
+            );
+            measurement.temperature = Some(temperature);
+            store.record_measurement_sent(&measurement).await.unwrap();
+        }
+        let next_day = Utc.with_ymd_and_hms(2025, 1, 16, 0, 0, 0).unwrap();
+        store
+            .record_measurement_sent(&sent(1, next_day))
+            .await
+            .unwrap();
+        store.create_views(60).await.unwrap();
+        // Replacing the views
+        store.create_views(60).await.unwrap();
+
+        let conn = store.conn.lock().unwrap();
+        let days: Vec<(String, u32, f32, f32, f64)> = conn
+            .prepare(
+                "SELECT day, measurements, min_temperature, max_temperature, avg_temperature
+                 FROM daily_temperatures ORDER BY day",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            days,
+            vec![
+                ("2025-01-15".to_string(), 3, 6.0, 7.0, 6.5),
+                ("2025-01-16".to_string(), 1, 17.5, 17.5, 17.5),
+            ]
+        );
+
+        let gaps: Vec<(String, String, i64)> = conn
+            .prepare("SELECT gap_start, gap_end, gap_minutes FROM measurement_gaps")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            gaps,
+            vec![
+                (
+                    "2025-01-15 10:10:00".to_string(),
+                    "2025-01-15 12:00:00".to_string(),
+                    110
+                ),
+                (
+                    "2025-01-15 12:00:00".to_string(),
+                    "2025-01-16 00:00:00".to_string(),
+                    720
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quarantine() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let mut measurement = QuarantinedMeasurement {
+            id: 0,
+            station_id: 2104,
+            time: now,
+            temperature: 21.0,
+            reason: "Not confirmed".to_string(),
+            quarantined_at: now,
+            released_at: None,
+        };
+
+        let id = store.quarantine_measurement(&measurement).await.unwrap();
+        measurement.id = id;
+        assert_eq!(
+            store.quarantined_measurement(id).await.unwrap(),
+            Some(measurement.clone())
+        );
+        assert_eq!(store.quarantined_measurement(id + 1).await.unwrap(), None);
+
+        store
+            .release_quarantined_measurement(id, now)
+            .await
+            .unwrap();
+        assert!(
+            store
+                .quarantined_measurements(false)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let all = store.quarantined_measurements(true).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].released_at, Some(now));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_state() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let at = |day| Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap();
+
+        store
+            .record_backfill_window(2104, at(3), at(4), at(10))
+            .await
+            .unwrap();
+        store
+            .record_backfill_window(2104, at(1), at(2), at(10))
+            .await
+            .unwrap();
+        store
+            .record_backfill_window(2176, at(1), at(2), at(10))
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .completed_backfill_windows(2104, at(1), at(5))
+                .await
+                .unwrap(),
+            vec![(at(1), at(2)), (at(3), at(4))]
+        );
+        assert_eq!(
+            store
+                .completed_backfill_windows(2104, at(2), at(3))
+                .await
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_locks() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let later = Utc.with_ymd_and_hms(2025, 1, 15, 12, 10, 0).unwrap();
+
+        assert!(acquire_lock(&conn, TENANT, "leader", "a", now, later).unwrap());
+        // Renewing by the same holder succeeds, other holders have to wait
+        assert!(acquire_lock(&conn, TENANT, "leader", "a", now, later).unwrap());
+        assert!(!acquire_lock(&conn, TENANT, "leader", "b", now, later).unwrap());
+        assert!(acquire_lock(&conn, TENANT, "other", "b", now, later).unwrap());
+
+        // Expired locks can be taken over
+        let much_later = Utc.with_ymd_and_hms(2025, 1, 15, 12, 20, 0).unwrap();
+        assert!(acquire_lock(&conn, TENANT, "leader", "b", later, much_later).unwrap());
+        assert!(!acquire_lock(&conn, TENANT, "leader", "a", later, much_later).unwrap());
+
+        // Other tenants have their own locks
+        assert!(acquire_lock(&conn, "lakes", "leader", "a", later, much_later).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tenants() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("measurements.db");
+        let path = path.to_str().unwrap();
+        let open = |tenant: &str| {
+            let options = SqliteOptions {
+                tenant: tenant.to_string(),
+                ..SqliteOptions::default()
+            };
+            SqliteStore::open(path, &options).unwrap()
+        };
+        let rivers = open("rivers");
+        let lakes = open("lakes");
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+
+        rivers
+            .record_measurement_sent(&sent(1, time))
+            .await
+            .unwrap();
+        rivers
+            .record_error(&ErrorRecord {
+                occurred_at: time,
+                station_id: 2104,
+                sensor_id: Some(1),
+                phase: ErrorPhase::Send,
+                http_status: Some(500),
+                message: "Internal Server Error".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert!(
+            rivers
+                .is_measurement_sent("default", 1, time)
+                .await
+                .unwrap()
+        );
+        assert!(!lakes.is_measurement_sent("default", 1, time).await.unwrap());
+        assert_eq!(rivers.recent_errors(10).await.unwrap().len(), 1);
+        assert!(lakes.recent_errors(10).await.unwrap().is_empty());
+
+        // The same measurement can be recorded for another tenant
+        lakes.record_measurement_sent(&sent(1, time)).await.unwrap();
+        assert!(lakes.is_measurement_sent("default", 1, time).await.unwrap());
+    }
+
+    #[test]
+    fn test_prepare_database_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/lindas/measurements.db");
+        let path = path.to_str().unwrap();
+        let mode = |path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        assert!(prepare_database_file(path, false).is_err());
+        init_database(path, &SqliteOptions::default()).unwrap();
+        assert_eq!(mode(path), 0o600);
+
+        // Permissions of existing files are restricted
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        prepare_database_file(path, false).unwrap();
+        assert_eq!(mode(path), 0o600);
+    }
+
+    #[test]
+    fn test_backup() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        let test_time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        record_measurement_sent(&conn, TENANT, &sent(1, test_time)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("test_backup_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        backup_database(&conn, &path, None).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        // Backing up to an existing file is refused
+        assert!(backup_database(&conn, &path, None).is_err());
+
+        let restored = Connection::open(&path).unwrap();
+        assert!(is_measurement_sent(&restored, TENANT, "default", 1, &test_time).unwrap());
+        drop(restored);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[tokio::test]
+    async fn test_backup_encrypted() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let database = dir.path().join("measurements.db");
+        let options = SqliteOptions {
+            key: Some("database-key".into()),
+            ..SqliteOptions::default()
+        };
+        let store = SqliteStore::open(database.to_str().unwrap(), &options).unwrap();
+        let test_time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        store
+            .record_measurement_sent(&sent(1, test_time))
+            .await
+            .unwrap();
+
+        let path = dir.path().join("backup.db");
+        store.backup(&path).await.unwrap();
+
+        // The backup can't be read without the key
+        let restored = Connection::open(&path).unwrap();
+        assert!(
+            restored
+                .query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+                .is_err()
+        );
+        drop(restored);
+
+        let restored = Connection::open(&path).unwrap();
+        apply_key(&restored, options.key.as_ref()).unwrap();
+        assert!(is_measurement_sent(&restored, TENANT, "default", 1, &test_time).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_open_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("measurements.db");
+        let path_str = path.to_str().unwrap();
+        let first = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let second = Utc.with_ymd_and_hms(2025, 1, 15, 12, 10, 0).unwrap();
+
+        // A missing database is not created
+        let store = SqliteStore::open_snapshot(path_str, &SqliteOptions::default()).unwrap();
+        store
+            .record_measurement_sent(&sent(1, first))
+            .await
+            .unwrap();
+        assert!(!path.exists());
+
+        let store = SqliteStore::open(path_str, &SqliteOptions::default()).unwrap();
+        store
+            .record_measurement_sent(&sent(1, first))
+            .await
+            .unwrap();
+        drop(store);
+
+        let snapshot = SqliteStore::open_snapshot(path_str, &SqliteOptions::default()).unwrap();
+        assert!(
+            snapshot
+                .is_measurement_sent("default", 1, first)
+                .await
+                .unwrap()
+        );
+        snapshot
+            .record_measurement_sent(&sent(1, second))
+            .await
+            .unwrap();
+        assert!(
+            snapshot
+                .is_measurement_sent("default", 1, second)
+                .await
+                .unwrap()
+        );
+
+        let store = SqliteStore::open(path_str, &SqliteOptions::default()).unwrap();
+        assert!(
+            !store
+                .is_measurement_sent("default", 1, second)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("measurements.db");
+        let path_str = path.to_str().unwrap();
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+
+        // A missing database is not created
+        assert!(SqliteStore::open_read_only(path_str, &SqliteOptions::default()).is_err());
+        assert!(!path.exists());
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        drop(conn);
+        // Migrations aren't applied read-only
+        assert!(SqliteStore::open_read_only(path_str, &SqliteOptions::default()).is_err());
+
+        let store = SqliteStore::open(path_str, &SqliteOptions::default()).unwrap();
+        store.record_measurement_sent(&sent(1, time)).await.unwrap();
+        drop(store);
+
+        let store = SqliteStore::open_read_only(path_str, &SqliteOptions::default()).unwrap();
+        assert!(store.is_measurement_sent("default", 1, time).await.unwrap());
+        assert!(store.update_danger_level(2104, 3, time).await.is_err());
+    }
+
+    #[test]
+    fn test_integrity_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("measurements.db");
+        let path_str = path.to_str().unwrap();
+        let options = SqliteOptions {
+            wal: false,
+            ..SqliteOptions::default()
+        };
+
+        let conn = init_database(path_str, &options).unwrap();
+        let start = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        for minute in 0..2000 {
+            let time = start + chrono::Duration::minutes(minute);
+            record_measurement_sent(&conn, TENANT, &sent(1, time)).unwrap();
+        }
+        check_integrity(&conn, "integrity_check").unwrap();
+        drop(conn);
+
+        // Overwrite a page in the middle of the file
+        let mut bytes = std::fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle..middle + 4096].fill(0xa5);
+        std::fs::write(&path, bytes).unwrap();
+        let error = init_database(path_str, &options).unwrap_err();
+        assert!(format!("{error:#}").contains("corrupted"), "{error:#}");
+    }
+
+    #[test]
+    fn test_migrate_existing_database() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        // Database created before migrations were introduced
+        conn.execute(MIGRATIONS[0], []).unwrap();
+        let test_time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        conn.execute(
+            "INSERT INTO sent_measurements (sensor_id, measurement_timestamp, sent_at) VALUES (?, ?, ?)",
+            params![1, test_time.timestamp(), test_time.timestamp()],
+        )
+        .unwrap();
+
+        migrate(&conn).unwrap();
+        migrate(&conn).unwrap();
+
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        assert!(is_measurement_sent(&conn, TENANT, "default", 1, &test_time).unwrap());
+    }
+
+    #[test]
+    fn test_wal_mode_enabled() {
+        let path = std::env::temp_dir().join(format!("test_wal_{}.db", std::process::id()));
+        let path_str = path.to_str().unwrap();
+
+        let conn = init_database(path_str, &SqliteOptions::default()).unwrap();
+        let mode: String = conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+        drop(conn);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path_str}{suffix}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        let store = SqliteStore::new(conn, TENANT);
+
+        let test_time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 30, 0).unwrap();
+
+        assert!(
+            !store
+                .is_measurement_sent("default", 1, test_time)
+                .await
+                .unwrap()
+        );
+        store
+            .record_measurement_sent(&sent(1, test_time))
+            .await
+            .unwrap();
+        assert!(
+            store
+                .is_measurement_sent("default", 1, test_time)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .is_measurement_sent("default", 2, test_time)
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Table output of the CLI subcommands
+
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
+/// Color of a table cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// Problems, e.g. values that disagree
+    Red,
+    /// Values that are as expected
+    Green,
+    /// Values that need attention, e.g. stale measurements
+    Yellow,
+}
+
+impl Color {
+    /// ANSI SGR code of the color
+    fn code(self) -> &'static str {
+        match self {
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+        }
+    }
+}
+
+/// Colorizes table cells, or leaves them as plain text
+///
+/// Cells are padded before they are painted, so that the escape codes don't
+/// count towards the column width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Painter {
+    color: bool,
+}
+
+impl Painter {
+    /// Colorize if stdout is a terminal, plain text when piped or if the
+    /// `NO_COLOR` environment variable is set
+    pub fn stdout() -> Self {
+        Self {
+            color: io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        }
+    }
+
+    /// Never colorize
+    pub fn plain() -> Self {
+        Self { color: false }
+    }
+
+    /// Paint a (padded) cell in a color, if any
+    pub fn paint(&self, cell: &str, color: Option<Color>) -> String {
+        match color {
+            Some(color) if self.color => format!("\x1b[{}m{cell}\x1b[0m", color.code()),
+            _ => cell.to_string(),
+        }
+    }
+}
+
+/// Format a temperature aligned on the decimal point
+pub fn format_temperature(temperature: f32) -> String {
+    format!("{temperature:>7.3}")
+}
+
+/// Format the change of a temperature since the previous value, e.g.
+/// " (+0.200)", or an empty string if there is no previous value
+pub fn format_delta(delta: Option<f32>) -> String {
+    delta
+        .map(|delta| format!(" ({delta:+.3})"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        let cell = format!("{:<8}", "ok");
+        assert_eq!(
+            Painter::plain().paint(&cell, Some(Color::Green)),
+            "ok      "
+        );
+
+        let painter = Painter { color: true };
+        assert_eq!(
+            painter.paint(&cell, Some(Color::Green)),
+            "\x1b[32mok      \x1b[0m"
+        );
+        assert_eq!(painter.paint(&cell, None), "ok      ");
+    }
+
+    #[test]
+    fn test_format_temperature() {
+        assert_eq!(format_temperature(6.4), "  6.400");
+        assert_eq!(format_temperature(17.25), " 17.250");
+        assert_eq!(format_temperature(-0.5), " -0.500");
+    }
+
+    #[test]
+    fn test_format_delta() {
+        assert_eq!(format_delta(Some(0.2)), " (+0.200)");
+        assert_eq!(format_delta(Some(-1.25)), " (-1.250)");
+        assert_eq!(format_delta(None), "");
+    }
+}
@@ -0,0 +1,172 @@
+//! Typed errors at the boundaries of the library
+//!
+//! The modules use `anyhow` internally and convert their errors into a
+//! [`FetcherError`] where they are called by other modules or library
+//! consumers, so that these can tell the kind of failure apart without
+//! matching on messages. The details stay available in the source chain.
+
+use std::any::Any;
+
+use reqwest::StatusCode;
+use thiserror::Error;
+
+use crate::http::ErrorClass;
+
+/// Error of the fetcher, by the part of the pipeline that failed
+#[derive(Debug, Error)]
+pub enum FetcherError {
+    /// The configuration couldn't be loaded or is invalid
+    #[error("Failed to load configuration")]
+    Config(#[source] anyhow::Error),
+    /// Observations of a station couldn't be fetched from LINDAS
+    #[error("Failed to fetch observations of station {station}")]
+    Sparql {
+        station: u32,
+        /// Status code of the SPARQL endpoint, if it responded
+        status: Option<StatusCode>,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// A request to the Gfrörli API for a sensor failed
+    #[error("Gfrörli API request failed for sensor {sensor}")]
+    Api {
+        sensor: u32,
+        /// Status code of the API, if it responded
+        status: Option<StatusCode>,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The database couldn't be opened
+    #[error("Failed to open the database")]
+    Storage(#[source] anyhow::Error),
+    /// A SPARQL response couldn't be parsed
+    #[error("Failed to parse SPARQL JSON response for station {station}")]
+    Parse {
+        station: u32,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Processing a station panicked, e.g. because of a bug in the parser
+    #[error("Processing of station {station} panicked: {message}")]
+    Panic { station: u32, message: String },
+}
+
+impl FetcherError {
+    /// Error of a station whose processing panicked with `payload`
+    pub fn panic(station: u32, payload: &(dyn Any + Send)) -> Self {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".to_string());
+        FetcherError::Panic { station, message }
+    }
+
+    /// Whether the error or one of its causes is a panic
+    pub fn is_panic(error: &anyhow::Error) -> bool {
+        error
+            .chain()
+            .any(|cause| matches!(cause.downcast_ref(), Some(FetcherError::Panic { .. })))
+    }
+
+    /// Status code of the failed HTTP request, if any
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            FetcherError::Sparql { status, .. } | FetcherError::Api { status, .. } => *status,
+            _ => None,
+        }
+    }
+
+    /// Whether the operation may succeed when it is retried
+    ///
+    /// Configuration errors are permanent, requests are classified by their
+    /// status code or request error, all other errors are retryable.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            FetcherError::Config(_) => ErrorClass::Permanent,
+            FetcherError::Sparql {
+                status: Some(status),
+                ..
+            }
+            | FetcherError::Api {
+                status: Some(status),
+                ..
+            } => ErrorClass::from_status(*status),
+            FetcherError::Sparql { source, .. } | FetcherError::Api { source, .. } => {
+                ErrorClass::of(source)
+            }
+            FetcherError::Storage(_) | FetcherError::Parse { .. } | FetcherError::Panic { .. } => {
+                ErrorClass::Retryable
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Context, anyhow};
+
+    use super::*;
+    use crate::http::{HttpStatusError, error_status};
+
+    #[test]
+    fn test_error_chain() {
+        let source = Err::<(), _>(HttpStatusError {
+            status: StatusCode::UNAUTHORIZED,
+            body: "Invalid API key".to_string(),
+        })
+        .context("Failed to send measurement")
+        .unwrap_err();
+        let error = FetcherError::Api {
+            sensor: 1,
+            status: error_status(&source),
+            source,
+        };
+        assert_eq!(error.status(), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(error.class(), ErrorClass::Permanent);
+
+        // The details are kept when the binary wraps the error with anyhow
+        let error = anyhow::Error::from(error).context("Failed to process station 2104");
+        assert_eq!(
+            format!("{error:#}"),
+            "Failed to process station 2104: Gfrörli API request failed for sensor 1: \
+            Failed to send measurement: HTTP 401 Unauthorized - Invalid API key"
+        );
+        assert_eq!(error_status(&error), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(
+            FetcherError::Config(anyhow!("Missing API key")).class(),
+            ErrorClass::Permanent
+        );
+        let error = FetcherError::Sparql {
+            station: 2104,
+            status: Some(StatusCode::BAD_GATEWAY),
+            source: anyhow!("upstream down"),
+        };
+        assert_eq!(error.class(), ErrorClass::Retryable);
+        let error = FetcherError::Parse {
+            station: 2104,
+            source: anyhow!("expected value"),
+        };
+        assert_eq!(error.class(), ErrorClass::Retryable);
+    }
+
+    #[test]
+    fn test_panic() {
+        let payload = std::panic::catch_unwind(|| panic!("index out of bounds")).unwrap_err();
+        let error = FetcherError::panic(2104, payload.as_ref());
+        assert_eq!(
+            error.to_string(),
+            "Processing of station 2104 panicked: index out of bounds"
+        );
+        assert_eq!(error.class(), ErrorClass::Retryable);
+
+        let error = anyhow::Error::from(error).context("Failed to process station 2104");
+        assert!(FetcherError::is_panic(&error));
+        assert!(!FetcherError::is_panic(&anyhow!("timeout")));
+    }
+}
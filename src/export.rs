@@ -0,0 +1,809 @@
+//! Export of the measurement history as a Parquet file
+//!
+//! Writes a single row group with one gzip compressed, PLAIN encoded data page
+//! per column, which every Parquet reader (pandas, DuckDB, Spark) supports.
+//! The file metadata is encoded with the Thrift compact protocol by hand, as
+//! the export only needs a small, fixed subset of the format.
+
+use std::{io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use flate2::{Compression, write::GzEncoder};
+
+use crate::database::{MeasurementStore, RecordedMeasurement};
+
+/// Magic bytes at the start and end of Parquet files
+const MAGIC: &[u8] = b"PAR1";
+
+/// Parquet physical types
+const INT64: i32 = 2;
+const FLOAT: i32 = 4;
+const BYTE_ARRAY: i32 = 6;
+
+/// Parquet converted types (for readers without logical type support)
+const UTF8: i32 = 0;
+const TIMESTAMP_MILLIS: i32 = 9;
+
+/// Parquet encodings
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+/// Parquet compression codec
+const GZIP: i32 = 2;
+
+/// Parquet field repetition types
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+
+/// Parquet page type
+const DATA_PAGE: i32 = 0;
+
+/// Thrift compact protocol types
+const TRUE: u8 = 1;
+const FALSE: u8 = 2;
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+/// Writes all sent measurements as a Parquet file, returns the number of
+/// exported measurements
+pub async fn export_parquet(store: &dyn MeasurementStore, path: &Path) -> Result<usize> {
+    let measurements = store.all_sent_measurements().await?;
+    let file = write_parquet(&measurements)?;
+    tokio::fs::write(path, file)
+        .await
+        .with_context(|| format!("Failed to write '{}'", path.display()))?;
+    Ok(measurements.len())
+}
+
+/// Kind of an exported column
+#[derive(Debug, Clone, Copy)]
+enum ColumnKind {
+    String,
+    Int64,
+    Float,
+    /// Milliseconds since the Unix epoch in UTC
+    Timestamp,
+}
+
+impl ColumnKind {
+    fn physical_type(self) -> i32 {
+        match self {
+            Self::String => BYTE_ARRAY,
+            Self::Int64 | Self::Timestamp => INT64,
+            Self::Float => FLOAT,
+        }
+    }
+}
+
+/// Column with its PLAIN encoded values
+struct Column {
+    name: &'static str,
+    kind: ColumnKind,
+    /// Whether each row has a value, for optional columns
+    present: Option<Vec<bool>>,
+    values: Vec<u8>,
+}
+
+impl Column {
+    fn new(
+        name: &'static str,
+        kind: ColumnKind,
+        measurements: &[RecordedMeasurement],
+        encode: impl Fn(&RecordedMeasurement, &mut Vec<u8>),
+    ) -> Self {
+        let mut values = Vec::new();
+        for measurement in measurements {
+            encode(measurement, &mut values);
+        }
+        Self {
+            name,
+            kind,
+            present: None,
+            values,
+        }
+    }
+
+    fn optional(
+        name: &'static str,
+        kind: ColumnKind,
+        measurements: &[RecordedMeasurement],
+        encode: impl Fn(&RecordedMeasurement, &mut Vec<u8>) -> bool,
+    ) -> Self {
+        let mut values = Vec::new();
+        let present = measurements
+            .iter()
+            .map(|measurement| encode(measurement, &mut values))
+            .collect();
+        Self {
+            name,
+            kind,
+            present: Some(present),
+            values,
+        }
+    }
+
+    /// Page content: the definition levels of optional columns, followed by
+    /// the values
+    fn page(&self) -> Vec<u8> {
+        let mut page = Vec::new();
+        if let Some(present) = &self.present {
+            let levels = definition_levels(present);
+            page.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+            page.extend_from_slice(&levels);
+        }
+        page.extend_from_slice(&self.values);
+        page
+    }
+}
+
+/// Encode the definition levels of an optional column (bit width 1) as runs
+/// of the RLE/bit-packing hybrid encoding
+fn definition_levels(present: &[bool]) -> Vec<u8> {
+    let mut levels = Vec::new();
+    let mut rows = present.iter().peekable();
+    while let Some(&value) = rows.next() {
+        let mut run = 1;
+        while rows.next_if_eq(&&value).is_some() {
+            run += 1;
+        }
+        write_varint(&mut levels, run << 1);
+        levels.push(u8::from(value));
+    }
+    levels
+}
+
+/// Encode measurements as a Parquet file
+fn write_parquet(measurements: &[RecordedMeasurement]) -> Result<Vec<u8>> {
+    let columns = [
+        Column::new("target", ColumnKind::String, measurements, |m, out| {
+            out.extend_from_slice(&(m.target.len() as u32).to_le_bytes());
+            out.extend_from_slice(m.target.as_bytes());
+        }),
+        Column::new("sensor_id", ColumnKind::Int64, measurements, |m, out| {
+            out.extend_from_slice(&i64::from(m.sensor_id).to_le_bytes());
+        }),
+        Column::new(
+            "timestamp",
+            ColumnKind::Timestamp,
+            measurements,
+            |m, out| {
+                out.extend_from_slice(&m.time.timestamp_millis().to_le_bytes());
+            },
+        ),
+        Column::optional("temperature", ColumnKind::Float, measurements, |m, out| {
+            m.temperature
+                .map(|temperature| out.extend_from_slice(&temperature.to_le_bytes()))
+                .is_some()
+        }),
+        Column::new("sent_at", ColumnKind::Timestamp, measurements, |m, out| {
+            out.extend_from_slice(&m.sent_at.timestamp_millis().to_le_bytes());
+        }),
+    ];
+    let num_rows = measurements.len() as i64;
+
+    let mut file = MAGIC.to_vec();
+    let mut chunks = Vec::new();
+    if !measurements.is_empty() {
+        for column in &columns {
+            let offset = file.len() as i64;
+            let page = column.page();
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&page)?;
+            let compressed = encoder.finish()?;
+
+            let mut header = CompactWriter::default();
+            header.struct_begin();
+            header.i32(1, DATA_PAGE);
+            header.i32(2, page.len().try_into()?);
+            header.i32(3, compressed.len().try_into()?);
+            header.struct_field(5);
+            header.i32(1, num_rows.try_into()?);
+            header.i32(2, PLAIN);
+            header.i32(3, RLE);
+            header.i32(4, RLE);
+            header.struct_end();
+            header.struct_end();
+
+            let header = header.finish();
+            file.extend_from_slice(&header);
+            file.extend_from_slice(&compressed);
+            chunks.push(ChunkInfo {
+                offset,
+                uncompressed_size: (header.len() + page.len()) as i64,
+                compressed_size: (header.len() + compressed.len()) as i64,
+            });
+        }
+    }
+
+    let metadata = file_metadata(&columns, &chunks, num_rows);
+    file.extend_from_slice(&metadata);
+    file.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+    file.extend_from_slice(MAGIC);
+    Ok(file)
+}
+
+/// Position and size of a written column chunk
+struct ChunkInfo {
+    offset: i64,
+    uncompressed_size: i64,
+    compressed_size: i64,
+}
+
+/// Encode the `FileMetaData` footer
+fn file_metadata(columns: &[Column], chunks: &[ChunkInfo], num_rows: i64) -> Vec<u8> {
+    let mut meta = CompactWriter::default();
+    meta.struct_begin();
+    meta.i32(1, 1);
+
+    // Schema: the root followed by the columns
+    meta.list_begin(2, STRUCT, columns.len() + 1);
+    meta.struct_begin();
+    meta.binary(4, b"schema");
+    meta.i32(5, columns.len() as i32);
+    meta.struct_end();
+    for column in columns {
+        meta.struct_begin();
+        meta.i32(1, column.kind.physical_type());
+        let repetition = if column.present.is_some() {
+            OPTIONAL
+        } else {
+            REQUIRED
+        };
+        meta.i32(3, repetition);
+        meta.binary(4, column.name.as_bytes());
+        match column.kind {
+            ColumnKind::String => {
+                meta.i32(6, UTF8);
+                // LogicalType STRING
+                meta.struct_field(10);
+                meta.struct_field(1);
+                meta.struct_end();
+                meta.struct_end();
+            }
+            ColumnKind::Timestamp => {
+                meta.i32(6, TIMESTAMP_MILLIS);
+                // LogicalType TIMESTAMP(isAdjustedToUTC = true, unit = MILLIS)
+                meta.struct_field(10);
+                meta.struct_field(8);
+                meta.bool(1, true);
+                meta.struct_field(2);
+                meta.struct_field(1);
+                meta.struct_end();
+                meta.struct_end();
+                meta.struct_end();
+                meta.struct_end();
+            }
+            ColumnKind::Int64 | ColumnKind::Float => {}
+        }
+        meta.struct_end();
+    }
+
+    meta.i64(3, num_rows);
+
+    // Row groups: a single one with all rows, unless there are none
+    meta.list_begin(4, STRUCT, usize::from(!chunks.is_empty()));
+    if !chunks.is_empty() {
+        meta.struct_begin();
+        meta.list_begin(1, STRUCT, columns.len());
+        for (column, chunk) in columns.iter().zip(chunks) {
+            meta.struct_begin();
+            meta.i64(2, chunk.offset);
+            meta.struct_field(3);
+            meta.i32(1, column.kind.physical_type());
+            meta.list_begin(2, I32, 2);
+            meta.list_i32(PLAIN);
+            meta.list_i32(RLE);
+            meta.list_begin(3, BINARY, 1);
+            meta.list_binary(column.name.as_bytes());
+            meta.i32(4, GZIP);
+            meta.i64(5, num_rows);
+            meta.i64(6, chunk.uncompressed_size);
+            meta.i64(7, chunk.compressed_size);
+            meta.i64(9, chunk.offset);
+            meta.struct_end();
+            meta.struct_end();
+        }
+        meta.i64(2, chunks.iter().map(|chunk| chunk.uncompressed_size).sum());
+        meta.i64(3, num_rows);
+        meta.struct_end();
+    }
+
+    meta.binary(
+        6,
+        format!(
+            "lindas-hydrodata-fetcher version {}",
+            env!("CARGO_PKG_VERSION")
+        )
+        .as_bytes(),
+    );
+    meta.struct_end();
+    meta.finish()
+}
+
+/// Writer of the Thrift compact protocol the Parquet metadata is encoded in
+#[derive(Default)]
+struct CompactWriter {
+    buffer: Vec<u8>,
+    /// ID of the last written field of each open struct
+    last_field: Vec<i16>,
+}
+
+impl CompactWriter {
+    fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    /// Start a struct that is a list element or the top-level struct
+    fn struct_begin(&mut self) {
+        self.last_field.push(0);
+    }
+
+    fn struct_end(&mut self) {
+        self.buffer.push(0);
+        self.last_field.pop();
+    }
+
+    /// Start a struct field, end it with `struct_end`
+    fn struct_field(&mut self, id: i16) {
+        self.field_header(id, STRUCT);
+        self.struct_begin();
+    }
+
+    fn field_header(&mut self, id: i16, kind: u8) {
+        let last = self
+            .last_field
+            .last_mut()
+            .expect("field outside of a struct");
+        let delta = id - *last;
+        if (1..=15).contains(&delta) {
+            self.buffer.push(((delta as u8) << 4) | kind);
+        } else {
+            self.buffer.push(kind);
+            write_varint(&mut self.buffer, zigzag(i64::from(id)));
+        }
+        *last = id;
+    }
+
+    fn bool(&mut self, id: i16, value: bool) {
+        self.field_header(id, if value { TRUE } else { FALSE });
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field_header(id, I32);
+        write_varint(&mut self.buffer, zigzag(i64::from(value)));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field_header(id, I64);
+        write_varint(&mut self.buffer, zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field_header(id, BINARY);
+        self.list_binary(value);
+    }
+
+    /// Start a list field, followed by its `len` elements
+    fn list_begin(&mut self, id: i16, element: u8, len: usize) {
+        self.field_header(id, LIST);
+        if len < 15 {
+            self.buffer.push(((len as u8) << 4) | element);
+        } else {
+            self.buffer.push(0xf0 | element);
+            write_varint(&mut self.buffer, len as u64);
+        }
+    }
+
+    fn list_i32(&mut self, value: i32) {
+        write_varint(&mut self.buffer, zigzag(i64::from(value)));
+    }
+
+    fn list_binary(&mut self, value: &[u8]) {
+        write_varint(&mut self.buffer, value.len() as u64);
+        self.buffer.extend_from_slice(value);
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Write an unsigned LEB128 varint
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, process::Command};
+
+    use chrono::{TimeZone, Utc};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    /// Reads a Parquet file with pyarrow and prints its schema and rows as
+    /// JSON, with the timestamps in milliseconds
+    const PYARROW_READER: &str = r#"
+import json, sys
+import pyarrow as pa, pyarrow.parquet as pq
+table = pq.read_table(sys.argv[1])
+schema = [f"{field.name}: {field.type}" for field in table.schema]
+for name in ("timestamp", "sent_at"):
+    index = table.schema.get_field_index(name)
+    table = table.set_column(index, name, table.column(name).cast(pa.int64()))
+print(json.dumps({"schema": schema, "rows": table.to_pylist()}))
+"#;
+
+    #[test]
+    fn test_compact_writer() {
+        let mut writer = CompactWriter::default();
+        writer.struct_begin();
+        writer.i32(1, -1);
+        writer.binary(4, b"ab");
+        writer.i64(20, 300);
+        writer.bool(21, true);
+        writer.list_begin(22, I32, 2);
+        writer.list_i32(0);
+        writer.list_i32(3);
+        writer.struct_end();
+        assert_eq!(
+            writer.finish(),
+            [
+                0x15, 0x01, // field 1, i32 -1
+                0x38, 0x02, b'a', b'b', // field 4 (delta 3), binary
+                0x06, 0x28, 0xd8, 0x04, // field 20 (delta 16), i64 300
+                0x11, // field 21, true
+                0x19, 0x25, 0x00, 0x06, // field 22, list of two i32
+                0x00, // end of struct
+            ]
+        );
+    }
+
+    #[test]
+    fn test_definition_levels() {
+        assert_eq!(
+            definition_levels(&[true, true, true, false, true]),
+            [0x06, 0x01, 0x02, 0x00, 0x02, 0x01]
+        );
+    }
+
+    /// Value of the Thrift compact protocol, decoded without a schema
+    #[derive(Debug, Clone, PartialEq)]
+    enum Thrift {
+        Bool(bool),
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Thrift>),
+        Struct(Vec<(i16, Thrift)>),
+    }
+
+    impl Thrift {
+        fn field(&self, id: i16) -> Option<&Thrift> {
+            let Thrift::Struct(fields) = self else {
+                panic!("not a struct: {self:?}");
+            };
+            fields
+                .iter()
+                .find(|(field, _)| *field == id)
+                .map(|(_, v)| v)
+        }
+
+        fn int(&self, id: i16) -> i64 {
+            match self.field(id) {
+                Some(Thrift::Int(value)) => *value,
+                other => panic!("field {id} is not an integer: {other:?}"),
+            }
+        }
+
+        fn string(&self, id: i16) -> String {
+            match self.field(id) {
+                Some(Thrift::Binary(value)) => String::from_utf8(value.clone()).unwrap(),
+                other => panic!("field {id} is not binary: {other:?}"),
+            }
+        }
+
+        fn list(&self, id: i16) -> &[Thrift] {
+            match self.field(id) {
+                Some(Thrift::List(values)) => values,
+                other => panic!("field {id} is not a list: {other:?}"),
+            }
+        }
+    }
+
+    /// Reader of the Thrift compact protocol, following the specification
+    /// rather than the writer
+    struct CompactReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl CompactReader<'_> {
+        fn byte(&mut self) -> u8 {
+            self.position += 1;
+            self.data[self.position - 1]
+        }
+
+        fn varint(&mut self) -> u64 {
+            let mut value = 0;
+            for shift in (0..64).step_by(7) {
+                let byte = self.byte();
+                value |= u64::from(byte & 0x7f) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            value
+        }
+
+        fn zigzag(&mut self) -> i64 {
+            let value = self.varint();
+            (value >> 1) as i64 ^ -((value & 1) as i64)
+        }
+
+        fn value(&mut self, kind: u8) -> Thrift {
+            match kind {
+                TRUE => Thrift::Bool(true),
+                FALSE => Thrift::Bool(false),
+                3..=6 => Thrift::Int(self.zigzag()),
+                BINARY => {
+                    let len = self.varint() as usize;
+                    self.position += len;
+                    Thrift::Binary(self.data[self.position - len..self.position].to_vec())
+                }
+                LIST => {
+                    let header = self.byte();
+                    let len = match header >> 4 {
+                        15 => self.varint() as usize,
+                        len => usize::from(len),
+                    };
+                    let element = header & 0x0f;
+                    Thrift::List((0..len).map(|_| self.value(element)).collect())
+                }
+                STRUCT => {
+                    let mut fields = Vec::new();
+                    let mut last = 0;
+                    loop {
+                        let header = self.byte();
+                        if header == 0 {
+                            return Thrift::Struct(fields);
+                        }
+                        let id = match header >> 4 {
+                            0 => self.zigzag() as i16,
+                            delta => last + i16::from(delta),
+                        };
+                        last = id;
+                        fields.push((id, self.value(header & 0x0f)));
+                    }
+                }
+                other => panic!("unsupported Thrift type {other}"),
+            }
+        }
+    }
+
+    /// Decode `count` values of the RLE/bit-packing hybrid encoding with bit
+    /// width 1
+    fn decode_levels(data: &[u8], count: usize) -> Vec<bool> {
+        let mut reader = CompactReader { data, position: 0 };
+        let mut levels = Vec::new();
+        while levels.len() < count {
+            let header = reader.varint();
+            if header & 1 == 0 {
+                let value = reader.byte() != 0;
+                levels.extend(std::iter::repeat_n(value, (header >> 1) as usize));
+            } else {
+                for _ in 0..header >> 1 {
+                    let byte = reader.byte();
+                    levels.extend((0..8).map(|bit| byte >> bit & 1 == 1));
+                }
+            }
+        }
+        levels.truncate(count);
+        levels
+    }
+
+    /// Column read back from a Parquet file: its schema element and values
+    struct ReadColumn {
+        schema: Thrift,
+        values: Vec<Option<Vec<u8>>>,
+    }
+
+    /// Read all columns of a Parquet file written by [`write_parquet`]
+    fn read_parquet(file: &[u8]) -> (i64, Vec<ReadColumn>) {
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[file.len() - 4..], MAGIC);
+        let footer = &file[file.len() - 8..file.len() - 4];
+        let metadata_len = u32::from_le_bytes(footer.try_into().unwrap()) as usize;
+        let metadata = CompactReader {
+            data: &file[file.len() - 8 - metadata_len..file.len() - 8],
+            position: 0,
+        }
+        .value(STRUCT);
+
+        let num_rows = metadata.int(3);
+        let schema = metadata.list(2);
+        assert_eq!(schema[0].int(5) as usize, schema.len() - 1);
+        let row_groups = metadata.list(4);
+        let Some(row_group) = row_groups.first() else {
+            return (num_rows, Vec::new());
+        };
+        assert_eq!(row_groups.len(), 1);
+        assert_eq!(row_group.int(3), num_rows);
+
+        let columns = schema[1..]
+            .iter()
+            .zip(row_group.list(1))
+            .map(|(schema, chunk)| {
+                let meta = chunk.field(3).unwrap();
+                assert_eq!(meta.int(1), schema.int(1));
+                assert_eq!(
+                    meta.list(3),
+                    [Thrift::Binary(schema.string(4).into_bytes())]
+                );
+                assert_eq!(meta.int(4), i64::from(GZIP));
+                assert_eq!(meta.int(5), num_rows);
+
+                let mut reader = CompactReader {
+                    data: file,
+                    position: meta.int(9) as usize,
+                };
+                let header = reader.value(STRUCT);
+                assert_eq!(header.int(1), i64::from(DATA_PAGE));
+                let compressed = header.int(3) as usize;
+                assert_eq!(
+                    (reader.position + compressed) as i64 - meta.int(9),
+                    meta.int(7)
+                );
+                let mut page = Vec::new();
+                flate2::read::GzDecoder::new(&file[reader.position..][..compressed])
+                    .read_to_end(&mut page)
+                    .unwrap();
+                assert_eq!(page.len() as i64, header.int(2));
+
+                let data_page = header.field(5).unwrap();
+                let rows = data_page.int(1) as usize;
+                assert_eq!(data_page.int(2), i64::from(PLAIN));
+                let (present, mut data) = if schema.int(3) == i64::from(OPTIONAL) {
+                    let len = u32::from_le_bytes(page[..4].try_into().unwrap()) as usize;
+                    (decode_levels(&page[4..4 + len], rows), &page[4 + len..])
+                } else {
+                    (vec![true; rows], &page[..])
+                };
+                let values = present
+                    .into_iter()
+                    .map(|present| {
+                        present.then(|| {
+                            let len = match schema.int(1) as i32 {
+                                BYTE_ARRAY => {
+                                    let len = u32::from_le_bytes(data[..4].try_into().unwrap());
+                                    data = &data[4..];
+                                    len as usize
+                                }
+                                INT64 => 8,
+                                FLOAT => 4,
+                                other => panic!("unexpected physical type {other}"),
+                            };
+                            let (value, rest) = data.split_at(len);
+                            data = rest;
+                            value.to_vec()
+                        })
+                    })
+                    .collect();
+                assert!(data.is_empty(), "trailing values in column");
+                ReadColumn {
+                    schema: schema.clone(),
+                    values,
+                }
+            })
+            .collect();
+        (num_rows, columns)
+    }
+
+    /// Measurements of two sensors and targets, three without temperature
+    fn measurements() -> Vec<RecordedMeasurement> {
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        (0..20)
+            .map(|i| RecordedMeasurement {
+                target: if i < 15 { "default" } else { "staging" }.to_string(),
+                sensor_id: 1 + i / 10,
+                time: time + chrono::Duration::minutes(i.into()),
+                temperature: (i % 7 != 3).then(|| 6.5 + i as f32 / 10.0),
+                sent_at: time + chrono::Duration::minutes(i.into()) + chrono::Duration::seconds(5),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        let measurements = measurements();
+        let file = write_parquet(&measurements).unwrap();
+
+        let (num_rows, columns) = read_parquet(&file);
+        assert_eq!(num_rows, 20);
+        let names: Vec<_> = columns.iter().map(|c| c.schema.string(4)).collect();
+        assert_eq!(
+            names,
+            ["target", "sensor_id", "timestamp", "temperature", "sent_at"]
+        );
+        // Logical types: STRING, and TIMESTAMP in milliseconds adjusted to UTC
+        assert!(columns[0].schema.field(10).unwrap().field(1).is_some());
+        for column in [&columns[2], &columns[4]] {
+            let timestamp = column.schema.field(10).unwrap().field(8).unwrap();
+            assert_eq!(timestamp.field(1), Some(&Thrift::Bool(true)));
+            assert!(timestamp.field(2).unwrap().field(1).is_some());
+        }
+        assert_eq!(columns[3].schema.int(3), i64::from(OPTIONAL));
+
+        for (i, measurement) in measurements.iter().enumerate() {
+            let value = |column: usize| columns[column].values[i].clone();
+            let int64 =
+                |column: usize| i64::from_le_bytes(value(column).unwrap().try_into().unwrap());
+            assert_eq!(value(0).unwrap(), measurement.target.as_bytes());
+            assert_eq!(int64(1), i64::from(measurement.sensor_id));
+            assert_eq!(int64(2), measurement.time.timestamp_millis());
+            assert_eq!(
+                value(3).map(|v| f32::from_le_bytes(v.try_into().unwrap())),
+                measurement.temperature
+            );
+            assert_eq!(int64(4), measurement.sent_at.timestamp_millis());
+        }
+        assert_eq!(columns[3].values.iter().filter(|v| v.is_none()).count(), 3);
+
+        let (num_rows, columns) = read_parquet(&write_parquet(&[]).unwrap());
+        assert_eq!(num_rows, 0);
+        assert!(columns.is_empty());
+    }
+
+    /// Checks the file against a real Parquet reader, requires python3 with
+    /// pyarrow (run with `cargo test export -- --ignored`)
+    #[test]
+    #[ignore = "requires python3 with pyarrow"]
+    fn test_read_with_pyarrow() {
+        let measurements = measurements();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("measurements.parquet");
+        std::fs::write(&path, write_parquet(&measurements).unwrap()).unwrap();
+
+        let output = Command::new("python3")
+            .args(["-c", PYARROW_READER])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let read: Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(
+            read["schema"],
+            json!([
+                "target: string",
+                "sensor_id: int64",
+                "timestamp: timestamp[ms, tz=UTC]",
+                "temperature: float",
+                "sent_at: timestamp[ms, tz=UTC]",
+            ])
+        );
+        let rows: Vec<_> = measurements
+            .iter()
+            .map(|measurement| {
+                json!({
+                    "target": measurement.target,
+                    "sensor_id": measurement.sensor_id,
+                    "timestamp": measurement.time.timestamp_millis(),
+                    "temperature": measurement.temperature.map(f64::from),
+                    "sent_at": measurement.sent_at.timestamp_millis(),
+                })
+            })
+            .collect();
+        assert_eq!(read["rows"], Value::Array(rows));
+    }
+}
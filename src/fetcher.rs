@@ -0,0 +1,670 @@
+//! Fetcher that runs the configured processing cycles
+//!
+//! [`Fetcher::builder`] sets up everything the binary needs for the default
+//! command (store, HTTP clients, SPARQL source and scheduling) with defaults
+//! from the configuration, so that other frontends or services can embed the
+//! fetcher without copying the command line interface.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    capture::Capture,
+    config::{Config, RunMode, RunSchedule},
+    control::{Control, CycleStatus, Wakeup},
+    database::{MeasurementStore, StoreAccess, load_station_mappings, open_store},
+    gaps::GapDetector,
+    healthcheck::{self, Ping},
+    http::HttpClients,
+    lock::{LeaderLock, holds_lock},
+    metrics,
+    notify::PermanentFailureAlerts,
+    pipeline::{CycleOutcome, run_cycle, sync_sent_measurements},
+    sink,
+    sparql::{SparqlEndpoint, SparqlSource},
+    supervisor::{StationContext, Supervisor},
+    tui::Tui,
+};
+
+/// Result of a run that finished, i.e. a run in oneshot mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The cycle succeeded, or the errors stayed below `fail_on`
+    Success,
+    /// More stations failed than allowed by `fail_on`
+    PartialFailure,
+}
+
+/// Builder of a [`Fetcher`]
+///
+/// Everything that isn't set is created from the configuration, like the
+/// binary does without command line options.
+pub struct FetcherBuilder {
+    config: Config,
+    station_ids: Option<Vec<u32>>,
+    targets: Vec<String>,
+    sinks: Vec<String>,
+    store: Option<Arc<dyn MeasurementStore>>,
+    clients: Option<HttpClients>,
+    source: Option<SparqlSource>,
+    capture: Option<Capture>,
+    mode: Option<RunMode>,
+    schedule: Option<RunSchedule>,
+    config_path: Option<PathBuf>,
+    tui: Option<Tui>,
+    shutdown: Option<CancellationToken>,
+    dry_run: bool,
+    leader_lock: bool,
+}
+
+impl FetcherBuilder {
+    /// Fetch these stations instead of the configured ones
+    pub fn stations(mut self, station_ids: Vec<u32>) -> Self {
+        self.station_ids = Some(station_ids);
+        self
+    }
+
+    /// Send to these Gfrörli targets instead of the configured ones
+    pub fn targets(mut self, targets: Vec<String>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// Enable these sinks in addition to the configured ones, see
+    /// [`Config::enable_sinks`]
+    pub fn sinks(mut self, sinks: Vec<String>) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    /// Use this store instead of opening the configured database
+    pub fn store(mut self, store: Arc<dyn MeasurementStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Use these HTTP clients instead of creating them from the configuration
+    pub fn clients(mut self, clients: HttpClients) -> Self {
+        self.clients = Some(clients);
+        self
+    }
+
+    /// Read the SPARQL responses from this source instead of the configured
+    /// endpoint
+    pub fn source(mut self, source: SparqlSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Capture the raw responses
+    pub fn capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Run in this mode instead of the configured one
+    pub fn mode(mut self, mode: RunMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Schedule the fetches in loop mode like this instead of as configured
+    pub fn schedule(mut self, schedule: RunSchedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    /// Reload the configuration from this file when requested through the
+    /// control socket (the configuration isn't reloaded without a file)
+    pub fn config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Show the outcome of the cycles on a dashboard
+    pub fn tui(mut self, tui: Tui) -> Self {
+        self.tui = Some(tui);
+        self
+    }
+
+    /// Stop when this token is cancelled
+    ///
+    /// The running cycle is cancelled, and the fetcher returns after
+    /// releasing the leader lock. Without a token, the fetcher only stops
+    /// through the `stop` command of the control socket (in loop mode).
+    pub fn shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Fetch, but don't send to the targets or write to the database
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Whether the leader lock may be used if configured (default `true`)
+    ///
+    /// Instances that never write to the database, like read-only runs,
+    /// must not become the leader.
+    pub fn leader_lock(mut self, leader_lock: bool) -> Self {
+        self.leader_lock = leader_lock;
+        self
+    }
+
+    /// Create the fetcher, opening the configured database and creating the
+    /// HTTP clients if they weren't set
+    pub async fn build(self) -> Result<Fetcher> {
+        let mut config = self.config;
+        if !self.targets.is_empty() {
+            config.set_run_targets(self.targets.clone())?;
+        }
+        config.enable_sinks(&self.sinks)?;
+        let store = match self.store {
+            Some(store) => store,
+            None => {
+                let access = if self.dry_run {
+                    StoreAccess::DryRun
+                } else {
+                    StoreAccess::ReadWrite
+                };
+                open_store(&config, access)
+                    .await
+                    .with_context(|| "Failed to initialize database")?
+            }
+        };
+        load_station_mappings(&mut config, store.as_ref()).await?;
+        let clients = match self.clients {
+            Some(clients) => clients,
+            None => HttpClients::from_config(&config)?,
+        };
+        let source = self
+            .source
+            .unwrap_or_else(|| SparqlSource::Endpoint(SparqlEndpoint::from_config(&config)));
+        let leader_lock = LeaderLock::from_config(&config).filter(|_| self.leader_lock);
+
+        Ok(Fetcher {
+            mode: self.mode.unwrap_or_else(|| config.run_mode()),
+            schedule: self.schedule.unwrap_or_else(|| config.run_schedule()),
+            config: Arc::new(config),
+            station_ids: self.station_ids,
+            targets: self.targets,
+            sinks: self.sinks,
+            store,
+            clients,
+            source,
+            capture: self.capture,
+            config_path: self.config_path,
+            tui: self.tui,
+            shutdown: self.shutdown.unwrap_or_default(),
+            dry_run: self.dry_run,
+            leader_lock,
+        })
+    }
+}
+
+/// Fetches the stations and delivers their measurements in oneshot or loop
+/// mode
+pub struct Fetcher {
+    config: Arc<Config>,
+    station_ids: Option<Vec<u32>>,
+    targets: Vec<String>,
+    sinks: Vec<String>,
+    store: Arc<dyn MeasurementStore>,
+    clients: HttpClients,
+    source: SparqlSource,
+    capture: Option<Capture>,
+    mode: RunMode,
+    schedule: RunSchedule,
+    config_path: Option<PathBuf>,
+    tui: Option<Tui>,
+    shutdown: CancellationToken,
+    dry_run: bool,
+    leader_lock: Option<LeaderLock>,
+}
+
+impl Fetcher {
+    /// Start building a fetcher for a configuration
+    pub fn builder(config: Config) -> FetcherBuilder {
+        FetcherBuilder {
+            config,
+            station_ids: None,
+            targets: Vec::new(),
+            sinks: Vec::new(),
+            store: None,
+            clients: None,
+            source: None,
+            capture: None,
+            mode: None,
+            schedule: None,
+            config_path: None,
+            tui: None,
+            shutdown: None,
+            dry_run: false,
+            leader_lock: true,
+        }
+    }
+
+    /// Store the measurements are recorded in
+    pub fn store(&self) -> &Arc<dyn MeasurementStore> {
+        &self.store
+    }
+
+    /// Stations to fetch
+    fn station_ids(&self) -> Vec<u32> {
+        self.station_ids
+            .clone()
+            .unwrap_or_else(|| self.config.foen_station_ids())
+    }
+
+    /// Reload the configuration file in loop mode
+    ///
+    /// Stations (including the mappings stored in the database), HTTP clients
+    /// and the SPARQL endpoint are replaced, all other settings (e.g. database
+    /// and logging) require a restart. An invalid configuration is logged and
+    /// the previous one kept.
+    async fn reload(&mut self) {
+        let Some(path) = &self.config_path else {
+            warn!("No configuration file to reload");
+            return;
+        };
+        let reloaded = async {
+            let mut config = load_config(path, &self.targets, &self.sinks)?;
+            load_station_mappings(&mut config, self.store.as_ref()).await?;
+            let clients = HttpClients::from_config(&config)?;
+            anyhow::Ok((config, clients))
+        }
+        .await;
+        match reloaded {
+            Ok((config, clients)) => {
+                if let SparqlSource::Endpoint(endpoint) = &mut self.source {
+                    *endpoint = SparqlEndpoint::from_config(&config);
+                }
+                for warning in &config.warnings {
+                    warn!("{}", warning);
+                }
+                info!(
+                    "Reloaded configuration with {} stations",
+                    config.stations.len()
+                );
+                self.config = Arc::new(config);
+                self.clients = clients;
+            }
+            Err(e) => error!(
+                "Failed to reload configuration, keeping the previous one: {:#}",
+                e
+            ),
+        }
+    }
+
+    /// Release the leader lock when shutting down
+    async fn stop(&self) -> Result<RunOutcome> {
+        info!("Shut down");
+        if let Some(lock) = &self.leader_lock
+            && let Err(e) = lock.release(self.store.as_ref()).await
+        {
+            warn!("Failed to release leader lock: {:#}", e);
+        }
+        Ok(RunOutcome::Success)
+    }
+
+    /// Run the processing cycles
+    ///
+    /// In oneshot mode, a single cycle runs and its outcome is returned. In
+    /// loop mode, the stations are processed until the shutdown.
+    pub async fn run(mut self) -> Result<RunOutcome> {
+        let station_ids = self.station_ids();
+        info!(
+            "Fetching water temperature data for {} stations: {:?}",
+            station_ids.len(),
+            station_ids
+        );
+
+        if self
+            .config
+            .gfroerli_target_configs()
+            .iter()
+            .any(|(_, api)| api.sync_on_startup())
+        {
+            info!("Syncing deduplication state with Gfrörli API");
+            sync_sent_measurements(
+                &self.clients,
+                &self.config,
+                self.store.as_ref(),
+                self.dry_run,
+            )
+            .await?;
+        }
+
+        if let Some(lock) = &self.leader_lock {
+            info!("Using leader lock as instance '{}'", lock.instance_id());
+        }
+
+        if let RunMode::Oneshot = self.mode {
+            debug!("Running in oneshot mode");
+            return self.run_once().await;
+        }
+
+        let control = Control::new(self.shutdown.clone());
+        if let Some(path) = self.config.control_socket_path() {
+            control.listen(Path::new(path))?;
+        }
+        info!(
+            "Running in loop mode with {} minute intervals",
+            self.config.run_interval_minutes()
+        );
+
+        let delay = self.config.run_initial_delay_seconds();
+        if delay > 0 {
+            info!("Waiting {} seconds before the first cycle", delay);
+            let first_cycle = Instant::now() + Duration::from_secs(delay);
+            match control.sleep_until(first_cycle).await {
+                Wakeup::Reload => self.reload().await,
+                Wakeup::Shutdown => return self.stop().await,
+                Wakeup::Elapsed | Wakeup::Trigger => {}
+            }
+        }
+        self.run_loop(&control).await
+    }
+
+    /// Fetch all stations once
+    async fn run_once(&self) -> Result<RunOutcome> {
+        if !holds_lock(self.leader_lock.as_ref(), self.store.as_ref()).await {
+            return Ok(RunOutcome::Success);
+        }
+        let config = &self.config;
+        let clients = &self.clients;
+        let dry_run = self.dry_run;
+        debug!("Starting station processing cycle");
+        healthcheck::ping(&clients.monitoring, config, Ping::Start, "", dry_run).await;
+
+        let outcome = run_cycle(
+            clients,
+            config,
+            &self.source,
+            self.capture.as_ref(),
+            self.store.as_ref(),
+            &self.station_ids(),
+            dry_run,
+            &self.shutdown,
+        )
+        .await;
+        if let Err(e) = sink::flush(clients, config, Utc::now(), dry_run).await {
+            warn!("Failed to flush sinks: {:#}", e);
+        }
+        let (ping, message) = Ping::for_outcome(config, &outcome);
+        healthcheck::ping(&clients.monitoring, config, ping, &message, dry_run).await;
+        if let Some(path) = config.metrics_textfile_path()
+            && !dry_run
+            && let Err(e) = metrics::write_textfile(
+                Path::new(path),
+                &metrics::render(config, &outcome, Utc::now()),
+            )
+        {
+            warn!("{:#}", e);
+        }
+        // Cancelled stations count as failed
+        let (total_success, total_errors) = (outcome.success, outcome.errors + outcome.cancelled);
+
+        if let Some(summary) = outcome.stats.summary(Utc::now()) {
+            info!("Cycle statistics: {}", summary);
+        }
+        info!(
+            "Successfully sent {} measurements to Gfrörli API",
+            total_success
+        );
+        if total_errors > 0 {
+            error!("Total errors encountered: {}", total_errors);
+        }
+        if let Some(url) = config.pushgateway_url() {
+            let body = metrics::render(config, &outcome, Utc::now());
+            if dry_run {
+                info!("Metrics would be pushed to the Pushgateway [DRY RUN]");
+            } else if let Err(e) =
+                metrics::push(&clients.monitoring, url, config.pushgateway_job(), body).await
+            {
+                warn!("Failed to push metrics: {:#}", e);
+            }
+        }
+        if let Some(lock) = &self.leader_lock
+            && let Err(e) = lock.release(self.store.as_ref()).await
+        {
+            warn!("Failed to release leader lock: {:#}", e);
+        }
+        if config.run_fail_on().is_failure(total_success, total_errors) {
+            return Ok(RunOutcome::PartialFailure);
+        }
+        Ok(RunOutcome::Success)
+    }
+
+    /// Context of the station tasks
+    fn station_context(&self, control: &Control) -> StationContext {
+        StationContext {
+            config: self.config.clone(),
+            clients: self.clients.clone(),
+            source: self.source.clone(),
+            capture: self.capture.clone(),
+            store: self.store.clone(),
+            control: control.clone(),
+            schedule: self.schedule,
+            dry_run: self.dry_run,
+        }
+    }
+
+    /// Start the station tasks if this instance holds the leader lock
+    async fn start_stations(&self, supervisor: &mut Supervisor, control: &Control) {
+        if holds_lock(self.leader_lock.as_ref(), self.store.as_ref()).await {
+            let context = self.station_context(control);
+            supervisor.start(context, &self.station_ids(), &self.shutdown);
+        }
+    }
+
+    /// Process every station in its own task until the shutdown
+    ///
+    /// The stations are fetched on their own schedule, see [`StationTimer`].
+    /// Once per interval, the latest outcome of every station is reported
+    /// like a cycle (healthchecks, metrics, status and alerts), and the
+    /// leader lock is renewed. The tasks only run while holding the lock.
+    ///
+    /// [`StationTimer`]: crate::schedule::StationTimer
+    async fn run_loop(mut self, control: &Control) -> Result<RunOutcome> {
+        if self.schedule == RunSchedule::Publication {
+            info!(
+                "Aligning fetches to FOEN publication times ({} minute delay)",
+                self.config.run_publication_delay_minutes()
+            );
+        }
+        let (sender, mut reports) = mpsc::channel(REPORT_CHANNEL_CAPACITY);
+        let mut supervisor = Supervisor::new(sender);
+        let mut report = LoopReport::new();
+        let mut next_report = Instant::now();
+
+        loop {
+            tokio::select! {
+                Some((station_id, outcome)) = reports.recv() => {
+                    if let Some(tui) = &self.tui {
+                        tui.record(&self.config, &outcome);
+                    }
+                    report.record(&self, station_id, outcome).await;
+                }
+                () = supervisor.supervise() => {}
+                wakeup = control.sleep_until(next_report) => match wakeup {
+                    Wakeup::Elapsed => {
+                        report.finish(&self, control).await;
+                        if !supervisor.is_running() {
+                            self.start_stations(&mut supervisor, control).await;
+                        } else if !holds_lock(self.leader_lock.as_ref(), self.store.as_ref()).await {
+                            info!("Stopping the stations without the leader lock");
+                            supervisor.stop().await;
+                        }
+                        next_report = Instant::now() + interval(&self.config);
+                    }
+                    Wakeup::Trigger => {
+                        // All stations are due right away after the restart
+                        supervisor.stop().await;
+                        self.start_stations(&mut supervisor, control).await;
+                    }
+                    Wakeup::Reload => {
+                        supervisor.stop().await;
+                        self.reload().await;
+                        self.start_stations(&mut supervisor, control).await;
+                    }
+                    Wakeup::Shutdown => {
+                        supervisor.stop().await;
+                        return self.stop().await;
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Capacity of the channel for the outcomes of the station tasks
+const REPORT_CHANNEL_CAPACITY: usize = 64;
+
+/// Collects the outcomes of the station tasks for the report once per
+/// interval in loop mode
+struct LoopReport {
+    /// Latest outcome per station since the last report
+    latest: BTreeMap<u32, CycleOutcome>,
+    started: Instant,
+    started_at: DateTime<Utc>,
+    gap_detector: GapDetector,
+    permanent_failure_alerts: PermanentFailureAlerts,
+    /// Consecutive reports in which all stations failed, for the failure
+    /// alert
+    failed_reports: u32,
+}
+
+impl LoopReport {
+    fn new() -> Self {
+        Self {
+            latest: BTreeMap::new(),
+            started: Instant::now(),
+            started_at: Utc::now(),
+            gap_detector: GapDetector::default(),
+            permanent_failure_alerts: PermanentFailureAlerts::default(),
+            failed_reports: 0,
+        }
+    }
+
+    /// Record the outcome of a fetch of a station and alert about a
+    /// permanent failure right away
+    async fn record(&mut self, fetcher: &Fetcher, station_id: u32, outcome: CycleOutcome) {
+        if let Err(e) = self
+            .permanent_failure_alerts
+            .check(
+                &fetcher.config,
+                &fetcher.clients.notifications,
+                &outcome,
+                fetcher.dry_run,
+            )
+            .await
+        {
+            warn!("Failed to send failure alert: {:#}", e);
+        }
+        self.latest.insert(station_id, outcome);
+    }
+
+    /// Report the latest outcome of the stations that were fetched since the
+    /// last report, and start the next one
+    async fn finish(&mut self, fetcher: &Fetcher, control: &Control) {
+        let config = &fetcher.config;
+        let clients = &fetcher.clients;
+        let dry_run = fetcher.dry_run;
+        let mut outcome = CycleOutcome {
+            started_at: self.started_at,
+            duration: self.started.elapsed(),
+            ..Default::default()
+        };
+        for (_, station_outcome) in std::mem::take(&mut self.latest) {
+            outcome.merge(station_outcome);
+        }
+        self.started = Instant::now();
+        self.started_at = Utc::now();
+
+        if let Err(e) = self
+            .gap_detector
+            .check(
+                config,
+                &clients.notifications,
+                fetcher.store.as_ref(),
+                Utc::now(),
+                dry_run,
+            )
+            .await
+        {
+            warn!("Failed to check for gaps: {:#}", e);
+        }
+        if let Err(e) = sink::flush(clients, config, Utc::now(), dry_run).await {
+            warn!("Failed to flush sinks: {:#}", e);
+        }
+        let (total_success, total_errors) = (outcome.success, outcome.errors + outcome.cancelled);
+        if total_success + total_errors == 0 {
+            return;
+        }
+
+        let (ping, message) = Ping::for_outcome(config, &outcome);
+        healthcheck::ping(&clients.monitoring, config, ping, &message, dry_run).await;
+        if let Some(path) = config.metrics_textfile_path()
+            && !dry_run
+            && let Err(e) = metrics::write_textfile(
+                Path::new(path),
+                &metrics::render(config, &outcome, Utc::now()),
+            )
+        {
+            warn!("{:#}", e);
+        }
+        if let Some(summary) = outcome.stats.summary(Utc::now()) {
+            info!("Cycle statistics: {}", summary);
+        }
+        info!(
+            "Interval complete - {} stations succeeded, {} failed",
+            total_success, total_errors
+        );
+        control.set_status(CycleStatus {
+            finished_at: Some(Utc::now()),
+            success: total_success,
+            errors: total_errors,
+        });
+        self.failed_reports = match total_success {
+            0 => self.failed_reports.saturating_add(1),
+            _ => 0,
+        };
+        if self.failed_reports == config.run_backoff_after_cycles()
+            && !dry_run
+            && let Err(e) = clients.notifications.alert_failures(config, &outcome).await
+        {
+            warn!("Failed to send failure alert: {:#}", e);
+        }
+    }
+}
+
+/// Load the configuration file and apply the targets and sinks given on the
+/// command line
+pub fn load_config(path: &Path, targets: &[String], sinks: &[String]) -> Result<Config> {
+    let mut config = Config::load_from_file(path)
+        .with_context(|| format!("Failed to load config from '{}'", path.display()))?;
+    if !targets.is_empty() {
+        config.set_run_targets(targets.to_vec())?;
+    }
+    config.enable_sinks(sinks)?;
+    Ok(config)
+}
+
+/// Interval of the reports in loop mode
+fn interval(config: &Config) -> Duration {
+    Duration::from_secs(config.run_interval_minutes() as u64 * 60)
+}
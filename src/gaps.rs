@@ -0,0 +1,271 @@
+//! Detection of sensors without recent measurements in loop mode
+
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, GapDetectionConfig},
+    database::MeasurementStore,
+    notify::{Notifier, Topic},
+    timezone,
+};
+
+/// Sensor whose last sent measurement is too old
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GappedSensor {
+    pub station_id: u32,
+    pub station_label: String,
+    pub sensor_id: u32,
+    pub target: String,
+    /// Time of the last measurement sent to the target
+    pub last_sent: DateTime<Utc>,
+}
+
+/// Notification posted to the webhook, listing all gapped sensors
+#[derive(Debug, Serialize)]
+struct GapNotification<'a> {
+    max_age_hours: u32,
+    sensors: &'a [GappedSensor],
+    time: DateTime<Utc>,
+}
+
+/// Find the sensors of enabled stations whose last sent measurement is older
+/// than the maximum age
+///
+/// Sensors without any sent measurement aren't checked, so that new stations
+/// aren't flagged before their first measurement.
+pub async fn find_gaps(
+    config: &Config,
+    gap_config: &GapDetectionConfig,
+    store: &dyn MeasurementStore,
+    now: DateTime<Utc>,
+) -> Result<Vec<GappedSensor>> {
+    let max_age = Duration::hours(gap_config.max_age_hours().into());
+    let mut gapped = Vec::new();
+    for station in config.enabled_stations() {
+        let station_id = station.foen_station_id;
+        for target in config.station_targets(station_id) {
+            let latest = store
+                .latest_sent_measurement(target, station.gfroerli_sensor_id)
+                .await?;
+            if let Some(latest) = latest
+                && now - latest.time > max_age
+            {
+                gapped.push(GappedSensor {
+                    station_id,
+                    station_label: config.station_label(station_id),
+                    sensor_id: station.gfroerli_sensor_id,
+                    target: target.to_string(),
+                    last_sent: latest.time,
+                });
+            }
+        }
+    }
+    Ok(gapped)
+}
+
+/// Periodic check for gaps that notifies once per gap
+///
+/// All gapped sensors are listed in a single notification, which is only
+/// sent when a sensor starts a new gap. Sensors that are still gapped in
+/// later checks aren't notified again until they received a measurement.
+#[derive(Debug, Default)]
+pub struct GapDetector {
+    last_check: Option<DateTime<Utc>>,
+    /// Gapped sensors by target and sensor ID, as of the last check
+    notified: BTreeSet<(String, u32)>,
+}
+
+impl GapDetector {
+    /// Check for gaps if the check interval elapsed, and notify about new
+    /// gaps
+    ///
+    /// The gaps are only remembered once the notification was sent, so that
+    /// a failed notification is retried with the next check.
+    pub async fn check(
+        &mut self,
+        config: &Config,
+        notifier: &Notifier,
+        store: &dyn MeasurementStore,
+        now: DateTime<Utc>,
+        dry_run: bool,
+    ) -> Result<()> {
+        let Some(gap_config) = &config.gap_detection else {
+            return Ok(());
+        };
+        let interval = Duration::minutes(gap_config.check_interval_minutes().into());
+        if self.last_check.is_some_and(|last| now - last < interval) {
+            return Ok(());
+        }
+        self.last_check = Some(now);
+
+        let gapped = find_gaps(config, gap_config, store, now).await?;
+        let current: BTreeSet<_> = gapped
+            .iter()
+            .map(|sensor| (sensor.target.clone(), sensor.sensor_id))
+            .collect();
+        for (target, sensor_id) in self.notified.difference(&current) {
+            info!("Sensor {sensor_id} (target '{target}') receives measurements again");
+        }
+        if current.is_subset(&self.notified) {
+            self.notified = current;
+            return Ok(());
+        }
+
+        warn!(
+            "{} sensors have no measurement in the last {} hours: {}",
+            gapped.len(),
+            gap_config.max_age_hours(),
+            gapped
+                .iter()
+                .map(|sensor| format!("{} (target '{}')", sensor.sensor_id, sensor.target))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if dry_run {
+            info!("Gaps would be notified [DRY RUN]");
+            self.notified = current;
+            return Ok(());
+        }
+
+        if let Some(url) = &gap_config.webhook_url {
+            let notification = GapNotification {
+                max_age_hours: gap_config.max_age_hours(),
+                sensors: &gapped,
+                time: now,
+            };
+            notifier
+                .post_webhook(url, "gap notification", &notification)
+                .await?;
+        }
+        let subject = format!(
+            "{} sensors without measurements in the last {} hours",
+            gapped.len(),
+            gap_config.max_age_hours()
+        );
+        notifier
+            .email(Topic::GapAlert, &subject, email_body(&gapped))
+            .await?;
+        self.notified = current;
+        Ok(())
+    }
+}
+
+/// List the gapped sensors for an email
+fn email_body(gapped: &[GappedSensor]) -> String {
+    let mut body = String::from("The following sensors have no recent measurements:\n\n");
+    for sensor in gapped {
+        body.push_str(&format!(
+            "Station {}, sensor {} (target '{}'): last measurement {}\n",
+            sensor.station_label,
+            sensor.sensor_id,
+            sensor.target,
+            timezone::log_time(sensor.last_sent)
+        ));
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{GfroerliConfig, StationConfig},
+        database::{SentMeasurement, SqliteStore},
+    };
+
+    fn config() -> Config {
+        let station = |foen_station_id, gfroerli_sensor_id| StationConfig {
+            foen_station_id,
+            gfroerli_sensor_id,
+            targets: None,
+            alias: None,
+            enabled: None,
+            station_type: None,
+            air_temperature: None,
+            alerts: None,
+            smoothing: None,
+        };
+        let mut config = Config::new(
+            vec![station(2104, 1), station(2176, 2), station(2135, 3)],
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        config.gap_detection = Some(GapDetectionConfig::default());
+        config
+    }
+
+    async fn record_sent(store: &SqliteStore, sensor_id: u32, time: DateTime<Utc>) {
+        store
+            .record_measurement_sent(&SentMeasurement {
+                target: "default".to_string(),
+                sensor_id,
+                time,
+                temperature: Some(6.5),
+                idempotency_key: format!("lindas-{sensor_id}-{}", time.timestamp()),
+                gfroerli_id: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_find_gaps() {
+        let config = config();
+        let store = SqliteStore::open_in_memory().unwrap();
+        let now: DateTime<Utc> = "2025-01-15T12:00:00Z".parse().unwrap();
+        record_sent(&store, 1, now - Duration::minutes(20)).await;
+        record_sent(&store, 2, now - Duration::hours(4)).await;
+
+        // Sensor 3 never received a measurement
+        let gapped = find_gaps(&config, config.gap_detection.as_ref().unwrap(), &store, now)
+            .await
+            .unwrap();
+        assert_eq!(
+            gapped,
+            vec![GappedSensor {
+                station_id: 2176,
+                station_label: "2176".to_string(),
+                sensor_id: 2,
+                target: "default".to_string(),
+                last_sent: now - Duration::hours(4),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gap_detector() {
+        let config = config();
+        let notifier = Notifier::from_config(&config).unwrap();
+        let store = SqliteStore::open_in_memory().unwrap();
+        let now: DateTime<Utc> = "2025-01-15T12:00:00Z".parse().unwrap();
+        record_sent(&store, 2, now - Duration::hours(4)).await;
+
+        let mut detector = GapDetector::default();
+        detector
+            .check(&config, &notifier, &store, now, false)
+            .await
+            .unwrap();
+        assert_eq!(detector.last_check, Some(now));
+        assert!(detector.notified.contains(&("default".to_string(), 2)));
+
+        // Not checked again before the interval elapsed
+        record_sent(&store, 2, now).await;
+        let later = now + Duration::minutes(30);
+        detector
+            .check(&config, &notifier, &store, later, false)
+            .await
+            .unwrap();
+        assert_eq!(detector.last_check, Some(now));
+
+        let later = now + Duration::hours(1);
+        detector
+            .check(&config, &notifier, &store, later, false)
+            .await
+            .unwrap();
+        assert!(detector.notified.is_empty());
+    }
+}
@@ -1,13 +1,59 @@
 //! Gfrörli API integration for sending measurement data
+//!
+//! [`GfroerliClient`] covers the endpoints of the API the fetcher uses:
+//! creating measurements, listing, fetching and updating sensors, and
+//! listing the measurements of a sensor.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
-use tracing::debug;
+use thiserror::Error;
+use tracing::{debug, warn};
 
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use reqwest::{
+    Method, Request, RequestBuilder, Response, StatusCode,
+    header::{AUTHORIZATION, CONTENT_TYPE, HeaderName, HeaderValue, InvalidHeaderValue},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::capture::Capture;
+use crate::config::{Config, GfroerliAuth, GfroerliConfig};
+use crate::crypto::{hex, hmac_sha256};
+use crate::error::FetcherError;
+use crate::http::{HttpClient, HttpClients, HttpStatusError, check_status};
+use crate::observation::StationObservation;
+use crate::secret::SecretString;
+
+/// A configured Gfrörli API target together with its client
+#[derive(Debug, Clone, Copy)]
+pub struct GfroerliTarget<'a> {
+    /// Name of the target ("default" for the `[gfroerli_api]` section)
+    pub name: &'a str,
+    /// API settings of the target
+    pub api: &'a GfroerliConfig,
+    /// Client of the target's API
+    pub client: GfroerliClient<'a>,
+}
 
-use crate::config::GfroerliConfig;
-use crate::parsing::StationMeasurement;
+impl<'a> GfroerliTarget<'a> {
+    /// Look up a target by name
+    pub fn new(config: &'a Config, clients: &'a HttpClients, name: &'a str) -> Result<Self> {
+        let api = config
+            .gfroerli_target(name)
+            .with_context(|| format!("Unknown Gfrörli target '{name}'"))?;
+        Ok(Self {
+            name,
+            api,
+            client: GfroerliClient::new(
+                clients.gfroerli(name)?,
+                api,
+                clients.gfroerli_tokens(name)?,
+            ),
+        })
+    }
+}
 
 /// Request payload for Gfrörli measurements API
 #[derive(Debug, Serialize)]
@@ -15,74 +61,1045 @@ struct MeasurementRequest {
     sensor_id: u32,
     temperature: f32,
     created_at: DateTime<Utc>,
+    /// Additional parameters under their configured API field names
+    #[serde(flatten)]
+    fields: BTreeMap<String, f32>,
 }
 
-/// Helper function to build API endpoint URL
-fn build_api_url(base_url: &str, endpoint: &str) -> String {
-    let base = base_url.trim_end_matches('/');
-    format!("{base}/{endpoint}")
+/// Response body of the Gfrörli measurements API
+#[derive(Debug, Deserialize)]
+struct MeasurementResponse {
+    id: i64,
+}
+
+/// Measurement of a sensor as returned by the Gfrörli API
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RemoteMeasurement {
+    /// ID of the measurement (not returned by all API versions)
+    #[serde(default)]
+    pub id: Option<i64>,
+    /// Water temperature
+    pub temperature: f32,
+    /// Time of the measurement
+    pub created_at: DateTime<Utc>,
 }
 
-/// Sends a measurement to the Gfrörli API
-pub async fn send_measurement(
-    client: &reqwest::Client,
+/// Result of sending a measurement to the Gfrörli API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOutcome {
+    /// The measurement was created, with the ID assigned by the API (if any)
+    Created(Option<i64>),
+    /// The API rejected the measurement as a duplicate (409 Conflict), e.g.
+    /// because it was sent before the local database was lost
+    AlreadyExists,
+}
+
+impl SendOutcome {
+    /// ID assigned to the measurement by the API, if known
+    pub fn gfroerli_id(self) -> Option<i64> {
+        match self {
+            SendOutcome::Created(id) => id,
+            SendOutcome::AlreadyExists => None,
+        }
+    }
+}
+
+/// Sensor as listed by the Gfrörli API
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RemoteSensor {
+    /// ID of the sensor
+    pub id: u32,
+    /// Name of the sensor device (not returned by all API versions)
+    #[serde(default)]
+    pub device_name: Option<String>,
+    /// Description of the location (not returned by all API versions)
+    #[serde(default)]
+    pub caption: Option<String>,
+}
+
+/// Sensor details as returned by the Gfrörli API
+#[derive(Debug, Deserialize)]
+struct SensorResponse {
+    last_measurement: Option<RemoteMeasurement>,
+}
+
+/// Extract the ID of the created measurement from a response body
+fn parse_measurement_id(body: &str) -> Result<i64> {
+    let response: MeasurementResponse =
+        serde_json::from_str(body).with_context(|| "Invalid measurement response")?;
+    Ok(response.id)
+}
+
+/// Idempotency key of a measurement, derived from the sensor ID and the
+/// measurement time
+///
+/// Repeated sends of the same measurement always carry the same key, so that
+/// the API can recognize them.
+pub fn idempotency_key(sensor_id: u32, time: DateTime<Utc>) -> String {
+    format!("lindas-{sensor_id}-{}", time.timestamp())
+}
+
+/// Values of the additional parameters of an observation that have an API
+/// field name configured, rounded as configured
+///
+/// Non-finite values are left out, as they can't be represented in JSON.
+fn additional_fields(
     config: &GfroerliConfig,
-    measurement: &StationMeasurement,
-    sensor_id: u32,
-) -> Result<()> {
-    let url = build_api_url(&config.api_url, "measurements");
+    observation: &StationObservation,
+) -> BTreeMap<String, f32> {
+    observation
+        .parameters()
+        .filter(|(_, value)| value.value.is_finite())
+        .filter_map(|(parameter, value)| {
+            config
+                .field_name(parameter)
+                .map(|name| (name.to_string(), config.round(value.value)))
+        })
+        .collect()
+}
 
-    let payload = MeasurementRequest {
-        sensor_id,
-        temperature: measurement.temperature,
-        created_at: measurement.time,
-    };
+/// Helper function to build API endpoint URL, with an optional API version prefix
+fn build_api_url(base_url: &str, api_version: Option<&str>, endpoint: &str) -> String {
+    let base = base_url.trim_end_matches('/');
+    let endpoint = endpoint.trim_start_matches('/');
+    match api_version.map(|version| version.trim_matches('/')) {
+        Some(version) if !version.is_empty() => format!("{base}/{version}/{endpoint}"),
+        _ => format!("{base}/{endpoint}"),
+    }
+}
 
-    debug!(
-        "Sending measurement to Gfrörli API for station {} (sensor {}): {}°C at {}",
-        measurement.station_id, sensor_id, measurement.temperature, measurement.time
-    );
-
-    let response = client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", config.api_key))
-        .json(&payload)
-        .send()
-        .await
-        .with_context(|| format!("Failed to send measurement to Gfrörli API at {url}"))?;
+/// Error of a request to the Gfrörli API
+///
+/// Error statuses keep the [`HttpStatusError`] as source, so that they can be
+/// classified like the errors of other HTTP requests.
+#[derive(Debug, Error)]
+pub enum GfroerliError {
+    /// The measurement has a temperature that can't be sent
+    #[error("Invalid temperature {temperature} for sensor {sensor}")]
+    InvalidTemperature { sensor: u32, temperature: f32 },
+    /// The API key or access token can't be sent as bearer token
+    #[error("API key or access token is not a valid bearer token")]
+    InvalidToken {
+        #[source]
+        source: InvalidHeaderValue,
+    },
+    /// The request couldn't be sent or the response wasn't received
+    #[error("Failed to send request to Gfrörli API at {url}")]
+    Request {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The API rejected the API key (401 Unauthorized or 403 Forbidden)
+    #[error("Gfrörli API at {url} rejected the API key")]
+    Unauthorized {
+        url: String,
+        #[source]
+        source: HttpStatusError,
+    },
+    /// The API responded with another error status
+    #[error("Gfrörli API request to {url} failed")]
+    Status {
+        url: String,
+        #[source]
+        source: HttpStatusError,
+    },
+    /// The response body isn't what the endpoint returns
+    #[error("Invalid Gfrörli API response from {url}")]
+    InvalidResponse {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+}
+
+impl GfroerliError {
+    /// Status code of the API, if it responded with an error
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            GfroerliError::Unauthorized { source, .. } | GfroerliError::Status { source, .. } => {
+                Some(source.status)
+            }
+            _ => None,
+        }
+    }
+
+    /// Error of a request for a sensor at the boundary of the library
+    pub fn for_sensor(self, sensor: u32) -> FetcherError {
+        FetcherError::Api {
+            sensor,
+            status: self.status(),
+            source: self.into(),
+        }
+    }
+}
+
+/// Changes to a sensor, fields that are `None` are left unchanged
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SensorUpdate {
+    /// Name of the sensor device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    /// Description of the location
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+}
+
+/// Header with the Unix timestamp of HMAC-signed requests
+const TIMESTAMP_HEADER: HeaderName = HeaderName::from_static("x-gfroerli-timestamp");
+
+/// Header with the HMAC-SHA256 signature of signed requests
+const SIGNATURE_HEADER: HeaderName = HeaderName::from_static("x-gfroerli-signature");
+
+/// Signature of a request for HMAC authentication
+///
+/// The HMAC-SHA256 of `<timestamp>.<body>` keyed with the shared secret, as
+/// lowercase hex with a `sha256=` prefix.
+fn hmac_signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut message = format!("{timestamp}.").into_bytes();
+    message.extend_from_slice(body);
+    format!("sha256={}", hex(&hmac_sha256(secret.as_bytes(), &message)))
+}
+
+/// Part of the validity of an access token not used, so that it doesn't
+/// expire while a request is sent
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// Response of an OAuth2 token endpoint
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: SecretString,
+    /// Validity of the token in seconds (not returned by all servers)
+    expires_in: Option<u64>,
+}
+
+/// Access token of the OAuth2 client credentials flow
+#[derive(Debug)]
+struct AccessToken {
+    token: SecretString,
+    /// Time after which a new token is requested, tokens without an
+    /// expiration are used until the API rejects them
+    refresh_at: Option<Instant>,
+}
+
+impl AccessToken {
+    fn new(token: SecretString, expires_in: Option<u64>) -> Self {
+        let refresh_at = expires_in.map(|seconds| {
+            let validity = Duration::from_secs(seconds);
+            Instant::now() + validity.saturating_sub(TOKEN_EXPIRY_MARGIN.min(validity / 2))
+        });
+        Self { token, refresh_at }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.refresh_at
+            .is_none_or(|refresh_at| Instant::now() < refresh_at)
+    }
+}
+
+/// Cache of the OAuth2 access token of a target, shared by all clones
+#[derive(Debug, Clone, Default)]
+pub struct TokenCache(Arc<Mutex<Option<AccessToken>>>);
+
+/// Typed client of the Gfrörli API of a target
+///
+/// Builds the URLs of the endpoints (including the API version), sends every
+/// request with the API key of the target (as bearer token, HMAC signature or
+/// OAuth2 client secret) and turns failed requests into a
+/// [`GfroerliError`].
+#[derive(Debug, Clone, Copy)]
+pub struct GfroerliClient<'a> {
+    http: &'a HttpClient,
+    config: &'a GfroerliConfig,
+    tokens: &'a TokenCache,
+}
+
+impl<'a> GfroerliClient<'a> {
+    /// Client for the API configured in `config`, caching OAuth2 access
+    /// tokens in `tokens`
+    pub fn new(http: &'a HttpClient, config: &'a GfroerliConfig, tokens: &'a TokenCache) -> Self {
+        Self {
+            http,
+            config,
+            tokens,
+        }
+    }
+
+    /// URL of an endpoint
+    fn url(&self, endpoint: &str) -> String {
+        build_api_url(
+            &self.config.api_url,
+            self.config.api_version.as_deref(),
+            endpoint,
+        )
+    }
+
+    /// Start building a request without a body
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.http.request(method, url)
+    }
+
+    /// Start building a request with a JSON body
+    fn request_json<T: Serialize>(&self, method: Method, url: &str, body: &T) -> RequestBuilder {
+        // Serialized here rather than with `RequestBuilder::json`, because
+        // the signature covers the exact bytes sent
+        let body = serde_json::to_vec(body).expect("API payloads are serializable");
+        self.http
+            .request(method, url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+    }
+
+    /// Add the configured authentication to a request
+    async fn authenticate(&self, request: &mut Request) -> Result<(), GfroerliError> {
+        let key = self.config.api_key.expose();
+        let headers = match self.config.auth() {
+            GfroerliAuth::Bearer => vec![(AUTHORIZATION, bearer(key)?)],
+            GfroerliAuth::Hmac => {
+                let timestamp = Utc::now().timestamp();
+                let body = request.body().and_then(|body| body.as_bytes());
+                let signature = hmac_signature(key, timestamp, body.unwrap_or_default());
+                vec![
+                    (TIMESTAMP_HEADER, HeaderValue::from(timestamp)),
+                    (
+                        SIGNATURE_HEADER,
+                        HeaderValue::from_str(&signature).expect("hex is a valid header value"),
+                    ),
+                ]
+            }
+            GfroerliAuth::OAuth2 => {
+                let token = self.access_token().await?;
+                vec![(AUTHORIZATION, bearer(token.expose())?)]
+            }
+        };
+        request.headers_mut().extend(headers);
+        Ok(())
+    }
+
+    /// Get the cached access token, or request a new one if there is none or
+    /// it expired
+    ///
+    /// The cache stays locked while requesting, so that concurrent requests
+    /// wait for the same new token.
+    async fn access_token(&self) -> Result<SecretString, GfroerliError> {
+        let mut cached = self.tokens.0.lock().await;
+        if let Some(token) = cached.as_ref()
+            && token.is_valid()
+        {
+            return Ok(token.token.clone());
+        }
+
+        let oauth2 = self
+            .config
+            .oauth2
+            .as_ref()
+            .expect("OAuth2 settings are validated with the configuration");
+        let url = &oauth2.token_url;
+        debug!("Requesting access token from {}", url);
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &oauth2.scope {
+            form.push(("scope", scope));
+        }
+        let request = self
+            .http
+            .post(url)
+            .basic_auth(&oauth2.client_id, Some(self.config.api_key.expose()))
+            .form(&form);
+        // The response contains the token, so it must never be traced
+        let response =
+            self.http
+                .untraced()
+                .send(request)
+                .await
+                .map_err(|source| GfroerliError::Request {
+                    url: url.clone(),
+                    source,
+                })?;
+        let response: TokenResponse = parse_json(url, response).await?;
+
+        let token = AccessToken::new(response.access_token, response.expires_in);
+        let secret = token.token.clone();
+        *cached = Some(token);
+        Ok(secret)
+    }
+
+    /// Send a request, without checking the status of the response
+    ///
+    /// With OAuth2 authentication, a request rejected with 401 Unauthorized
+    /// is repeated once with a new access token, as the token may have been
+    /// revoked before it expired.
+    async fn send(&self, url: &str, request: RequestBuilder) -> Result<Response, GfroerliError> {
+        let request_error = |source| GfroerliError::Request {
+            url: url.to_string(),
+            source,
+        };
+        let request = request.build().map_err(request_error)?;
+        let retry = match self.config.auth() {
+            GfroerliAuth::OAuth2 => request.try_clone(),
+            _ => None,
+        };
+
+        let response = self.send_authenticated(url, request).await?;
+        if let Some(retry) = retry
+            && response.status() == StatusCode::UNAUTHORIZED
+        {
+            debug!(
+                "Gfrörli API at {} rejected the access token, requesting a new one",
+                url
+            );
+            *self.tokens.0.lock().await = None;
+            return self.send_authenticated(url, retry).await;
+        }
+        Ok(response)
+    }
+
+    /// Authenticate and send a request
+    async fn send_authenticated(
+        &self,
+        url: &str,
+        mut request: Request,
+    ) -> Result<Response, GfroerliError> {
+        self.authenticate(&mut request).await?;
+        self.http
+            .execute(request)
+            .await
+            .map_err(|source| GfroerliError::Request {
+                url: url.to_string(),
+                source,
+            })
+    }
+
+    /// Send a request and fail for responses with an error status
+    async fn send_checked(
+        &self,
+        url: &str,
+        request: RequestBuilder,
+    ) -> Result<Response, GfroerliError> {
+        let response = self.send(url, request).await?;
+        check_status(response)
+            .await
+            .map_err(|source| status_error(url, source))
+    }
+
+    /// Send a request and parse the JSON response
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        request: RequestBuilder,
+    ) -> Result<T, GfroerliError> {
+        let response = self.send(url, request).await?;
+        parse_json(url, response).await
+    }
+
+    /// Sends the water temperature of an observation to the measurements
+    /// endpoint
+    ///
+    /// Other parameters of the observation are included if an API field name
+    /// is configured for them. All values are rounded to the configured number
+    /// of decimal places, and a non-finite temperature is rejected.
+    ///
+    /// The request fails after `send_timeout_seconds`, if configured, instead
+    /// of the timeout of the HTTP client.
+    ///
+    /// Returns the ID assigned to the measurement by the API. A response
+    /// without a (valid) ID is logged, but not treated as an error, because
+    /// the measurement was accepted nevertheless. A measurement the API
+    /// already has isn't an error either.
+    pub async fn create_measurement(
+        &self,
+        observation: &StationObservation,
+        sensor_id: u32,
+        capture: Option<&Capture>,
+    ) -> Result<SendOutcome, GfroerliError> {
+        if !observation.temperature().is_finite() {
+            return Err(GfroerliError::InvalidTemperature {
+                sensor: sensor_id,
+                temperature: observation.temperature(),
+            });
+        }
+
+        let url = self.url(self.config.measurements_path());
+        let payload = MeasurementRequest {
+            sensor_id,
+            temperature: self.config.round(observation.temperature()),
+            created_at: observation.time(),
+            fields: additional_fields(self.config, observation),
+        };
+
+        debug!(
+            "Sending measurement to Gfrörli API for station {} (sensor {}): {}°C at {}",
+            observation.station_id,
+            sensor_id,
+            payload.temperature,
+            observation.time()
+        );
+
+        let mut request = self.request_json(Method::POST, &url, &payload).header(
+            "Idempotency-Key",
+            idempotency_key(sensor_id, observation.time()),
+        );
+        if let Some(seconds) = self.config.send_timeout_seconds {
+            request = request.timeout(Duration::from_secs(seconds));
+        }
+        let response = self.send(&url, request).await?;
 
-    if !response.status().is_success() {
         let status = response.status();
-        let error_text = response
+        let body = response
             .text()
             .await
             .unwrap_or_else(|_| "Unable to read error response".to_string());
-        return Err(anyhow::anyhow!(
-            "Gfrörli API request failed: HTTP {status} - {error_text}"
-        ));
+        if let Some(capture) = capture {
+            capture
+                .gfroerli_exchange(observation.station_id, &url, &payload, status, &body)
+                .await;
+        }
+
+        if status == StatusCode::CONFLICT {
+            debug!(
+                "Gfrörli API already has the measurement of station {} (sensor {}) at {}: {}",
+                observation.station_id,
+                sensor_id,
+                observation.time(),
+                body
+            );
+            return Ok(SendOutcome::AlreadyExists);
+        }
+        if !status.is_success() {
+            return Err(status_error(&url, HttpStatusError { status, body }));
+        }
+
+        match parse_measurement_id(&body) {
+            Ok(id) => Ok(SendOutcome::Created(Some(id))),
+            Err(e) => {
+                warn!(
+                    "Gfrörli API response for station {} (sensor {}) contains no measurement ID: {:#}",
+                    observation.station_id, sensor_id, e
+                );
+                Ok(SendOutcome::Created(None))
+            }
+        }
+    }
+
+    /// Fetches the most recent measurement of a sensor
+    pub async fn latest_measurement(
+        &self,
+        sensor_id: u32,
+    ) -> Result<Option<RemoteMeasurement>, GfroerliError> {
+        let url = self.url(&format!("sensors/{sensor_id}"));
+        debug!("Fetching sensor {} from Gfrörli API", sensor_id);
+        let sensor: SensorResponse = self
+            .send_json(&url, self.request(Method::GET, &url))
+            .await?;
+        Ok(sensor.last_measurement)
     }
 
-    Ok(())
+    /// Fetches the sensors visible to the API key
+    pub async fn sensors(&self) -> Result<Vec<RemoteSensor>, GfroerliError> {
+        let url = self.url("sensors");
+        debug!("Fetching sensors from Gfrörli API");
+        self.send_json(&url, self.request(Method::GET, &url)).await
+    }
+
+    /// Changes the name or caption of a sensor, returns the updated sensor
+    pub async fn update_sensor(
+        &self,
+        sensor_id: u32,
+        update: &SensorUpdate,
+    ) -> Result<RemoteSensor, GfroerliError> {
+        let url = self.url(&format!("sensors/{sensor_id}"));
+        debug!("Updating sensor {} in Gfrörli API", sensor_id);
+        let request = self.request_json(Method::PATCH, &url, update);
+        self.send_json(&url, request).await
+    }
+
+    /// Fetches the measurements of a sensor in a time range (inclusive)
+    pub async fn measurements(
+        &self,
+        sensor_id: u32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<RemoteMeasurement>, GfroerliError> {
+        let url = self.url(self.config.measurements_path());
+        debug!(
+            "Fetching measurements of sensor {} between {} and {} from Gfrörli API",
+            sensor_id, from, to
+        );
+        let request = self.request(Method::GET, &url).query(&[
+            ("sensor_id", sensor_id.to_string()),
+            ("created_after", from.to_rfc3339()),
+            ("created_before", to.to_rfc3339()),
+        ]);
+        self.send_json(&url, request).await
+    }
+
+    /// Checks that the API accepts the API key, by requesting the sensor list
+    pub async fn verify_auth(&self) -> Result<(), GfroerliError> {
+        let url = self.url("sensors");
+        self.send_checked(&url, self.request(Method::GET, &url))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Authorization header with a bearer token
+fn bearer(token: &str) -> Result<HeaderValue, GfroerliError> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+        .map_err(|source| GfroerliError::InvalidToken { source })?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+/// Error for a response with an error status, telling a rejected API key
+/// apart
+fn status_error(url: &str, source: HttpStatusError) -> GfroerliError {
+    let url = url.to_string();
+    match source.status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            GfroerliError::Unauthorized { url, source }
+        }
+        _ => GfroerliError::Status { url, source },
+    }
+}
+
+/// Parse the JSON body of a response, failing for an error status
+async fn parse_json<T: DeserializeOwned>(
+    url: &str,
+    response: Response,
+) -> Result<T, GfroerliError> {
+    check_status(response)
+        .await
+        .map_err(|source| status_error(url, source))?
+        .json()
+        .await
+        .map_err(|source| GfroerliError::InvalidResponse {
+            url: url.to_string(),
+            source,
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{GfroerliFieldsConfig, GfroerliOAuth2Config};
+    use crate::http::ErrorClass;
+    use crate::observation::{Parameter, ParameterValue};
     use chrono::{TimeZone, Utc};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_json, body_string, header, method, path},
+    };
+
+    fn api_config(server: &MockServer) -> GfroerliConfig {
+        GfroerliConfig {
+            api_url: format!("{}/api", server.uri()),
+            api_key: "test-api-key".into(),
+            auth: None,
+            oauth2: None,
+            api_version: None,
+            measurements_path: None,
+            sync_on_startup: None,
+            http: None,
+            send_timeout_seconds: None,
+            fields: None,
+            decimal_places: None,
+            dedup_tolerance_minutes: None,
+        }
+    }
+
+    fn observation(temperature: f32) -> StationObservation {
+        StationObservation::new(
+            2104,
+            "Weesen",
+            Utc.with_ymd_and_hms(2025, 1, 15, 11, 30, 0).unwrap(),
+            temperature,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_create_measurement() {
+        let server = MockServer::start().await;
+        let config = api_config(&server);
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+        let client = GfroerliClient::new(&http, &config, &tokens);
+
+        Mock::given(method("POST"))
+            .and(path("/api/measurements"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .and(header("Idempotency-Key", "lindas-1-1736940600"))
+            .and(body_json(serde_json::json!({
+                "sensor_id": 1,
+                "temperature": 6.5,
+                "created_at": "2025-01-15T11:30:00Z"
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({"id": 4711})))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/measurements"))
+            .respond_with(ResponseTemplate::new(409))
+            .mount(&server)
+            .await;
+
+        let outcome = client.create_measurement(&observation(6.5), 1, None).await;
+        assert_eq!(outcome.unwrap(), SendOutcome::Created(Some(4711)));
+        let outcome = client.create_measurement(&observation(6.5), 1, None).await;
+        assert_eq!(outcome.unwrap(), SendOutcome::AlreadyExists);
+
+        let error = client
+            .create_measurement(&observation(f32::NAN), 1, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, GfroerliError::InvalidTemperature { .. }));
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_timeout() {
+        let server = MockServer::start().await;
+        let mut config = api_config(&server);
+        config.send_timeout_seconds = Some(1);
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+        let client = GfroerliClient::new(&http, &config, &tokens);
+
+        let delayed = ResponseTemplate::new(200)
+            .set_body_json(serde_json::json!({"id": 1}))
+            .set_delay(Duration::from_millis(1500));
+        Mock::given(method("POST"))
+            .and(path("/api/measurements"))
+            .respond_with(delayed.clone())
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/sensors/1"))
+            .respond_with(delayed)
+            .mount(&server)
+            .await;
+
+        let error = client
+            .create_measurement(&observation(6.5), 1, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, GfroerliError::Request { ref source, .. } if source.is_timeout()));
+        assert_eq!(error.for_sensor(1).class(), ErrorClass::Retryable);
+
+        // Other requests only time out after the timeout of the client
+        client.latest_measurement(1).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let server = MockServer::start().await;
+        let config = api_config(&server);
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+        let client = GfroerliClient::new(&http, &config, &tokens);
+
+        Mock::given(method("GET"))
+            .and(path("/api/sensors/1"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("Invalid API key"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/sensors/2"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let error = client.latest_measurement(1).await.unwrap_err();
+        assert!(matches!(error, GfroerliError::Unauthorized { .. }));
+        assert_eq!(error.status(), Some(StatusCode::UNAUTHORIZED));
+        let error = error.for_sensor(1);
+        assert_eq!(error.class(), ErrorClass::Permanent);
+        assert!(
+            format!("{:#}", anyhow::Error::from(error))
+                .ends_with("rejected the API key: HTTP 401 Unauthorized - Invalid API key")
+        );
+
+        let error = client.latest_measurement(2).await.unwrap_err();
+        assert!(matches!(error, GfroerliError::Status { .. }));
+        assert_eq!(error.for_sensor(2).class(), ErrorClass::Retryable);
+    }
+
+    #[tokio::test]
+    async fn test_update_sensor() {
+        let server = MockServer::start().await;
+        let config = api_config(&server);
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+        let client = GfroerliClient::new(&http, &config, &tokens);
+
+        Mock::given(method("PATCH"))
+            .and(path("/api/sensors/1"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .and(body_json(serde_json::json!({"caption": "Linth, Weesen"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 1,
+                "device_name": "gfroerli-1",
+                "caption": "Linth, Weesen"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let update = SensorUpdate {
+            caption: Some("Linth, Weesen".to_string()),
+            ..Default::default()
+        };
+        let sensor = client.update_sensor(1, &update).await.unwrap();
+        assert_eq!(
+            sensor,
+            RemoteSensor {
+                id: 1,
+                device_name: Some("gfroerli-1".to_string()),
+                caption: Some("Linth, Weesen".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_bearer() {
+        let value = bearer("token").unwrap();
+        assert_eq!(value, "Bearer token");
+        assert!(value.is_sensitive());
+        assert!(matches!(
+            bearer("token\nX-Injected: 1"),
+            Err(GfroerliError::InvalidToken { .. })
+        ));
+    }
+
+    #[test]
+    fn test_hmac_signature() {
+        assert_eq!(
+            hmac_signature("shared-secret", 1736940600, br#"{"sensor_id":1}"#),
+            "sha256=e461bd22151bb6d80a0a1e7806beff5021cf3571262261813480a726797ff381"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hmac_auth() {
+        let server = MockServer::start().await;
+        let mut config = api_config(&server);
+        config.auth = Some(GfroerliAuth::Hmac);
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+        let client = GfroerliClient::new(&http, &config, &tokens);
+
+        Mock::given(method("POST"))
+            .and(path("/api/measurements"))
+            .and(header("Content-Type", "application/json"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({"id": 1})))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/sensors"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+
+        client
+            .create_measurement(&observation(6.5), 1, None)
+            .await
+            .unwrap();
+        client.verify_auth().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        for request in requests {
+            assert!(!request.headers.contains_key("Authorization"));
+            let timestamp: i64 = request.headers[TIMESTAMP_HEADER.as_str()]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!((Utc::now().timestamp() - timestamp).abs() < 60);
+            assert_eq!(
+                request.headers[SIGNATURE_HEADER.as_str()].to_str().unwrap(),
+                hmac_signature("test-api-key", timestamp, &request.body)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_auth() {
+        let server = MockServer::start().await;
+        let mut config = api_config(&server);
+        config.auth = Some(GfroerliAuth::OAuth2);
+        config.oauth2 = Some(GfroerliOAuth2Config {
+            token_url: format!("{}/oauth/token", server.uri()),
+            client_id: "lindas-fetcher".to_string(),
+            scope: Some("measurements:write".to_string()),
+        });
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+        let client = GfroerliClient::new(&http, &config, &tokens);
+
+        Mock::given(method("POST"))
+            .and(path("/oauth/token"))
+            .and(header(
+                "Authorization",
+                "Basic bGluZGFzLWZldGNoZXI6dGVzdC1hcGkta2V5",
+            ))
+            .and(body_string(
+                "grant_type=client_credentials&scope=measurements%3Awrite",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "first-token",
+                "token_type": "Bearer",
+                "expires_in": 3600
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/oauth/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "second-token",
+                "token_type": "Bearer"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/sensors"))
+            .and(header("Authorization", "Bearer first-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        // The first token is revoked after two requests
+        Mock::given(method("GET"))
+            .and(path("/api/sensors"))
+            .and(header("Authorization", "Bearer first-token"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/sensors"))
+            .and(header("Authorization", "Bearer second-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        // The token is requested once and cached
+        client.sensors().await.unwrap();
+        client.sensors().await.unwrap();
+        // A rejected token is replaced and the request repeated
+        client.sensors().await.unwrap();
+        client.sensors().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_token_rejected() {
+        let server = MockServer::start().await;
+        let mut config = api_config(&server);
+        config.auth = Some(GfroerliAuth::OAuth2);
+        config.oauth2 = Some(GfroerliOAuth2Config {
+            token_url: format!("{}/oauth/token", server.uri()),
+            client_id: "lindas-fetcher".to_string(),
+            scope: None,
+        });
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+        let client = GfroerliClient::new(&http, &config, &tokens);
+
+        Mock::given(method("POST"))
+            .and(path("/oauth/token"))
+            .and(body_string("grant_type=client_credentials"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("invalid_client"))
+            .mount(&server)
+            .await;
+
+        let error = client.sensors().await.unwrap_err();
+        assert!(
+            matches!(error, GfroerliError::Unauthorized { ref url, .. } if url.ends_with("/oauth/token"))
+        );
+        assert_eq!(error.for_sensor(1).class(), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn test_access_token_expiry() {
+        assert!(AccessToken::new("token".into(), None).is_valid());
+        assert!(AccessToken::new("token".into(), Some(3600)).is_valid());
+        // Tokens are refreshed before they expire
+        assert!(!AccessToken::new("token".into(), Some(0)).is_valid());
+        let token = AccessToken::new("token".into(), Some(60));
+        let validity = token.refresh_at.unwrap() - Instant::now();
+        assert!(validity <= Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn test_verify_auth() {
+        let server = MockServer::start().await;
+        let mut config = api_config(&server);
+        let http = HttpClient::default();
+        let tokens = TokenCache::default();
+
+        Mock::given(method("GET"))
+            .and(path("/api/sensors"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/sensors"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        GfroerliClient::new(&http, &config, &tokens)
+            .verify_auth()
+            .await
+            .unwrap();
+        config.api_key = "wrong-key".into();
+        let error = GfroerliClient::new(&http, &config, &tokens)
+            .verify_auth()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, GfroerliError::Unauthorized { .. }));
+    }
 
     #[test]
     fn test_build_api_url_with_trailing_slash() {
-        let url = build_api_url("http://localhost:3000/api/", "measurements");
+        let url = build_api_url("http://localhost:3000/api/", None, "measurements");
         assert_eq!(url, "http://localhost:3000/api/measurements");
     }
 
     #[test]
     fn test_build_api_url_without_trailing_slash() {
-        let url = build_api_url("http://localhost:3000/api", "measurements");
+        let url = build_api_url("http://localhost:3000/api", None, "measurements");
+        assert_eq!(url, "http://localhost:3000/api/measurements");
+    }
+
+    #[test]
+    fn test_build_api_url_with_version() {
+        let url = build_api_url("http://localhost:3000/api/", Some("v2/"), "/measurements");
+        assert_eq!(url, "http://localhost:3000/api/v2/measurements");
+        let url = build_api_url("http://localhost:3000/api", Some(""), "measurements");
         assert_eq!(url, "http://localhost:3000/api/measurements");
     }
 
+    #[test]
+    fn test_parse_measurement_id() {
+        assert_eq!(
+            parse_measurement_id(r#"{"id": 4711, "sensor_id": 1}"#).unwrap(),
+            4711
+        );
+        assert!(parse_measurement_id("").is_err());
+        assert!(parse_measurement_id(r#"{"status": "ok"}"#).is_err());
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 11, 30, 0).unwrap();
+        assert_eq!(idempotency_key(1, time), "lindas-1-1736940600");
+        assert_ne!(idempotency_key(2, time), idempotency_key(1, time));
+    }
+
     #[test]
     fn test_measurement_request_serialization() {
         let timestamp = Utc.with_ymd_and_hms(2023, 1, 1, 12, 30, 45).unwrap();
@@ -90,6 +1107,7 @@ mod tests {
             sensor_id: 1,
             temperature: 20.7,
             created_at: timestamp,
+            fields: BTreeMap::new(),
         };
 
         let json = serde_json::to_string(&request).unwrap();
@@ -97,4 +1115,66 @@ mod tests {
         assert!(json.contains("\"temperature\":20.7"));
         assert!(json.contains("\"created_at\":\"2023-01-01T12:30:45Z\""));
     }
+
+    #[test]
+    fn test_additional_fields() {
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 11, 30, 0).unwrap();
+        let mut observation = StationObservation::new(2104, "Weesen", time, 6.5);
+        observation.set(
+            Parameter::WaterLevel,
+            ParameterValue {
+                value: 419.25,
+                time,
+            },
+        );
+        observation.set(Parameter::Discharge, ParameterValue { value: 35.5, time });
+        let mut config = GfroerliConfig {
+            api_url: "http://localhost:3000/api".to_string(),
+            api_key: "test-api-key".into(),
+            auth: None,
+            oauth2: None,
+            api_version: None,
+            measurements_path: None,
+            sync_on_startup: None,
+            http: None,
+            send_timeout_seconds: None,
+            fields: None,
+            decimal_places: None,
+            dedup_tolerance_minutes: None,
+        };
+        assert!(additional_fields(&config, &observation).is_empty());
+
+        config.fields = Some(GfroerliFieldsConfig {
+            water_level: Some("level".to_string()),
+            discharge: None,
+            air_temperature: Some("air".to_string()),
+        });
+        // Non-finite values are left out
+        observation.set(
+            Parameter::AirTemperature,
+            ParameterValue {
+                value: f32::NAN,
+                time,
+            },
+        );
+        let request = MeasurementRequest {
+            sensor_id: 1,
+            temperature: 6.5,
+            created_at: time,
+            fields: additional_fields(&config, &observation),
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "sensor_id": 1,
+                "temperature": 6.5,
+                "created_at": "2025-01-15T11:30:00Z",
+                "level": 419.25
+            })
+        );
+
+        config.decimal_places = Some(1);
+        let fields = serde_json::to_string(&additional_fields(&config, &observation)).unwrap();
+        assert_eq!(fields, r#"{"level":419.3}"#);
+    }
 }
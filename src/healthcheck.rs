@@ -0,0 +1,128 @@
+//! Dead man's switch pings in the style of healthchecks.io
+
+use anyhow::{Context, Result};
+use tracing::warn;
+
+use crate::{
+    config::Config,
+    http::{HttpClient, check_status},
+    pipeline::CycleOutcome,
+    secret::SecretString,
+};
+
+/// Signal sent to the healthcheck URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ping {
+    /// A run or cycle started (`<url>/start`)
+    Start,
+    /// A run or cycle finished successfully (`<url>`)
+    Success,
+    /// A run or cycle failed (`<url>/fail`)
+    Fail,
+}
+
+impl Ping {
+    /// URL of the signal for a healthcheck URL
+    fn url(self, url: &str) -> String {
+        let url = url.trim_end_matches('/');
+        match self {
+            Ping::Start => format!("{url}/start"),
+            Ping::Success => url.to_string(),
+            Ping::Fail => format!("{url}/fail"),
+        }
+    }
+
+    /// Signal and message for the outcome of a run or cycle
+    ///
+    /// Whether it failed follows `fail_on` of the `[run]` section, like the
+    /// exit code in oneshot mode. Cancelled stations count as failed.
+    pub fn for_outcome(config: &Config, outcome: &CycleOutcome) -> (Self, String) {
+        let errors = outcome.errors + outcome.cancelled;
+        let ping = if config.run_fail_on().is_failure(outcome.success, errors) {
+            Ping::Fail
+        } else {
+            Ping::Success
+        };
+        let message = format!("{} stations succeeded, {} failed", outcome.success, errors);
+        (ping, message)
+    }
+}
+
+/// Send a signal with a message, which is shown in the log of the check
+///
+/// The URL is left out of errors, as it identifies the check.
+pub async fn send(
+    client: &HttpClient,
+    url: &SecretString,
+    ping: Ping,
+    message: &str,
+) -> Result<()> {
+    let request = client
+        .post(&ping.url(url.expose()))
+        .body(message.to_string());
+    let response = client
+        .send(request)
+        .await
+        .map_err(|e| e.without_url())
+        .with_context(|| "Failed to ping healthcheck")?;
+    check_status(response)
+        .await
+        .with_context(|| "Healthcheck rejected the ping")?;
+    Ok(())
+}
+
+/// Ping the configured healthcheck URL, if any
+///
+/// Failures are only logged, as monitoring must not stop the fetcher. Dry runs
+/// don't ping, so that they don't hide a stopped production instance.
+pub async fn ping(client: &HttpClient, config: &Config, ping: Ping, message: &str, dry_run: bool) {
+    let Some(url) = config.healthcheck_url() else {
+        return;
+    };
+    if dry_run {
+        return;
+    }
+    if let Err(e) = send(client, url, ping, message).await {
+        warn!("Failed to ping healthcheck: {:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GfroerliConfig;
+
+    #[test]
+    fn test_ping_url() {
+        let url = "https://hc-ping.com/1234";
+        assert_eq!(Ping::Start.url(url), "https://hc-ping.com/1234/start");
+        assert_eq!(Ping::Success.url(url), "https://hc-ping.com/1234");
+        assert_eq!(
+            Ping::Fail.url("https://hc-ping.com/1234/"),
+            "https://hc-ping.com/1234/fail"
+        );
+    }
+
+    #[test]
+    fn test_for_outcome() {
+        let config = Config::new(
+            Vec::new(),
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        let outcome = CycleOutcome {
+            success: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            Ping::for_outcome(&config, &outcome),
+            (Ping::Success, "2 stations succeeded, 0 failed".to_string())
+        );
+
+        let outcome = CycleOutcome {
+            success: 2,
+            cancelled: 1,
+            ..Default::default()
+        };
+        assert_eq!(Ping::for_outcome(&config, &outcome).0, Ping::Fail);
+    }
+}
@@ -0,0 +1,590 @@
+//! Shared HTTP helpers
+
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use anyhow::{Context, Result, anyhow};
+use reqwest::{
+    Client, Method, Proxy, Request, RequestBuilder, Response, StatusCode, Url,
+    header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue, PROXY_AUTHORIZATION},
+};
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::{
+    config::{Config, HttpClientConfig},
+    error::FetcherError,
+    gfroerli::TokenCache,
+    notify::Notifier,
+    secret::SecretString,
+};
+
+/// User agent sent with all requests, e.g. "lindas-hydrodata-fetcher/0.1.0"
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Maximum number of bytes of a request or response body that are logged
+/// when tracing requests
+const TRACE_BODY_LIMIT: usize = 2048;
+
+/// Separately configured HTTP clients for every endpoint
+#[derive(Debug, Clone)]
+pub struct HttpClients {
+    /// Client for the SPARQL endpoint
+    pub sparql: HttpClient,
+    /// Clients for the Gfrörli API targets by name
+    gfroerli: BTreeMap<String, HttpClient>,
+    /// OAuth2 access tokens of the Gfrörli API targets by name
+    gfroerli_tokens: BTreeMap<String, TokenCache>,
+    /// Channels for notifications (webhooks and email)
+    pub notifications: Notifier,
+    /// Client for monitoring integrations (Pushgateway and healthcheck pings),
+    /// never traced as the URLs may contain credentials
+    pub monitoring: HttpClient,
+    /// Client for the webhook sinks, never traced as the URLs and headers
+    /// may contain credentials
+    pub sinks: HttpClient,
+}
+
+impl HttpClients {
+    /// Build the clients according to the endpoint settings in the config
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let trace = config.http_trace_requests();
+        Ok(Self {
+            sparql: build_client(config.sparql_http())
+                .map(|client| HttpClient::new(client, trace))
+                .with_context(|| "Failed to build HTTP client for SPARQL endpoint")?,
+            gfroerli: config
+                .gfroerli_target_configs()
+                .into_iter()
+                .map(|(name, api)| {
+                    let client = build_client(api.http.as_ref()).with_context(|| {
+                        format!("Failed to build HTTP client for Gfrörli target '{name}'")
+                    })?;
+                    Ok((name.to_string(), HttpClient::new(client, trace)))
+                })
+                .collect::<Result<_>>()?,
+            gfroerli_tokens: config
+                .gfroerli_target_configs()
+                .into_iter()
+                .map(|(name, _)| (name.to_string(), TokenCache::default()))
+                .collect(),
+            notifications: Notifier::from_config(config)?,
+            monitoring: build_client(None)
+                .map(|client| HttpClient::new(client, false))
+                .with_context(|| "Failed to build HTTP client for monitoring")?,
+            sinks: build_client(None)
+                .map(|client| HttpClient::new(client, false))
+                .with_context(|| "Failed to build HTTP client for sinks")?,
+        })
+    }
+
+    /// Get the client for a Gfrörli target
+    pub fn gfroerli(&self, target: &str) -> Result<&HttpClient> {
+        self.gfroerli
+            .get(target)
+            .ok_or_else(|| anyhow!("Unknown Gfrörli target '{target}'"))
+    }
+
+    /// Get the access token cache of a Gfrörli target
+    pub fn gfroerli_tokens(&self, target: &str) -> Result<&TokenCache> {
+        self.gfroerli_tokens
+            .get(target)
+            .ok_or_else(|| anyhow!("Unknown Gfrörli target '{target}'"))
+    }
+}
+
+/// HTTP client for an endpoint, which optionally logs every request
+///
+/// Requests are built with [`HttpClient::get`] or [`HttpClient::post`] and
+/// sent with [`HttpClient::send`]. With tracing enabled, the method, URL,
+/// headers, status, duration and the beginning of the bodies are logged.
+/// Authorization headers are redacted.
+#[derive(Debug, Clone, Default)]
+pub struct HttpClient {
+    client: Client,
+    trace: bool,
+}
+
+impl HttpClient {
+    /// Wrap a client, logging its requests if `trace` is set
+    pub fn new(client: Client, trace: bool) -> Self {
+        Self { client, trace }
+    }
+
+    /// Start building a GET request
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// Start building a POST request
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Start building a PUT request
+    pub fn put(&self, url: &str) -> RequestBuilder {
+        self.client.put(url)
+    }
+
+    /// Start building a request with any method
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// The same client without tracing, for requests whose responses
+    /// contain credentials
+    pub fn untraced(&self) -> HttpClient {
+        Self::new(self.client.clone(), false)
+    }
+
+    /// Send a request
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        if !self.trace {
+            return request.send().await;
+        }
+        // Boxed, so that the logging doesn't enlarge the futures of all
+        // requests
+        Box::pin(self.execute(request.build()?)).await
+    }
+
+    /// Send a request that was already built
+    pub async fn execute(&self, request: Request) -> reqwest::Result<Response> {
+        if !self.trace {
+            return self.client.execute(request).await;
+        }
+
+        let method = request.method().clone();
+        let url = request.url().clone();
+        trace_request(&request);
+
+        let start = Instant::now();
+        let response = match self.client.execute(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                info!(
+                    "HTTP {} {} failed after {} ms: {}",
+                    method,
+                    url,
+                    start.elapsed().as_millis(),
+                    e
+                );
+                return Err(e);
+            }
+        };
+        trace_response(&method, &url, start, response).await
+    }
+}
+
+/// Log an outgoing request
+fn trace_request(request: &Request) {
+    let body = request
+        .body()
+        .map(|body| match body.as_bytes() {
+            Some(bytes) => format_body(bytes),
+            None => "(stream)".to_string(),
+        })
+        .unwrap_or_else(|| format_body(&[]));
+    info!(
+        "HTTP {} {} request headers: [{}], body: {}",
+        request.method(),
+        request.url(),
+        format_headers(request.headers()),
+        body
+    );
+}
+
+/// Log a response and return an equivalent response, as the body has to be
+/// read for logging
+async fn trace_response(
+    method: &Method,
+    url: &Url,
+    start: Instant,
+    response: Response,
+) -> reqwest::Result<Response> {
+    let status = response.status();
+    let version = response.version();
+    let headers = response.headers().clone();
+    let body = response.bytes().await?;
+    info!(
+        "HTTP {} {} responded {} in {} ms, headers: [{}], body: {}",
+        method,
+        url,
+        status,
+        start.elapsed().as_millis(),
+        format_headers(&headers),
+        format_body(&body)
+    );
+
+    let mut rebuilt = http::Response::new(body);
+    *rebuilt.status_mut() = status;
+    *rebuilt.version_mut() = version;
+    *rebuilt.headers_mut() = headers;
+    Ok(Response::from(rebuilt))
+}
+
+/// Format headers for logging, with credentials redacted
+fn format_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value =
+                if value.is_sensitive() || name == AUTHORIZATION || name == PROXY_AUTHORIZATION {
+                    "[REDACTED]"
+                } else {
+                    value.to_str().unwrap_or("(binary)")
+                };
+            format!("{name}: {value}")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Format a body for logging, truncated to [`TRACE_BODY_LIMIT`] bytes
+fn format_body(body: &[u8]) -> String {
+    if body.is_empty() {
+        return "(empty)".to_string();
+    }
+    let text = String::from_utf8_lossy(&body[..body.len().min(TRACE_BODY_LIMIT)]);
+    if body.len() > TRACE_BODY_LIMIT {
+        format!("{text}... ({} bytes)", body.len())
+    } else {
+        text.into_owned()
+    }
+}
+
+/// Build an HTTP client with the given settings (or the defaults)
+pub fn build_client(config: Option<&HttpClientConfig>) -> Result<Client> {
+    let default = HttpClientConfig::default();
+    let config = config.unwrap_or(&default);
+
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(config.timeout_seconds()))
+        .user_agent(USER_AGENT)
+        .gzip(config.compression())
+        .brotli(config.compression());
+    if let Some(proxy) = &config.proxy {
+        // The URL may contain credentials, so it isn't included in the error
+        builder = builder.proxy(Proxy::all(proxy.expose()).with_context(|| "Invalid proxy URL")?);
+    }
+    if let Some(headers) = &config.headers {
+        builder = builder.default_headers(build_headers(headers)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Build the additional headers of an endpoint
+///
+/// The values are marked as sensitive, as they may contain shared secrets.
+pub fn build_headers(headers: &BTreeMap<String, SecretString>) -> Result<HeaderMap> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name '{name}'"))?;
+            let mut value = HeaderValue::from_str(value.expose())
+                .with_context(|| format!("Invalid value for header '{name}'"))?;
+            value.set_sensitive(true);
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Error for a response with a non-success HTTP status code
+#[derive(Debug)]
+pub struct HttpStatusError {
+    /// Status code returned by the server
+    pub status: StatusCode,
+    /// Response body (if it could be read)
+    pub body: String,
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP {} - {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Turn a non-success response into an [`HttpStatusError`]
+pub async fn check_status(response: Response) -> Result<Response, HttpStatusError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Unable to read error response".to_string());
+    Err(HttpStatusError { status, body })
+}
+
+/// Find the HTTP status code of a failed request anywhere in an error chain
+pub fn error_status(error: &anyhow::Error) -> Option<StatusCode> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<HttpStatusError>())
+        .map(|e| e.status)
+}
+
+/// Whether a failed request may succeed when it is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Temporary failure: timeouts, connection errors, 408, 429 and 5xx
+    Retryable,
+    /// Failure that repeats until the configuration or the request is fixed,
+    /// e.g. 400, 401, 403, 404 or 422
+    Permanent,
+}
+
+impl ErrorClass {
+    /// Classify a non-success status code
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => ErrorClass::Retryable,
+            status if status.is_client_error() => ErrorClass::Permanent,
+            _ => ErrorClass::Retryable,
+        }
+    }
+
+    /// Classify an error by the first HTTP status code or request error in
+    /// its chain
+    ///
+    /// Other errors (e.g. a malformed response) are retryable, as they are
+    /// usually caused by a temporary problem of the server.
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<FetcherError>() {
+                return e.class();
+            }
+            if let Some(e) = cause.downcast_ref::<HttpStatusError>() {
+                return Self::from_status(e.status);
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                return match e.status() {
+                    Some(status) => Self::from_status(status),
+                    // The request couldn't be built, e.g. because of an invalid URL
+                    None if e.is_builder() => ErrorClass::Permanent,
+                    None => ErrorClass::Retryable,
+                };
+            }
+        }
+        ErrorClass::Retryable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, header_exists, headers, method, path},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_error_status_in_chain() {
+        let error = Err::<(), _>(HttpStatusError {
+            status: StatusCode::BAD_GATEWAY,
+            body: "upstream down".to_string(),
+        })
+        .context("SPARQL query failed")
+        .context("Error fetching data")
+        .unwrap_err();
+
+        assert_eq!(error_status(&error), Some(StatusCode::BAD_GATEWAY));
+        assert_eq!(
+            format!("{error:#}"),
+            "Error fetching data: SPARQL query failed: HTTP 502 Bad Gateway - upstream down"
+        );
+    }
+
+    #[test]
+    fn test_error_class() {
+        let class = |status: StatusCode| {
+            let error = Err::<(), _>(HttpStatusError {
+                status,
+                body: String::new(),
+            })
+            .context("Gfrörli API request failed")
+            .unwrap_err();
+            ErrorClass::of(&error)
+        };
+        assert_eq!(class(StatusCode::REQUEST_TIMEOUT), ErrorClass::Retryable);
+        assert_eq!(class(StatusCode::TOO_MANY_REQUESTS), ErrorClass::Retryable);
+        assert_eq!(
+            class(StatusCode::SERVICE_UNAVAILABLE),
+            ErrorClass::Retryable
+        );
+        assert_eq!(class(StatusCode::BAD_REQUEST), ErrorClass::Permanent);
+        assert_eq!(class(StatusCode::UNAUTHORIZED), ErrorClass::Permanent);
+        assert_eq!(class(StatusCode::FORBIDDEN), ErrorClass::Permanent);
+        assert_eq!(
+            class(StatusCode::UNPROCESSABLE_ENTITY),
+            ErrorClass::Permanent
+        );
+        assert_eq!(
+            ErrorClass::of(&anyhow!("Invalid SPARQL response")),
+            ErrorClass::Retryable
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_class_of_request_error() {
+        // Nothing listens on port 9 (discard) of localhost
+        let error = Client::new()
+            .get("http://127.0.0.1:9/")
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorClass::of(&error.into()), ErrorClass::Retryable);
+    }
+
+    #[test]
+    fn test_build_client_invalid_proxy() {
+        let config = HttpClientConfig {
+            timeout_seconds: None,
+            proxy: Some("not a url".into()),
+            headers: None,
+            compression: None,
+        };
+        assert!(build_client(Some(&config)).is_err());
+        assert!(build_client(None).is_ok());
+    }
+
+    #[test]
+    fn test_format_headers_redacted() {
+        let request = Client::new()
+            .get("http://localhost/")
+            .bearer_auth("secret-key")
+            .header("Idempotency-Key", "1-2")
+            .header("Proxy-Authorization", "Basic c2VjcmV0")
+            .build()
+            .unwrap();
+        let headers = format_headers(request.headers());
+        assert!(!headers.contains("secret"));
+        assert!(!headers.contains("c2VjcmV0"));
+        assert!(headers.contains("authorization: [REDACTED]"));
+        assert!(headers.contains("idempotency-key: 1-2"));
+    }
+
+    #[test]
+    fn test_format_body() {
+        assert_eq!(format_body(b""), "(empty)");
+        assert_eq!(format_body(b"{\"id\":1}"), "{\"id\":1}");
+        let long = "x".repeat(TRACE_BODY_LIMIT + 10);
+        assert_eq!(
+            format_body(long.as_bytes()),
+            format!(
+                "{}... ({} bytes)",
+                &long[..TRACE_BODY_LIMIT],
+                TRACE_BODY_LIMIT + 10
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traced_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/measurements"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .insert_header("X-Request-Id", "abc")
+                    .set_body_string("{\"id\":42}"),
+            )
+            .mount(&server)
+            .await;
+
+        // The response is still complete after its body was logged
+        let client = HttpClient::new(Client::new(), true);
+        let request = client
+            .post(&format!("{}/measurements", server.uri()))
+            .bearer_auth("secret-key")
+            .body("{\"temperature\":6.5}");
+        let response = client.send(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["X-Request-Id"], "abc");
+        assert_eq!(response.text().await.unwrap(), "{\"id\":42}");
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_and_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("User-Agent", USER_AGENT))
+            .and(header("X-Allow-List", "fetcher"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let config = HttpClientConfig {
+            timeout_seconds: None,
+            proxy: None,
+            headers: Some(BTreeMap::from([(
+                "X-Allow-List".to_string(),
+                "fetcher".into(),
+            )])),
+            compression: None,
+        };
+        let client = build_client(Some(&config)).unwrap();
+        let response = client.get(server.uri()).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let config = HttpClientConfig {
+            headers: Some(BTreeMap::from([("Invalid Header".to_string(), "x".into())])),
+            ..HttpClientConfig::default()
+        };
+        assert!(build_client(Some(&config)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/compressed"))
+            .and(headers("Accept-Encoding", vec!["gzip", "br"]))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/plain"))
+            .and(header_exists("Accept-Encoding"))
+            .respond_with(ResponseTemplate::new(406))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/plain"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let client = build_client(None).unwrap();
+        let response = client
+            .get(format!("{}/compressed", server.uri()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let config = HttpClientConfig {
+            compression: Some(false),
+            ..HttpClientConfig::default()
+        };
+        let client = build_client(Some(&config)).unwrap();
+        let response = client
+            .get(format!("{}/plain", server.uri()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_error_status_missing() {
+        let error = anyhow::anyhow!("connection refused");
+        assert_eq!(error_status(&error), None);
+    }
+}
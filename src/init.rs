@@ -0,0 +1,310 @@
+//! Creation of a starter configuration file (`init` subcommand)
+
+use std::{
+    fs::OpenOptions,
+    io::{self, BufRead, IsTerminal, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use crate::{
+    config::{Config, ConfigFormat, GfroerliConfig, StationConfig},
+    http::{HttpClient, build_client},
+    parsing::DiscoveredStation,
+    sparql::{SparqlEndpoint, discover_stations},
+};
+
+/// Settings given on the command line, missing ones are asked for interactively
+#[derive(Debug, Default)]
+pub struct InitOptions {
+    /// Gfrörli API base URL
+    pub api_url: Option<String>,
+    /// Gfrörli private API key
+    pub api_key: Option<String>,
+    /// Stations as (FOEN station ID, Gfrörli sensor ID)
+    pub stations: Vec<(u32, u32)>,
+    /// Overwrite an existing configuration file
+    pub force: bool,
+}
+
+impl InitOptions {
+    /// Whether all settings were given, so that nothing has to be asked for
+    fn is_complete(&self) -> bool {
+        self.api_url.is_some() && self.api_key.is_some() && !self.stations.is_empty()
+    }
+}
+
+/// Parse a station given as `<FOEN station ID>=<Gfrörli sensor ID>`
+pub fn parse_station_mapping(s: &str) -> Result<(u32, u32)> {
+    let (station, sensor) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected <FOEN station ID>=<Gfrörli sensor ID>"))?;
+    let station = station
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid FOEN station ID '{station}'"))?;
+    let sensor = sensor
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid Gfrörli sensor ID '{sensor}'"))?;
+    Ok((station, sensor))
+}
+
+/// Create a starter configuration file at `path`
+///
+/// Settings missing from `options` are asked for on the terminal, where the
+/// stations can be picked from the stations listed by LINDAS. The
+/// configuration is validated before it is written, and the file is only
+/// readable by the owner because it contains the API key.
+pub async fn init(path: &Path, options: InitOptions) -> Result<()> {
+    if path.exists() && !options.force {
+        bail!(
+            "'{}' already exists (use --force to overwrite it)",
+            path.display()
+        );
+    }
+
+    let mut discovered = Vec::new();
+    if !options.is_complete() {
+        if !io::stdin().is_terminal() {
+            bail!(
+                "--api-url, --api-key and at least one --station are required when not running interactively"
+            );
+        }
+        if options.stations.is_empty() {
+            discovered = discover().await;
+        }
+    }
+
+    let config = prompt_config(
+        options,
+        &discovered,
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+    )?;
+    config.validate()?;
+    write_config(path, &config)?;
+
+    // Make sure the written file can be loaded by the fetcher
+    Config::load_from_file(path)?;
+    println!(
+        "Wrote configuration with {} stations to '{}'",
+        config.stations.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// List the stations measuring the water temperature, or none if LINDAS
+/// can't be queried
+async fn discover() -> Vec<DiscoveredStation> {
+    let stations = match build_client(None) {
+        Ok(client) => {
+            let client = HttpClient::new(client, false);
+            discover_stations(&client, &SparqlEndpoint::default()).await
+        }
+        Err(e) => Err(e),
+    };
+    stations.unwrap_or_else(|e| {
+        eprintln!("Failed to list the stations from LINDAS: {e:#}");
+        Vec::new()
+    })
+}
+
+/// Complete the options by asking for the missing settings
+fn prompt_config(
+    options: InitOptions,
+    discovered: &[DiscoveredStation],
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Config> {
+    let api_url = match options.api_url {
+        Some(api_url) => api_url,
+        None => prompt_required(input, output, "Gfrörli API URL")?,
+    };
+    let api_key = match options.api_key {
+        Some(api_key) => api_key,
+        None => prompt_required(input, output, "Gfrörli API key")?,
+    };
+
+    let mut stations: Vec<_> = options
+        .stations
+        .iter()
+        .map(|&(station, sensor)| station_config(station, sensor, discovered))
+        .collect();
+    if stations.is_empty() {
+        if !discovered.is_empty() {
+            writeln!(output, "Stations measuring the water temperature:")?;
+            for station in discovered {
+                writeln!(output, "{:>7}  {}", station.id, station.name)?;
+            }
+        }
+        writeln!(
+            output,
+            "Enter the stations to fetch, an empty station ID finishes"
+        )?;
+
+        loop {
+            let station = prompt(input, output, "FOEN station ID")?;
+            if station.is_empty() {
+                if stations.is_empty() {
+                    writeln!(output, "At least one station is required")?;
+                    continue;
+                }
+                break;
+            }
+            let Ok(station) = station.parse::<u32>() else {
+                writeln!(output, "Invalid station ID '{station}'")?;
+                continue;
+            };
+            if !discovered.is_empty() && !discovered.iter().any(|s| s.id == station) {
+                writeln!(
+                    output,
+                    "Station {station} doesn't measure the water temperature"
+                )?;
+                continue;
+            }
+            let sensor = loop {
+                let sensor = prompt_required(input, output, "Gfrörli sensor ID")?;
+                match sensor.parse::<u32>() {
+                    Ok(sensor) => break sensor,
+                    Err(_) => writeln!(output, "Invalid sensor ID '{sensor}'")?,
+                }
+            };
+            stations.push(station_config(station, sensor, discovered));
+        }
+    }
+
+    Ok(Config::new(
+        stations,
+        GfroerliConfig::new(api_url, api_key.into()),
+    ))
+}
+
+/// Station configuration, with the name of the discovered station as alias
+fn station_config(station: u32, sensor: u32, discovered: &[DiscoveredStation]) -> StationConfig {
+    StationConfig {
+        foen_station_id: station,
+        gfroerli_sensor_id: sensor,
+        targets: None,
+        alias: discovered
+            .iter()
+            .find(|s| s.id == station)
+            .map(|s| s.name.clone()),
+        enabled: None,
+        station_type: None,
+        air_temperature: None,
+        alerts: None,
+        smoothing: None,
+    }
+}
+
+/// Ask for a value, which may be empty
+fn prompt(input: &mut impl BufRead, output: &mut impl Write, label: &str) -> Result<String> {
+    write!(output, "{label}: ")?;
+    output.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        bail!("Unexpected end of input");
+    }
+    Ok(line.trim().to_string())
+}
+
+/// Ask for a value until a non-empty one is entered
+fn prompt_required(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    label: &str,
+) -> Result<String> {
+    loop {
+        let value = prompt(input, output, label)?;
+        if !value.is_empty() {
+            return Ok(value);
+        }
+    }
+}
+
+/// Write the configuration in the format matching the extension, readable
+/// only by the owner
+fn write_config(path: &Path, config: &Config) -> Result<()> {
+    let format = ConfigFormat::from_path(path);
+    let mut content = format.serialize(config)?;
+    if format != ConfigFormat::Json {
+        content.insert_str(
+            0,
+            "# Generated by `lindas-hydrodata-fetcher init`, see config.example.toml for all options\n\n",
+        );
+    }
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .with_context(|| format!("Failed to write config file '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, os::unix::fs::PermissionsExt};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_station_mapping() {
+        assert_eq!(parse_station_mapping("2104=1").unwrap(), (2104, 1));
+        assert_eq!(parse_station_mapping(" 2176 = 2 ").unwrap(), (2176, 2));
+        assert!(parse_station_mapping("2104").is_err());
+        assert!(parse_station_mapping("2104=x").is_err());
+    }
+
+    #[test]
+    fn test_prompt_config() {
+        let discovered = vec![DiscoveredStation {
+            id: 2104,
+            name: "Linth - Weesen".to_string(),
+        }];
+        let mut input =
+            Cursor::new("http://localhost:3000/api\n\nkey\n\n9999\nabc\n2104\nx\n1\n\n");
+        let mut output = Vec::new();
+        let config =
+            prompt_config(InitOptions::default(), &discovered, &mut input, &mut output).unwrap();
+
+        assert_eq!(config.gfroerli_api.api_url, "http://localhost:3000/api");
+        assert_eq!(config.gfroerli_api.api_key.expose(), "key");
+        assert_eq!(config.stations.len(), 1);
+        assert_eq!(config.stations[0].foen_station_id, 2104);
+        assert_eq!(config.stations[0].gfroerli_sensor_id, 1);
+        assert_eq!(config.stations[0].alias.as_deref(), Some("Linth - Weesen"));
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("At least one station is required"));
+        assert!(output.contains("Station 9999 doesn't measure the water temperature"));
+        assert!(output.contains("Invalid station ID 'abc'"));
+        assert!(output.contains("Invalid sensor ID 'x'"));
+    }
+
+    #[test]
+    fn test_write_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let options = InitOptions {
+            api_url: Some("http://localhost:3000/api".to_string()),
+            api_key: Some("key".to_string()),
+            stations: vec![(2104, 1), (2176, 2)],
+            force: false,
+        };
+        // Complete options don't read any input
+        let config = prompt_config(options, &[], &mut io::empty(), &mut io::sink()).unwrap();
+        write_config(&path, &config).unwrap();
+
+        let loaded = Config::load_from_file(&path).unwrap();
+        assert_eq!(loaded.foen_station_ids(), vec![2104, 2176]);
+        assert_eq!(loaded.find_gfroerli_sensor_id(2176), Some(2));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,118 @@
+//! Single-instance guard and PID file
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{Context, Result, bail};
+use tracing::warn;
+
+/// Guard that prevents two processes from using the same database or PID file
+///
+/// The files are locked with an exclusive OS file lock, which is released
+/// automatically when the process exits, even if it crashed. The PID file is
+/// removed when the guard is dropped.
+#[derive(Debug)]
+pub struct InstanceGuard {
+    _locks: Vec<File>,
+    pid_file: Option<PathBuf>,
+}
+
+impl InstanceGuard {
+    /// Lock the lock file of the SQLite database (`<database>.lock`) and the
+    /// PID file, if given
+    ///
+    /// Fails if another process holds either of them.
+    pub fn acquire(database_path: Option<&Path>, pid_file: Option<&Path>) -> Result<Self> {
+        let mut locks = Vec::new();
+        if let Some(database_path) = database_path {
+            let mut lock_path = database_path.as_os_str().to_owned();
+            lock_path.push(".lock");
+            let lock_path = PathBuf::from(lock_path);
+            locks.push(lock_file(&lock_path).with_context(|| {
+                format!(
+                    "Another instance is already using the database '{}'",
+                    database_path.display()
+                )
+            })?);
+        }
+        if let Some(pid_file) = pid_file {
+            locks.push(lock_file(pid_file)?);
+        }
+        Ok(Self {
+            _locks: locks,
+            pid_file: pid_file.map(Path::to_path_buf),
+        })
+    }
+}
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        if let Some(pid_file) = &self.pid_file
+            && let Err(e) = fs::remove_file(pid_file)
+        {
+            warn!("Failed to remove PID file '{}': {}", pid_file.display(), e);
+        }
+    }
+}
+
+/// Lock a file exclusively and write the own PID to it
+fn lock_file(path: &Path) -> Result<File> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open lock file '{}'", path.display()))?;
+
+    // `File::try_lock` would need Rust 1.89
+    // SAFETY: The file descriptor is valid as long as `file` is open
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::WouldBlock {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            bail!("'{}' is locked by process {}", path.display(), pid.trim());
+        }
+        return Err(e).with_context(|| format!("Failed to lock '{}'", path.display()));
+    }
+
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{}", process::id())
+        .with_context(|| format!("Failed to write PID to '{}'", path.display()))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_single_instance() {
+        let dir = TempDir::new().unwrap();
+        let database = dir.path().join("measurements.db");
+        let pid_file = dir.path().join("fetcher.pid");
+
+        let guard = InstanceGuard::acquire(Some(&database), Some(&pid_file)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&pid_file).unwrap().trim(),
+            process::id().to_string()
+        );
+
+        let error = InstanceGuard::acquire(Some(&database), None).unwrap_err();
+        assert!(format!("{error:#}").contains(&process::id().to_string()));
+        assert!(InstanceGuard::acquire(None, Some(&pid_file)).is_err());
+
+        drop(guard);
+        assert!(!pid_file.exists());
+        InstanceGuard::acquire(Some(&database), Some(&pid_file)).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+//! LINDAS Hydrodata Fetcher
+//!
+//! Library part of the fetcher. The binary in `main.rs` only contains the
+//! command line interface, everything else lives here so that it can be used
+//! by integration tests.
+
+pub mod alert;
+pub mod anomaly;
+pub mod backfill;
+pub mod capture;
+pub mod commands;
+pub mod config;
+pub mod control;
+pub mod crypto;
+pub mod danger;
+pub mod database;
+pub mod display;
+pub mod error;
+pub mod export;
+pub mod fetcher;
+pub mod gaps;
+pub mod gfroerli;
+pub mod healthcheck;
+pub mod http;
+pub mod init;
+pub mod instance;
+pub mod lock;
+pub mod logging;
+pub mod metrics;
+pub mod notify;
+pub mod observation;
+pub mod parsing;
+pub mod pipeline;
+pub mod report;
+pub mod schedule;
+pub mod secret;
+pub mod sink;
+pub mod smoothing;
+pub mod sparql;
+pub mod stats;
+pub mod summary;
+pub mod supervisor;
+pub mod timezone;
+pub mod tui;
@@ -0,0 +1,85 @@
+//! Leader lock, so that only one of several instances processes stations
+
+use std::env;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use tracing::{debug, error, info};
+
+use crate::{config::Config, database::MeasurementStore};
+
+/// A lock in the shared database that only one instance can hold at a time
+///
+/// The lock is a lease: the holder renews it before every cycle, and another
+/// instance takes over once it expired, e.g. because the holder crashed.
+#[derive(Debug, Clone)]
+pub struct LeaderLock {
+    name: String,
+    instance_id: String,
+    lease: Duration,
+}
+
+impl LeaderLock {
+    /// Create the lock configured in the `[leader_lock]` section, if any
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let lock = config.leader_lock.as_ref()?;
+        let lease_minutes = lock
+            .lease_minutes
+            .unwrap_or(2 * config.run_interval_minutes());
+        Some(Self {
+            name: lock.name.clone().unwrap_or_else(|| "leader".to_string()),
+            instance_id: lock.instance_id.clone().unwrap_or_else(default_instance_id),
+            lease: Duration::minutes(lease_minutes.into()),
+        })
+    }
+
+    /// ID of this instance
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Acquire or renew the lock
+    ///
+    /// Returns `false` if another instance holds the lock.
+    pub async fn acquire(&self, store: &dyn MeasurementStore, now: DateTime<Utc>) -> Result<bool> {
+        store
+            .acquire_lock(&self.name, &self.instance_id, now, now + self.lease)
+            .await
+    }
+
+    /// Release the lock, so that a standby instance can take over immediately
+    pub async fn release(&self, store: &dyn MeasurementStore) -> Result<()> {
+        store.release_lock(&self.name, &self.instance_id).await
+    }
+}
+
+/// Default instance ID: host name and process ID
+fn default_instance_id() -> String {
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+    format!("{host}-{}", std::process::id())
+}
+
+/// Whether this instance may process stations now
+///
+/// Always true if no lock is configured. Failing to access the lock is logged
+/// and treated like another instance holding it, so that a database outage
+/// never leads to two active instances.
+pub async fn holds_lock(lock: Option<&LeaderLock>, store: &dyn MeasurementStore) -> bool {
+    let Some(lock) = lock else {
+        return true;
+    };
+    match lock.acquire(store, Utc::now()).await {
+        Ok(true) => {
+            debug!("Holding leader lock as '{}'", lock.instance_id());
+            true
+        }
+        Ok(false) => {
+            info!("Another instance holds the leader lock, standing by");
+            false
+        }
+        Err(e) => {
+            error!("Failed to acquire leader lock: {:#}", e);
+            false
+        }
+    }
+}
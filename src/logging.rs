@@ -0,0 +1,154 @@
+//! Logging setup: stdout or journald and optional rotating log files
+
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{SecondsFormat, Utc};
+use chrono_tz::Tz;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    EnvFilter,
+    fmt::{self, format::Writer, time::FormatTime, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
+
+use crate::{
+    config::{Config, LogRotation, LogTarget},
+    timezone,
+};
+
+/// Timestamps of log lines in the configured time zone
+#[derive(Debug, Clone, Copy)]
+struct Timer(Tz);
+
+impl FormatTime for Timer {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        let now = Utc::now().with_timezone(&self.0);
+        write!(w, "{}", now.to_rfc3339_opts(SecondsFormat::Micros, true))
+    }
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Create a rotating file appender for a log file path like `logs/fetcher.log`
+///
+/// Rotated files are named `<stem>.<date>.<extension>`, e.g.
+/// `logs/fetcher.2025-01-15.log`.
+fn file_appender(
+    path: &Path,
+    rotation: LogRotation,
+    max_files: Option<usize>,
+) -> Result<RollingFileAppender> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow!("Invalid log file path '{}'", path.display()))?;
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation.into())
+        .filename_prefix(stem);
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        builder = builder.filename_suffix(extension);
+    }
+    if let Some(max_files) = max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    builder
+        .build(dir)
+        .with_context(|| format!("Failed to open log file '{}'", path.display()))
+}
+
+/// Initialize tracing with the configured level and outputs
+///
+/// The returned guard flushes the log file when dropped, so it must be kept
+/// alive until the application exits. With `quiet_stdout` (e.g. while the
+/// dashboard takes over the terminal), nothing is logged to stdout. While the
+/// NDJSON sink writes to stdout, the log lines go to stderr instead.
+pub fn init(config: &Config, quiet_stdout: bool) -> Result<Option<WorkerGuard>> {
+    let logging_level = config.logging_level();
+    let env_filter = EnvFilter::try_new(logging_level)
+        .with_context(|| format!("Invalid logging level: '{logging_level}'"))?;
+
+    let timezone = config.logging_timezone();
+    timezone::set_log_timezone(timezone);
+    let timer = Timer(timezone);
+
+    let (file_layer, guard) = match config.logging_file() {
+        Some(path) => {
+            let appender = file_appender(
+                Path::new(path),
+                config.logging_rotation(),
+                config.logging_max_files(),
+            )?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer()
+                .with_ansi(false)
+                .with_timer(timer)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    // Event fields like `station_id` become journal fields like `STATION_ID`
+    let (stdout_layer, journald_layer) = match config.logging_target() {
+        LogTarget::Stdout if quiet_stdout => (None, None),
+        LogTarget::Stdout => {
+            let writer = if config.sink_ndjson_stdout() {
+                BoxMakeWriter::new(std::io::stderr)
+            } else {
+                BoxMakeWriter::new(std::io::stdout)
+            };
+            (
+                Some(fmt::layer().with_timer(timer).with_writer(writer)),
+                None,
+            )
+        }
+        LogTarget::Journald => {
+            let layer = tracing_journald::layer()
+                .with_context(|| "Failed to connect to the systemd journal")?
+                .with_field_prefix(None);
+            (None, Some(layer))
+        }
+    };
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(stdout_layer)
+        .with(journald_layer)
+        .with(file_layer)
+        .init();
+
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_appender_creates_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("logs").join("fetcher.log");
+
+        file_appender(&path, LogRotation::Never, None).unwrap();
+
+        assert!(path.exists());
+    }
+}
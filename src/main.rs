@@ -4,199 +4,529 @@
 //! Federal Office for the Environment) LINDAS SPARQL endpoint and sends them
 //! to the Gfrörli API.
 
-mod config;
-mod database;
-mod gfroerli;
-mod parsing;
-mod sparql;
-
-use anyhow::{Context, Result, anyhow};
-use clap::Parser;
-use rusqlite::Connection;
-use tokio::time::{Duration, sleep};
-use tracing::{debug, error, info, warn};
-
-use crate::{
-    config::{Config, RunMode},
-    database::{init_database, is_measurement_sent, record_measurement_sent},
-    gfroerli::send_measurement,
-    sparql::fetch_station_measurement,
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use lindas_hydrodata_fetcher::{
+    backfill::{self, BackfillOptions},
+    capture::Capture,
+    commands,
+    config::{CONFIG_FILE_NAME, DEFAULT_GFROERLI_TARGET, RunMode, find_config_file},
+    control,
+    database::{StoreAccess, load_station_mappings, open_store},
+    fetcher::{Fetcher, RunOutcome, load_config},
+    gfroerli::GfroerliTarget,
+    http::HttpClients,
+    init::{self, InitOptions, parse_station_mapping},
+    instance::InstanceGuard,
+    logging,
+    sparql::{SparqlEndpoint, SparqlSource},
+    tui::Tui,
+};
+
+/// Exit code in oneshot mode if station errors exceeded the configured threshold
+const EXIT_PARTIAL_FAILURE: u8 = 2;
+
 /// Command line arguments
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Path to configuration file
-    #[arg(short, long, default_value = "config.toml")]
-    config: String,
+    /// Path to configuration file (searched in the default locations if omitted)
+    #[arg(short, long)]
+    config: Option<PathBuf>,
     /// Dry run mode - fetch data but don't send to API or record in database
     #[arg(long)]
     dry_run: bool,
+    /// Open the database read-only and never write to it, e.g. to inspect a
+    /// copy of a production database (implies --dry-run unless --allow-send
+    /// is given)
+    #[arg(long)]
+    read_only: bool,
+    /// Send measurements despite --read-only, without recording them as sent
+    /// (oneshot mode and subcommands only)
+    #[arg(long, requires = "read_only", conflicts_with = "dry_run")]
+    allow_send: bool,
+    /// Read SPARQL responses from `<station_id>.json` files in this directory
+    /// instead of querying the endpoint
+    #[arg(long, value_name = "DIR")]
+    from_file: Option<PathBuf>,
+    /// Write every raw SPARQL response to a timestamped file in this directory
+    #[arg(long, value_name = "DIR")]
+    capture_dir: Option<PathBuf>,
+    /// Also capture Gfrörli API requests and responses (requires --capture-dir)
+    #[arg(long, requires = "capture_dir")]
+    capture_gfroerli: bool,
+    /// Send to this Gfrörli target instead of the configured ones (repeatable)
+    #[arg(long = "target", value_name = "NAME")]
+    targets: Vec<String>,
+    /// Also forward new measurements to this sink, e.g. `ndjson` for JSON
+    /// lines on stdout (logs go to stderr then) (repeatable)
+    #[arg(long = "sink", value_name = "KIND", value_parser = ["ndjson"])]
+    sinks: Vec<String>,
+    /// Write the process ID to this file and refuse to start if another
+    /// instance holds it
+    #[arg(long, value_name = "PATH")]
+    pid_file: Option<PathBuf>,
+    /// Show a live dashboard of the stations in loop mode instead of logging
+    /// to stdout
+    #[arg(long)]
+    tui: bool,
+    /// Subcommand to run (fetches and sends measurements if omitted)
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-/// Processes a single station: Fetches data and sends to API
-async fn process_station(
-    client: &reqwest::Client,
-    config: &Config,
-    db_conn: &Connection,
-    station_id: u32,
-    dry_run: bool,
-) -> Result<()> {
-    // Query latest measurement from LINDAS
-    let measurement = fetch_station_measurement(client, station_id)
-        .await
-        .with_context(|| format!("Error fetching data for station {station_id}"))?
-        .ok_or_else(|| anyhow!("No temperature data found for station {}", station_id))?;
-    info!(
-        "Station {} ({}) fetched: {:.3}°C (at {})",
-        measurement.station_id,
-        measurement.station_name,
-        measurement.temperature,
-        measurement.time.format("%Y-%m-%d %H:%M:%S %z"),
-    );
-
-    // Get Gfrörli sensor ID from config
-    let sensor_id = config
-        .find_gfroerli_sensor_id(measurement.station_id)
-        .ok_or_else(|| {
-            anyhow!(
-                "No sensor mapping found for station {}",
-                measurement.station_id
-            )
-        })?;
-
-    // Check if this measurement was already sent
-    if is_measurement_sent(db_conn, sensor_id, &measurement.time)? {
-        warn!(
-            "Station {} ({}) measurement at {} already sent, skipping",
-            measurement.station_id,
-            measurement.station_name,
-            measurement.time.format("%Y-%m-%d %H:%M:%S %z")
-        );
-        return Ok(());
-    }
-
-    if dry_run {
-        info!(
-            "Station {} ({}) would be sent to API (sensor {}) [DRY RUN]",
-            measurement.station_id, measurement.station_name, sensor_id,
-        );
-        return Ok(());
-    }
+/// Subcommands
+#[derive(Subcommand)]
+enum Command {
+    /// Create a starter configuration file at the --config path or ./config.toml (asks for missing settings)
+    Init {
+        /// Gfrörli API base URL
+        #[arg(long)]
+        api_url: Option<String>,
+        /// Gfrörli private API key
+        #[arg(long)]
+        api_key: Option<String>,
+        /// Station to fetch (repeatable)
+        #[arg(long = "station", value_name = "FOEN_ID=SENSOR_ID", value_parser = parse_station_mapping)]
+        stations: Vec<(u32, u32)>,
+        /// Overwrite an existing configuration file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Re-send measurements of a sensor that were already sent (bypasses deduplication)
+    Replay {
+        /// Gfrörli sensor ID
+        #[arg(long)]
+        sensor: u32,
+        /// Start of the time range (RFC 3339, e.g. 2025-01-15T00:00:00Z)
+        #[arg(long)]
+        from: DateTime<Utc>,
+        /// End of the time range (RFC 3339, inclusive)
+        #[arg(long)]
+        to: DateTime<Utc>,
+    },
+    /// Send a manual measurement of a sensor, e.g. a correction value (skipped if already sent)
+    Send {
+        /// Gfrörli sensor ID
+        #[arg(long)]
+        sensor: u32,
+        /// Water temperature in °C
+        #[arg(long, allow_negative_numbers = true)]
+        temperature: f32,
+        /// Time of the measurement (RFC 3339, e.g. 2025-01-15T12:30:00Z)
+        #[arg(long)]
+        time: DateTime<Utc>,
+    },
+    /// Compare the latest measurement per sensor between LINDAS, the local database and the Gfrörli API
+    Compare,
+    /// Inspect the configured stations
+    Stations {
+        #[command(subcommand)]
+        command: StationsCommand,
+    },
+    /// Inspect the sensors of the Gfrörli API
+    Sensors {
+        #[command(subcommand)]
+        command: SensorsCommand,
+    },
+    /// Manage the station mappings stored in the database
+    Mapping {
+        #[command(subcommand)]
+        command: MappingCommand,
+    },
+    /// Inspect the measurement database
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Summarize the sent measurements
+    Report {
+        #[command(subcommand)]
+        command: ReportCommand,
+    },
+    /// Review measurements rejected by anomaly detection
+    Quarantine {
+        #[command(subcommand)]
+        command: QuarantineCommand,
+    },
+    /// Fetch and send past measurements of a time range (resumes an interrupted backfill)
+    Backfill {
+        /// Start of the time range (RFC 3339, e.g. 2025-01-01T00:00:00Z)
+        #[arg(long)]
+        from: DateTime<Utc>,
+        /// End of the time range (RFC 3339, exclusive, defaults to now)
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+        /// Station to backfill (repeatable, defaults to all enabled stations)
+        #[arg(long = "station", value_name = "FOEN_ID")]
+        stations: Vec<u32>,
+        /// Length of the time window of a query in hours
+        #[arg(long, default_value_t = 24)]
+        window_hours: u32,
+        /// Number of windows processed at the same time
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Maximum number of SPARQL queries per second
+        #[arg(long, value_name = "QUERIES")]
+        rate_limit: Option<f64>,
+    },
+}
 
-    // Send to API
-    match send_measurement(client, &config.gfroerli_api, &measurement, sensor_id).await {
-        Ok(()) => {
-            // Record that we successfully sent this measurement
-            record_measurement_sent(db_conn, sensor_id, &measurement.time)?;
-            info!(
-                "Station {} ({}) sent to API (sensor {})",
-                measurement.station_id, measurement.station_name, sensor_id,
-            );
-            Ok(())
-        }
-        Err(e) => Err(anyhow!(
-            "Failed to send measurement for station {} (sensor {}): {}",
-            measurement.station_id,
-            sensor_id,
-            e
-        )),
-    }
+/// Station subcommands
+#[derive(Subcommand)]
+enum StationsCommand {
+    /// List the configured stations with the time of the last sent measurement
+    List,
 }
 
-/// Main application entry point
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+/// Sensor subcommands
+#[derive(Subcommand)]
+enum SensorsCommand {
+    /// List the sensors visible to the API key and flag missing and unmapped sensors
+    List,
+}
 
-    // Load configuration
-    let config = Config::load_from_file(&args.config)
-        .with_context(|| format!("Failed to load config from '{}'", args.config))?;
+/// Mapping subcommands
+#[derive(Subcommand)]
+enum MappingCommand {
+    /// List the station mappings stored in the database
+    List,
+    /// Map a FOEN station to a Gfrörli sensor (replaces a stored mapping of the station)
+    Add {
+        /// FOEN station ID
+        #[arg(long)]
+        station: u32,
+        /// Gfrörli sensor ID
+        #[arg(long)]
+        sensor: u32,
+    },
+    /// Remove the stored mapping of a station
+    Remove {
+        /// FOEN station ID
+        #[arg(long)]
+        station: u32,
+    },
+}
 
-    // Initialize tracing with config-based logging level
-    let logging_level = config.logging_level();
-    let env_filter = tracing_subscriber::EnvFilter::try_new(logging_level)
-        .with_context(|| format!("Invalid logging level: '{logging_level}'"))?;
+/// Report subcommands
+#[derive(Subcommand)]
+enum ReportCommand {
+    /// Summarize the measurements sent during a day per sensor (count, temperatures and gaps)
+    Daily {
+        /// Day to summarize (e.g. 2025-01-15, defaults to yesterday)
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+}
 
-    tracing_subscriber::fmt().with_env_filter(env_filter).init();
+/// Quarantine subcommands
+#[derive(Subcommand)]
+enum QuarantineCommand {
+    /// List the quarantined measurements
+    List {
+        /// Include measurements that were already released
+        #[arg(long)]
+        all: bool,
+    },
+    /// Send a quarantined measurement to the targets of its station (for false positives)
+    Release {
+        /// ID of the quarantined measurement
+        id: i64,
+    },
+}
 
-    let station_ids = config.foen_station_ids();
+/// Database subcommands
+#[derive(Subcommand)]
+enum DbCommand {
+    /// List recent fetch and send errors
+    Errors {
+        /// Maximum number of errors to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Run a full integrity check of the SQLite database
+    Check,
+    /// Create a consistent backup of the database (safe while the fetcher is running)
+    Backup {
+        /// Path of the backup file to create
+        path: PathBuf,
+    },
+    /// Create SQL views of daily temperatures and measurement gaps for ad-hoc analysis
+    Views {
+        /// Minimum interval between two measurements listed as gap
+        #[arg(long, default_value_t = 60)]
+        gap_minutes: u32,
+    },
+    /// Export all sent measurements for analysis, e.g. with pandas or DuckDB
+    Export {
+        /// File format of the export
+        #[arg(long, value_parser = ["parquet"])]
+        format: String,
+        /// Path of the file to create
+        path: PathBuf,
+    },
+    /// Replace the sent measurements of a time range by the measurements in the Gfrörli API
+    RebuildFromApi {
+        /// Start of the time range (RFC 3339, defaults to 30 days ago)
+        #[arg(long)]
+        from: Option<DateTime<Utc>>,
+        /// End of the time range (RFC 3339, inclusive, defaults to now)
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+    },
+}
 
-    info!(
-        "Fetching water temperature data for {} stations: {:?}",
-        station_ids.len(),
-        station_ids
-    );
+/// Main application entry point
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let args = Args::parse();
 
-    // Initialize database
-    let db_conn =
-        init_database(config.database_path()).with_context(|| "Failed to initialize database")?;
+    // The configuration doesn't exist yet when creating it
+    if let Some(Command::Init {
+        api_url,
+        api_key,
+        stations,
+        force,
+    }) = args.command
+    {
+        let options = InitOptions {
+            api_url,
+            api_key,
+            stations,
+            force,
+        };
+        let path = args
+            .config
+            .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_NAME));
+        init::init(&path, options).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    // Initialize HTTP client
-    let client = reqwest::Client::new();
+    // Load configuration
+    let targets = args.targets.clone();
+    let config_path = match args.config {
+        Some(path) => path,
+        None => find_config_file()?,
+    };
+    let sinks = args.sinks.clone();
+    let mut config = load_config(&config_path, &targets, &sinks)?;
 
-    if args.dry_run {
-        info!("Running in DRY RUN mode - no data will be sent to API or recorded in database");
+    // The dashboard only runs the processing loop, not the subcommands
+    let tui = args.tui && args.command.is_none();
+    if tui && !matches!(config.run_mode(), RunMode::Loop) {
+        bail!("--tui requires loop mode ([run] mode = \"loop\")");
+    }
+    if tui && config.sink_ndjson_stdout() {
+        bail!("--tui can't be combined with the NDJSON sink writing to stdout");
     }
 
-    let interval_minutes = config.run_interval_minutes();
-    let mode = config.run_mode();
-
-    match mode {
-        RunMode::Oneshot => debug!("Running in oneshot mode"),
-        RunMode::Loop => info!(
-            "Running in loop mode with {} minute intervals",
-            interval_minutes
-        ),
+    // Initialize tracing with config-based logging level and outputs
+    let _log_guard = logging::init(&config, tui)?;
+    info!("Using configuration file '{}'", config_path.display());
+    for warning in &config.warnings {
+        warn!("{}", warning);
     }
 
-    loop {
-        debug!("Starting station processing cycle");
+    // Sends can't be recorded in a read-only database, so the loop would
+    // send the same measurements again in every cycle
+    if args.allow_send && args.command.is_none() && matches!(config.run_mode(), RunMode::Loop) {
+        bail!("--allow-send can't be used in loop mode, sends aren't recorded with --read-only");
+    }
+    let dry_run = args.dry_run || (args.read_only && !args.allow_send);
 
-        let mut total_success = 0;
-        let mut total_errors = 0;
+    // Initialize database
+    let access = if args.read_only {
+        StoreAccess::ReadOnly
+    } else if args.dry_run {
+        StoreAccess::DryRun
+    } else {
+        StoreAccess::ReadWrite
+    };
+    let store = open_store(&config, access)
+        .await
+        .with_context(|| "Failed to initialize database")?;
+    load_station_mappings(&mut config, store.as_ref()).await?;
 
-        for &station_id in &station_ids {
-            if let Err(e) =
-                process_station(&client, &config, &db_conn, station_id, args.dry_run).await
-            {
-                error!("Failed to process station {}: {}", station_id, e);
-                total_errors += 1;
-            } else {
-                total_success += 1;
-            }
+    let source = match args.from_file {
+        Some(dir) => {
+            info!("Reading SPARQL responses from '{}'", dir.display());
+            SparqlSource::Directory(dir)
         }
+        None => SparqlSource::Endpoint(SparqlEndpoint::from_config(&config)),
+    };
 
-        match mode {
-            RunMode::Oneshot => {
-                info!(
-                    "Successfully sent {} measurements to Gfrörli API",
-                    total_success
-                );
-                if total_errors > 0 {
-                    error!("Total errors encountered: {}", total_errors);
+    // Initialize HTTP clients
+    let clients = HttpClients::from_config(&config)?;
+
+    if let Some(command) = args.command {
+        match command {
+            Command::Replay { sensor, from, to } => {
+                let targets = if args.targets.is_empty() {
+                    vec![DEFAULT_GFROERLI_TARGET.to_string()]
+                } else {
+                    args.targets
+                };
+                for name in &targets {
+                    let target = GfroerliTarget::new(&config, &clients, name)?;
+                    commands::replay(&config, &target, store.as_ref(), sensor, from, to, dry_run)
+                        .await?
                 }
-                break;
             }
-            RunMode::Loop => {
-                info!(
-                    "Cycle complete - Successfully sent {} measurements to Gfrörli API",
-                    total_success
-                );
-                if total_errors > 0 {
-                    error!(
-                        "Cycle complete - Total errors encountered: {}",
-                        total_errors
-                    );
-                }
-
-                let sleep_duration = Duration::from_secs(interval_minutes as u64 * 60);
-                info!("Sleeping for {} minutes until next cycle", interval_minutes);
-                sleep(sleep_duration).await;
+            Command::Init { .. } => unreachable!("handled before loading the configuration"),
+            Command::Send {
+                sensor,
+                temperature,
+                time,
+            } => {
+                commands::send(
+                    &clients,
+                    &config,
+                    store.as_ref(),
+                    sensor,
+                    temperature,
+                    time,
+                    dry_run,
+                )
+                .await?
+            }
+            Command::Compare => {
+                commands::compare(&clients, &config, &source, store.as_ref()).await?
+            }
+            Command::Stations {
+                command: StationsCommand::List,
+            } => commands::stations_list(&config, store.as_ref()).await?,
+            Command::Sensors {
+                command: SensorsCommand::List,
+            } => commands::sensors_list(&clients, &config).await?,
+            Command::Mapping {
+                command: MappingCommand::List,
+            } => commands::mapping_list(&config, store.as_ref()).await?,
+            Command::Mapping {
+                command: MappingCommand::Add { station, sensor },
+            } => commands::mapping_add(&config, store.as_ref(), station, sensor).await?,
+            Command::Mapping {
+                command: MappingCommand::Remove { station },
+            } => commands::mapping_remove(&config, store.as_ref(), station).await?,
+            Command::Db {
+                command: DbCommand::Errors { limit },
+            } => commands::db_errors(&config, store.as_ref(), limit).await?,
+            Command::Db {
+                command: DbCommand::Backup { path },
+            } => store.backup(&path).await?,
+            Command::Db {
+                command: DbCommand::Check,
+            } => store.check_integrity().await?,
+            Command::Db {
+                command: DbCommand::Views { gap_minutes },
+            } => store.create_views(gap_minutes).await?,
+            Command::Db {
+                command: DbCommand::Export { format: _, path },
+            } => commands::db_export(store.as_ref(), &path).await?,
+            Command::Db {
+                command: DbCommand::RebuildFromApi { from, to },
+            } => {
+                let to = to.unwrap_or_else(Utc::now);
+                let from = from.unwrap_or(to - chrono::Duration::days(30));
+                commands::db_rebuild_from_api(&clients, &config, store.as_ref(), from, to, dry_run)
+                    .await?
+            }
+            Command::Report {
+                command: ReportCommand::Daily { date },
+            } => commands::report_daily(&clients, &config, store.as_ref(), date).await?,
+            Command::Quarantine {
+                command: QuarantineCommand::List { all },
+            } => commands::quarantine_list(&config, store.as_ref(), all).await?,
+            Command::Quarantine {
+                command: QuarantineCommand::Release { id },
+            } => {
+                commands::quarantine_release(&clients, &config, store.as_ref(), id, dry_run).await?
+            }
+            Command::Backfill {
+                from,
+                to,
+                stations,
+                window_hours,
+                concurrency,
+                rate_limit,
+            } => {
+                let options = BackfillOptions {
+                    from,
+                    to: to.unwrap_or_else(Utc::now),
+                    station_ids: if stations.is_empty() {
+                        config.foen_station_ids()
+                    } else {
+                        stations
+                    },
+                    window: chrono::Duration::hours(window_hours.into()),
+                    concurrency,
+                    rate_limit,
+                };
+                backfill::run(
+                    &clients,
+                    &config,
+                    &source,
+                    store.as_ref(),
+                    &options,
+                    dry_run,
+                )
+                .await?
             }
         }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Refuse to run concurrently with another instance using the same SQLite
+    // database (dry runs and read-only runs don't write to it)
+    let database_path = match config.database_postgres_url() {
+        None if access == StoreAccess::ReadWrite => Some(Path::new(config.database_path())),
+        _ => None,
+    };
+    let _instance_guard = InstanceGuard::acquire(database_path, args.pid_file.as_deref())?;
+
+    if args.read_only && !dry_run {
+        info!("Running in READ-ONLY mode - data will be sent to API but not recorded in database");
+    } else if dry_run {
+        info!("Running in DRY RUN mode - no data will be sent to API or recorded in database");
     }
 
-    Ok(())
+    let tui = if tui {
+        Some(Tui::start(&config)?)
+    } else {
+        None
+    };
+
+    // Dry runs and read-only runs don't write to the database, so they never
+    // become the leader
+    let mut builder = Fetcher::builder(config)
+        .targets(targets)
+        .sinks(sinks)
+        .store(store)
+        .clients(clients)
+        .source(source)
+        .config_path(config_path)
+        .dry_run(dry_run)
+        .leader_lock(access == StoreAccess::ReadWrite);
+    if let Some(dir) = args.capture_dir {
+        info!("Capturing raw responses to '{}'", dir.display());
+        builder = builder.capture(Capture::new(dir, args.capture_gfroerli)?);
+    }
+    if let Some(tui) = tui {
+        builder = builder.tui(tui);
+    }
+    let shutdown = CancellationToken::new();
+    control::cancel_on_signal(shutdown.clone())?;
+    builder = builder.shutdown(shutdown);
+    match builder.build().await?.run().await? {
+        RunOutcome::Success => Ok(ExitCode::SUCCESS),
+        RunOutcome::PartialFailure => Ok(ExitCode::from(EXIT_PARTIAL_FAILURE)),
+    }
 }
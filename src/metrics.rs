@@ -0,0 +1,185 @@
+//! Cycle metrics in the Prometheus text exposition format
+
+use std::{ffi::OsString, fmt::Write, fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+
+use crate::{
+    config::Config,
+    http::{HttpClient, check_status},
+    pipeline::CycleOutcome,
+    secret::SecretString,
+};
+
+/// Content type of the Prometheus text exposition format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Append a gauge with its help text and samples (label set and value)
+fn gauge(out: &mut String, name: &str, help: &str, samples: &[(String, f64)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+/// Render the metrics of a cycle that finished at `now`
+pub fn render(config: &Config, outcome: &CycleOutcome, now: DateTime<Utc>) -> String {
+    let mut out = String::new();
+    gauge(
+        &mut out,
+        "lindas_fetcher_stations",
+        "Number of stations by outcome of the last cycle",
+        &[
+            (r#"{outcome="success"}"#.to_string(), outcome.success as f64),
+            (r#"{outcome="error"}"#.to_string(), outcome.errors as f64),
+            (
+                r#"{outcome="cancelled"}"#.to_string(),
+                outcome.cancelled as f64,
+            ),
+        ],
+    );
+    gauge(
+        &mut out,
+        "lindas_fetcher_panics",
+        "Number of failed stations whose processing panicked in the last cycle",
+        &[(String::new(), outcome.panics as f64)],
+    );
+    gauge(
+        &mut out,
+        "lindas_fetcher_cycle_duration_seconds",
+        "Duration of the last cycle",
+        &[(String::new(), outcome.duration.as_secs_f64())],
+    );
+    gauge(
+        &mut out,
+        "lindas_fetcher_cycle_finished_timestamp_seconds",
+        "Unix time the last cycle finished",
+        &[(String::new(), now.timestamp() as f64)],
+    );
+    let ages: Vec<_> = outcome
+        .observations
+        .iter()
+        .map(|(station_id, observation)| {
+            let sensor_id = config
+                .find_gfroerli_sensor_id(*station_id)
+                .map(|sensor_id| sensor_id.to_string())
+                .unwrap_or_default();
+            let age = (now - observation.time()).num_seconds();
+            (
+                format!(r#"{{station_id="{station_id}",sensor_id="{sensor_id}"}}"#),
+                age as f64,
+            )
+        })
+        .collect();
+    gauge(
+        &mut out,
+        "lindas_fetcher_measurement_age_seconds",
+        "Age of the newest measurement per station in the last cycle",
+        &ages,
+    );
+    out
+}
+
+/// Push metrics to a Prometheus Pushgateway, replacing the previous metrics
+/// of the job
+///
+/// The URL is left out of errors, as it may contain credentials.
+pub async fn push(client: &HttpClient, url: &SecretString, job: &str, body: String) -> Result<()> {
+    let url = format!("{}/metrics/job/{job}", url.expose().trim_end_matches('/'));
+    let request = client
+        .put(&url)
+        .header(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)
+        .body(body);
+    let response = client
+        .send(request)
+        .await
+        .map_err(|e| e.without_url())
+        .with_context(|| "Failed to push metrics")?;
+    check_status(response)
+        .await
+        .with_context(|| "Pushgateway rejected the metrics")?;
+    Ok(())
+}
+
+/// Write metrics to a file for the textfile collector of node_exporter
+///
+/// The metrics are written to a temporary file next to it first, which is
+/// then renamed, so that the collector never reads a partially written file.
+pub fn write_textfile(path: &Path, metrics: &str) -> Result<()> {
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".tmp");
+    let temporary = Path::new(&temporary);
+    fs::write(temporary, metrics)
+        .and_then(|()| fs::rename(temporary, path))
+        .with_context(|| format!("Failed to write metrics to '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration as StdDuration;
+
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{
+        config::{GfroerliConfig, StationConfig},
+        observation::StationObservation,
+    };
+
+    #[test]
+    fn test_render() {
+        let config = Config::new(
+            vec![StationConfig {
+                foen_station_id: 2104,
+                gfroerli_sensor_id: 1,
+                targets: None,
+                alias: None,
+                enabled: None,
+                station_type: None,
+                air_temperature: None,
+                alerts: None,
+                smoothing: None,
+            }],
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        let now: DateTime<Utc> = "2025-01-15T12:00:00Z".parse().unwrap();
+        let mut outcome = CycleOutcome {
+            success: 1,
+            errors: 2,
+            panics: 1,
+            duration: StdDuration::from_millis(1500),
+            ..Default::default()
+        };
+        outcome.observations.insert(
+            2104,
+            StationObservation::new(2104, "Linth", now - Duration::minutes(25), 6.5),
+        );
+
+        let metrics = render(&config, &outcome, now);
+        assert!(metrics.contains("# TYPE lindas_fetcher_stations gauge\n"));
+        assert!(metrics.contains("lindas_fetcher_stations{outcome=\"error\"} 2\n"));
+        assert!(metrics.contains("lindas_fetcher_panics 1\n"));
+        assert!(metrics.contains("lindas_fetcher_cycle_duration_seconds 1.5\n"));
+        assert!(metrics.contains("lindas_fetcher_cycle_finished_timestamp_seconds 1736942400\n"));
+        assert!(metrics.contains(
+            "lindas_fetcher_measurement_age_seconds{station_id=\"2104\",sensor_id=\"1\"} 1500\n"
+        ));
+    }
+
+    #[test]
+    fn test_write_textfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lindas.prom");
+        write_textfile(&path, "lindas_fetcher_stations 1\n").unwrap();
+        write_textfile(&path, "lindas_fetcher_stations 2\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "lindas_fetcher_stations 2\n"
+        );
+        assert!(!dir.path().join("lindas.prom.tmp").exists());
+    }
+}
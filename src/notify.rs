@@ -0,0 +1,307 @@
+//! Delivery of notifications by webhook and email
+
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result};
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, header::ContentType},
+    transport::smtp::authentication::Credentials,
+};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    config::{Config, EmailConfig, EmailTls},
+    http::{HttpClient, build_client, check_status},
+    pipeline::CycleOutcome,
+    secret::SecretString,
+};
+
+/// Kind of a notification, email can be enabled per kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    /// The danger level of a station rose
+    DangerLevel,
+    /// Summary of the sent measurements of a day
+    DailyReport,
+    /// All stations keep failing, or a station failed permanently
+    FailureAlert,
+    /// The temperature of a station crossed a threshold
+    TemperatureAlert,
+    /// Sensors without recent measurements
+    GapAlert,
+}
+
+/// Sends emails to the configured recipients through an SMTP server
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    topics: Vec<Topic>,
+}
+
+impl Mailer {
+    /// Create a mailer, the connection is only opened when sending
+    pub fn from_config(config: &EmailConfig) -> Result<Self> {
+        let mut builder = match config.tls() {
+            EmailTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.server)
+                    .with_context(|| format!("Invalid SMTP server '{}'", config.server))?
+            }
+            EmailTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.server)
+                .with_context(|| format!("Invalid SMTP server '{}'", config.server))?,
+            EmailTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(config.server.as_str())
+            }
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &config.username {
+            let password = config.password.as_ref().map(SecretString::expose);
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                password.unwrap_or_default().to_string(),
+            ));
+        }
+
+        let from = config
+            .from
+            .parse()
+            .with_context(|| format!("Invalid sender address '{}'", config.from))?;
+        let to = config
+            .to
+            .iter()
+            .map(|to| {
+                to.parse()
+                    .with_context(|| format!("Invalid recipient address '{to}'"))
+            })
+            .collect::<Result<_>>()?;
+        let topics = [
+            (Topic::DangerLevel, config.danger_levels()),
+            (Topic::DailyReport, config.daily_reports()),
+            (Topic::FailureAlert, config.failure_alerts()),
+            (Topic::TemperatureAlert, config.temperature_alerts()),
+            (Topic::GapAlert, config.gap_alerts()),
+        ]
+        .into_iter()
+        .filter_map(|(topic, enabled)| enabled.then_some(topic))
+        .collect();
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+            to,
+            topics,
+        })
+    }
+
+    /// Build a plain text email to all recipients
+    fn message(&self, subject: &str, body: String) -> Result<Message> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        builder.body(body).with_context(|| "Failed to build email")
+    }
+
+    /// Send an email to all recipients
+    pub async fn send(&self, subject: &str, body: String) -> Result<()> {
+        let message = self.message(subject, body)?;
+        self.transport
+            .send(message)
+            .await
+            .with_context(|| format!("Failed to send email '{subject}'"))?;
+        Ok(())
+    }
+}
+
+/// Notification channels: webhooks and optionally email
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    client: HttpClient,
+    mailer: Option<Mailer>,
+}
+
+impl Notifier {
+    /// Create the channels configured in the config
+    pub fn from_config(config: &Config) -> Result<Self> {
+        // Webhook URLs may contain tokens, so their requests are never traced
+        let client = build_client(None)
+            .map(|client| HttpClient::new(client, false))
+            .with_context(|| "Failed to build HTTP client for notifications")?;
+        let mailer = config
+            .email
+            .as_ref()
+            .map(Mailer::from_config)
+            .transpose()
+            .with_context(|| "Invalid email configuration")?;
+        Ok(Self { client, mailer })
+    }
+
+    /// Posts a notification as JSON to a webhook
+    ///
+    /// `what` names the notification in errors, e.g. "danger level
+    /// notification". The URL is left out of errors, as it may contain a
+    /// token.
+    pub async fn post_webhook(
+        &self,
+        url: &SecretString,
+        what: &str,
+        payload: &impl Serialize,
+    ) -> Result<()> {
+        let request = self.client.post(url.expose()).json(payload);
+        let response = self
+            .client
+            .send(request)
+            .await
+            .map_err(|e| e.without_url())
+            .with_context(|| format!("Failed to send {what}"))?;
+        check_status(response)
+            .await
+            .with_context(|| format!("Webhook rejected the {what}"))?;
+        Ok(())
+    }
+
+    /// Whether notifications of a topic are sent by email
+    pub fn emails(&self, topic: Topic) -> bool {
+        self.mailer
+            .as_ref()
+            .is_some_and(|mailer| mailer.topics.contains(&topic))
+    }
+
+    /// Sends an email, if email is configured and enabled for the topic
+    pub async fn email(&self, topic: Topic, subject: &str, body: String) -> Result<()> {
+        match &self.mailer {
+            Some(mailer) if self.emails(topic) => mailer.send(subject, body).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends an alert that all stations failed in consecutive cycles, with
+    /// the errors of the last cycle
+    pub async fn alert_failures(&self, config: &Config, outcome: &CycleOutcome) -> Result<()> {
+        let subject = format!(
+            "All stations failed in {} consecutive cycles",
+            config.run_backoff_after_cycles()
+        );
+        let mut body = format!(
+            "All stations failed in {} consecutive cycles, the fetcher backs off \
+            with intervals of up to {} minutes until a station succeeds again.\n\n",
+            config.run_backoff_after_cycles(),
+            config.run_max_backoff_minutes()
+        );
+        for (station_id, error) in &outcome.failures {
+            body.push_str(&format!(
+                "Station {}: {}\n",
+                config.station_label(*station_id),
+                error
+            ));
+        }
+        self.email(Topic::FailureAlert, &subject, body).await
+    }
+}
+
+/// Alerts about stations that failed with a permanent error, once per
+/// failure
+///
+/// Permanent errors (e.g. rejected credentials) need an operator to fix the
+/// configuration, so they are alerted right away instead of waiting for the
+/// backoff. A station is alerted again after it succeeded in between.
+#[derive(Debug, Default)]
+pub struct PermanentFailureAlerts {
+    alerted: BTreeSet<u32>,
+}
+
+impl PermanentFailureAlerts {
+    /// Alert about the stations of a cycle that newly failed permanently
+    ///
+    /// The stations are only remembered once the alert was sent, so that a
+    /// failed alert is retried after the next cycle.
+    pub async fn check(
+        &mut self,
+        config: &Config,
+        notifier: &Notifier,
+        outcome: &CycleOutcome,
+        dry_run: bool,
+    ) -> Result<()> {
+        for station_id in outcome.observations.keys() {
+            self.alerted.remove(station_id);
+        }
+        let new: Vec<_> = outcome
+            .permanent_failures
+            .difference(&self.alerted)
+            .copied()
+            .collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+        if dry_run {
+            info!("Permanent failures would be alerted [DRY RUN]");
+            self.alerted.extend(new);
+            return Ok(());
+        }
+
+        let subject = format!("{} stations failed permanently", new.len());
+        let mut body = String::from(
+            "The following stations failed with errors that won't go away by \
+            retrying, check the configuration and the credentials:\n\n",
+        );
+        for station_id in &new {
+            body.push_str(&format!(
+                "Station {}: {}\n",
+                config.station_label(*station_id),
+                outcome.failures.get(station_id).map_or("", String::as_str)
+            ));
+        }
+        notifier.email(Topic::FailureAlert, &subject, body).await?;
+        self.alerted.extend(new);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email_config() -> EmailConfig {
+        EmailConfig {
+            server: "localhost".to_string(),
+            port: Some(2525),
+            tls: Some(EmailTls::None),
+            username: None,
+            password: None,
+            from: "Fetcher <fetcher@example.com>".to_string(),
+            to: vec!["ops@example.com".to_string(), "dev@example.com".to_string()],
+            danger_levels: None,
+            daily_reports: Some(false),
+            failure_alerts: None,
+            temperature_alerts: Some(false),
+            gap_alerts: Some(false),
+        }
+    }
+
+    #[test]
+    fn test_mailer() {
+        let mailer = Mailer::from_config(&email_config()).unwrap();
+        assert_eq!(mailer.topics, vec![Topic::DangerLevel, Topic::FailureAlert]);
+
+        let message = mailer
+            .message("Daily report", "All measurements sent".to_string())
+            .unwrap();
+        let message = String::from_utf8(message.formatted()).unwrap();
+        assert!(message.contains("From: Fetcher <fetcher@example.com>"));
+        assert!(message.contains("To: ops@example.com, dev@example.com"));
+        assert!(message.contains("Subject: Daily report"));
+        assert!(message.contains("All measurements sent"));
+
+        let mut config = email_config();
+        config.to.push("not an address".to_string());
+        assert!(Mailer::from_config(&config).is_err());
+    }
+}
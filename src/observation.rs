@@ -0,0 +1,174 @@
+//! Measurement model for station observations with multiple parameters
+
+use std::{collections::BTreeMap, fmt};
+
+use chrono::{DateTime, Utc};
+
+/// A hydrological parameter measured by a FOEN station
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Parameter {
+    /// Water temperature in °C
+    WaterTemperature,
+    /// Water level in m above sea level
+    WaterLevel,
+    /// Discharge in m³/s
+    Discharge,
+    /// Air temperature in °C
+    AirTemperature,
+}
+
+impl Parameter {
+    /// Human readable name of the parameter
+    pub fn label(&self) -> &'static str {
+        match self {
+            Parameter::WaterTemperature => "water temperature",
+            Parameter::WaterLevel => "water level",
+            Parameter::Discharge => "discharge",
+            Parameter::AirTemperature => "air temperature",
+        }
+    }
+
+    /// Unit of the parameter values
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Parameter::WaterTemperature | Parameter::AirTemperature => "°C",
+            Parameter::WaterLevel => "m",
+            Parameter::Discharge => "m³/s",
+        }
+    }
+}
+
+/// Value of a single parameter with its own measurement time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParameterValue {
+    pub value: f32,
+    pub time: DateTime<Utc>,
+}
+
+/// An observation of a monitoring station
+///
+/// The water temperature is always present, since it's what the fetcher
+/// forwards to Gfrörli. Other parameters are optional.
+#[derive(Debug, Clone)]
+pub struct StationObservation {
+    pub station_id: u32,
+    pub station_name: String,
+    /// FOEN danger level from 1 (no or little danger) to 5 (very high
+    /// danger), if it was queried and is available
+    pub danger_level: Option<u8>,
+    values: BTreeMap<Parameter, ParameterValue>,
+}
+
+impl StationObservation {
+    /// Create an observation containing only the water temperature
+    pub fn new(
+        station_id: u32,
+        station_name: impl Into<String>,
+        time: DateTime<Utc>,
+        temperature: f32,
+    ) -> Self {
+        let values = BTreeMap::from([(
+            Parameter::WaterTemperature,
+            ParameterValue {
+                value: temperature,
+                time,
+            },
+        )]);
+        Self {
+            station_id,
+            station_name: station_name.into(),
+            danger_level: None,
+            values,
+        }
+    }
+
+    /// Water temperature value and time
+    pub fn water_temperature(&self) -> &ParameterValue {
+        self.get(Parameter::WaterTemperature)
+            .expect("water temperature is always present")
+    }
+
+    /// Water temperature in °C
+    pub fn temperature(&self) -> f32 {
+        self.water_temperature().value
+    }
+
+    /// Time of the water temperature measurement
+    pub fn time(&self) -> DateTime<Utc> {
+        self.water_temperature().time
+    }
+
+    /// Get the value of a parameter, if available
+    pub fn get(&self, parameter: Parameter) -> Option<&ParameterValue> {
+        self.values.get(&parameter)
+    }
+
+    /// Set the value of a parameter
+    pub fn set(&mut self, parameter: Parameter, value: ParameterValue) {
+        self.values.insert(parameter, value);
+    }
+
+    /// Iterate over all available parameters
+    pub fn parameters(&self) -> impl Iterator<Item = (Parameter, &ParameterValue)> {
+        self.values
+            .iter()
+            .map(|(parameter, value)| (*parameter, value))
+    }
+
+    /// Formats the parameters other than the water temperature for log output
+    ///
+    /// Returns an empty string if there are no other parameters.
+    pub fn additional_summary(&self) -> String {
+        let values = self
+            .parameters()
+            .filter(|(parameter, _)| *parameter != Parameter::WaterTemperature)
+            .map(|(parameter, value)| {
+                format!("{parameter} {:.3} {}", value.value, parameter.unit())
+            })
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return String::new();
+        }
+        format!(" [{}]", values.join(", "))
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_get_and_set_parameters() {
+        let time = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let mut observation = StationObservation::new(2104, "Weesen", time, 6.5);
+        assert_eq!(observation.temperature(), 6.5);
+        assert_eq!(observation.time(), time);
+        assert_eq!(observation.get(Parameter::Discharge), None);
+        assert_eq!(observation.additional_summary(), "");
+
+        observation.set(Parameter::Discharge, ParameterValue { value: 35.5, time });
+        observation.set(
+            Parameter::WaterLevel,
+            ParameterValue {
+                value: 419.25,
+                time,
+            },
+        );
+        assert_eq!(
+            observation.get(Parameter::Discharge),
+            Some(&ParameterValue { value: 35.5, time })
+        );
+        assert_eq!(
+            observation.additional_summary(),
+            " [water level 419.250 m, discharge 35.500 m³/s]"
+        );
+    }
+}
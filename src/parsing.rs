@@ -1,80 +1,610 @@
 //! Data parsing and structures for SPARQL responses
 
+use std::borrow::Cow;
+
+use anyhow::{Context, Result, anyhow, bail};
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::observation::{Parameter, ParameterValue, StationObservation};
 
 /// Response structure for SPARQL JSON results format
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 pub struct SparqlResponse {
+    /// Variables listed in the `head` section
+    pub vars: Vec<String>,
     pub results: Results,
 }
 
 /// Container for SPARQL query result bindings
-#[derive(Debug, Deserialize)]
+///
+/// The bindings are kept as raw JSON values, so that every binding can be
+/// parsed independently and a single malformed binding doesn't fail the whole
+/// response.
+#[derive(Debug)]
 pub struct Results {
-    pub bindings: Vec<SparqlBinding>,
+    pub bindings: Vec<serde_json::Value>,
 }
 
-/// SPARQL binding structure for station temperature queries
+/// A response that is valid JSON, but not shaped like the results of the
+/// query, e.g. because LINDAS changed its schema
+#[derive(Debug, Error)]
+#[error("SPARQL endpoint returned unexpected result shape: {0}")]
+pub struct UnexpectedShape(String);
+
+/// Parse a SPARQL JSON response of a query selecting `variables`
+///
+/// The `head.vars` of the response must contain all of the variables (more
+/// are fine), and `results.bindings` must be an array. The bindings
+/// themselves are parsed later, one at a time.
+pub fn parse_sparql_response(body: &str, variables: &[&str]) -> Result<SparqlResponse> {
+    let mut response: serde_json::Value =
+        serde_json::from_str(body).with_context(|| "Invalid JSON")?;
+
+    let vars = response
+        .pointer("/head/vars")
+        .and_then(|vars| vars.as_array())
+        .ok_or_else(|| UnexpectedShape("missing head.vars".to_string()))?
+        .iter()
+        .map(|var| {
+            var.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| UnexpectedShape(format!("head.vars contains {var}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let missing: Vec<_> = variables
+        .iter()
+        .filter(|variable| !vars.iter().any(|var| var == *variable))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        return Err(UnexpectedShape(format!(
+            "head.vars [{}] lacks the requested variables [{}]",
+            vars.join(", "),
+            missing.join(", ")
+        ))
+        .into());
+    }
+
+    let bindings = match response
+        .pointer_mut("/results/bindings")
+        .map(serde_json::Value::take)
+    {
+        Some(serde_json::Value::Array(bindings)) => bindings,
+        _ => return Err(UnexpectedShape("missing results.bindings array".to_string()).into()),
+    };
+    Ok(SparqlResponse {
+        vars,
+        results: Results { bindings },
+    })
+}
+
+/// XML Schema datatypes accepted for numeric values
+const NUMERIC_DATATYPES: &[&str] = &[
+    "http://www.w3.org/2001/XMLSchema#decimal",
+    "http://www.w3.org/2001/XMLSchema#float",
+    "http://www.w3.org/2001/XMLSchema#double",
+];
+
+/// XML Schema datatypes accepted for danger levels
+const INTEGER_DATATYPES: &[&str] = &[
+    "http://www.w3.org/2001/XMLSchema#integer",
+    "http://www.w3.org/2001/XMLSchema#int",
+    "http://www.w3.org/2001/XMLSchema#nonNegativeInteger",
+    "http://www.w3.org/2001/XMLSchema#decimal",
+];
+
+/// XML Schema datatypes accepted for timestamps
+const DATETIME_DATATYPES: &[&str] = &["http://www.w3.org/2001/XMLSchema#dateTime"];
+
+/// A single value in a SPARQL binding
 #[derive(Debug, Deserialize)]
-pub struct SparqlBinding {
-    #[serde(deserialize_with = "deserialize_sparql_value")]
-    pub name: String,
-    #[serde(deserialize_with = "deserialize_sparql_datetime")]
-    pub time: DateTime<Utc>,
-    #[serde(deserialize_with = "deserialize_sparql_temperature")]
-    pub temperature: f32,
+pub struct BindingValue {
+    pub value: String,
+    /// Datatype IRI of a typed literal
+    pub datatype: Option<String>,
 }
 
-/// Custom deserializer to extract the "value" field from SPARQL binding objects
-fn deserialize_sparql_value<'de, D>(deserializer: D) -> Result<String, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    deserialize_binding_value(deserializer)
+impl BindingValue {
+    /// Ensure that the literal has one of the expected datatypes
+    fn check_datatype(&self, variable: &str, expected: &[&str]) -> Result<()> {
+        match self.datatype.as_deref() {
+            Some(datatype) if expected.contains(&datatype) => Ok(()),
+            Some(datatype) => Err(anyhow!(
+                "Unexpected datatype <{datatype}> for variable '{variable}'"
+            )),
+            None => Err(anyhow!("Missing datatype for variable '{variable}'")),
+        }
+    }
+
+    /// Parse the value as a timestamp
+    fn parse_datetime(&self, variable: &str) -> Result<DateTime<Utc>> {
+        self.check_datatype(variable, DATETIME_DATATYPES)?;
+        DateTime::parse_from_rfc3339(&self.value)
+            .map(|dt| dt.with_timezone(&Utc))
+            .with_context(|| {
+                format!(
+                    "Invalid datetime format '{}' for variable '{variable}'",
+                    self.value
+                )
+            })
+    }
+
+    /// Parse the value as a finite number
+    ///
+    /// Surrounding whitespace is ignored and a single decimal comma is
+    /// accepted instead of a decimal point, as seen in some LINDAS literals.
+    fn parse_number(&self, variable: &str) -> Result<f32> {
+        self.check_datatype(variable, NUMERIC_DATATYPES)?;
+        let value = normalize_number(&self.value)
+            .parse::<f32>()
+            .with_context(|| {
+                format!(
+                    "Invalid number format '{}' for variable '{variable}'",
+                    self.value
+                )
+            })?;
+        if !value.is_finite() {
+            bail!(
+                "Non-finite number '{}' for variable '{variable}'",
+                self.value
+            );
+        }
+        Ok(value)
+    }
+
+    /// Parse the value as a danger level from 1 to 5
+    fn parse_danger_level(&self, variable: &str) -> Result<u8> {
+        self.check_datatype(variable, INTEGER_DATATYPES)?;
+        match self.value.trim().parse::<u8>() {
+            Ok(level @ 1..=5) => Ok(level),
+            _ => Err(anyhow!(
+                "Invalid danger level '{}' for variable '{variable}'",
+                self.value
+            )),
+        }
+    }
+}
+
+/// Normalize the lexical form of a number for parsing
+///
+/// A single comma is taken as decimal separator if there is no decimal point.
+/// Anything else, e.g. thousands separators, is left as it is and fails to
+/// parse.
+fn normalize_number(value: &str) -> Cow<'_, str> {
+    let value = value.trim();
+    if value.matches(',').count() == 1 && !value.contains('.') {
+        Cow::Owned(value.replace(',', "."))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Raw SPARQL binding, every variable may be missing
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBinding {
+    name: Option<BindingValue>,
+    time: Option<BindingValue>,
+    temperature: Option<BindingValue>,
+    water_level: Option<BindingValue>,
+    discharge: Option<BindingValue>,
+    air_temperature: Option<BindingValue>,
+    danger_level: Option<BindingValue>,
 }
 
-/// Helper function to extract the value string from a SPARQL binding
-fn deserialize_binding_value<'de, D>(deserializer: D) -> Result<String, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    #[derive(Deserialize)]
-    struct BindingValue {
-        value: String,
+/// Parse a single binding into an observation
+///
+/// A missing station name falls back to the station ID, while a missing or
+/// malformed time or temperature is an error. Malformed optional parameters
+/// are dropped with a warning. Literals must have the expected datatype
+/// (`xsd:dateTime` for times, `xsd:decimal`, `xsd:float` or `xsd:double` for
+/// numbers).
+pub fn parse_binding(station_id: u32, value: serde_json::Value) -> Result<StationObservation> {
+    let raw: RawBinding =
+        serde_json::from_value(value).with_context(|| "Invalid binding structure")?;
+
+    let name = raw
+        .name
+        .map(|name| name.value)
+        .unwrap_or_else(|| station_id.to_string());
+    let time = raw
+        .time
+        .ok_or_else(|| anyhow!("Missing variable 'time'"))
+        .and_then(|time| time.parse_datetime("time"))?;
+    let temperature = raw
+        .temperature
+        .ok_or_else(|| anyhow!("Missing variable 'temperature'"))
+        .and_then(|temperature| temperature.parse_number("temperature"))?;
+
+    let mut observation = StationObservation::new(station_id, name, time, temperature);
+    for (parameter, variable, raw_value) in [
+        (Parameter::WaterLevel, "waterLevel", raw.water_level),
+        (Parameter::Discharge, "discharge", raw.discharge),
+        (
+            Parameter::AirTemperature,
+            "airTemperature",
+            raw.air_temperature,
+        ),
+    ] {
+        let Some(raw_value) = raw_value else {
+            continue;
+        };
+        match raw_value.parse_number(variable) {
+            Ok(value) => observation.set(parameter, ParameterValue { value, time }),
+            Err(e) => warn!(
+                "Ignoring malformed {} for station {}: {:#}",
+                parameter, station_id, e
+            ),
+        }
+    }
+
+    if let Some(raw_level) = raw.danger_level {
+        match raw_level.parse_danger_level("dangerLevel") {
+            Ok(level) => observation.danger_level = Some(level),
+            Err(e) => warn!(
+                "Ignoring malformed danger level for station {}: {:#}",
+                station_id, e
+            ),
+        }
     }
 
-    let binding_value = BindingValue::deserialize(deserializer)?;
-    Ok(binding_value.value)
+    Ok(observation)
 }
 
-/// Custom deserializer to extract and parse DateTime from SPARQL binding objects
-fn deserialize_sparql_datetime<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let value = deserialize_binding_value(deserializer)?;
-    DateTime::parse_from_rfc3339(&value)
-        .map(|dt| dt.with_timezone(&Utc))
-        .map_err(|e| serde::de::Error::custom(format!("Invalid datetime format: {e}")))
+/// Parse all bindings of a response, skipping malformed ones with a warning
+///
+/// Fails with the error of the first binding if none of them is valid, e.g.
+/// because all temperatures have an unexpected datatype.
+pub fn parse_bindings(
+    station_id: u32,
+    bindings: Vec<serde_json::Value>,
+) -> Result<Vec<StationObservation>> {
+    let mut observations = Vec::new();
+    let mut first_error = None;
+    for value in bindings {
+        match parse_binding(station_id, value) {
+            Ok(observation) => observations.push(observation),
+            Err(e) => {
+                warn!(
+                    "Skipping malformed SPARQL binding for station {}: {:#}",
+                    station_id, e
+                );
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(error) if observations.is_empty() => {
+            Err(error.context("No valid binding in the response"))
+        }
+        _ => Ok(observations),
+    }
 }
 
-/// Custom deserializer to extract and parse temperature from SPARQL binding objects
-fn deserialize_sparql_temperature<'de, D>(deserializer: D) -> Result<f32, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let value = deserialize_binding_value(deserializer)?;
-    value
-        .parse::<f32>()
-        .map_err(|e| serde::de::Error::custom(format!("Invalid temperature format: {e}")))
+/// A station that measures the water temperature, as listed by LINDAS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredStation {
+    /// FOEN station ID
+    pub id: u32,
+    /// Station name
+    pub name: String,
 }
 
-/// Represents a water temperature measurement from a monitoring station
-#[derive(Debug)]
-pub struct StationMeasurement {
-    pub station_id: u32,
-    pub station_name: String,
-    pub time: DateTime<Utc>,
-    pub temperature: f32,
+/// Raw binding of the station discovery query
+#[derive(Debug, Deserialize)]
+struct RawStationBinding {
+    id: BindingValue,
+    name: BindingValue,
+}
+
+/// Parse the bindings of the station discovery query, sorted by station ID
+///
+/// Malformed bindings, e.g. with a non-numeric ID, are skipped with a warning.
+pub fn parse_station_bindings(bindings: Vec<serde_json::Value>) -> Vec<DiscoveredStation> {
+    let mut stations: Vec<_> = bindings
+        .into_iter()
+        .filter_map(|value| {
+            let station = serde_json::from_value::<RawStationBinding>(value)
+                .with_context(|| "Invalid binding structure")
+                .and_then(|raw| {
+                    let id = raw
+                        .id
+                        .value
+                        .parse()
+                        .with_context(|| format!("Invalid station ID '{}'", raw.id.value))?;
+                    Ok(DiscoveredStation {
+                        id,
+                        name: raw.name.value,
+                    })
+                });
+            match station {
+                Ok(station) => Some(station),
+                Err(e) => {
+                    warn!("Skipping malformed station binding: {:#}", e);
+                    None
+                }
+            }
+        })
+        .collect();
+    stations.sort_by_key(|station| station.id);
+    stations.dedup_by_key(|station| station.id);
+    stations
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    const XSD_DATETIME: &str = "http://www.w3.org/2001/XMLSchema#dateTime";
+    const XSD_DECIMAL: &str = "http://www.w3.org/2001/XMLSchema#decimal";
+    const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+
+    #[test]
+    fn test_parse_sparql_response() {
+        let body = json!({
+            "head": { "vars": ["name", "time", "temperature", "waterLevel"] },
+            "results": { "bindings": [{}, {}] }
+        })
+        .to_string();
+        let response = parse_sparql_response(&body, &["time", "temperature"]).unwrap();
+        assert_eq!(response.vars.len(), 4);
+        assert_eq!(response.results.bindings.len(), 2);
+
+        let error =
+            parse_sparql_response(&body, &["time", "temperature", "dangerLevel"]).unwrap_err();
+        assert!(error.downcast_ref::<UnexpectedShape>().is_some());
+        assert_eq!(
+            error.to_string(),
+            "SPARQL endpoint returned unexpected result shape: head.vars \
+             [name, time, temperature, waterLevel] lacks the requested variables [dangerLevel]"
+        );
+
+        // Renamed sections, e.g. after a schema change of the endpoint
+        for body in [
+            json!({ "results": { "bindings": [] } }),
+            json!({ "head": { "vars": "time" }, "results": { "bindings": [] } }),
+            json!({ "head": { "vars": ["time", "temperature"] }, "rows": [] }),
+            json!({ "head": { "vars": ["time", "temperature"] }, "results": { "bindings": {} } }),
+        ] {
+            let error = parse_sparql_response(&body.to_string(), &["time"]).unwrap_err();
+            assert!(error.downcast_ref::<UnexpectedShape>().is_some(), "{body}");
+        }
+
+        let error = parse_sparql_response("<html>", &["time"]).unwrap_err();
+        assert!(error.downcast_ref::<UnexpectedShape>().is_none());
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        let observation = parse_binding(
+            2104,
+            json!({
+                "name": { "type": "literal", "value": "Linth - Weesen" },
+                "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00+01:00" },
+                "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "6.42" }
+            }),
+        )
+        .unwrap();
+        assert_eq!(observation.station_name, "Linth - Weesen");
+        assert_eq!(
+            observation.time(),
+            Utc.with_ymd_and_hms(2025, 1, 15, 11, 30, 0).unwrap()
+        );
+        assert_eq!(observation.temperature(), 6.42);
+        assert_eq!(observation.parameters().count(), 1);
+    }
+
+    #[test]
+    fn test_parse_binding_additional_parameters() {
+        let observation = parse_binding(
+            2104,
+            json!({
+                "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00Z" },
+                "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "6.42" },
+                "waterLevel": { "type": "literal", "datatype": XSD_DECIMAL, "value": "419.25" },
+                "discharge": { "type": "literal", "datatype": XSD_DECIMAL, "value": "n/a" }
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            observation.get(Parameter::WaterLevel).map(|v| v.value),
+            Some(419.25)
+        );
+        assert_eq!(observation.get(Parameter::Discharge), None);
+    }
+
+    #[test]
+    fn test_parse_binding_danger_level() {
+        let binding = |level: &str| {
+            json!({
+                "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00Z" },
+                "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "6.42" },
+                "dangerLevel": { "type": "literal", "datatype": XSD_INTEGER, "value": level }
+            })
+        };
+        let observation = parse_binding(2104, binding("3")).unwrap();
+        assert_eq!(observation.danger_level, Some(3));
+
+        // Out of range levels are dropped, the observation is kept
+        let observation = parse_binding(2104, binding("6")).unwrap();
+        assert_eq!(observation.danger_level, None);
+        assert_eq!(observation.temperature(), 6.42);
+    }
+
+    #[test]
+    fn test_parse_number_literal_shapes() {
+        let number = |value: &str| {
+            BindingValue {
+                value: value.to_string(),
+                datatype: Some(XSD_DECIMAL.to_string()),
+            }
+            .parse_number("temperature")
+        };
+        assert_eq!(number("6.42").unwrap(), 6.42);
+        assert_eq!(number(" 6.42\n").unwrap(), 6.42);
+        assert_eq!(number("6,42").unwrap(), 6.42);
+        assert_eq!(number("\t-0,5 ").unwrap(), -0.5);
+        assert_eq!(number("+6.42").unwrap(), 6.42);
+        assert_eq!(number("6.").unwrap(), 6.0);
+        assert_eq!(number(".5").unwrap(), 0.5);
+        assert_eq!(number("6.42E0").unwrap(), 6.42);
+        assert_eq!(number("17").unwrap(), 17.0);
+
+        for invalid in ["", "  ", "6,4,2", "1,234.5", "6.42 °C", "6 42", "n/a"] {
+            assert!(number(invalid).is_err(), "'{invalid}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_binding_non_finite() {
+        for value in ["NaN", "INF", "-inf"] {
+            let error = parse_binding(
+                2104,
+                json!({
+                    "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00Z" },
+                    "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": value }
+                }),
+            )
+            .unwrap_err();
+            assert!(
+                format!("{error:#}").contains("Non-finite number"),
+                "{error:#}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_binding_missing_name() {
+        let observation = parse_binding(
+            2104,
+            json!({
+                "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00Z" },
+                "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "6.42" }
+            }),
+        )
+        .unwrap();
+        assert_eq!(observation.station_name, "2104");
+    }
+
+    #[test]
+    fn test_parse_bindings_skips_malformed() {
+        let bindings = parse_bindings(
+            2104,
+            vec![
+                json!({
+                    "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00Z" },
+                    "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "warm" }
+                }),
+                json!({
+                    "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "6.42" }
+                }),
+                json!("not a binding"),
+                json!({
+                    "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:40:00Z" },
+                    "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "6.5" }
+                }),
+            ],
+        )
+        .unwrap();
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].temperature(), 6.5);
+    }
+
+    #[test]
+    fn test_parse_bindings_all_malformed() {
+        let error = parse_bindings(
+            2104,
+            vec![
+                json!({
+                    "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00Z" },
+                    "temperature": {
+                        "type": "literal",
+                        "datatype": "http://www.w3.org/2001/XMLSchema#string",
+                        "value": "6.42"
+                    }
+                }),
+                json!("not a binding"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "No valid binding in the response: Unexpected datatype \
+            <http://www.w3.org/2001/XMLSchema#string> for variable 'temperature'"
+        );
+
+        // A response without bindings has no observation, but isn't malformed
+        assert!(parse_bindings(2104, Vec::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_binding_unexpected_datatype() {
+        let error = parse_binding(
+            2104,
+            json!({
+                "time": { "type": "literal", "datatype": XSD_DATETIME, "value": "2025-01-15T12:30:00Z" },
+                "temperature": {
+                    "type": "literal",
+                    "datatype": "http://www.w3.org/2001/XMLSchema#string",
+                    "value": "6.42"
+                }
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unexpected datatype <http://www.w3.org/2001/XMLSchema#string> for variable 'temperature'"
+        );
+    }
+
+    #[test]
+    fn test_parse_binding_missing_datatype() {
+        let error = parse_binding(
+            2104,
+            json!({
+                "time": { "type": "literal", "value": "2025-01-15T12:30:00Z" },
+                "temperature": { "type": "literal", "datatype": XSD_DECIMAL, "value": "6.42" }
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Missing datatype for variable 'time'");
+    }
+
+    #[test]
+    fn test_parse_station_bindings() {
+        let stations = parse_station_bindings(vec![
+            json!({
+                "id": { "type": "literal", "value": "2176" },
+                "name": { "type": "literal", "value": "Sihl - Zürich" }
+            }),
+            json!({
+                "id": { "type": "literal", "value": "not-a-number" },
+                "name": { "type": "literal", "value": "Unknown" }
+            }),
+            json!({
+                "id": { "type": "literal", "value": "2104" },
+                "name": { "type": "literal", "value": "Linth - Weesen" }
+            }),
+            json!({ "id": { "type": "literal", "value": "2135" } }),
+        ]);
+        assert_eq!(
+            stations,
+            vec![
+                DiscoveredStation {
+                    id: 2104,
+                    name: "Linth - Weesen".to_string()
+                },
+                DiscoveredStation {
+                    id: 2176,
+                    name: "Sihl - Zürich".to_string()
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,788 @@
+//! Processing pipeline: Fetch a station's observation and deliver it to the API
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    panic::AssertUnwindSafe,
+};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant, timeout_at},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    alert,
+    anomaly::{self, Evaluation},
+    capture::Capture,
+    config::Config,
+    danger,
+    database::{ErrorPhase, ErrorRecord, MeasurementStore, SentMeasurement, StationState},
+    display::format_delta,
+    error::FetcherError,
+    gfroerli::{GfroerliTarget, SendOutcome, idempotency_key},
+    http::{ErrorClass, HttpClients, error_status},
+    observation::StationObservation,
+    sink, smoothing,
+    sparql::{SparqlSource, fetch_station_observation, station_query},
+    stats::CycleStats,
+    summary, timezone,
+};
+
+/// Records a fetch or send failure in the database
+///
+/// Failing to record the error is only logged, so that the original error is
+/// never masked.
+async fn record_error(
+    store: &dyn MeasurementStore,
+    station_id: u32,
+    sensor_id: Option<u32>,
+    phase: ErrorPhase,
+    error: &anyhow::Error,
+) {
+    let record = ErrorRecord {
+        occurred_at: Utc::now(),
+        station_id,
+        sensor_id,
+        phase,
+        http_status: error_status(error).map(|status| status.as_u16()),
+        message: format!("{error:#}"),
+    };
+    if let Err(e) = store.record_error(&record).await {
+        warn!("Failed to record error for station {}: {:#}", station_id, e);
+    }
+}
+
+/// Cross-checks the local deduplication state with the Gfrörli API
+///
+/// For every sensor whose latest measurement in the API is newer than the
+/// latest one recorded locally (e.g. because the database file was wiped), the
+/// API's measurement is recorded as sent, so that it isn't sent again. Sensors
+/// with newer local measurements than in the API are only reported. Only
+/// targets with `sync_on_startup` enabled are checked.
+pub async fn sync_sent_measurements(
+    clients: &HttpClients,
+    config: &Config,
+    store: &dyn MeasurementStore,
+    dry_run: bool,
+) -> Result<()> {
+    for station in config.enabled_stations() {
+        for name in config.station_targets(station.foen_station_id) {
+            let target = GfroerliTarget::new(config, clients, name)?;
+            if target.api.sync_on_startup() {
+                sync_sensor(&target, store, station.gfroerli_sensor_id, dry_run).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cross-checks the local deduplication state of a sensor with a Gfrörli target
+async fn sync_sensor(
+    target: &GfroerliTarget<'_>,
+    store: &dyn MeasurementStore,
+    sensor_id: u32,
+    dry_run: bool,
+) -> Result<()> {
+    let Some(remote) = target
+        .client
+        .latest_measurement(sensor_id)
+        .await
+        .map_err(|e| e.for_sensor(sensor_id))
+        .with_context(|| format!("Failed to sync sensor {sensor_id} (target {})", target.name))?
+    else {
+        return Ok(());
+    };
+    let local = store
+        .latest_sent_measurement(target.name, sensor_id)
+        .await?;
+
+    match local {
+        Some(local) if local.time > remote.created_at => warn!(
+            "Sensor {} latest measurement at {} is missing in Gfrörli target '{}' (latest is {})",
+            sensor_id, local.time, target.name, remote.created_at
+        ),
+        Some(local) if local.time == remote.created_at => {}
+        _ if dry_run => info!(
+            "Sensor {} measurement at {} would be recorded as sent to '{}' [DRY RUN]",
+            sensor_id, remote.created_at, target.name
+        ),
+        _ => {
+            info!(
+                "Sensor {} measurement at {} found in Gfrörli target '{}', recording as sent",
+                sensor_id, remote.created_at, target.name
+            );
+            store
+                .record_measurement_sent(&SentMeasurement {
+                    target: target.name.to_string(),
+                    sensor_id,
+                    time: remote.created_at,
+                    temperature: Some(remote.temperature),
+                    idempotency_key: idempotency_key(sensor_id, remote.created_at),
+                    gfroerli_id: None,
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Capacity of the channel between the fetch and the delivery stage
+///
+/// The fetch stage waits if the delivery stage falls behind by this many
+/// events.
+const EVENT_CHANNEL_CAPACITY: usize = 16;
+
+/// Event passed from the fetch stage to the delivery stage
+#[derive(Debug)]
+pub enum OutputEvent {
+    /// The observation of a station was fetched
+    Measurement(StationObservation),
+    /// Fetching a station failed
+    FetchFailed {
+        station_id: u32,
+        error: anyhow::Error,
+    },
+}
+
+impl OutputEvent {
+    /// ID of the station the event belongs to
+    pub fn station_id(&self) -> u32 {
+        match self {
+            OutputEvent::Measurement(observation) => observation.station_id,
+            OutputEvent::FetchFailed { station_id, .. } => *station_id,
+        }
+    }
+}
+
+/// Outcome of a processing cycle over all stations
+#[derive(Debug, Default)]
+pub struct CycleOutcome {
+    /// Number of stations processed successfully
+    pub success: usize,
+    /// Number of stations that failed to fetch or deliver
+    pub errors: usize,
+    /// Statistics over the successfully processed observations
+    pub stats: CycleStats,
+    /// Stations whose newest measurement is older than one run interval
+    pub lagging: Vec<u32>,
+    /// Newest observation per successfully processed station
+    pub observations: BTreeMap<u32, StationObservation>,
+    /// Error message per failed station
+    pub failures: BTreeMap<u32, String>,
+    /// Failed stations whose error won't go away by retrying, e.g. because
+    /// the API rejected the credentials or the measurement
+    pub permanent_failures: BTreeSet<u32>,
+    /// Number of stations cancelled because the cycle exceeded its deadline
+    pub cancelled: usize,
+    /// Number of failed stations whose processing panicked, also counted in
+    /// `errors`
+    pub panics: usize,
+    /// Time the cycle started
+    pub started_at: DateTime<Utc>,
+    /// How long the cycle took
+    pub duration: Duration,
+    /// Time spent fetching and delivering per station that finished
+    pub station_durations: BTreeMap<u32, Duration>,
+}
+
+impl CycleOutcome {
+    /// Whether stations were cancelled because the cycle took too long
+    pub fn is_degraded(&self) -> bool {
+        self.cancelled > 0
+    }
+
+    /// Add the outcome of another cycle, e.g. of a single station in loop
+    /// mode
+    ///
+    /// The start time and duration are kept.
+    pub fn merge(&mut self, other: CycleOutcome) {
+        self.success += other.success;
+        self.errors += other.errors;
+        self.cancelled += other.cancelled;
+        self.panics += other.panics;
+        self.stats.merge(other.stats);
+        self.lagging.extend(other.lagging);
+        self.observations.extend(other.observations);
+        self.failures.extend(other.failures);
+        self.permanent_failures.extend(other.permanent_failures);
+        self.station_durations.extend(other.station_durations);
+    }
+}
+
+/// Runs a future to completion, or until the deadline elapses or the token
+/// is cancelled
+async fn with_deadline<F: Future>(
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+    future: F,
+) -> Option<F::Output> {
+    let future = async {
+        match deadline {
+            Some(deadline) => timeout_at(deadline, future).await.ok(),
+            None => Some(future.await),
+        }
+    };
+    cancel.run_until_cancelled(future).await.flatten()
+}
+
+/// Runs a future of a station, turning a panic into a [`FetcherError::Panic`]
+///
+/// A bug in the processing of one station, e.g. an unexpected response that
+/// trips the parser, fails only this station instead of the whole process.
+async fn catch_panic<F: Future>(station_id: u32, future: F) -> Result<F::Output> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| FetcherError::panic(station_id, payload.as_ref()).into())
+}
+
+/// Runs one processing cycle over the given stations
+///
+/// Stations are fetched by a producer, which pushes the results onto a
+/// bounded channel. The delivery stage consumes the events concurrently and
+/// takes care of deduplication, sending and all database writes.
+///
+/// If a cycle timeout is configured, the stations that are not done when it
+/// elapses are cancelled and the cycle is marked as degraded. The same
+/// happens to the stations that are not done when `cancel` is cancelled,
+/// e.g. on shutdown: their requests are aborted at the next await point.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_cycle(
+    clients: &HttpClients,
+    config: &Config,
+    source: &SparqlSource,
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    station_ids: &[u32],
+    dry_run: bool,
+    cancel: &CancellationToken,
+) -> CycleOutcome {
+    let started = Instant::now();
+    let started_at = Utc::now();
+    let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+    let deadline = config
+        .run_cycle_timeout_seconds()
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    let expired =
+        || cancel.is_cancelled() || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+
+    let produce = async move {
+        for (index, &station_id) in station_ids.iter().enumerate() {
+            let fetch_started = Instant::now();
+            let fetch = fetch_station(clients, source, capture, store, config, station_id);
+            let Some(event) = with_deadline(deadline, cancel, catch_panic(station_id, fetch)).await
+            else {
+                return station_ids.len() - index;
+            };
+            let event =
+                event.unwrap_or_else(|error| OutputEvent::FetchFailed { station_id, error });
+            if sender.send((event, fetch_started.elapsed())).await.is_err() {
+                break;
+            }
+        }
+        0
+    };
+
+    let consume = async {
+        let interval = chrono::Duration::minutes(config.run_interval_minutes().into());
+        let mut outcome = CycleOutcome::default();
+        while let Some((event, fetch_duration)) = receiver.recv().await {
+            let station_id = event.station_id();
+            if expired() {
+                outcome.cancelled += 1;
+                continue;
+            }
+            let handle_started = Instant::now();
+            let handle = handle_event(clients, config, capture, store, event, dry_run);
+            let handle = async {
+                catch_panic(station_id, handle)
+                    .await
+                    .and_then(|result| result)
+            };
+            let Some(result) = with_deadline(deadline, cancel, handle).await else {
+                outcome.cancelled += 1;
+                continue;
+            };
+            outcome
+                .station_durations
+                .insert(station_id, fetch_duration + handle_started.elapsed());
+            match result {
+                Ok(observation) => {
+                    if Utc::now() - observation.time() > interval {
+                        outcome.lagging.push(station_id);
+                    }
+                    outcome.stats.add(&observation);
+                    outcome.observations.insert(station_id, observation);
+                    outcome.success += 1;
+                }
+                Err(e) => {
+                    error!(
+                        station_id,
+                        "Failed to process station {}: {:#}",
+                        config.station_label(station_id),
+                        e
+                    );
+                    if ErrorClass::of(&e) == ErrorClass::Permanent {
+                        warn!(
+                            "Error of station {} is permanent, it isn't retried early",
+                            config.station_label(station_id)
+                        );
+                        outcome.permanent_failures.insert(station_id);
+                    }
+                    if FetcherError::is_panic(&e) {
+                        outcome.panics += 1;
+                    }
+                    outcome.failures.insert(station_id, format!("{e:#}"));
+                    outcome.errors += 1;
+                }
+            }
+        }
+        outcome
+    };
+
+    let (not_fetched, mut outcome) = tokio::join!(produce, consume);
+    outcome.cancelled += not_fetched;
+    outcome.started_at = started_at;
+    outcome.duration = started.elapsed();
+    if outcome.is_degraded() && cancel.is_cancelled() {
+        info!(
+            "Cycle cancelled, {} stations not processed",
+            outcome.cancelled
+        );
+    } else if outcome.is_degraded() {
+        warn!(
+            "Cycle exceeded its time budget of {} seconds, {} stations cancelled",
+            config.run_cycle_timeout_seconds().unwrap_or_default(),
+            outcome.cancelled
+        );
+    }
+    summary::emit(config, station_ids, &outcome, dry_run);
+    outcome
+}
+
+/// Processes a single station: Fetches data and sends to API
+///
+/// Returns the fetched observation, even if it was skipped because it was
+/// already sent or held back by anomaly detection.
+pub async fn process_station(
+    clients: &HttpClients,
+    config: &Config,
+    source: &SparqlSource,
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    station_id: u32,
+    dry_run: bool,
+) -> Result<StationObservation> {
+    let event = fetch_station(clients, source, capture, store, config, station_id).await;
+    handle_event(clients, config, capture, store, event, dry_run).await
+}
+
+/// Fetch stage: Queries the latest observation of a station from LINDAS
+///
+/// The database is only read (to log the change since the previous cycle),
+/// never written.
+async fn fetch_station(
+    clients: &HttpClients,
+    source: &SparqlSource,
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    config: &Config,
+    station_id: u32,
+) -> OutputEvent {
+    let query = station_query(config, station_id);
+    let fetch_result = fetch_station_observation(&clients.sparql, source, capture, &query)
+        .await
+        .with_context(|| {
+            format!(
+                "Error fetching data for station {}",
+                config.station_label(station_id)
+            )
+        })
+        .and_then(|observation| {
+            observation
+                .ok_or_else(|| anyhow!("No temperature data found for station {}", station_id))
+        });
+    let mut observation = match fetch_result {
+        Ok(observation) => observation,
+        Err(error) => return OutputEvent::FetchFailed { station_id, error },
+    };
+    // The alias replaces the LINDAS name in all further output
+    if let Some(alias) = config.station_alias(station_id) {
+        observation.station_name = alias.to_string();
+    }
+
+    // Change since the previously stored value of the station
+    let delta = match store.station_state(station_id).await {
+        Ok(state) => state.map(|state| observation.temperature() - state.last_temperature),
+        Err(e) => {
+            warn!("Failed to read state of station {}: {:#}", station_id, e);
+            None
+        }
+    };
+    info!(
+        station_id = observation.station_id,
+        temperature = observation.temperature(),
+        delta,
+        "Station {} ({}) fetched: {:.3}°C{} (at {}){}",
+        observation.station_id,
+        observation.station_name,
+        observation.temperature(),
+        format_delta(delta),
+        timezone::log_time(observation.time()),
+        observation.additional_summary(),
+    );
+
+    // Warn about stations that stopped publishing new measurements
+    let stale_after = chrono::Duration::minutes(config.stale_after_minutes().into());
+    let age = Utc::now() - observation.time();
+    if age > stale_after {
+        warn!(
+            station_id = observation.station_id,
+            "Station {} ({}) measurement is stale: newest measurement is {} minutes old",
+            observation.station_id,
+            observation.station_name,
+            age.num_minutes(),
+        );
+    }
+
+    OutputEvent::Measurement(observation)
+}
+
+/// Delivery stage: Handles an event of the fetch stage
+///
+/// Returns the delivered observation, or the fetch error.
+async fn handle_event(
+    clients: &HttpClients,
+    config: &Config,
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    event: OutputEvent,
+    dry_run: bool,
+) -> Result<StationObservation> {
+    match event {
+        OutputEvent::Measurement(observation) => {
+            deliver_observation(clients, config, capture, store, &observation, dry_run).await?;
+            Ok(observation)
+        }
+        OutputEvent::FetchFailed { station_id, error } => {
+            let stale_after = chrono::Duration::minutes(config.stale_after_minutes().into());
+            if let Some(state) = store.station_state(station_id).await?
+                && Utc::now() - state.last_fetch_at > stale_after
+            {
+                warn!(
+                    "Station {} has not been fetched successfully since {}",
+                    config.station_label(station_id),
+                    timezone::log_time(state.last_fetch_at),
+                );
+            }
+            if !dry_run {
+                record_error(store, station_id, None, ErrorPhase::Fetch, &error).await;
+            }
+            Err(error)
+        }
+    }
+}
+
+/// Delivers a fetched observation: Checks for anomalies, updates the station
+/// state and sends it to all targets of the station
+async fn deliver_observation(
+    clients: &HttpClients,
+    config: &Config,
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    observation: &StationObservation,
+    dry_run: bool,
+) -> Result<()> {
+    let station_id = observation.station_id;
+    let previous_state = store.station_state(station_id).await?;
+
+    // Get Gfrörli sensor ID from config
+    let sensor_id = config
+        .find_gfroerli_sensor_id(station_id)
+        .ok_or_else(|| anyhow!("No sensor mapping found for station {}", station_id))?;
+
+    // Check for sudden temperature jumps
+    let evaluation = match config.anomaly_detection.as_ref() {
+        Some(anomaly_config) => {
+            anomaly::evaluate(
+                anomaly_config,
+                store,
+                previous_state.as_ref(),
+                observation,
+                dry_run,
+            )
+            .await?
+        }
+        None => Evaluation::default(),
+    };
+
+    // Held measurements don't become the new reference for the station
+    if !dry_run && !evaluation.hold {
+        store
+            .update_station_state(&StationState {
+                station_id,
+                last_fetch_at: Utc::now(),
+                last_measurement_time: observation.time(),
+                last_temperature: observation.temperature(),
+            })
+            .await?;
+    }
+
+    // A failed notification doesn't prevent sending the measurement
+    if let Some(danger_config) = &config.danger_level
+        && let Err(e) = danger::check(
+            danger_config,
+            &clients.notifications,
+            store,
+            observation,
+            dry_run,
+        )
+        .await
+    {
+        warn!(
+            "Failed to check danger level of station {}: {:#}",
+            config.station_label(station_id),
+            e
+        );
+    }
+
+    // Held measurements may be outliers, so they don't trigger alerts
+    if !evaluation.hold
+        && let Err(e) = alert::check(
+            config,
+            &clients.notifications,
+            previous_state.as_ref().map(|state| state.last_temperature),
+            observation,
+            dry_run,
+        )
+        .await
+    {
+        warn!(
+            "Failed to send temperature alert of station {}: {:#}",
+            config.station_label(station_id),
+            e
+        );
+    }
+
+    let targets = config
+        .station_targets(station_id)
+        .into_iter()
+        .map(|name| GfroerliTarget::new(config, clients, name))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(held) = evaluation.confirmed {
+        let confirmed = StationObservation::new(
+            station_id,
+            observation.station_name.clone(),
+            held.time,
+            held.temperature,
+        );
+        let confirmed = smoothing::apply(config, store, &confirmed, dry_run).await?;
+        deliver_everywhere(
+            clients, config, &targets, capture, store, &confirmed, sensor_id, dry_run,
+        )
+        .await?;
+    }
+
+    if !evaluation.hold {
+        let observation = smoothing::apply(config, store, observation, dry_run).await?;
+        deliver_everywhere(
+            clients,
+            config,
+            &targets,
+            capture,
+            store,
+            &observation,
+            sensor_id,
+            dry_run,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Sends an observation to all targets and all sinks
+///
+/// A failing target doesn't prevent forwarding to the sinks and vice versa.
+#[allow(clippy::too_many_arguments)]
+async fn deliver_everywhere(
+    clients: &HttpClients,
+    config: &Config,
+    targets: &[GfroerliTarget<'_>],
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    observation: &StationObservation,
+    sensor_id: u32,
+    dry_run: bool,
+) -> Result<()> {
+    let delivered =
+        deliver_to_targets(targets, capture, store, observation, sensor_id, dry_run).await;
+    let forwarded =
+        sink::deliver_to_sinks(clients, config, store, observation, sensor_id, dry_run).await;
+    if let (Err(_), Err(e)) = (&delivered, &forwarded) {
+        warn!("{:#}", e);
+    }
+    delivered.and(forwarded)
+}
+
+/// Sends an observation to all targets
+///
+/// A failure for one target doesn't prevent sending to the others, the first
+/// error is returned after all targets were tried.
+pub async fn deliver_to_targets(
+    targets: &[GfroerliTarget<'_>],
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    observation: &StationObservation,
+    sensor_id: u32,
+    dry_run: bool,
+) -> Result<()> {
+    let mut result = Ok(());
+    for target in targets {
+        let delivery =
+            deliver_measurement(target, capture, store, observation, sensor_id, dry_run).await;
+        if result.is_ok() {
+            result = delivery;
+        } else if let Err(e) = delivery {
+            warn!("{:#}", e);
+        }
+    }
+    result
+}
+
+/// Finds a measurement sent to a target with the same temperature as an
+/// observation within the dedup tolerance of the target, if configured
+async fn value_duplicate(
+    target: &GfroerliTarget<'_>,
+    store: &dyn MeasurementStore,
+    observation: &StationObservation,
+    sensor_id: u32,
+) -> Result<Option<SentMeasurement>> {
+    let Some(minutes) = target.api.dedup_tolerance_minutes else {
+        return Ok(None);
+    };
+    let tolerance = chrono::Duration::minutes(minutes.into());
+    let temperature = target.api.round(observation.temperature());
+    let sent = store
+        .sent_measurements(
+            target.name,
+            sensor_id,
+            observation.time() - tolerance,
+            observation.time() + tolerance,
+        )
+        .await?;
+    Ok(sent
+        .into_iter()
+        .find(|sent| sent.temperature == Some(temperature)))
+}
+
+/// Sends an observation to a target, unless it was already sent
+async fn deliver_measurement(
+    target: &GfroerliTarget<'_>,
+    capture: Option<&Capture>,
+    store: &dyn MeasurementStore,
+    observation: &StationObservation,
+    sensor_id: u32,
+    dry_run: bool,
+) -> Result<()> {
+    // Check if this observation was already sent
+    if store
+        .is_measurement_sent(target.name, sensor_id, observation.time())
+        .await?
+    {
+        warn!(
+            "Station {} ({}) measurement at {} already sent to target '{}', skipping",
+            observation.station_id,
+            observation.station_name,
+            timezone::log_time(observation.time()),
+            target.name,
+        );
+        return Ok(());
+    }
+
+    // FOEN sometimes republishes a measurement with a shifted timestamp
+    if let Some(duplicate) = value_duplicate(target, store, observation, sensor_id).await? {
+        warn!(
+            "Station {} ({}) measurement at {} has the same value as the measurement at {} sent to target '{}', skipping",
+            observation.station_id,
+            observation.station_name,
+            timezone::log_time(observation.time()),
+            timezone::log_time(duplicate.time),
+            target.name,
+        );
+        return Ok(());
+    }
+
+    if dry_run {
+        info!(
+            "Station {} ({}) would be sent to API (sensor {}, target '{}') [DRY RUN]",
+            observation.station_id, observation.station_name, sensor_id, target.name,
+        );
+        return Ok(());
+    }
+
+    // Send to API
+    match target
+        .client
+        .create_measurement(observation, sensor_id, capture)
+        .await
+        .map_err(|e| e.for_sensor(sensor_id))
+    {
+        Ok(outcome) => {
+            // Record that we successfully sent this measurement, or that the
+            // API already has it
+            store
+                .record_measurement_sent(&SentMeasurement {
+                    target: target.name.to_string(),
+                    sensor_id,
+                    time: observation.time(),
+                    temperature: Some(target.api.round(observation.temperature())),
+                    idempotency_key: idempotency_key(sensor_id, observation.time()),
+                    gfroerli_id: outcome.gfroerli_id(),
+                })
+                .await?;
+            if outcome == SendOutcome::AlreadyExists {
+                debug!(
+                    "Station {} ({}) measurement at {} already exists in target '{}', recorded as sent",
+                    observation.station_id,
+                    observation.station_name,
+                    timezone::log_time(observation.time()),
+                    target.name,
+                );
+                return Ok(());
+            }
+            info!(
+                station_id = observation.station_id,
+                sensor_id,
+                temperature = observation.temperature(),
+                "Station {} ({}) sent to API (sensor {}, target '{}')",
+                observation.station_id,
+                observation.station_name,
+                sensor_id,
+                target.name,
+            );
+            Ok(())
+        }
+        Err(e) => {
+            let e = anyhow::Error::from(e);
+            record_error(
+                store,
+                observation.station_id,
+                Some(sensor_id),
+                ErrorPhase::Send,
+                &e,
+            )
+            .await;
+            Err(e.context(format!(
+                "Failed to send measurement for station {} ({}) (sensor {}, target '{}')",
+                observation.station_id, observation.station_name, sensor_id, target.name
+            )))
+        }
+    }
+}
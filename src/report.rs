@@ -0,0 +1,326 @@
+//! Daily summary of the sent measurements (`report daily` subcommand)
+
+use std::{fmt, fs, path::Path};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Days, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    config::{Config, StationConfig},
+    database::MeasurementStore,
+    display::format_temperature,
+    http::HttpClients,
+    notify::Topic,
+    schedule::PUBLICATION_GRID_MINUTES,
+    timezone::format_time,
+};
+
+/// Period without sent measurements that is longer than the publication grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Gap {
+    /// Last measurement before the gap, or the start of the day
+    pub from: DateTime<Utc>,
+    /// First measurement after the gap, or the end of the day
+    pub to: DateTime<Utc>,
+}
+
+/// Summary of the measurements of a sensor sent to one target
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SensorSummary {
+    pub station_id: u32,
+    pub sensor_id: u32,
+    pub target: String,
+    /// Number of sent measurements
+    pub count: usize,
+    /// Number of measurements published by FOEN per day
+    pub expected: usize,
+    /// Lowest temperature in °C, if any values are stored
+    pub min_temperature: Option<f32>,
+    /// Highest temperature in °C, if any values are stored
+    pub max_temperature: Option<f32>,
+    /// Average temperature in °C, if any values are stored
+    pub mean_temperature: Option<f32>,
+    /// Periods without measurements, oldest first
+    pub gaps: Vec<Gap>,
+}
+
+/// Summary of the measurements sent during a day
+#[derive(Debug, Clone, Serialize)]
+pub struct DailyReport {
+    /// Day of the report
+    pub date: NaiveDate,
+    /// Time zone the day starts and ends in
+    pub timezone: Tz,
+    /// Summary per sensor and target
+    pub sensors: Vec<SensorSummary>,
+}
+
+/// Start of a day in a time zone
+fn start_of_day(date: NaiveDate, timezone: Tz) -> Result<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()
+        .map(|start| start.with_timezone(&Utc))
+        .ok_or_else(|| anyhow!("{date} has no midnight in time zone {timezone}"))
+}
+
+/// Summarize the measurements of a sensor during a day (ordered by time)
+fn summarize(
+    station: &StationConfig,
+    target: &str,
+    measurements: &[(DateTime<Utc>, Option<f32>)],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> SensorSummary {
+    let grid = Duration::minutes(PUBLICATION_GRID_MINUTES);
+    let temperatures: Vec<f32> = measurements.iter().filter_map(|(_, t)| *t).collect();
+
+    let times = measurements.iter().map(|(time, _)| *time);
+    let bounds: Vec<_> = [start].into_iter().chain(times).chain([end]).collect();
+    let gaps = bounds
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] > grid)
+        .map(|pair| Gap {
+            from: pair[0],
+            to: pair[1],
+        })
+        .collect();
+
+    SensorSummary {
+        station_id: station.foen_station_id,
+        sensor_id: station.gfroerli_sensor_id,
+        target: target.to_string(),
+        count: measurements.len(),
+        expected: ((end - start).num_minutes() / PUBLICATION_GRID_MINUTES) as usize,
+        min_temperature: temperatures.iter().copied().reduce(f32::min),
+        max_temperature: temperatures.iter().copied().reduce(f32::max),
+        mean_temperature: (!temperatures.is_empty())
+            .then(|| temperatures.iter().sum::<f32>() / temperatures.len() as f32),
+        gaps,
+    }
+}
+
+/// Summarize the measurements sent during a day (in the display time zone)
+/// per enabled station and target
+pub async fn daily_report(
+    config: &Config,
+    store: &dyn MeasurementStore,
+    date: NaiveDate,
+) -> Result<DailyReport> {
+    let timezone = config.display_timezone();
+    let start = start_of_day(date, timezone)?;
+    let next_day = date
+        .checked_add_days(Days::new(1))
+        .ok_or_else(|| anyhow!("{date} is out of range"))?;
+    let end = start_of_day(next_day, timezone)?;
+
+    let mut sensors = Vec::new();
+    for station in config.enabled_stations() {
+        for target in config.station_targets(station.foen_station_id) {
+            // The time range of the query includes its end
+            let measurements: Vec<_> = store
+                .sent_measurements(
+                    target,
+                    station.gfroerli_sensor_id,
+                    start,
+                    end - Duration::seconds(1),
+                )
+                .await?
+                .into_iter()
+                .map(|sent| (sent.time, sent.temperature))
+                .collect();
+            sensors.push(summarize(station, target, &measurements, start, end));
+        }
+    }
+
+    Ok(DailyReport {
+        date,
+        timezone,
+        sensors,
+    })
+}
+
+/// Write the report to the configured directory, post it to the configured
+/// webhook and send it by email
+pub async fn deliver(config: &Config, clients: &HttpClients, report: &DailyReport) -> Result<()> {
+    if let Some(directory) = config.report_directory() {
+        let path = Path::new(directory).join(format!("daily-{}.txt", report.date));
+        fs::create_dir_all(directory)
+            .and_then(|()| fs::write(&path, report.to_string()))
+            .with_context(|| format!("Failed to write report '{}'", path.display()))?;
+        info!("Wrote daily report to '{}'", path.display());
+    }
+    if let Some(url) = config.report_webhook_url() {
+        clients
+            .notifications
+            .post_webhook(url, "daily report", report)
+            .await?;
+        info!("Posted daily report to the webhook");
+    }
+    if clients.notifications.emails(Topic::DailyReport) {
+        let subject = format!("Daily report for {}", report.date);
+        clients
+            .notifications
+            .email(Topic::DailyReport, &subject, report.to_string())
+            .await?;
+        info!("Sent daily report by email");
+    }
+    Ok(())
+}
+
+impl fmt::Display for DailyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let temperature = |t: Option<f32>| t.map(format_temperature).unwrap_or("      -".into());
+
+        writeln!(f, "Daily report for {} ({})", self.date, self.timezone)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>7} {:>6} {:<10} {:>9} {:>7} {:>7} {:>7} {:>4}",
+            "STATION", "SENSOR", "TARGET", "COUNT", "MIN", "MAX", "MEAN", "GAPS"
+        )?;
+        for sensor in &self.sensors {
+            writeln!(
+                f,
+                "{:>7} {:>6} {:<10} {:>9} {} {} {} {:>4}",
+                sensor.station_id,
+                sensor.sensor_id,
+                sensor.target,
+                format!("{}/{}", sensor.count, sensor.expected),
+                temperature(sensor.min_temperature),
+                temperature(sensor.max_temperature),
+                temperature(sensor.mean_temperature),
+                sensor.gaps.len(),
+            )?;
+        }
+
+        for sensor in self.sensors.iter().filter(|s| !s.gaps.is_empty()) {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Gaps of station {} (sensor {}, target '{}'):",
+                sensor.station_id, sensor.sensor_id, sensor.target
+            )?;
+            for gap in &sensor.gaps {
+                writeln!(
+                    f,
+                    "  {} - {}",
+                    format_time(gap.from, self.timezone),
+                    format_time(gap.to, self.timezone)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{DisplayConfig, GfroerliConfig},
+        database::{SentMeasurement, SqliteStore},
+    };
+
+    fn station() -> StationConfig {
+        StationConfig {
+            foen_station_id: 2104,
+            gfroerli_sensor_id: 1,
+            targets: None,
+            alias: None,
+            enabled: None,
+            station_type: None,
+            air_temperature: None,
+            alerts: None,
+            smoothing: None,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let start = "2025-01-15T00:00:00Z".parse().unwrap();
+        let end = start + Duration::days(1);
+        let at = |minutes| start + Duration::minutes(minutes);
+
+        // Every 10 minutes, except for a missing measurement at 01:00 and
+        // the last hour of the day
+        let measurements: Vec<_> = (0..138)
+            .filter(|&i| i != 6)
+            .map(|i| (at(i * 10), Some(6.0 + (i % 2) as f32)))
+            .collect();
+        let summary = summarize(&station(), "default", &measurements, start, end);
+
+        assert_eq!(summary.count, 137);
+        assert_eq!(summary.expected, 144);
+        assert_eq!(summary.min_temperature, Some(6.0));
+        assert_eq!(summary.max_temperature, Some(7.0));
+        assert_eq!(
+            summary.gaps,
+            vec![
+                Gap {
+                    from: at(50),
+                    to: at(70)
+                },
+                Gap {
+                    from: at(1370),
+                    to: end
+                },
+            ]
+        );
+
+        let summary = summarize(&station(), "default", &[], start, end);
+        assert_eq!(summary.mean_temperature, None);
+        assert_eq!(
+            summary.gaps,
+            vec![Gap {
+                from: start,
+                to: end
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_daily_report() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let mut config = Config::new(
+            vec![station()],
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        config.display = Some(DisplayConfig {
+            timezone: Some(Tz::Europe__Zurich),
+        });
+
+        // 23:00 UTC is already the next day in Zurich
+        for time in [
+            "2025-01-14T22:50:00Z",
+            "2025-01-15T12:00:00Z",
+            "2025-01-15T23:00:00Z",
+        ] {
+            let time = time.parse().unwrap();
+            store
+                .record_measurement_sent(&SentMeasurement {
+                    target: "default".to_string(),
+                    sensor_id: 1,
+                    time,
+                    temperature: Some(6.5),
+                    idempotency_key: format!("lindas-1-{}", time.timestamp()),
+                    gfroerli_id: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let date = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        let report = daily_report(&config, &store, date).await.unwrap();
+        assert_eq!(report.sensors.len(), 1);
+        assert_eq!(report.sensors[0].count, 1);
+        assert_eq!(report.sensors[0].mean_temperature, Some(6.5));
+
+        let text = report.to_string();
+        assert!(text.contains("Daily report for 2025-01-15 (Europe/Zurich)"));
+        assert!(text.contains("2025-01-15 00:00:00 +0100 - 2025-01-15 13:00:00 +0100"));
+    }
+}
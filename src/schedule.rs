@@ -0,0 +1,387 @@
+//! Scheduling of station fetches: Alignment to the FOEN publication times and
+//! backoff after failing cycles
+
+use std::{collections::BTreeMap, time};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, RunSchedule},
+    pipeline::CycleOutcome,
+};
+
+/// FOEN publishes measurements on a fixed grid of this many minutes
+pub const PUBLICATION_GRID_MINUTES: i64 = 10;
+
+/// Expected publication time of the measurement following `last_measurement`
+///
+/// The measurement time is aligned to the publication grid first, so that
+/// slightly off timestamps don't shift the schedule.
+pub fn next_publication(last_measurement: DateTime<Utc>, delay: Duration) -> DateTime<Utc> {
+    let grid = Duration::minutes(PUBLICATION_GRID_MINUTES);
+    let aligned = last_measurement
+        .duration_trunc(grid)
+        .unwrap_or(last_measurement);
+    aligned + grid + delay
+}
+
+/// Next fetch time per station, based on the expected publication times
+#[derive(Debug)]
+pub struct PublicationSchedule {
+    next_fetch: BTreeMap<u32, DateTime<Utc>>,
+    delay: Duration,
+    retry: Duration,
+    max_wait: Duration,
+}
+
+impl PublicationSchedule {
+    /// Create a schedule with all stations due immediately
+    ///
+    /// Stations whose measurement is late are fetched again after `retry`,
+    /// but no more often than every `max_wait` once they are late by more
+    /// than that, so that stations that stopped publishing don't cause
+    /// useless queries.
+    pub fn new(
+        station_ids: &[u32],
+        now: DateTime<Utc>,
+        delay: Duration,
+        retry: Duration,
+        max_wait: Duration,
+    ) -> Self {
+        Self {
+            next_fetch: station_ids.iter().map(|&id| (id, now)).collect(),
+            delay,
+            retry,
+            max_wait,
+        }
+    }
+
+    /// Stations that are due at `now`
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<u32> {
+        self.next_fetch
+            .iter()
+            .filter(|(_, next)| **next <= now)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Time at which the next station is due
+    pub fn next_due(&self) -> Option<DateTime<Utc>> {
+        self.next_fetch.values().min().copied()
+    }
+
+    /// Schedule the next fetch of a station after it was fetched at `now`
+    ///
+    /// `last_measurement` is the time of the newest measurement of the
+    /// station, or `None` if fetching failed.
+    pub fn update(
+        &mut self,
+        station_id: u32,
+        last_measurement: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) {
+        let next = match last_measurement {
+            Some(time) => {
+                let expected = next_publication(time, self.delay);
+                if expected > now {
+                    expected
+                } else if now - expected > self.max_wait {
+                    now + self.max_wait
+                } else {
+                    now + self.retry
+                }
+            }
+            None => now + self.retry,
+        };
+        self.next_fetch.insert(station_id, next);
+    }
+
+    /// Schedule the next fetch of a station after a permanent error at
+    /// `now`, which isn't retried soon as it would only fail again
+    pub fn update_permanent_failure(&mut self, station_id: u32, now: DateTime<Utc>) {
+        self.next_fetch.insert(station_id, now + self.max_wait);
+    }
+}
+
+/// Increases the interval of a station after consecutive failed fetches
+///
+/// Once `threshold` fetches in a row failed (e.g. during an outage of the
+/// endpoint), the interval is doubled with every further failed fetch, up to
+/// `max_interval`. The first successful fetch restores the normal interval.
+#[derive(Debug)]
+pub struct FailureBackoff {
+    threshold: u32,
+    max_interval: time::Duration,
+    failed_cycles: u32,
+    /// What failed, for the log messages
+    subject: String,
+}
+
+impl FailureBackoff {
+    /// Create a backoff for what `subject` names in the log messages, e.g.
+    /// a single station, which is disabled if `threshold` is 0
+    pub fn new(subject: impl Into<String>, threshold: u32, max_interval: time::Duration) -> Self {
+        Self {
+            threshold,
+            max_interval,
+            failed_cycles: 0,
+            subject: subject.into(),
+        }
+    }
+
+    /// Whether the fetcher is backing off
+    pub fn is_degraded(&self) -> bool {
+        self.threshold > 0 && self.failed_cycles >= self.threshold
+    }
+
+    /// Record the outcome of a cycle
+    ///
+    /// Cycles without any processed stations are ignored. Returns whether
+    /// this cycle entered the degraded mode.
+    pub fn record(&mut self, success: usize, errors: usize) -> bool {
+        if success > 0 {
+            if self.is_degraded() {
+                info!("{} succeeded again, leaving degraded mode", self.subject);
+            }
+            self.failed_cycles = 0;
+        } else if errors > 0 {
+            self.failed_cycles = self.failed_cycles.saturating_add(1);
+            if self.threshold > 0 && self.failed_cycles == self.threshold {
+                warn!(
+                    "{} failed in {} consecutive cycles, entering degraded mode \
+                    with increasing intervals of up to {} minutes",
+                    self.subject,
+                    self.failed_cycles,
+                    self.max_interval.as_secs() / 60
+                );
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Interval until the next cycle, based on the normal interval
+    pub fn interval(&self, base: time::Duration) -> time::Duration {
+        if !self.is_degraded() {
+            return base;
+        }
+        let exponent = (self.failed_cycles - self.threshold + 1).min(16);
+        base.saturating_mul(1 << exponent)
+            .min(self.max_interval)
+            .max(base)
+    }
+}
+
+/// Schedules the fetches of a single station in loop mode
+///
+/// With the `publication` schedule, the station is fetched after its next
+/// measurement is expected, see [`PublicationSchedule`]. With the `interval`
+/// schedule, it is fetched once per interval, and after `retry` if its
+/// measurement is outdated. Permanent errors wait for one interval, and with
+/// the `interval` schedule consecutive failures back off independently of the
+/// other stations.
+#[derive(Debug)]
+pub struct StationTimer {
+    station_id: u32,
+    schedule: RunSchedule,
+    publication: PublicationSchedule,
+    interval: Duration,
+    retry: Option<Duration>,
+    backoff: FailureBackoff,
+}
+
+impl StationTimer {
+    /// Create a timer for a station that is due at `now`
+    pub fn new(
+        config: &Config,
+        schedule: RunSchedule,
+        station_id: u32,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let minutes = |minutes: u32| Duration::minutes(minutes.into());
+        let retry = match schedule {
+            RunSchedule::Interval => config.run_retry_interval_minutes(),
+            RunSchedule::Publication => Some(config.run_retry_interval_minutes().unwrap_or(1)),
+        };
+        Self {
+            station_id,
+            schedule,
+            publication: PublicationSchedule::new(
+                &[station_id],
+                now,
+                minutes(config.run_publication_delay_minutes()),
+                minutes(retry.unwrap_or(1)),
+                minutes(config.run_interval_minutes()),
+            ),
+            interval: minutes(config.run_interval_minutes()),
+            retry: retry.map(minutes),
+            backoff: FailureBackoff::new(
+                format!("Station {}", config.station_label(station_id)),
+                config.run_backoff_after_cycles(),
+                time::Duration::from_secs(config.run_max_backoff_minutes() as u64 * 60),
+            ),
+        }
+    }
+
+    /// Record the outcome of a fetch that finished at `now`, and return the
+    /// time of the next fetch
+    pub fn next(&mut self, outcome: &CycleOutcome, now: DateTime<Utc>) -> DateTime<Utc> {
+        let station_id = self.station_id;
+        // The publication schedule retries on its own and never backs off
+        if self.schedule == RunSchedule::Interval {
+            self.backoff
+                .record(outcome.success, outcome.errors + outcome.cancelled);
+        }
+        if self.backoff.is_degraded() {
+            let interval = self
+                .interval
+                .to_std()
+                .map(|interval| self.backoff.interval(interval))
+                .ok()
+                .and_then(|interval| Duration::from_std(interval).ok())
+                .unwrap_or(self.interval);
+            return now + interval;
+        }
+        let observation = outcome.observations.get(&station_id);
+        let permanent = outcome.permanent_failures.contains(&station_id);
+        match self.schedule {
+            RunSchedule::Publication if permanent => {
+                self.publication.update_permanent_failure(station_id, now);
+                self.publication.next_due().unwrap_or(now + self.interval)
+            }
+            RunSchedule::Interval if permanent => now + self.interval,
+            RunSchedule::Publication => {
+                self.publication
+                    .update(station_id, observation.map(|o| o.time()), now);
+                self.publication.next_due().unwrap_or(now + self.interval)
+            }
+            RunSchedule::Interval => match self.retry {
+                Some(retry) if outcome.lagging.contains(&station_id) && retry < self.interval => {
+                    now + retry
+                }
+                _ => now + self.interval,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::config::GfroerliConfig;
+
+    fn time(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 1, 15, hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_next_publication() {
+        let delay = Duration::minutes(5);
+        assert_eq!(next_publication(time(12, 30, 0), delay), time(12, 45, 0));
+        assert_eq!(next_publication(time(12, 32, 10), delay), time(12, 45, 0));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let mut schedule = PublicationSchedule::new(
+            &[2104, 2176, 2135],
+            time(12, 40, 0),
+            Duration::minutes(5),
+            Duration::minutes(1),
+            Duration::minutes(10),
+        );
+        assert_eq!(schedule.due(time(12, 40, 0)), vec![2104, 2135, 2176]);
+
+        let now = time(12, 40, 30);
+        // Fresh measurement: wait for the next publication
+        schedule.update(2104, Some(time(12, 30, 0)), now);
+        // Late measurement: retry soon
+        schedule.update(2176, Some(time(12, 20, 0)), now);
+        // Stopped publishing: retry rarely
+        schedule.update(2135, Some(time(10, 0, 0)), now);
+
+        assert_eq!(schedule.next_due(), Some(time(12, 41, 30)));
+        assert_eq!(schedule.due(time(12, 41, 30)), vec![2176]);
+        assert_eq!(schedule.due(time(12, 45, 0)), vec![2104, 2176]);
+        assert_eq!(schedule.due(time(12, 50, 30)), vec![2104, 2135, 2176]);
+
+        // Failed fetch: retry soon
+        schedule.update(2104, None, now);
+        assert_eq!(schedule.due(time(12, 41, 30)), vec![2104, 2176]);
+
+        // Permanent error: retry rarely
+        schedule.update_permanent_failure(2104, now);
+        assert_eq!(schedule.due(time(12, 41, 30)), vec![2176]);
+        assert_eq!(schedule.due(time(12, 50, 30)), vec![2104, 2135, 2176]);
+    }
+
+    #[test]
+    fn test_failure_backoff() {
+        let minutes = |minutes: u64| time::Duration::from_secs(minutes * 60);
+        let mut backoff = FailureBackoff::new("Station 2104", 2, minutes(30));
+        assert_eq!(backoff.interval(minutes(5)), minutes(5));
+
+        assert!(!backoff.record(0, 3));
+        assert!(!backoff.is_degraded());
+        backoff.record(0, 0);
+        assert!(backoff.record(0, 3));
+        assert!(backoff.is_degraded());
+        assert_eq!(backoff.interval(minutes(5)), minutes(10));
+        assert!(!backoff.record(0, 3));
+        assert_eq!(backoff.interval(minutes(5)), minutes(20));
+        backoff.record(0, 3);
+        assert_eq!(backoff.interval(minutes(5)), minutes(30));
+
+        // A partially successful cycle ends the backoff
+        backoff.record(1, 2);
+        assert!(!backoff.is_degraded());
+        assert_eq!(backoff.interval(minutes(5)), minutes(5));
+
+        // Disabled
+        let mut backoff = FailureBackoff::new("Station 2104", 0, minutes(30));
+        backoff.record(0, 3);
+        assert_eq!(backoff.interval(minutes(5)), minutes(5));
+    }
+
+    #[test]
+    fn test_station_timer() {
+        let config = Config::new(
+            Vec::new(),
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        let succeeded = CycleOutcome {
+            success: 1,
+            ..Default::default()
+        };
+        let failed = || CycleOutcome {
+            errors: 1,
+            ..Default::default()
+        };
+        let mut timer = StationTimer::new(&config, RunSchedule::Interval, 2104, time(12, 0, 0));
+        assert_eq!(timer.next(&succeeded, time(12, 0, 0)), time(12, 5, 0));
+
+        // The station backs off on its own after 3 failed fetches
+        assert_eq!(timer.next(&failed(), time(12, 5, 0)), time(12, 10, 0));
+        assert_eq!(timer.next(&failed(), time(12, 10, 0)), time(12, 15, 0));
+        assert_eq!(timer.next(&failed(), time(12, 15, 0)), time(12, 25, 0));
+        assert_eq!(timer.next(&failed(), time(12, 25, 0)), time(12, 45, 0));
+        assert_eq!(timer.next(&succeeded, time(12, 45, 0)), time(12, 50, 0));
+
+        // A permanent failure waits one interval instead of the retry
+        let mut timer = StationTimer::new(&config, RunSchedule::Publication, 2104, time(12, 0, 0));
+        assert_eq!(timer.next(&failed(), time(12, 0, 30)), time(12, 1, 30));
+        let mut outcome = failed();
+        outcome.permanent_failures.insert(2104);
+        assert_eq!(timer.next(&outcome, time(12, 1, 30)), time(12, 6, 30));
+
+        // The publication schedule never enters the degraded mode
+        for minute in 7..12 {
+            timer.next(&failed(), time(12, minute, 30));
+        }
+        assert!(!timer.backoff.is_degraded());
+    }
+}
@@ -0,0 +1,70 @@
+//! Wrapper for secrets in the configuration
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Placeholder shown instead of a secret
+const REDACTED: &str = "[REDACTED]";
+
+/// A secret string, e.g. an API key or a URL with a password
+///
+/// `Debug` and `Display` print a placeholder, so that secrets don't end up in
+/// logs, error messages or debug output by accident. The value is only
+/// accessible through [`SecretString::expose`]. Serialization writes the
+/// actual value, so that configuration files can be written.
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    /// Get the secret value
+    ///
+    /// Only use it where the secret is actually needed, e.g. to authenticate
+    /// a request, never to log it.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString({REDACTED})")
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_redacted() {
+        let secret = SecretString::from("gfroerli-api-key");
+        assert_eq!(format!("{secret}"), "[REDACTED]");
+        assert_eq!(format!("{secret:?}"), "SecretString([REDACTED])");
+        assert_eq!(secret.expose(), "gfroerli-api-key");
+
+        // Serialization keeps the value
+        let json = serde_json::to_string(&secret).unwrap();
+        assert_eq!(json, "\"gfroerli-api-key\"");
+        assert_eq!(serde_json::from_str::<SecretString>(&json).unwrap(), secret);
+    }
+}
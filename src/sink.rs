@@ -0,0 +1,214 @@
+//! Forwarding of measurements to other services than the Gfrörli API
+//!
+//! Sinks receive every new measurement of all stations, independently of the
+//! Gfrörli targets. The measurements a sink received are recorded under the
+//! sink's name (e.g. `webhook:home_assistant`, `ndjson` or `s3`), so that each
+//! measurement is forwarded once and a sink that was unreachable catches up
+//! in the next cycle.
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "nats")]
+mod nats;
+mod ndjson;
+mod s3;
+mod webhook;
+
+use std::future::Future;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+pub use self::webhook::PayloadTemplate;
+use crate::{
+    config::Config,
+    database::{MeasurementStore, SentMeasurement},
+    gfroerli::idempotency_key,
+    http::HttpClients,
+    observation::StationObservation,
+    timezone,
+};
+
+/// Name the NDJSON sink records its measurements under
+const NDJSON_SINK: &str = "ndjson";
+
+/// Name the S3 sink records its measurements under
+const S3_SINK: &str = "s3";
+
+/// Name the Kafka sink records its measurements under
+#[cfg(feature = "kafka")]
+const KAFKA_SINK: &str = "kafka";
+
+/// Name the NATS sink records its measurements under
+#[cfg(feature = "nats")]
+const NATS_SINK: &str = "nats";
+
+/// Measurement as written by the NDJSON sink, published by the message bus
+/// sinks and sent to webhooks without a body template
+#[derive(Debug, Serialize)]
+struct Measurement<'a> {
+    sensor_id: u32,
+    station_id: u32,
+    station_name: &'a str,
+    temperature: f32,
+    time: String,
+}
+
+impl<'a> Measurement<'a> {
+    fn new(observation: &'a StationObservation, sensor_id: u32) -> Self {
+        Self {
+            sensor_id,
+            station_id: observation.station_id,
+            station_name: &observation.station_name,
+            temperature: observation.temperature(),
+            time: format_time(observation.time()),
+        }
+    }
+}
+
+/// Format the time of a measurement as RFC 3339 in UTC
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Sends an observation of a sensor to all configured sinks
+///
+/// A failure for one sink doesn't prevent sending to the others, the first
+/// error is returned after all sinks were tried.
+pub async fn deliver_to_sinks(
+    clients: &HttpClients,
+    config: &Config,
+    store: &dyn MeasurementStore,
+    observation: &StationObservation,
+    sensor_id: u32,
+    dry_run: bool,
+) -> Result<()> {
+    let mut result = Ok(());
+    let mut keep_first_error = |delivery: Result<()>| {
+        if result.is_ok() {
+            result = delivery;
+        } else if let Err(e) = delivery {
+            warn!("{:#}", e);
+        }
+    };
+    for (name, webhook) in config.sink_webhooks() {
+        let sink = format!("webhook:{name}");
+        let send = webhook::send(&clients.sinks, webhook, observation, sensor_id);
+        keep_first_error(deliver(store, &sink, observation, sensor_id, dry_run, send).await);
+    }
+    if let Some(ndjson) = config.sink_ndjson() {
+        let send = ndjson::write(ndjson, observation, sensor_id);
+        keep_first_error(deliver(store, NDJSON_SINK, observation, sensor_id, dry_run, send).await);
+    }
+    if let Some(s3) = config.sink_s3() {
+        let send = s3::buffer(s3, observation, sensor_id);
+        keep_first_error(deliver(store, S3_SINK, observation, sensor_id, dry_run, send).await);
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(kafka) = config.sink_kafka() {
+        let send = kafka::produce(&clients.sinks, kafka, observation, sensor_id);
+        keep_first_error(deliver(store, KAFKA_SINK, observation, sensor_id, dry_run, send).await);
+    }
+    #[cfg(feature = "nats")]
+    if let Some(nats) = config.sink_nats() {
+        let send = nats::publish(nats, observation, sensor_id);
+        keep_first_error(deliver(store, NATS_SINK, observation, sensor_id, dry_run, send).await);
+    }
+    result
+}
+
+/// Uploads the data sinks have buffered, e.g. the completed days of the S3
+/// sink
+pub async fn flush(
+    clients: &HttpClients,
+    config: &Config,
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<()> {
+    if let Some(s3) = config.sink_s3() {
+        s3::upload_completed_days(&clients.sinks, s3, now, dry_run).await?;
+    }
+    Ok(())
+}
+
+/// Sends an observation to a sink with `send`, unless the sink already
+/// received it
+async fn deliver(
+    store: &dyn MeasurementStore,
+    sink: &str,
+    observation: &StationObservation,
+    sensor_id: u32,
+    dry_run: bool,
+    send: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    if store
+        .is_measurement_sent(sink, sensor_id, observation.time())
+        .await?
+    {
+        debug!(
+            "Station {} ({}) measurement at {} already forwarded to sink '{}', skipping",
+            observation.station_id,
+            observation.station_name,
+            timezone::log_time(observation.time()),
+            sink,
+        );
+        return Ok(());
+    }
+
+    if dry_run {
+        info!(
+            "Station {} ({}) would be forwarded to sink '{}' [DRY RUN]",
+            observation.station_id, observation.station_name, sink,
+        );
+        return Ok(());
+    }
+
+    send.await.with_context(|| {
+        format!(
+            "Failed to forward measurement of station {} ({}) to sink '{}'",
+            observation.station_id, observation.station_name, sink
+        )
+    })?;
+    store
+        .record_measurement_sent(&SentMeasurement {
+            target: sink.to_string(),
+            sensor_id,
+            time: observation.time(),
+            temperature: Some(observation.temperature()),
+            idempotency_key: idempotency_key(sensor_id, observation.time()),
+            gfroerli_id: None,
+        })
+        .await?;
+    info!(
+        station_id = observation.station_id,
+        sensor_id,
+        "Station {} ({}) forwarded to sink '{}'",
+        observation.station_id,
+        observation.station_name,
+        sink,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_measurement() {
+        let observation = StationObservation::new(
+            2104,
+            "Linth - \"Mollis\"",
+            Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+            6.7,
+        );
+        assert_eq!(
+            serde_json::to_string(&Measurement::new(&observation, 1)).unwrap(),
+            r#"{"sensor_id":1,"station_id":2104,"station_name":"Linth - \"Mollis\"","temperature":6.7,"time":"2025-01-15T12:00:00Z"}"#
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Kafka sink: Produces measurements to a topic through a Kafka REST Proxy
+//!
+//! The REST Proxy (v2 API) takes the records as JSON over HTTP, so the
+//! fetcher doesn't need to link against a native Kafka client.
+
+use anyhow::{Context, Result, bail};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+
+use super::Measurement;
+use crate::{
+    config::KafkaSinkConfig,
+    http::{HttpClient, build_headers, check_status},
+    observation::StationObservation,
+};
+
+/// Content type of produce requests with JSON keys and values
+const KAFKA_JSON: &str = "application/vnd.kafka.json.v2+json";
+
+/// Content type of the responses of the REST Proxy
+const KAFKA_RESPONSE: &str = "application/vnd.kafka.v2+json";
+
+/// Request body of the REST Proxy produce endpoint
+#[derive(Debug, Serialize)]
+struct ProduceRequest<'a> {
+    records: [Record<'a>; 1],
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    key: String,
+    value: Measurement<'a>,
+}
+
+/// Response body of the REST Proxy produce endpoint
+#[derive(Debug, Deserialize)]
+struct ProduceResponse {
+    offsets: Vec<RecordOffset>,
+}
+
+/// Result of producing a record, with an error if it failed
+#[derive(Debug, Deserialize)]
+struct RecordOffset {
+    error_code: Option<i64>,
+    error: Option<String>,
+}
+
+/// Produces a measurement of a sensor to the configured topic
+///
+/// The URL is left out of errors, as it may contain credentials.
+pub async fn produce(
+    client: &HttpClient,
+    config: &KafkaSinkConfig,
+    observation: &StationObservation,
+    sensor_id: u32,
+) -> Result<()> {
+    let url = format!(
+        "{}/topics/{}",
+        config.rest_proxy_url.expose().trim_end_matches('/'),
+        config.topic
+    );
+    let body = ProduceRequest {
+        records: [Record {
+            key: config
+                .key()
+                .of(observation.station_id, sensor_id)
+                .to_string(),
+            value: Measurement::new(observation, sensor_id),
+        }],
+    };
+    let mut request = client
+        .post(&url)
+        .header(CONTENT_TYPE, KAFKA_JSON)
+        .header(ACCEPT, KAFKA_RESPONSE)
+        .body(serde_json::to_vec(&body)?);
+    if let Some(headers) = &config.headers {
+        request = request.headers(build_headers(headers)?);
+    }
+    let response = client
+        .send(request)
+        .await
+        .map_err(|e| e.without_url())
+        .with_context(|| format!("Failed to produce measurement to topic '{}'", config.topic))?;
+    let response = check_status(response)
+        .await
+        .with_context(|| "Kafka REST Proxy rejected the measurement")?;
+
+    let response: ProduceResponse = response
+        .json()
+        .await
+        .with_context(|| "Invalid response of the Kafka REST Proxy")?;
+    for offset in response.offsets {
+        if let Some(code) = offset.error_code {
+            bail!(
+                "Kafka rejected the measurement with error code {}: {}",
+                code,
+                offset.error.unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,294 @@
+//! NATS sink: Publishes measurements to a NATS subject
+//!
+//! Speaks the text-based NATS client protocol over a new connection for every
+//! measurement, which is plenty for a measurement every few minutes per
+//! station. The connection is upgraded to TLS for `tls://` URLs and servers
+//! that require it, credentials are never sent without TLS.
+
+use anyhow::{Context, Result, anyhow, bail};
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::{Duration, timeout},
+};
+use tokio_native_tls::{TlsConnector, native_tls};
+
+use super::Measurement;
+use crate::{
+    config::NatsSinkConfig, http::USER_AGENT, observation::StationObservation, secret::SecretString,
+};
+
+/// Port of NATS servers if the URL has none
+const DEFAULT_PORT: u16 = 4222;
+
+/// Time to connect, publish and get the confirmation of the server
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Greeting of the server
+#[derive(Debug, Deserialize)]
+struct ServerInfo {
+    #[serde(default)]
+    tls_required: bool,
+}
+
+/// Options of the CONNECT message
+#[derive(Debug, Serialize)]
+struct ConnectOptions<'a> {
+    verbose: bool,
+    pedantic: bool,
+    name: &'a str,
+    lang: &'a str,
+    version: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_token: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pass: Option<&'a str>,
+}
+
+/// Publishes a measurement of a sensor to the configured subject, followed by
+/// the key of the measurement
+///
+/// Waits until the server confirmed the message with a PONG, so that
+/// rejected messages (e.g. for missing permissions) fail the delivery. The URL
+/// is left out of errors, as it may contain credentials.
+pub async fn publish(
+    config: &NatsSinkConfig,
+    observation: &StationObservation,
+    sensor_id: u32,
+) -> Result<()> {
+    let subject = format!(
+        "{}.{}",
+        config.subject,
+        config.key().of(observation.station_id, sensor_id)
+    );
+    let payload = serde_json::to_vec(&Measurement::new(observation, sensor_id))?;
+    timeout(
+        PUBLISH_TIMEOUT,
+        publish_message(&config.url, config.token.as_ref(), &subject, &payload),
+    )
+    .await
+    .with_context(|| format!("Timed out publishing to NATS subject '{subject}'"))?
+    .with_context(|| format!("Failed to publish measurement to NATS subject '{subject}'"))
+}
+
+async fn publish_message(
+    url: &SecretString,
+    token: Option<&SecretString>,
+    subject: &str,
+    payload: &[u8],
+) -> Result<()> {
+    let url = Url::parse(url.expose()).map_err(|_| anyhow!("Invalid NATS URL"))?;
+    let host = url.host_str().context("NATS URL without host")?;
+    let stream = TcpStream::connect((host, url.port().unwrap_or(DEFAULT_PORT)))
+        .await
+        .context("Failed to connect to the NATS server")?;
+    let mut stream = BufReader::new(stream);
+
+    // The server greets in plain text, the client then starts the TLS
+    // handshake
+    let greeting = read_line(&mut stream).await?;
+    let info = greeting
+        .strip_prefix("INFO ")
+        .with_context(|| format!("Unexpected greeting of the NATS server: {greeting}"))?;
+    let info: ServerInfo =
+        serde_json::from_str(info).context("Invalid greeting of the NATS server")?;
+
+    let user = decode_credential(url.username())?;
+    let pass = url.password().map(decode_credential).transpose()?;
+    let credentials = Credentials {
+        auth_token: token.map(SecretString::expose),
+        user: Some(user.as_str()).filter(|user| !user.is_empty()),
+        pass: pass.as_deref(),
+    };
+    if url.scheme() == "tls" || info.tls_required {
+        let connector = native_tls::TlsConnector::new().context("Failed to initialize TLS")?;
+        let stream = TlsConnector::from(connector)
+            .connect(host, stream.into_inner())
+            .await
+            .context("TLS handshake with the NATS server failed")?;
+        return send_message(BufReader::new(stream), credentials, subject, payload).await;
+    }
+    if credentials.is_some() {
+        bail!("NATS credentials are only sent over TLS, use a tls:// URL");
+    }
+    send_message(stream, credentials, subject, payload).await
+}
+
+/// Credentials of the CONNECT message
+struct Credentials<'a> {
+    auth_token: Option<&'a str>,
+    user: Option<&'a str>,
+    pass: Option<&'a str>,
+}
+
+impl Credentials<'_> {
+    fn is_some(&self) -> bool {
+        self.auth_token.is_some() || self.user.is_some() || self.pass.is_some()
+    }
+}
+
+/// Connect and publish a message on a connection the server greeted, and
+/// wait for the confirmation
+async fn send_message<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: BufReader<S>,
+    credentials: Credentials<'_>,
+    subject: &str,
+    payload: &[u8],
+) -> Result<()> {
+    let connect = ConnectOptions {
+        verbose: false,
+        pedantic: false,
+        name: USER_AGENT,
+        lang: "rust",
+        version: env!("CARGO_PKG_VERSION"),
+        auth_token: credentials.auth_token,
+        user: credentials.user,
+        pass: credentials.pass,
+    };
+    let mut message = format!(
+        "CONNECT {}\r\nPUB {} {}\r\n",
+        serde_json::to_string(&connect)?,
+        subject,
+        payload.len()
+    )
+    .into_bytes();
+    message.extend_from_slice(payload);
+    message.extend_from_slice(b"\r\nPING\r\n");
+    stream.write_all(&message).await?;
+
+    loop {
+        let line = read_line(&mut stream).await?;
+        match line.as_str() {
+            "PONG" => return Ok(()),
+            "PING" => stream.write_all(b"PONG\r\n").await?,
+            "+OK" => {}
+            _ if line.starts_with("INFO ") => {}
+            _ if line.starts_with("-ERR") => {
+                bail!(
+                    "NATS server returned an error: {}",
+                    line["-ERR".len()..].trim()
+                )
+            }
+            _ => bail!("Unexpected message of the NATS server: {line}"),
+        }
+    }
+}
+
+/// Decode a user name or password of the URL, which are percent-encoded
+fn decode_credential(value: &str) -> Result<String> {
+    percent_decode_str(value)
+        .decode_utf8()
+        .map(String::from)
+        .map_err(|_| anyhow!("Invalid credentials in NATS URL"))
+}
+
+/// Read a protocol line without the line ending
+async fn read_line(reader: &mut BufReader<impl AsyncRead + Unpin>) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        bail!("NATS server closed the connection");
+    }
+    Ok(line.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use super::*;
+
+    /// Accept a connection, greet and answer the PING with `reply`, and
+    /// return everything the client sent until then
+    async fn serve(listener: TcpListener, reply: &'static str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream
+            .write_all(b"INFO {\"server_id\":\"test\",\"max_payload\":1048576}\r\n")
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        while !received.ends_with(b"PING\r\n") {
+            let mut buffer = [0; 1024];
+            let read = stream.read(&mut buffer).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            received.extend_from_slice(&buffer[..read]);
+        }
+        stream.write_all(reply.as_bytes()).await.unwrap();
+        String::from_utf8(received).unwrap()
+    }
+
+    async fn publish_to(reply: &'static str) -> (Result<()>, String) {
+        publish_with("", reply).await
+    }
+
+    async fn publish_with(credentials: &str, reply: &'static str) -> (Result<()>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = NatsSinkConfig {
+            url: format!("nats://{credentials}{}", listener.local_addr().unwrap()).into(),
+            subject: "gfroerli.measurements".to_string(),
+            key: None,
+            token: None,
+        };
+        let server = tokio::spawn(serve(listener, reply));
+        let observation = StationObservation::new(
+            2104,
+            "Linth",
+            Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+            6.5,
+        );
+        let result = publish(&config, &observation, 1).await;
+        (result, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let (result, received) = publish_to("PONG\r\n").await;
+        result.unwrap();
+        let payload = r#"{"sensor_id":1,"station_id":2104,"station_name":"Linth","temperature":6.5,"time":"2025-01-15T12:00:00Z"}"#;
+        let mut lines = received.split("\r\n");
+        let connect = lines.next().unwrap();
+        assert!(connect.starts_with("CONNECT {"));
+        assert!(!connect.contains("user"));
+        assert_eq!(
+            lines.next(),
+            Some(format!("PUB gfroerli.measurements.1 {}", payload.len()).as_str())
+        );
+        assert_eq!(lines.next(), Some(payload));
+        assert_eq!(lines.next(), Some("PING"));
+    }
+
+    #[tokio::test]
+    async fn test_publish_credentials_without_tls() {
+        let (result, received) = publish_with("fetcher:secret@", "PONG\r\n").await;
+        assert_eq!(
+            format!("{:#}", result.unwrap_err()),
+            "Failed to publish measurement to NATS subject 'gfroerli.measurements.1': \
+            NATS credentials are only sent over TLS, use a tls:// URL"
+        );
+        assert_eq!(received, "");
+    }
+
+    #[test]
+    fn test_decode_credential() {
+        assert_eq!(decode_credential("fetcher").unwrap(), "fetcher");
+        assert_eq!(decode_credential("p%40ss%3Aw%2Frd").unwrap(), "p@ss:w/rd");
+    }
+
+    #[tokio::test]
+    async fn test_publish_rejected() {
+        let (result, _) =
+            publish_to("-ERR 'Permissions Violation for Publish to gfroerli'\r\n").await;
+        assert_eq!(
+            format!("{:#}", result.unwrap_err()),
+            "Failed to publish measurement to NATS subject 'gfroerli.measurements.1': \
+            NATS server returned an error: 'Permissions Violation for Publish to gfroerli'"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! NDJSON sink: Writes measurements as JSON lines for other tools
+
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use super::Measurement;
+use crate::{config::NdjsonSinkConfig, observation::StationObservation};
+
+/// Writes a measurement of a sensor as one line of JSON to stdout or the
+/// configured file
+///
+/// The file is opened for every line, so that it can be rotated or a named
+/// pipe can be reopened by another reader. Opening a named pipe waits until
+/// it has a reader.
+pub async fn write(
+    config: &NdjsonSinkConfig,
+    observation: &StationObservation,
+    sensor_id: u32,
+) -> Result<()> {
+    let mut line = serde_json::to_vec(&Measurement::new(observation, sensor_id))?;
+    line.push(b'\n');
+
+    let Some(path) = &config.path else {
+        // A single write of the whole line, so that the lines of stations
+        // processed in parallel don't interleave
+        let mut stdout = std::io::stdout().lock();
+        stdout
+            .write_all(&line)
+            .and_then(|()| stdout.flush())
+            .with_context(|| "Failed to write measurement to stdout")?;
+        return Ok(());
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open '{path}'"))?;
+    file.write_all(&line)
+        .await
+        .with_context(|| format!("Failed to write measurement to '{path}'"))?;
+    // Tokio completes writes in the background, make sure the line is
+    // written before the next measurement opens the file
+    file.flush()
+        .await
+        .with_context(|| format!("Failed to write measurement to '{path}'"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_to_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("measurements.ndjson");
+        let config = NdjsonSinkConfig {
+            path: Some(path.display().to_string()),
+        };
+        for (hour, temperature) in [(12, 6.5), (13, 6.7)] {
+            let observation = StationObservation::new(
+                2104,
+                "Linth",
+                Utc.with_ymd_and_hms(2025, 1, 15, hour, 0, 0).unwrap(),
+                temperature,
+            );
+            write(&config, &observation, 1).await.unwrap();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["temperature"], 6.7);
+        assert_eq!(lines[1]["time"], "2025-01-15T13:00:00Z");
+    }
+}
@@ -0,0 +1,247 @@
+//! Webhook sink: Sends measurements to arbitrary HTTP services
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{TimeZone, Utc};
+use reqwest::{Method, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Measurement, format_time};
+use crate::{
+    config::{WebhookMethod, WebhookSinkConfig},
+    http::{HttpClient, build_headers, check_status},
+    observation::StationObservation,
+};
+
+/// Placeholder in a payload template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    /// Gfrörli sensor ID, e.g. `1`
+    SensorId,
+    /// FOEN station ID, e.g. `2104`
+    StationId,
+    /// Name of the station, e.g. `"Linth - Mollis"`
+    StationName,
+    /// Water temperature in °C, e.g. `6.5`
+    Temperature,
+    /// Time of the measurement, e.g. `"2025-01-15T12:00:00Z"`
+    Time,
+}
+
+impl Placeholder {
+    /// Names of all placeholders, as written in templates
+    const NAMES: &[&str] = &[
+        "sensor_id",
+        "station_id",
+        "station_name",
+        "temperature",
+        "time",
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sensor_id" => Some(Placeholder::SensorId),
+            "station_id" => Some(Placeholder::StationId),
+            "station_name" => Some(Placeholder::StationName),
+            "temperature" => Some(Placeholder::Temperature),
+            "time" => Some(Placeholder::Time),
+            _ => None,
+        }
+    }
+
+    /// Value of the placeholder for a measurement, encoded as JSON
+    fn value(self, observation: &StationObservation, sensor_id: u32) -> String {
+        let value = match self {
+            Placeholder::SensorId => serde_json::to_string(&sensor_id),
+            Placeholder::StationId => serde_json::to_string(&observation.station_id),
+            Placeholder::StationName => serde_json::to_string(&observation.station_name),
+            Placeholder::Temperature => serde_json::to_string(&observation.temperature()),
+            Placeholder::Time => serde_json::to_string(&format_time(observation.time())),
+        };
+        // Can't fail for these values, non-finite temperatures become null
+        value.unwrap_or_else(|_| "null".to_string())
+    }
+}
+
+/// Part of a parsed payload template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// JSON body template of a webhook sink
+///
+/// Placeholders are written as `{sensor_id}`, `{station_id}`,
+/// `{station_name}`, `{temperature}` or `{time}` and replaced with the values
+/// of the measurement as JSON values, so strings are quoted and the
+/// placeholders must not be put in quotes. Braces that don't enclose a
+/// lowercase name, like the ones of JSON objects, are kept as they are. The
+/// template is validated when the configuration is loaded: it must not
+/// contain unknown placeholders and must render valid JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PayloadTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl PayloadTemplate {
+    /// Parse and validate a template
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let after = &rest[start + 1..];
+            let name_len = after
+                .find(|c: char| !(c.is_ascii_lowercase() || c == '_'))
+                .unwrap_or(after.len());
+            if name_len == 0 || !after[name_len..].starts_with('}') {
+                segments.push(Segment::Text(rest[..=start].to_string()));
+                rest = after;
+                continue;
+            }
+
+            let name = &after[..name_len];
+            let placeholder = Placeholder::from_name(name).ok_or_else(|| {
+                anyhow!(
+                    "Unknown placeholder '{{{name}}}' in webhook body template (expected one of {})",
+                    Placeholder::NAMES.join(", ")
+                )
+            })?;
+            segments.push(Segment::Text(rest[..start].to_string()));
+            segments.push(Segment::Placeholder(placeholder));
+            rest = &after[name_len + 1..];
+        }
+        segments.push(Segment::Text(rest.to_string()));
+
+        let template = Self {
+            template: template.to_string(),
+            segments,
+        };
+        let example = StationObservation::new(
+            2104,
+            "Linth - Mollis",
+            Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+            6.5,
+        );
+        serde_json::from_str::<Value>(&template.render(&example, 1))
+            .with_context(|| "Webhook body template doesn't render valid JSON")?;
+        Ok(template)
+    }
+
+    /// Build the body for a measurement of a sensor
+    pub fn render(&self, observation: &StationObservation, sensor_id: u32) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Placeholder(placeholder) => placeholder.value(observation, sensor_id),
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<String> for PayloadTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        Self::parse(&template)
+    }
+}
+
+impl From<PayloadTemplate> for String {
+    fn from(template: PayloadTemplate) -> Self {
+        template.template
+    }
+}
+
+fn method(method: WebhookMethod) -> Method {
+    match method {
+        WebhookMethod::Post => Method::POST,
+        WebhookMethod::Put => Method::PUT,
+        WebhookMethod::Patch => Method::PATCH,
+    }
+}
+
+/// Sends a measurement of a sensor to a webhook
+///
+/// The URL is left out of errors, as it may contain a token.
+pub async fn send(
+    client: &HttpClient,
+    webhook: &WebhookSinkConfig,
+    observation: &StationObservation,
+    sensor_id: u32,
+) -> Result<()> {
+    let body = match &webhook.body {
+        Some(template) => template.render(observation, sensor_id),
+        None => serde_json::to_string(&Measurement::new(observation, sensor_id))?,
+    };
+    let mut request = client
+        .request(method(webhook.method()), webhook.url.expose())
+        .header(CONTENT_TYPE, "application/json")
+        .body(body);
+    if let Some(headers) = &webhook.headers {
+        request = request.headers(build_headers(headers)?);
+    }
+    let response = client
+        .send(request)
+        .await
+        .map_err(|e| e.without_url())
+        .with_context(|| "Failed to send measurement to webhook")?;
+    check_status(response)
+        .await
+        .with_context(|| "Webhook rejected the measurement")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation() -> StationObservation {
+        StationObservation::new(
+            2104,
+            "Linth - \"Mollis\"",
+            Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap(),
+            6.7,
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        let template = PayloadTemplate::parse(
+            r#"{"entity": "water_{station_id}", "state": {temperature}, "attributes": {"sensor": {sensor_id}, "name": {station_name}, "measured_at": {time}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&observation(), 1),
+            r#"{"entity": "water_2104", "state": 6.7, "attributes": {"sensor": 1, "name": "Linth - \"Mollis\"", "measured_at": "2025-01-15T12:00:00Z"}}"#
+        );
+
+        // Non-finite values can't be represented in JSON
+        let observation = StationObservation::new(2104, "Linth", Utc::now(), f32::NAN);
+        assert_eq!(
+            PayloadTemplate::parse("[{temperature}]")
+                .unwrap()
+                .render(&observation, 1),
+            "[null]"
+        );
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        let error = PayloadTemplate::parse(r#"{"value": {temp}}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown placeholder '{temp}' in webhook body template (expected one of \
+            sensor_id, station_id, station_name, temperature, time)"
+        );
+        // Quoted placeholders render invalid JSON
+        let error = PayloadTemplate::parse(r#"{"name": "{station_name}"}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Webhook body template doesn't render valid JSON"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Smoothing of noisy water temperature readings before they are sent
+
+use std::borrow::Cow;
+
+use anyhow::Result;
+use tracing::debug;
+
+use crate::{
+    config::Config,
+    database::MeasurementStore,
+    observation::{Parameter, StationObservation},
+};
+
+/// Median of a list of values, the lower of the two middle values for an
+/// even number of values, so that it is always one of the values
+pub fn median(values: &mut [f32]) -> Option<f32> {
+    values.sort_by(f32::total_cmp);
+    let middle = values.len().checked_sub(1)? / 2;
+    Some(values[middle])
+}
+
+/// Smooths the water temperature of an observation, if configured for its
+/// station
+///
+/// The raw reading is recorded in the database, and the observation is sent
+/// with the median of the reading and the preceding raw readings, so that a
+/// single glitch of a noisy probe doesn't reach the targets. Dry runs don't
+/// record the reading.
+pub async fn apply<'a>(
+    config: &Config,
+    store: &dyn MeasurementStore,
+    observation: &'a StationObservation,
+    dry_run: bool,
+) -> Result<Cow<'a, StationObservation>> {
+    let station_id = observation.station_id;
+    let Some(window) = config.station_smoothing(station_id) else {
+        return Ok(Cow::Borrowed(observation));
+    };
+
+    if !dry_run {
+        store
+            .record_raw_measurement(station_id, observation.time(), observation.temperature())
+            .await?;
+    }
+    let mut temperatures = store
+        .raw_temperatures(station_id, observation.time(), window - 1)
+        .await?;
+    temperatures.push(observation.temperature());
+    let Some(smoothed) = median(&mut temperatures) else {
+        return Ok(Cow::Borrowed(observation));
+    };
+
+    debug!(
+        "Station {} temperature {:.3}°C smoothed to {:.3}°C over {} readings",
+        config.station_label(station_id),
+        observation.temperature(),
+        smoothed,
+        temperatures.len()
+    );
+    let mut smoothed_observation = observation.clone();
+    let mut water_temperature = *observation.water_temperature();
+    water_temperature.value = smoothed;
+    smoothed_observation.set(Parameter::WaterTemperature, water_temperature);
+    Ok(Cow::Owned(smoothed_observation))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::{
+        config::{GfroerliConfig, StationConfig},
+        database::SqliteStore,
+    };
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&mut []), None);
+        assert_eq!(median(&mut [6.5]), Some(6.5));
+        assert_eq!(median(&mut [6.5, 30.0, 6.4]), Some(6.5));
+        assert_eq!(median(&mut [6.5, 6.4, 6.8, 6.6]), Some(6.5));
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let config = Config::new(
+            vec![StationConfig {
+                foen_station_id: 2104,
+                gfroerli_sensor_id: 1,
+                targets: None,
+                alias: None,
+                enabled: None,
+                station_type: None,
+                air_temperature: None,
+                alerts: None,
+                smoothing: Some(3),
+            }],
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        let store = SqliteStore::open_in_memory().unwrap();
+        let observation = |minute, temperature| {
+            StationObservation::new(
+                2104,
+                "Linth",
+                Utc.with_ymd_and_hms(2025, 1, 15, 12, minute, 0).unwrap(),
+                temperature,
+            )
+        };
+
+        let mut sent = Vec::new();
+        for (minute, temperature) in [(0, 6.5), (10, 30.0), (20, 6.6), (30, 6.7)] {
+            let raw = observation(minute, temperature);
+            let smoothed = apply(&config, &store, &raw, false).await.unwrap();
+            sent.push(smoothed.temperature());
+        }
+        // The glitch at 12:10 is never sent
+        assert_eq!(sent, vec![6.5, 6.5, 6.6, 6.7]);
+        // Raw values are kept
+        let before = Utc.with_ymd_and_hms(2025, 1, 15, 13, 0, 0).unwrap();
+        assert_eq!(
+            store.raw_temperatures(2104, before, 10).await.unwrap(),
+            vec![6.7, 6.6, 30.0, 6.5]
+        );
+    }
+}
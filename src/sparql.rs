@@ -1,87 +1,515 @@
 //! SPARQL query building and data fetching
 
+mod query;
+
+use std::path::PathBuf;
+
 use anyhow::{Context, Result};
-use tracing::debug;
+use tracing::{debug, warn};
 
-use crate::parsing::{SparqlResponse, StationMeasurement};
+pub use self::query::{ObservationQuery, QueryTemplate};
+use crate::{
+    capture::Capture,
+    config::{Config, SparqlMethod},
+    error::FetcherError,
+    http::{HttpClient, check_status, error_status},
+    observation::{Parameter, StationObservation},
+    parsing::{DiscoveredStation, parse_bindings, parse_sparql_response, parse_station_bindings},
+};
 
-/// SPARQL endpoint URL for the LINDAS platform
-pub const SPARQL_ENDPOINT: &str = "https://lindas.admin.ch/query";
+/// Default SPARQL endpoint URL for the LINDAS platform
+pub const DEFAULT_SPARQL_ENDPOINT: &str = "https://lindas.admin.ch/query";
 
-/// SPARQL query template to fetch station name and latest water temperature
-const SPARQL_QUERY_TEMPLATE: &str = r#"
-PREFIX rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#>
-PREFIX rdfs: <http://www.w3.org/2000/01/rdf-schema#>
+/// SPARQL query to list all stations that measure the water temperature
+///
+/// The station ID is the last segment of the observation IRI.
+const STATION_DISCOVERY_QUERY: &str = r#"
 PREFIX station: <https://environment.ld.admin.ch/foen/hydro/station/>
 PREFIX riverOberservation: <https://environment.ld.admin.ch/foen/hydro/river/observation/>
 PREFIX dimension: <https://environment.ld.admin.ch/foen/hydro/dimension/>
 
-SELECT ?name ?time ?temperature WHERE {
-    station:{STATION_ID} <http://schema.org/name> ?name .
-    riverOberservation:{STATION_ID}
-        dimension:waterTemperature ?temperature ;
-        dimension:measurementTime ?time .
+SELECT DISTINCT ?id ?name WHERE {
+    ?observation dimension:waterTemperature ?temperature .
+    FILTER(STRSTARTS(STR(?observation), STR(riverOberservation:)))
+    BIND(STRAFTER(STR(?observation), STR(riverOberservation:)) AS ?id)
+    BIND(IRI(CONCAT(STR(station:), ?id)) AS ?station)
+    ?station <http://schema.org/name> ?name .
 }
-ORDER BY DESC(?time)
-LIMIT 1
 "#;
 
-/// Fetches and parses station measurement data
-pub async fn fetch_station_measurement(
-    client: &reqwest::Client,
-    station_id: u32,
-) -> Result<Option<StationMeasurement>> {
-    // Create query
-    let query = SPARQL_QUERY_TEMPLATE.replace("{STATION_ID}", &station_id.to_string());
-    let params = [("query", query.as_str())];
+/// SPARQL endpoint and how to query it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparqlEndpoint {
+    /// Endpoint URL
+    pub url: String,
+    /// HTTP method used to send queries
+    pub method: SparqlMethod,
+    /// Custom observation query, replacing the built-in one
+    pub query_template: Option<QueryTemplate>,
+}
+
+impl SparqlEndpoint {
+    /// Get the endpoint from the configuration
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            url: config.sparql_endpoint().to_string(),
+            method: config.sparql_method(),
+            query_template: config.sparql_query_template().cloned(),
+        }
+    }
+
+    /// Get the query string, from the custom template if configured
+    fn query_string(&self, query: &ObservationQuery) -> String {
+        match &self.query_template {
+            Some(template) => template.render(query),
+            None => query.build(),
+        }
+    }
+
+    /// Get the variables the responses to a query must contain, only the
+    /// required ones for a custom template
+    fn variables(&self, query: &ObservationQuery) -> Vec<&'static str> {
+        match &self.query_template {
+            Some(_) => query::REQUIRED_VARIABLES.to_vec(),
+            None => query.variables(),
+        }
+    }
+}
+
+impl Default for SparqlEndpoint {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_SPARQL_ENDPOINT.to_string(),
+            method: SparqlMethod::default(),
+            query_template: None,
+        }
+    }
+}
+
+/// Source of SPARQL responses
+#[derive(Debug, Clone)]
+pub enum SparqlSource {
+    /// Query a SPARQL endpoint over HTTP
+    Endpoint(SparqlEndpoint),
+    /// Read previously saved responses from `<station_id>.json` files in a directory
+    Directory(PathBuf),
+}
+
+impl SparqlSource {
+    /// Get the variables the responses to a query must contain
+    fn variables(&self, query: &ObservationQuery) -> Vec<&'static str> {
+        match self {
+            SparqlSource::Endpoint(endpoint) => endpoint.variables(query),
+            SparqlSource::Directory(_) => query.variables(),
+        }
+    }
+
+    /// Get the raw SPARQL JSON response for a station
+    async fn fetch_response(
+        &self,
+        client: &HttpClient,
+        query: &ObservationQuery,
+    ) -> Result<String> {
+        let station_id = query.station_id();
+        match self {
+            SparqlSource::Endpoint(endpoint) => query_endpoint(client, endpoint, query).await,
+            SparqlSource::Directory(dir) => {
+                let path = dir.join(format!("{station_id}.json"));
+                debug!(
+                    "Reading SPARQL response for station {} from '{}'",
+                    station_id,
+                    path.display()
+                );
+                tokio::fs::read_to_string(&path).await.with_context(|| {
+                    format!(
+                        "Failed to read SPARQL response for station {station_id} from '{}'",
+                        path.display()
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Sends the SPARQL query for a station to the endpoint and returns the response body
+async fn query_endpoint(
+    client: &HttpClient,
+    endpoint: &SparqlEndpoint,
+    query: &ObservationQuery,
+) -> Result<String> {
+    let station_id = query.station_id();
+    let query = endpoint.query_string(query);
 
     // Send request
     debug!("Sending SPARQL request for station {}", station_id);
+    send_query(client, endpoint, &query).await
+}
+
+/// Sends a SPARQL query to the endpoint and returns the response body
+async fn send_query(client: &HttpClient, endpoint: &SparqlEndpoint, query: &str) -> Result<String> {
+    let request = match endpoint.method {
+        SparqlMethod::Post => client.post(&endpoint.url).form(&[("query", query)]),
+        SparqlMethod::Get => client.get(&endpoint.url).query(&[("query", query)]),
+    };
+    let request = request.header("Accept", "application/sparql-results+json");
     let response = client
-        .post(SPARQL_ENDPOINT)
-        .header("Accept", "application/sparql-results+json")
-        .form(&params)
-        .send()
+        .send(request)
         .await
-        .with_context(|| format!("Failed to send SPARQL request for station {station_id}"))?;
+        .with_context(|| "Failed to send SPARQL request")?;
 
     // Handle errors
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unable to read error response".to_string());
-        return Err(anyhow::anyhow!(
-            "SPARQL query failed for station {station_id}: HTTP {status} - {error_text}"
-        ));
+    let response = check_status(response).await?;
+
+    response
+        .text()
+        .await
+        .with_context(|| "Failed to read SPARQL response")
+}
+
+/// Lists all stations that measure the water temperature
+pub async fn discover_stations(
+    client: &HttpClient,
+    endpoint: &SparqlEndpoint,
+) -> Result<Vec<DiscoveredStation>> {
+    debug!("Sending SPARQL station discovery request");
+    let body = send_query(client, endpoint, STATION_DISCOVERY_QUERY)
+        .await
+        .with_context(|| "SPARQL station discovery query failed")?;
+    let sparql_response = parse_sparql_response(&body, &["id", "name"])
+        .with_context(|| "Failed to parse SPARQL JSON response for station discovery")?;
+    Ok(parse_station_bindings(sparql_response.results.bindings))
+}
+
+/// Query for the latest observation of a configured station
+///
+/// Selects the observation graph of the station type, the air temperature if
+/// enabled for the station and the danger level if notifications are
+/// configured.
+pub fn station_query(config: &Config, station_id: u32) -> ObservationQuery {
+    let mut query =
+        ObservationQuery::latest(station_id).station_type(config.station_type(station_id));
+    if config.station_air_temperature(station_id) {
+        query = query.parameter(Parameter::AirTemperature);
+    }
+    if config.danger_level.is_some() {
+        query = query.danger_level();
+    }
+    query
+}
+
+/// Fetches and parses station measurement data
+pub async fn fetch_station_observation(
+    client: &HttpClient,
+    source: &SparqlSource,
+    capture: Option<&Capture>,
+    query: &ObservationQuery,
+) -> Result<Option<StationObservation>, FetcherError> {
+    let station_id = query.station_id();
+    let body = fetch_body(client, source, capture, query).await?;
+    let observations = parse_response(station_id, &body, &source.variables(query))?;
+    Ok(latest_observation(station_id, observations))
+}
+
+/// Fetches all observations matching a query, e.g. of a time window, oldest
+/// first
+///
+/// Observations with a non-finite temperature are skipped.
+pub async fn fetch_station_observations(
+    client: &HttpClient,
+    source: &SparqlSource,
+    capture: Option<&Capture>,
+    query: &ObservationQuery,
+) -> Result<Vec<StationObservation>, FetcherError> {
+    let station_id = query.station_id();
+    let body = fetch_body(client, source, capture, query).await?;
+    let mut observations: Vec<_> = parse_response(station_id, &body, &source.variables(query))?
+        .into_iter()
+        .filter(|observation| is_valid(station_id, observation))
+        .collect();
+    observations.sort_by_key(|observation| observation.time());
+    Ok(observations)
+}
+
+/// Fetches the raw response for a query and captures it if enabled
+async fn fetch_body(
+    client: &HttpClient,
+    source: &SparqlSource,
+    capture: Option<&Capture>,
+    query: &ObservationQuery,
+) -> Result<String, FetcherError> {
+    let body = source
+        .fetch_response(client, query)
+        .await
+        .map_err(|source| FetcherError::Sparql {
+            station: query.station_id(),
+            status: error_status(&source),
+            source,
+        })?;
+    if let Some(capture) = capture {
+        capture.sparql_response(query.station_id(), &body).await;
     }
+    Ok(body)
+}
 
-    // Parse response
-    let sparql_response: SparqlResponse = response.json().await.with_context(|| {
-        format!("Failed to parse SPARQL JSON response for station {station_id}")
-    })?;
+/// Parses a raw SPARQL JSON response of a query selecting `variables` into
+/// observations
+fn parse_response(
+    station_id: u32,
+    body: &str,
+    variables: &[&str],
+) -> Result<Vec<StationObservation>, FetcherError> {
+    let sparql_response =
+        parse_sparql_response(body, variables).map_err(|source| FetcherError::Parse {
+            station: station_id,
+            source,
+        })?;
     debug!(
         "Successfully received SPARQL response for station {} with {} bindings",
         station_id,
         sparql_response.results.bindings.len()
     );
-    if sparql_response.results.bindings.len() > 1 {
-        return Err(anyhow::anyhow!(
-            "Expected 1 result for SPARQL query for station {station_id}, but got {}",
-            sparql_response.results.bindings.len(),
-        ));
+    parse_bindings(station_id, sparql_response.results.bindings).map_err(|source| {
+        FetcherError::Parse {
+            station: station_id,
+            source,
+        }
+    })
+}
+
+/// Whether the temperature of an observation is valid, logs invalid ones
+fn is_valid(station_id: u32, observation: &StationObservation) -> bool {
+    let valid = observation.temperature().is_finite();
+    if !valid {
+        warn!(
+            "Skipping invalid temperature {} at {} for station {}",
+            observation.temperature(),
+            observation.time(),
+            station_id
+        );
     }
+    valid
+}
 
-    Ok(sparql_response
-        .results
-        .bindings
+/// Picks the most recent valid observation out of the parsed bindings
+///
+/// Observations with a non-finite temperature are skipped.
+fn latest_observation(
+    station_id: u32,
+    observations: Vec<StationObservation>,
+) -> Option<StationObservation> {
+    observations
         .into_iter()
-        .next()
-        .map(|binding| StationMeasurement {
-            station_id,
-            station_name: binding.name,
-            time: binding.time,
-            temperature: binding.temperature,
-        }))
+        .filter(|observation| is_valid(station_id, observation))
+        .max_by_key(|observation| observation.time())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_string_contains, method, path, query_param_contains},
+    };
+
+    use super::*;
+    use crate::config::StationType;
+
+    /// SPARQL response with one observation of station 2104
+    const RESPONSE: &str = r#"{
+        "head": { "vars": ["name", "time", "temperature", "waterLevel", "discharge"] },
+        "results": { "bindings": [{
+            "name": { "type": "literal", "value": "Linth - Weesen" },
+            "time": {
+                "type": "literal",
+                "datatype": "http://www.w3.org/2001/XMLSchema#dateTime",
+                "value": "2025-01-15T12:30:00Z"
+            },
+            "temperature": {
+                "type": "literal",
+                "datatype": "http://www.w3.org/2001/XMLSchema#decimal",
+                "value": "6.5"
+            }
+        }] }
+    }"#;
+
+    fn observation(hour: u32, temperature: f32) -> StationObservation {
+        StationObservation::new(
+            2104,
+            "Weesen",
+            Utc.with_ymd_and_hms(2025, 1, 15, hour, 0, 0).unwrap(),
+            temperature,
+        )
+    }
+
+    #[test]
+    fn test_latest_observation_picks_most_recent() {
+        let observation = latest_observation(
+            2104,
+            vec![
+                observation(11, 5.0),
+                observation(13, 7.0),
+                observation(12, 6.0),
+            ],
+        )
+        .unwrap();
+        assert_eq!(observation.station_id, 2104);
+        assert_eq!(observation.temperature(), 7.0);
+        assert_eq!(
+            observation.time(),
+            Utc.with_ymd_and_hms(2025, 1, 15, 13, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_latest_observation_skips_invalid() {
+        let observation =
+            latest_observation(2104, vec![observation(11, 5.0), observation(13, f32::NAN)])
+                .unwrap();
+        assert_eq!(observation.temperature(), 5.0);
+    }
+
+    #[test]
+    fn test_latest_observation_empty() {
+        assert!(latest_observation(2104, vec![]).is_none());
+    }
+
+    #[test]
+    fn test_station_query() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [gfroerli_api]
+            api_url = "http://localhost:3000/api"
+            api_key = "test-api-key"
+
+            [[stations]]
+            foen_station_id = 2104
+            gfroerli_sensor_id = 1
+
+            [[stations]]
+            foen_station_id = 2099
+            gfroerli_sensor_id = 2
+            station_type = "lake"
+            air_temperature = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(station_query(&config, 2104), ObservationQuery::latest(2104));
+        assert_eq!(
+            station_query(&config, 2099),
+            ObservationQuery::latest(2099)
+                .station_type(StationType::Lake)
+                .parameter(Parameter::AirTemperature)
+        );
+
+        config.danger_level = Some(Default::default());
+        assert_eq!(
+            station_query(&config, 2104),
+            ObservationQuery::latest(2104).danger_level()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("2104.json"), RESPONSE).unwrap();
+        let source = SparqlSource::Directory(dir.path().to_path_buf());
+        let client = HttpClient::default();
+
+        let observation =
+            fetch_station_observation(&client, &source, None, &ObservationQuery::latest(2104))
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(observation.station_name, "Linth - Weesen");
+        assert_eq!(observation.temperature(), 6.5);
+
+        // No saved response for this station
+        assert!(
+            fetch_station_observation(&client, &source, None, &ObservationQuery::latest(2176))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_methods() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/query"))
+            .and(body_string_contains("observation%3A2104"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(RESPONSE))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/query"))
+            .and(query_param_contains("query", "observation:2104"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(RESPONSE))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = HttpClient::default();
+
+        for method in [SparqlMethod::Post, SparqlMethod::Get] {
+            let source = SparqlSource::Endpoint(SparqlEndpoint {
+                url: format!("{}/query", server.uri()),
+                method,
+                query_template: None,
+            });
+            let observation =
+                fetch_station_observation(&client, &source, None, &ObservationQuery::latest(2104))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(observation.temperature(), 6.5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_result_shape() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("2104.json"), RESPONSE).unwrap();
+        let source = SparqlSource::Directory(dir.path().to_path_buf());
+        let client = HttpClient::default();
+
+        // The saved response lacks the requested danger level
+        let query = ObservationQuery::latest(2104).danger_level();
+        let error = fetch_station_observation(&client, &source, None, &query)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, FetcherError::Parse { station: 2104, .. }));
+        assert!(format!("{:#}", anyhow::Error::from(error)).contains(
+            "SPARQL endpoint returned unexpected result shape: head.vars \
+                 [name, time, temperature, waterLevel, discharge] lacks the requested variables \
+                 [dangerLevel]"
+        ));
+
+        // A custom template only needs to select the time and temperature
+        let server = MockServer::start().await;
+        let response = RESPONSE.replace(
+            r#"["name", "time", "temperature", "waterLevel", "discharge"]"#,
+            r#"["time", "temperature"]"#,
+        );
+        Mock::given(method("POST"))
+            .and(path("/query"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(response))
+            .mount(&server)
+            .await;
+        let source = SparqlSource::Endpoint(SparqlEndpoint {
+            url: format!("{}/query", server.uri()),
+            method: SparqlMethod::Post,
+            query_template: Some(
+                QueryTemplate::parse(
+                    "SELECT ?time ?temperature WHERE { {observation_iri} ?p ?time, ?temperature }",
+                )
+                .unwrap(),
+            ),
+        });
+        let observation =
+            fetch_station_observation(&client, &source, None, &ObservationQuery::latest(2104))
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(observation.temperature(), 6.5);
+    }
 }
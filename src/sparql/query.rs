@@ -0,0 +1,477 @@
+//! Builder for the SPARQL observation query
+
+use std::fmt::Write;
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::StationType, observation::Parameter};
+
+/// Base IRI of the FOEN hydrological stations
+const STATION_IRI: &str = "https://environment.ld.admin.ch/foen/hydro/station/";
+
+/// Base IRI of the latest observations of the stations of a kind
+fn observation_iri(station_type: StationType) -> &'static str {
+    match station_type {
+        StationType::River => "https://environment.ld.admin.ch/foen/hydro/river/observation/",
+        StationType::Lake => "https://environment.ld.admin.ch/foen/hydro/lake/observation/",
+        StationType::Groundwater => {
+            "https://environment.ld.admin.ch/foen/hydro/groundwater/observation/"
+        }
+    }
+}
+
+/// Base IRI of the observation dimensions
+const DIMENSION_IRI: &str = "https://environment.ld.admin.ch/foen/hydro/dimension/";
+
+/// SPARQL variable and LINDAS dimension of a parameter
+fn dimension(parameter: Parameter) -> (&'static str, &'static str) {
+    match parameter {
+        Parameter::WaterTemperature => ("temperature", "waterTemperature"),
+        Parameter::WaterLevel => ("waterLevel", "waterLevel"),
+        Parameter::Discharge => ("discharge", "discharge"),
+        Parameter::AirTemperature => ("airTemperature", "airTemperature"),
+    }
+}
+
+/// Variables every query must select, also the only ones required from
+/// custom query templates
+pub const REQUIRED_VARIABLES: &[&str] = &["time", "temperature"];
+
+/// Format a time as `xsd:dateTime` literal
+fn datetime_literal(time: DateTime<Utc>) -> String {
+    format!(
+        "\"{}\"^^xsd:dateTime",
+        time.to_rfc3339_opts(SecondsFormat::Secs, true)
+    )
+}
+
+/// SPARQL query for the observations of a station
+///
+/// The station ID is a number and the times are formatted by the builder, so
+/// no user-provided text ends up in the query. The station name, time and
+/// water temperature are always selected, the other parameters only when
+/// requested, and they are optional in the results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObservationQuery {
+    station_id: u32,
+    station_type: StationType,
+    parameters: Vec<Parameter>,
+    danger_level: bool,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<u32>,
+}
+
+impl ObservationQuery {
+    /// Query all observations of a station, only selecting the water temperature
+    pub fn new(station_id: u32) -> Self {
+        Self {
+            station_id,
+            station_type: StationType::default(),
+            parameters: Vec::new(),
+            danger_level: false,
+            since: None,
+            until: None,
+            limit: None,
+        }
+    }
+
+    /// Query the latest observation of a station, including the water level
+    /// and discharge
+    pub fn latest(station_id: u32) -> Self {
+        Self::new(station_id)
+            .parameters([Parameter::WaterLevel, Parameter::Discharge])
+            .limit(1)
+    }
+
+    /// ID of the queried station
+    pub fn station_id(&self) -> u32 {
+        self.station_id
+    }
+
+    /// Query the observation graph of another kind of station than rivers
+    pub fn station_type(mut self, station_type: StationType) -> Self {
+        self.station_type = station_type;
+        self
+    }
+
+    /// Also select a parameter
+    ///
+    /// The water temperature is always selected, and every parameter is only
+    /// selected once.
+    pub fn parameter(mut self, parameter: Parameter) -> Self {
+        if parameter != Parameter::WaterTemperature && !self.parameters.contains(&parameter) {
+            self.parameters.push(parameter);
+        }
+        self
+    }
+
+    /// Also select several parameters
+    pub fn parameters(self, parameters: impl IntoIterator<Item = Parameter>) -> Self {
+        parameters
+            .into_iter()
+            .fold(self, |query, parameter| query.parameter(parameter))
+    }
+
+    /// Also select the danger level of the station
+    pub fn danger_level(mut self) -> Self {
+        self.danger_level = true;
+        self
+    }
+
+    /// Only select observations measured at or after `time`
+    pub fn since(mut self, time: DateTime<Utc>) -> Self {
+        self.since = Some(time);
+        self
+    }
+
+    /// Only select observations measured before `time`
+    pub fn until(mut self, time: DateTime<Utc>) -> Self {
+        self.until = Some(time);
+        self
+    }
+
+    /// Select at most `limit` observations, the most recent ones first
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Variables selected by the query, without the leading `?`
+    pub fn variables(&self) -> Vec<&'static str> {
+        let mut variables = vec!["name", "time", "temperature"];
+        variables.extend(
+            self.parameters
+                .iter()
+                .map(|&parameter| dimension(parameter).0),
+        );
+        if self.danger_level {
+            variables.push("dangerLevel");
+        }
+        variables
+    }
+
+    /// Build the query string
+    pub fn build(&self) -> String {
+        let id = self.station_id;
+        let mut query = String::new();
+
+        writeln!(query, "PREFIX xsd: <http://www.w3.org/2001/XMLSchema#>").unwrap();
+        writeln!(query, "PREFIX station: <{STATION_IRI}>").unwrap();
+        writeln!(
+            query,
+            "PREFIX observation: <{}>",
+            observation_iri(self.station_type)
+        )
+        .unwrap();
+        writeln!(query, "PREFIX dimension: <{DIMENSION_IRI}>").unwrap();
+
+        query.push_str("\nSELECT ?name ?time ?temperature");
+        for &parameter in &self.parameters {
+            let (variable, _) = dimension(parameter);
+            write!(query, " ?{variable}").unwrap();
+        }
+        if self.danger_level {
+            query.push_str(" ?dangerLevel");
+        }
+        query.push_str(" WHERE {\n");
+
+        writeln!(query, "    station:{id} <http://schema.org/name> ?name .").unwrap();
+        writeln!(query, "    observation:{id}").unwrap();
+        writeln!(query, "        dimension:waterTemperature ?temperature ;").unwrap();
+        writeln!(query, "        dimension:measurementTime ?time .").unwrap();
+        for &parameter in &self.parameters {
+            let (variable, dimension) = dimension(parameter);
+            writeln!(
+                query,
+                "    OPTIONAL {{ observation:{id} dimension:{dimension} ?{variable} . }}"
+            )
+            .unwrap();
+        }
+        if self.danger_level {
+            writeln!(
+                query,
+                "    OPTIONAL {{ observation:{id} dimension:dangerLevel ?dangerLevel . }}"
+            )
+            .unwrap();
+        }
+        if let Some(since) = self.since {
+            writeln!(query, "    FILTER(?time >= {})", datetime_literal(since)).unwrap();
+        }
+        if let Some(until) = self.until {
+            writeln!(query, "    FILTER(?time < {})", datetime_literal(until)).unwrap();
+        }
+        query.push_str("}\nORDER BY DESC(?time)\n");
+
+        if let Some(limit) = self.limit {
+            writeln!(query, "LIMIT {limit}").unwrap();
+        }
+        query
+    }
+}
+
+/// Placeholder in a custom query template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    /// FOEN station ID, e.g. `2104`
+    StationId,
+    /// IRI of the station, e.g. `<https://environment.ld.admin.ch/foen/hydro/station/2104>`
+    StationIri,
+    /// IRI of the latest observation of the station
+    ObservationIri,
+}
+
+impl Placeholder {
+    /// Names of all placeholders, as written in templates
+    const NAMES: &[&str] = &["station_id", "station_iri", "observation_iri"];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "station_id" => Some(Placeholder::StationId),
+            "station_iri" => Some(Placeholder::StationIri),
+            "observation_iri" => Some(Placeholder::ObservationIri),
+            _ => None,
+        }
+    }
+
+    fn value(self, station_id: u32, station_type: StationType) -> String {
+        match self {
+            Placeholder::StationId => station_id.to_string(),
+            Placeholder::StationIri => format!("<{STATION_IRI}{station_id}>"),
+            Placeholder::ObservationIri => {
+                format!("<{}{station_id}>", observation_iri(station_type))
+            }
+        }
+    }
+}
+
+/// Part of a parsed query template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Custom SPARQL query template replacing the built-in observation query
+///
+/// Placeholders are written as `{station_id}`, `{station_iri}` or
+/// `{observation_iri}` and replaced for every station. Braces that don't
+/// enclose a lowercase name, like the ones of a `WHERE { ... }` block, are
+/// kept as they are. The template is validated when the configuration is
+/// loaded: it must reference the station through a placeholder, must not
+/// contain unknown placeholders and must select the `?time` and
+/// `?temperature` variables.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QueryTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl QueryTemplate {
+    /// Parse and validate a template
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let after = &rest[start + 1..];
+            let name_len = after
+                .find(|c: char| !(c.is_ascii_lowercase() || c == '_'))
+                .unwrap_or(after.len());
+            if name_len == 0 || !after[name_len..].starts_with('}') {
+                segments.push(Segment::Text(rest[..=start].to_string()));
+                rest = after;
+                continue;
+            }
+
+            let name = &after[..name_len];
+            let placeholder = Placeholder::from_name(name).ok_or_else(|| {
+                anyhow!(
+                    "Unknown placeholder '{{{name}}}' in SPARQL query template (expected one of {})",
+                    Placeholder::NAMES.join(", ")
+                )
+            })?;
+            segments.push(Segment::Text(rest[..start].to_string()));
+            segments.push(Segment::Placeholder(placeholder));
+            rest = &after[name_len + 1..];
+        }
+        segments.push(Segment::Text(rest.to_string()));
+
+        if !segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Placeholder(_)))
+        {
+            bail!("SPARQL query template doesn't reference the station through a placeholder");
+        }
+        for variable in REQUIRED_VARIABLES {
+            if !template.contains(&format!("?{variable}")) {
+                bail!("SPARQL query template doesn't select the '?{variable}' variable");
+            }
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+            segments,
+        })
+    }
+
+    /// Build the query for the station of an observation query, the other
+    /// options of the query are ignored
+    pub fn render(&self, query: &ObservationQuery) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Placeholder(placeholder) => {
+                    placeholder.value(query.station_id, query.station_type)
+                }
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<String> for QueryTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        Self::parse(&template)
+    }
+}
+
+impl From<QueryTemplate> for String {
+    fn from(template: QueryTemplate) -> Self {
+        template.template
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_latest_query() {
+        assert_eq!(
+            ObservationQuery::latest(2104).build(),
+            r#"PREFIX xsd: <http://www.w3.org/2001/XMLSchema#>
+PREFIX station: <https://environment.ld.admin.ch/foen/hydro/station/>
+PREFIX observation: <https://environment.ld.admin.ch/foen/hydro/river/observation/>
+PREFIX dimension: <https://environment.ld.admin.ch/foen/hydro/dimension/>
+
+SELECT ?name ?time ?temperature ?waterLevel ?discharge WHERE {
+    station:2104 <http://schema.org/name> ?name .
+    observation:2104
+        dimension:waterTemperature ?temperature ;
+        dimension:measurementTime ?time .
+    OPTIONAL { observation:2104 dimension:waterLevel ?waterLevel . }
+    OPTIONAL { observation:2104 dimension:discharge ?discharge . }
+}
+ORDER BY DESC(?time)
+LIMIT 1
+"#
+        );
+    }
+
+    #[test]
+    fn test_query_variables() {
+        assert_eq!(
+            ObservationQuery::latest(2104).variables(),
+            vec!["name", "time", "temperature", "waterLevel", "discharge"]
+        );
+        assert_eq!(
+            ObservationQuery::new(2104)
+                .parameter(Parameter::AirTemperature)
+                .danger_level()
+                .variables(),
+            vec![
+                "name",
+                "time",
+                "temperature",
+                "airTemperature",
+                "dangerLevel"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_time_range_query() {
+        let query = ObservationQuery::new(2176)
+            .station_type(StationType::Lake)
+            .parameters([
+                Parameter::WaterTemperature,
+                Parameter::AirTemperature,
+                Parameter::AirTemperature,
+            ])
+            .danger_level()
+            .since(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap())
+            .until(Utc.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap())
+            .build();
+        assert_eq!(
+            query,
+            r#"PREFIX xsd: <http://www.w3.org/2001/XMLSchema#>
+PREFIX station: <https://environment.ld.admin.ch/foen/hydro/station/>
+PREFIX observation: <https://environment.ld.admin.ch/foen/hydro/lake/observation/>
+PREFIX dimension: <https://environment.ld.admin.ch/foen/hydro/dimension/>
+
+SELECT ?name ?time ?temperature ?airTemperature ?dangerLevel WHERE {
+    station:2176 <http://schema.org/name> ?name .
+    observation:2176
+        dimension:waterTemperature ?temperature ;
+        dimension:measurementTime ?time .
+    OPTIONAL { observation:2176 dimension:airTemperature ?airTemperature . }
+    OPTIONAL { observation:2176 dimension:dangerLevel ?dangerLevel . }
+    FILTER(?time >= "2025-01-01T00:00:00Z"^^xsd:dateTime)
+    FILTER(?time < "2025-02-01T00:00:00Z"^^xsd:dateTime)
+}
+ORDER BY DESC(?time)
+"#
+        );
+    }
+
+    #[test]
+    fn test_query_template() {
+        let template = QueryTemplate::parse(
+            "SELECT ?name ?time ?temperature WHERE {\n    \
+             {station_iri} <http://schema.org/name> ?name .\n    \
+             {observation_iri} <x:temperature> ?temperature ; <x:time> ?time .\n    \
+             BIND({station_id} AS ?id)\n}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&ObservationQuery::new(2104)),
+            "SELECT ?name ?time ?temperature WHERE {\n    \
+             <https://environment.ld.admin.ch/foen/hydro/station/2104> <http://schema.org/name> ?name .\n    \
+             <https://environment.ld.admin.ch/foen/hydro/river/observation/2104> <x:temperature> ?temperature ; <x:time> ?time .\n    \
+             BIND(2104 AS ?id)\n}"
+        );
+        assert!(
+            template
+                .render(&ObservationQuery::new(2104).station_type(StationType::Groundwater))
+                .contains(
+                    "<https://environment.ld.admin.ch/foen/hydro/groundwater/observation/2104>"
+                )
+        );
+        assert_eq!(
+            String::from(template.clone()),
+            QueryTemplate::parse(&String::from(template))
+                .unwrap()
+                .template
+        );
+    }
+
+    #[test]
+    fn test_query_template_invalid() {
+        // Unknown placeholder
+        let error = QueryTemplate::parse("SELECT ?time ?temperature WHERE { {station} }")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Unknown placeholder '{station}'"), "{error}");
+        // No placeholder
+        assert!(QueryTemplate::parse("SELECT ?time ?temperature WHERE { }").is_err());
+        // Missing variable
+        assert!(QueryTemplate::parse("SELECT ?time WHERE { {station_iri} }").is_err());
+    }
+}
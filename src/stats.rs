@@ -0,0 +1,115 @@
+//! Summary statistics over the measurements of a processing cycle
+
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::observation::StationObservation;
+
+/// Accumulates the measurements fetched during one cycle
+#[derive(Debug, Default)]
+pub struct CycleStats {
+    temperatures: Vec<f32>,
+    times: Vec<DateTime<Utc>>,
+}
+
+/// Summary of the measurements fetched during one cycle
+#[derive(Debug, PartialEq)]
+pub struct CycleSummary {
+    /// Number of measurements
+    pub count: usize,
+    /// Lowest temperature across all stations
+    pub min_temperature: f32,
+    /// Highest temperature across all stations
+    pub max_temperature: f32,
+    /// Mean temperature across all stations
+    pub mean_temperature: f32,
+    /// Age of the most recent observation
+    pub newest_age: Duration,
+    /// Age of the least recent observation
+    pub oldest_age: Duration,
+}
+
+impl CycleStats {
+    /// Add a fetched observation
+    pub fn add(&mut self, observation: &StationObservation) {
+        self.temperatures.push(observation.temperature());
+        self.times.push(observation.time());
+    }
+
+    /// Add the measurements of another cycle
+    pub fn merge(&mut self, other: CycleStats) {
+        self.temperatures.extend(other.temperatures);
+        self.times.extend(other.times);
+    }
+
+    /// Summarize the measurements, relative to `now`
+    ///
+    /// Returns `None` if no measurements were added.
+    pub fn summary(&self, now: DateTime<Utc>) -> Option<CycleSummary> {
+        let newest = self.times.iter().max()?;
+        let oldest = self.times.iter().min()?;
+        let count = self.temperatures.len();
+        Some(CycleSummary {
+            count,
+            min_temperature: self.temperatures.iter().copied().fold(f32::MAX, f32::min),
+            max_temperature: self.temperatures.iter().copied().fold(f32::MIN, f32::max),
+            mean_temperature: self.temperatures.iter().sum::<f32>() / count as f32,
+            newest_age: now - *newest,
+            oldest_age: now - *oldest,
+        })
+    }
+}
+
+impl fmt::Display for CycleSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} measurements, min {:.3}°C, max {:.3}°C, mean {:.3}°C, newest {} min old, oldest {} min old",
+            self.count,
+            self.min_temperature,
+            self.max_temperature,
+            self.mean_temperature,
+            self.newest_age.num_minutes(),
+            self.oldest_age.num_minutes(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn observation(temperature: f32, minute: u32) -> StationObservation {
+        StationObservation::new(
+            2104,
+            "Weesen",
+            Utc.with_ymd_and_hms(2025, 1, 15, 12, minute, 0).unwrap(),
+            temperature,
+        )
+    }
+
+    #[test]
+    fn test_empty_summary() {
+        assert_eq!(CycleStats::default().summary(Utc::now()), None);
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut stats = CycleStats::default();
+        stats.add(&observation(10.0, 0));
+        stats.add(&observation(14.0, 30));
+        stats.add(&observation(12.0, 20));
+
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 13, 0, 0).unwrap();
+        let summary = stats.summary(now).unwrap();
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.min_temperature, 10.0);
+        assert_eq!(summary.max_temperature, 14.0);
+        assert_eq!(summary.mean_temperature, 12.0);
+        assert_eq!(summary.newest_age, Duration::minutes(30));
+        assert_eq!(summary.oldest_age, Duration::minutes(60));
+    }
+}
@@ -0,0 +1,231 @@
+//! Machine-readable summary of every processing cycle
+//!
+//! The summary is a single JSON object per cycle, logged and/or appended to a
+//! file as JSON lines, so that automation can react to partial failures
+//! without parsing the log messages.
+
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{config::Config, pipeline::CycleOutcome};
+
+/// Outcome of a station in a cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StationStatus {
+    /// Fetched and delivered (or already sent before)
+    Ok,
+    /// Fetching or delivering failed
+    Failed,
+    /// Not done before the cycle exceeded its time budget
+    Cancelled,
+}
+
+/// Summary of a station in a cycle
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StationSummary {
+    pub station_id: u32,
+    pub sensor_id: Option<u32>,
+    pub status: StationStatus,
+    /// Time of the fetched measurement
+    pub measurement_time: Option<DateTime<Utc>>,
+    pub temperature: Option<f32>,
+    /// Time spent fetching and delivering
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Summary of a cycle
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CycleSummaryEvent {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub dry_run: bool,
+    pub success: usize,
+    pub errors: usize,
+    pub cancelled: usize,
+    pub stations: Vec<StationSummary>,
+}
+
+impl CycleSummaryEvent {
+    /// Summarize the outcome of a cycle over the given stations
+    pub fn new(
+        config: &Config,
+        station_ids: &[u32],
+        outcome: &CycleOutcome,
+        dry_run: bool,
+    ) -> Self {
+        let stations = station_ids
+            .iter()
+            .map(|&station_id| {
+                let observation = outcome.observations.get(&station_id);
+                let error = outcome.failures.get(&station_id);
+                let status = match (observation, error) {
+                    (Some(_), _) => StationStatus::Ok,
+                    (None, Some(_)) => StationStatus::Failed,
+                    (None, None) => StationStatus::Cancelled,
+                };
+                StationSummary {
+                    station_id,
+                    sensor_id: config.find_gfroerli_sensor_id(station_id),
+                    status,
+                    measurement_time: observation.map(|o| o.time()),
+                    temperature: observation.map(|o| o.temperature()),
+                    duration_ms: outcome
+                        .station_durations
+                        .get(&station_id)
+                        .map(|duration| duration.as_millis() as u64),
+                    error: error.cloned(),
+                }
+            })
+            .collect();
+
+        let duration = chrono::Duration::from_std(outcome.duration).unwrap_or_default();
+        Self {
+            started_at: outcome.started_at,
+            finished_at: outcome.started_at + duration,
+            duration_ms: outcome.duration.as_millis() as u64,
+            dry_run,
+            success: outcome.success,
+            errors: outcome.errors,
+            cancelled: outcome.cancelled,
+            stations,
+        }
+    }
+}
+
+/// Append a summary as a JSON line to a file
+fn append(path: &Path, line: &str) -> Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{line}"))
+        .with_context(|| format!("Failed to write cycle summary to '{}'", path.display()))
+}
+
+/// Log the summary of a cycle and append it to the configured file
+///
+/// Failing to write the summary is only logged.
+pub fn emit(config: &Config, station_ids: &[u32], outcome: &CycleOutcome, dry_run: bool) {
+    let path = config.summary_path();
+    if !config.summary_log() && path.is_none() {
+        return;
+    }
+    let summary = CycleSummaryEvent::new(config, station_ids, outcome, dry_run);
+    let line = match serde_json::to_string(&summary) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Failed to serialize cycle summary: {}", e);
+            return;
+        }
+    };
+    if config.summary_log() {
+        info!(target: "cycle_summary", "{}", line);
+    }
+    if let Some(path) = path
+        && let Err(e) = append(Path::new(path), &line)
+    {
+        warn!("{:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration as StdDuration;
+
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{
+        config::{GfroerliConfig, StationConfig},
+        observation::StationObservation,
+    };
+
+    #[test]
+    fn test_cycle_summary() {
+        let station = |foen_station_id, gfroerli_sensor_id| StationConfig {
+            foen_station_id,
+            gfroerli_sensor_id,
+            targets: None,
+            alias: None,
+            enabled: None,
+            station_type: None,
+            air_temperature: None,
+            alerts: None,
+            smoothing: None,
+        };
+        let config = Config::new(
+            vec![station(2104, 1), station(2176, 2), station(2135, 3)],
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        );
+        let started_at: DateTime<Utc> = "2025-01-15T12:00:00Z".parse().unwrap();
+        let mut outcome = CycleOutcome {
+            success: 1,
+            errors: 1,
+            cancelled: 1,
+            started_at,
+            duration: StdDuration::from_millis(2500),
+            ..Default::default()
+        };
+        let time = started_at - Duration::minutes(20);
+        outcome
+            .observations
+            .insert(2104, StationObservation::new(2104, "Linth", time, 6.5));
+        outcome
+            .failures
+            .insert(2176, "LINDAS is unavailable".to_string());
+        outcome
+            .station_durations
+            .insert(2104, StdDuration::from_millis(800));
+
+        let summary = CycleSummaryEvent::new(&config, &[2104, 2176, 2135], &outcome, false);
+        assert_eq!(
+            summary.finished_at,
+            started_at + Duration::milliseconds(2500)
+        );
+        assert_eq!(summary.duration_ms, 2500);
+        let statuses: Vec<_> = summary.stations.iter().map(|s| s.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StationStatus::Ok,
+                StationStatus::Failed,
+                StationStatus::Cancelled
+            ]
+        );
+        assert_eq!(
+            summary.stations[0],
+            StationSummary {
+                station_id: 2104,
+                sensor_id: Some(1),
+                status: StationStatus::Ok,
+                measurement_time: Some(time),
+                temperature: Some(6.5),
+                duration_ms: Some(800),
+                error: None,
+            }
+        );
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["stations"][1]["status"], "failed");
+        assert_eq!(json["stations"][1]["error"], "LINDAS is unavailable");
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cycles.jsonl");
+        append(&path, r#"{"cycle":1}"#).unwrap();
+        append(&path, r#"{"cycle":2}"#).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"cycle\":1}\n{\"cycle\":2}\n"
+        );
+    }
+}
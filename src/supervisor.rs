@@ -0,0 +1,271 @@
+//! Supervision of the per-station tasks of the loop mode
+//!
+//! Every station runs in its own task with its own [`StationTimer`], so that
+//! a station that keeps failing, hangs or panics never delays the others. The
+//! tasks report the outcome of every fetch through a channel. A panic while
+//! processing a station only fails that fetch, and a task that panicked
+//! anyway is restarted with an increasing delay, so that a crash loop doesn't
+//! hammer the endpoints.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::Utc;
+use tokio::{
+    sync::{Semaphore, mpsc},
+    task::{Id, JoinSet},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+
+use crate::{
+    capture::Capture,
+    config::{Config, RunSchedule},
+    control::Control,
+    database::MeasurementStore,
+    error::FetcherError,
+    http::HttpClients,
+    pipeline::{CycleOutcome, run_cycle},
+    schedule::StationTimer,
+    sparql::SparqlSource,
+};
+
+/// Maximum number of stations processed at the same time, so that the
+/// stations that are due together don't flood the endpoints
+const MAX_CONCURRENT_STATIONS: usize = 4;
+
+/// Delay before restarting a task after its first panic, doubled with every
+/// further panic
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay before restarting a task. A task that ran longer than this
+/// without panicking starts over with the minimum delay.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Delay before restarting a task after its `panics`-th consecutive panic
+fn restart_delay(panics: u32) -> Duration {
+    MIN_RESTART_DELAY
+        .saturating_mul(1 << panics.saturating_sub(1).min(16))
+        .min(MAX_RESTART_DELAY)
+}
+
+/// Everything a station task needs to process its station
+pub struct StationContext {
+    pub config: Arc<Config>,
+    pub clients: HttpClients,
+    pub source: SparqlSource,
+    pub capture: Option<Capture>,
+    pub store: Arc<dyn MeasurementStore>,
+    pub control: Control,
+    pub schedule: RunSchedule,
+    pub dry_run: bool,
+}
+
+/// Runs one task per station and restarts the tasks that panicked
+pub struct Supervisor {
+    tasks: JoinSet<()>,
+    /// Station and start time of every running task
+    stations: BTreeMap<Id, (u32, Instant)>,
+    /// Consecutive panics per station
+    panics: BTreeMap<u32, u32>,
+    context: Option<Arc<StationContext>>,
+    cancel: CancellationToken,
+    permits: Arc<Semaphore>,
+    reports: mpsc::Sender<(u32, CycleOutcome)>,
+}
+
+impl Supervisor {
+    /// Create a supervisor that sends the outcome of every fetch to `reports`,
+    /// together with the station ID
+    pub fn new(reports: mpsc::Sender<(u32, CycleOutcome)>) -> Self {
+        Self {
+            tasks: JoinSet::new(),
+            stations: BTreeMap::new(),
+            panics: BTreeMap::new(),
+            context: None,
+            cancel: CancellationToken::new(),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_STATIONS)),
+            reports,
+        }
+    }
+
+    /// Whether the station tasks are running
+    pub fn is_running(&self) -> bool {
+        !self.tasks.is_empty()
+    }
+
+    /// Start a task for each station, all due immediately
+    ///
+    /// The first fetch runs even while the fetcher is paused, so that a
+    /// triggered restart fetches every station once. The tasks are cancelled
+    /// with `shutdown` or [`Supervisor::stop`].
+    pub fn start(
+        &mut self,
+        context: StationContext,
+        station_ids: &[u32],
+        shutdown: &CancellationToken,
+    ) {
+        debug!("Starting tasks for {} stations", station_ids.len());
+        self.cancel = shutdown.child_token();
+        self.context = Some(Arc::new(context));
+        for &station_id in station_ids {
+            self.spawn(station_id, Duration::ZERO);
+        }
+    }
+
+    /// Cancel the station tasks and wait until they ended
+    pub async fn stop(&mut self) {
+        self.cancel.cancel();
+        while self.tasks.join_next().await.is_some() {}
+        self.stations.clear();
+        self.context = None;
+    }
+
+    /// Wait until a station task ended, and restart it if it panicked
+    ///
+    /// The panic is reported as a failed fetch of the station. Never returns
+    /// while no task is running. Cancel safe, so that it can be used in
+    /// `select!`.
+    pub async fn supervise(&mut self) {
+        let Some(result) = self.tasks.join_next_with_id().await else {
+            return std::future::pending().await;
+        };
+        let (id, panic) = match result {
+            Ok((id, ())) => (id, None),
+            Err(e) => (e.id(), e.try_into_panic().ok()),
+        };
+        let Some((station_id, started)) = self.stations.remove(&id) else {
+            return;
+        };
+        let Some(payload) = panic else {
+            return;
+        };
+        if self.cancel.is_cancelled() {
+            return;
+        }
+
+        let panics = self.panics.entry(station_id).or_default();
+        *panics = if started.elapsed() > MAX_RESTART_DELAY {
+            1
+        } else {
+            panics.saturating_add(1)
+        };
+        let delay = restart_delay(*panics);
+        let error = FetcherError::panic(station_id, payload.as_ref());
+        error!(
+            station_id,
+            "{}, restarting the task in {} seconds",
+            error,
+            delay.as_secs()
+        );
+        let mut outcome = CycleOutcome {
+            errors: 1,
+            panics: 1,
+            started_at: Utc::now(),
+            ..Default::default()
+        };
+        outcome.failures.insert(station_id, error.to_string());
+        // Not awaited, as the reports are received by the caller of this method
+        if self.reports.try_send((station_id, outcome)).is_err() {
+            warn!("Failed to report the panic of station {}", station_id);
+        }
+        self.spawn(station_id, delay);
+    }
+
+    fn spawn(&mut self, station_id: u32, delay: Duration) {
+        let Some(context) = self.context.clone() else {
+            return;
+        };
+        let task = run_station(
+            context,
+            station_id,
+            delay,
+            self.permits.clone(),
+            self.reports.clone(),
+            self.cancel.clone(),
+        );
+        let handle = self.tasks.spawn(task);
+        self.stations
+            .insert(handle.id(), (station_id, Instant::now() + delay));
+    }
+}
+
+/// Fetch a station after `delay` and then whenever its timer is due, until
+/// cancelled
+async fn run_station(
+    context: Arc<StationContext>,
+    station_id: u32,
+    delay: Duration,
+    permits: Arc<Semaphore>,
+    reports: mpsc::Sender<(u32, CycleOutcome)>,
+    cancel: CancellationToken,
+) {
+    if cancel
+        .run_until_cancelled(tokio::time::sleep(delay))
+        .await
+        .is_none()
+    {
+        return;
+    }
+    let mut timer = StationTimer::new(&context.config, context.schedule, station_id, Utc::now());
+    loop {
+        let outcome = {
+            let Some(Ok(_permit)) = cancel.run_until_cancelled(permits.acquire()).await else {
+                return;
+            };
+            run_cycle(
+                &context.clients,
+                &context.config,
+                &context.source,
+                context.capture.as_ref(),
+                context.store.as_ref(),
+                &[station_id],
+                context.dry_run,
+                &cancel,
+            )
+            .await
+        };
+        if cancel.is_cancelled() {
+            return;
+        }
+
+        let next = timer.next(&outcome, Utc::now());
+        // Not blocked by a full channel while the supervisor stops the tasks
+        if !matches!(
+            cancel
+                .run_until_cancelled(reports.send((station_id, outcome)))
+                .await,
+            Some(Ok(()))
+        ) {
+            return;
+        }
+        debug!(
+            "Next fetch of station {} in {} seconds",
+            context.config.station_label(station_id),
+            (next - Utc::now()).num_seconds()
+        );
+        let sleep = (next - Utc::now()).to_std().unwrap_or_default();
+        let wait = async {
+            tokio::time::sleep(sleep).await;
+            context.control.wait_resumed().await;
+        };
+        if cancel.run_until_cancelled(wait).await.is_none() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_delay() {
+        assert_eq!(restart_delay(1), Duration::from_secs(1));
+        assert_eq!(restart_delay(2), Duration::from_secs(2));
+        assert_eq!(restart_delay(5), Duration::from_secs(16));
+        assert_eq!(restart_delay(9), Duration::from_secs(256));
+        assert_eq!(restart_delay(10), MAX_RESTART_DELAY);
+        assert_eq!(restart_delay(u32::MAX), MAX_RESTART_DELAY);
+    }
+}
@@ -0,0 +1,60 @@
+//! Time zones of human-facing timestamps
+//!
+//! Everything is stored and sent in UTC, only timestamps in log lines and CLI
+//! output are converted to the configured time zones.
+
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+/// Format of timestamps in log lines and CLI output
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+/// Time zone of log timestamps, set once when logging is initialized
+static LOG_TIMEZONE: OnceLock<Tz> = OnceLock::new();
+
+/// Set the time zone of log timestamps
+///
+/// Only the first call has an effect, like initializing the logger.
+pub fn set_log_timezone(timezone: Tz) {
+    let _ = LOG_TIMEZONE.set(timezone);
+}
+
+/// Get the time zone of log timestamps, UTC until it is set
+pub fn log_timezone() -> Tz {
+    LOG_TIMEZONE.get().copied().unwrap_or(Tz::UTC)
+}
+
+/// Format a timestamp in a time zone
+pub fn format_time(time: DateTime<Utc>, timezone: Tz) -> String {
+    time.with_timezone(&timezone)
+        .format(TIME_FORMAT)
+        .to_string()
+}
+
+/// Format a timestamp for a log line
+pub fn log_time(time: DateTime<Utc>) -> String {
+    format_time(time, log_timezone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        let winter = "2025-01-15T12:00:00Z".parse().unwrap();
+        let summer = "2025-07-15T12:00:00Z".parse().unwrap();
+
+        assert_eq!(format_time(winter, Tz::UTC), "2025-01-15 12:00:00 +0000");
+        assert_eq!(
+            format_time(winter, Tz::Europe__Zurich),
+            "2025-01-15 13:00:00 +0100"
+        );
+        assert_eq!(
+            format_time(summer, Tz::Europe__Zurich),
+            "2025-07-15 14:00:00 +0200"
+        );
+    }
+}
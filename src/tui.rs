@@ -0,0 +1,349 @@
+//! Live dashboard of the stations in loop mode (`--tui`)
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration as StdDuration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::Constraint,
+    style::{Color, Style},
+    widgets::{Block, Cell, Row, Table},
+};
+
+use crate::{
+    config::Config,
+    display::{format_delta, format_temperature},
+    pipeline::CycleOutcome,
+};
+
+/// How often the dashboard is redrawn, so that the ages stay current
+const REDRAW_INTERVAL: StdDuration = StdDuration::from_secs(1);
+
+/// Outcome of the most recent processing of a station
+#[derive(Debug, Clone, PartialEq)]
+enum SendStatus {
+    /// Fetched and delivered (or already sent before)
+    Ok,
+    /// Fetching or delivering failed
+    Failed(String),
+}
+
+/// Row of a station in the dashboard
+#[derive(Debug, Clone, PartialEq)]
+struct StationRow {
+    label: String,
+    sensor_id: u32,
+    temperature: Option<f32>,
+    /// Change of the temperature since the previous measurement
+    delta: Option<f32>,
+    measurement_time: Option<DateTime<Utc>>,
+    status: Option<SendStatus>,
+    errors: u32,
+}
+
+/// State shown by the dashboard, updated after every cycle
+#[derive(Debug)]
+pub struct Dashboard {
+    rows: BTreeMap<u32, StationRow>,
+    cycles: u64,
+    last_cycle_at: Option<DateTime<Utc>>,
+    stale_after: Duration,
+    timezone: Tz,
+}
+
+impl Dashboard {
+    /// Create a dashboard with an empty row per enabled station
+    pub fn new(config: &Config) -> Self {
+        let mut dashboard = Self {
+            rows: BTreeMap::new(),
+            cycles: 0,
+            last_cycle_at: None,
+            stale_after: Duration::zero(),
+            timezone: Tz::UTC,
+        };
+        dashboard.apply_config(config);
+        dashboard
+    }
+
+    /// Follow the stations and settings of a (reloaded) configuration
+    fn apply_config(&mut self, config: &Config) {
+        let stations: BTreeMap<_, _> = config
+            .enabled_stations()
+            .map(|s| (s.foen_station_id, s))
+            .collect();
+        self.rows.retain(|id, _| stations.contains_key(id));
+        for (&station_id, station) in &stations {
+            let row = self.rows.entry(station_id).or_insert_with(|| StationRow {
+                label: String::new(),
+                sensor_id: 0,
+                temperature: None,
+                delta: None,
+                measurement_time: None,
+                status: None,
+                errors: 0,
+            });
+            row.label = config.station_label(station_id);
+            row.sensor_id = station.gfroerli_sensor_id;
+        }
+        self.stale_after = Duration::minutes(config.stale_after_minutes().into());
+        self.timezone = config.display_timezone();
+    }
+
+    /// Record the outcome of a cycle
+    ///
+    /// Stations that weren't part of the cycle keep their previous values.
+    pub fn record(&mut self, config: &Config, outcome: &CycleOutcome, now: DateTime<Utc>) {
+        self.apply_config(config);
+        for (station_id, observation) in &outcome.observations {
+            if let Some(row) = self.rows.get_mut(station_id) {
+                if let Some(previous) = row.temperature
+                    && row.measurement_time != Some(observation.time())
+                {
+                    row.delta = Some(observation.temperature() - previous);
+                }
+                row.temperature = Some(observation.temperature());
+                row.measurement_time = Some(observation.time());
+                row.status = Some(SendStatus::Ok);
+            }
+        }
+        for (station_id, error) in &outcome.failures {
+            if let Some(row) = self.rows.get_mut(station_id) {
+                row.status = Some(SendStatus::Failed(error.clone()));
+                row.errors += 1;
+            }
+        }
+        self.cycles += 1;
+        self.last_cycle_at = Some(now);
+    }
+
+    /// Draw the station table
+    pub fn render(&self, frame: &mut Frame, now: DateTime<Utc>) {
+        let header = Row::new(vec![
+            "STATION",
+            "SENSOR",
+            "TEMPERATURE",
+            "MEASURED",
+            "AGE",
+            "STATUS",
+            "ERRORS",
+            "LAST ERROR",
+        ])
+        .style(Style::new().fg(Color::Cyan));
+
+        let rows = self.rows.values().map(|row| {
+            let age = row.measurement_time.map(|time| now - time);
+            let age_style = match age {
+                Some(age) if age > self.stale_after => Style::new().fg(Color::Yellow),
+                _ => Style::new(),
+            };
+            let (status, status_style, last_error) = match &row.status {
+                Some(SendStatus::Ok) => ("ok", Style::new().fg(Color::Green), ""),
+                Some(SendStatus::Failed(error)) => {
+                    ("failed", Style::new().fg(Color::Red), error.as_str())
+                }
+                None => ("-", Style::new(), ""),
+            };
+            Row::new(vec![
+                Cell::from(row.label.clone()),
+                Cell::from(row.sensor_id.to_string()),
+                Cell::from(
+                    row.temperature
+                        .map(|t| format!("{} °C{}", format_temperature(t), format_delta(row.delta)))
+                        .unwrap_or_else(|| "-".to_string()),
+                ),
+                Cell::from(
+                    row.measurement_time
+                        .map(|time| {
+                            time.with_timezone(&self.timezone)
+                                .format("%Y-%m-%d %H:%M")
+                                .to_string()
+                        })
+                        .unwrap_or_else(|| "-".to_string()),
+                ),
+                Cell::from(age.map(format_age).unwrap_or_else(|| "-".to_string())).style(age_style),
+                Cell::from(status).style(status_style),
+                Cell::from(row.errors.to_string()),
+                Cell::from(last_error.to_string()),
+            ])
+        });
+
+        let title = match self.last_cycle_at {
+            Some(time) => format!(
+                " {} cycles, last finished {} (q to quit) ",
+                self.cycles,
+                time.with_timezone(&self.timezone).format("%H:%M:%S")
+            ),
+            None => " Waiting for the first cycle (q to quit) ".to_string(),
+        };
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(30),
+                Constraint::Length(6),
+                Constraint::Length(22),
+                Constraint::Length(16),
+                Constraint::Length(8),
+                Constraint::Length(6),
+                Constraint::Length(6),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(header)
+        .block(Block::bordered().title(title));
+        frame.render_widget(table, frame.area());
+    }
+}
+
+/// Format the age of a measurement, e.g. "5m" or "2h 03m"
+fn format_age(age: Duration) -> String {
+    let minutes = age.num_minutes().max(0);
+    if minutes < 60 {
+        format!("{minutes}m")
+    } else {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// Dashboard drawn on the terminal by a background thread
+///
+/// The terminal is restored and the process exits when `q` or Ctrl-C is
+/// pressed.
+#[derive(Debug, Clone)]
+pub struct Tui {
+    dashboard: Arc<Mutex<Dashboard>>,
+}
+
+impl Tui {
+    /// Take over the terminal and start drawing the dashboard
+    pub fn start(config: &Config) -> Result<Self> {
+        let dashboard = Arc::new(Mutex::new(Dashboard::new(config)));
+        let terminal = ratatui::try_init().with_context(|| "Failed to initialize the terminal")?;
+
+        let shared = dashboard.clone();
+        thread::Builder::new()
+            .name("tui".to_string())
+            .spawn(move || {
+                let result = draw_loop(terminal, &shared);
+                ratatui::restore();
+                if let Err(e) = result {
+                    eprintln!("Dashboard failed: {e:#}");
+                }
+                std::process::exit(0);
+            })
+            .with_context(|| "Failed to start the dashboard")?;
+        Ok(Self { dashboard })
+    }
+
+    /// Record the outcome of a cycle
+    pub fn record(&self, config: &Config, outcome: &CycleOutcome) {
+        self.dashboard
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(config, outcome, Utc::now());
+    }
+}
+
+/// Redraw the dashboard until `q` or Ctrl-C is pressed
+fn draw_loop(mut terminal: DefaultTerminal, dashboard: &Mutex<Dashboard>) -> Result<()> {
+    loop {
+        terminal.draw(|frame| {
+            dashboard
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .render(frame, Utc::now())
+        })?;
+        if event::poll(REDRAW_INTERVAL)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.code == KeyCode::Char('q') || ctrl_c {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{Terminal, backend::TestBackend};
+
+    use super::*;
+    use crate::{
+        config::{GfroerliConfig, StationConfig},
+        observation::StationObservation,
+    };
+
+    fn config() -> Config {
+        let station = |foen_station_id, gfroerli_sensor_id| StationConfig {
+            foen_station_id,
+            gfroerli_sensor_id,
+            targets: None,
+            alias: None,
+            enabled: None,
+            station_type: None,
+            air_temperature: None,
+            alerts: None,
+            smoothing: None,
+        };
+        Config::new(
+            vec![station(2104, 1), station(2176, 2)],
+            GfroerliConfig::new("http://localhost:3000/api".to_string(), "key".into()),
+        )
+    }
+
+    #[test]
+    fn test_dashboard() {
+        let config = config();
+        let mut dashboard = Dashboard::new(&config);
+        let now = "2025-01-15T12:00:00Z".parse().unwrap();
+
+        let observation = |minutes, temperature| {
+            StationObservation::new(2104, "Linth", now - Duration::minutes(minutes), temperature)
+        };
+        let mut outcome = CycleOutcome::default();
+        outcome.observations.insert(2104, observation(135, 6.25));
+        outcome
+            .failures
+            .insert(2176, "LINDAS is unavailable".to_string());
+        dashboard.record(&config, &outcome, now);
+        let mut outcome = CycleOutcome::default();
+        outcome.observations.insert(2104, observation(125, 6.5));
+        dashboard.record(&config, &outcome, now);
+
+        let row = &dashboard.rows[&2104];
+        assert_eq!(row.temperature, Some(6.5));
+        assert_eq!(row.delta, Some(0.25));
+        assert_eq!(row.status, Some(SendStatus::Ok));
+        let row = &dashboard.rows[&2176];
+        assert_eq!(row.errors, 1);
+        assert_eq!(
+            row.status,
+            Some(SendStatus::Failed("LINDAS is unavailable".to_string()))
+        );
+
+        let mut terminal = Terminal::new(TestBackend::new(140, 6)).unwrap();
+        terminal.draw(|frame| dashboard.render(frame, now)).unwrap();
+        let screen = format!("{:?}", terminal.backend().buffer());
+        assert!(screen.contains("2 cycles"));
+        assert!(screen.contains("6.500 °C (+0.250)"));
+        assert!(screen.contains("2h 05m"));
+        assert!(screen.contains("LINDAS is unavailable"));
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::minutes(5)), "5m");
+        assert_eq!(format_age(Duration::minutes(123)), "2h 03m");
+        assert_eq!(format_age(Duration::minutes(-1)), "0m");
+    }
+}